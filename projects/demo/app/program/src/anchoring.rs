@@ -0,0 +1,138 @@
+//! Anchors an event's lifecycle on bitcoin. Creating, closing, resolving and
+//! cancelling events each spend the event account's utxo to a new one in a
+//! state transition the account signs, so anyone can follow the account's
+//! history on bitcoin and check each change against the transaction that
+//! carried it.
+//!
+//! Off-chain the runtime can neither look up the account's utxo nor sign, so
+//! unit tests run against the stand-ins in `test_utils`, which record the
+//! transaction instead.
+use arch_program::{
+    account::AccountInfo,
+    bitcoin::{absolute::LockTime, consensus, transaction::Version, Transaction},
+    entrypoint::ProgramResult,
+    input_to_sign::InputToSign,
+    transaction_to_sign::TransactionToSign,
+};
+#[cfg(not(test))]
+use arch_program::{helper::add_state_transition, program::set_transaction_to_sign};
+
+#[cfg(test)]
+use crate::test_utils::{add_state_transition, set_transaction_to_sign};
+
+/// Hands the runtime a transaction carrying just the event account's state
+/// transition, for the account to sign. Called once per instruction, after
+/// the event account is stored.
+pub(crate) fn anchor_state_transition(event_account: &AccountInfo<'_>) -> ProgramResult {
+    let mut transaction = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![],
+        output: vec![],
+    };
+    add_state_transition(&mut transaction, event_account);
+
+    let inputs_to_sign = [InputToSign {
+        index: 0,
+        signer: *event_account.key,
+    }];
+
+    let tx_bytes = consensus::serialize(&transaction);
+    set_transaction_to_sign(
+        std::slice::from_ref(event_account),
+        TransactionToSign {
+            tx_bytes: &tx_bytes,
+            inputs_to_sign: &inputs_to_sign,
+        },
+    )
+}
+
+#[cfg(test)]
+mod anchoring_tests {
+    use arch_program::{program_error::ProgramError, pubkey::Pubkey, utxo::UtxoMeta};
+
+    use super::*;
+    use crate::test_utils::{take_transactions_to_sign, Market, TestAccount};
+    use crate::{process_cancel_event, process_close_event, process_resolve_event};
+
+    // the one transaction anchored since the last call, checked to spend the
+    // event account's utxo and be signed by it alone
+    fn anchored_once(event: &TestAccount) {
+        let anchored = take_transactions_to_sign();
+        assert_eq!(anchored.len(), 1);
+        let (transaction, inputs_to_sign) = &anchored[0];
+        assert_eq!(transaction.input.len(), 1);
+        assert_eq!(transaction.output.len(), 1);
+        assert_eq!(
+            transaction.input[0].previous_output,
+            event.utxo.to_outpoint()
+        );
+        assert_eq!(
+            inputs_to_sign,
+            &[InputToSign {
+                index: 0,
+                signer: event.key(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_create_close_and_resolve_are_anchored() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, [75; 32], &[(alice.key(), 100)]);
+        market.event.utxo = UtxoMeta::from([0xe1; 32], 3);
+        // creating was anchored, activating is not among the anchored changes
+        assert_eq!(take_transactions_to_sign().len(), 1);
+
+        market.buy(&mut alice, 0, 10).unwrap();
+        assert!(take_transactions_to_sign().is_empty());
+
+        process_close_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            [75; 32],
+        )
+        .unwrap();
+        anchored_once(&market.event);
+
+        process_resolve_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            [75; 32],
+            0,
+        )
+        .unwrap();
+        anchored_once(&market.event);
+    }
+
+    #[test]
+    fn test_cancel_is_anchored_and_a_failed_one_is_not() {
+        let program_id = Pubkey::new_unique();
+        let mut market = Market::new(&program_id, [76; 32], &[]);
+        let mut stranger = TestAccount::new(&program_id, &[]).signer();
+        take_transactions_to_sign();
+
+        assert_eq!(
+            process_cancel_event(
+                &program_id,
+                &market.event.info(),
+                &stranger.info(),
+                [76; 32],
+            ),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert!(take_transactions_to_sign().is_empty());
+
+        process_cancel_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            [76; 32],
+        )
+        .unwrap();
+        anchored_once(&market.event);
+    }
+}
@@ -0,0 +1,122 @@
+//! The event archive: one append-only account that `ArchiveEvent` moves
+//! finished events into, so their event account stops carrying them while
+//! `QueryArchivedEvent` can still read them. After a u32 record count, each
+//! record is the event's id, the length of its borsh bytes as a u32 and the
+//! bytes themselves. Records are never rewritten or dropped; an id freed by
+//! archiving can be reused, and archived again, in which case the latest
+//! record is the one read.
+use arch_program::{
+    account::AccountInfo,
+    bitcoin::hashes::{sha256, Hash, HashEngine},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+use borsh::BorshDeserialize;
+
+use crate::{
+    ensure_program_owned, storage,
+    types::{PredictionEvent, PredictionMarketError},
+};
+
+// the record count
+const HEADER_LEN: usize = 4;
+// id and length in front of each event's bytes
+const RECORD_HEADER_LEN: usize = 36;
+
+/// Address of the archive. Derived like `escrow_address`, so no signer can
+/// hold it.
+pub fn derive_archive_address(program_id: &Pubkey) -> Pubkey {
+    let mut engine = sha256::Hash::engine();
+    engine.input(b"archive");
+    engine.input(&program_id.0);
+    Pubkey(sha256::Hash::from_engine(engine).to_byte_array())
+}
+
+/// Fails with `ArchiveAddressMismatch` unless `archive_account` is the
+/// program's archive.
+pub fn ensure_archive(
+    program_id: &Pubkey,
+    archive_account: &AccountInfo<'_>,
+) -> Result<(), ProgramError> {
+    ensure_program_owned(program_id, &[archive_account])?;
+
+    if *archive_account.key != derive_archive_address(program_id) {
+        return Err(PredictionMarketError::ArchiveAddressMismatch.into());
+    }
+
+    Ok(())
+}
+
+/// Number of archived events; an empty account holds none yet.
+pub fn record_count(data: &[u8]) -> Result<usize, ProgramError> {
+    if data.is_empty() {
+        return Ok(0);
+    }
+
+    data.get(..HEADER_LEN)
+        .and_then(|header| u32::try_from_slice(header).ok())
+        .map(|count| count as usize)
+        .ok_or(ProgramError::InvalidAccountData)
+}
+
+// byte range of the latest record of `unique_id`'s event, checking every
+// record lies within the account on the way
+fn find(data: &[u8], unique_id: &[u8; 32]) -> Result<Option<(usize, usize)>, ProgramError> {
+    let mut found = None;
+    let mut start = HEADER_LEN;
+    for _ in 0..record_count(data)? {
+        let header = data
+            .get(start..start + RECORD_HEADER_LEN)
+            .ok_or(ProgramError::InvalidAccountData)?;
+        let len = u32::from_le_bytes(header[32..].try_into().unwrap()) as usize;
+        let end = start + RECORD_HEADER_LEN + len;
+        if end > data.len() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        if header[..32] == unique_id[..] {
+            found = Some((start + RECORD_HEADER_LEN, end));
+        }
+        start = end;
+    }
+    if start != data.len().max(HEADER_LEN) {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    Ok(found)
+}
+
+/// Decodes the archived event with `unique_id`; fails with `EventNotFound`
+/// if it was never archived.
+pub fn load_archived(data: &[u8], unique_id: &[u8; 32]) -> Result<PredictionEvent, ProgramError> {
+    let (start, end) = find(data, unique_id)?.ok_or(PredictionMarketError::EventNotFound)?;
+
+    PredictionEvent::try_from_slice(&data[start..end])
+        .map_err(|e| ProgramError::BorshIoError(e.to_string()))
+}
+
+/// Grows the archive by one record holding `event`.
+pub fn append(
+    archive_account: &AccountInfo<'_>,
+    event: &PredictionEvent,
+) -> Result<(), ProgramError> {
+    let bytes = borsh::to_vec(event).map_err(|e| ProgramError::BorshIoError(e.to_string()))?;
+    let (count, old_len) = {
+        let data = archive_account.try_borrow_data()?;
+        // walks the records, so a corrupt archive is never appended to
+        find(&data, &event.unique_id)?;
+        (record_count(&data)?, data.len().max(HEADER_LEN))
+    };
+    let len = old_len + RECORD_HEADER_LEN + bytes.len();
+
+    storage::realloc(archive_account, len, true)?;
+    storage::ensure_len(archive_account, len)?;
+
+    let mut data = archive_account.try_borrow_mut_data()?;
+    data[..HEADER_LEN].copy_from_slice(&(count as u32 + 1).to_le_bytes());
+    data[old_len..old_len + 32].copy_from_slice(&event.unique_id);
+    data[old_len + 32..old_len + RECORD_HEADER_LEN]
+        .copy_from_slice(&(bytes.len() as u32).to_le_bytes());
+    data[old_len + RECORD_HEADER_LEN..].copy_from_slice(&bytes);
+
+    Ok(())
+}
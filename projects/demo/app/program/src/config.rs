@@ -0,0 +1,607 @@
+use arch_program::{account::AccountInfo, msg, program_error::ProgramError, pubkey::Pubkey};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::math::BPS_DENOMINATOR;
+use crate::types::PredictionMarketError;
+use crate::{ensure_distinct_accounts, get_bitcoin_block_height, storage};
+
+/// Program wide settings, stored in a single program owned account.
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct ProgramConfig {
+    pub admin: Pubkey,
+    pub paused: bool,
+    // kept sorted; empty means anyone may create events
+    pub creator_allowlist: Vec<Pubkey>,
+    // token holder that unclaimed payouts are swept to
+    pub treasury: Pubkey,
+    // tokens a creator locks in the event's escrow to create it, 0 for none
+    pub creator_bond: u64,
+    // charged on top of every buy's cost and paid to the treasury, in basis
+    // points of the cost
+    pub protocol_fee_bps: u16,
+    // part of the protocol fee of a referred buy paid to its referrer instead
+    pub referral_share_bps: u16,
+    // withheld from a cashout's value and left in the pool, in basis points
+    pub cashout_haircut_bps: u16,
+    // what a market order does when it would fill the taker's own order
+    pub self_trade_policy: SelfTradePolicy,
+    // mints events may take bets in, kept sorted; empty means any mint
+    pub mint_allowlist: Vec<Pubkey>,
+    // bitcoin blocks a utxo-funded bet's deposit must be buried under before
+    // it is credited, 0 credits it at once
+    pub min_confirmations: u64,
+    // events a creator may have `Created` or `Active` at once, counted in
+    // the registry; 0 is unlimited
+    pub max_active_events_per_creator: u32,
+}
+
+/// How matching treats a resting order of the taker's own at the crossing
+/// price. A user never trades with themselves; the policy decides which of
+/// the two orders gives way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub enum SelfTradePolicy {
+    /// The resting order is cancelled and matching carries on past it.
+    #[default]
+    CancelResting,
+    /// Matching stops and what is left of the market order is cancelled.
+    CancelIncoming,
+    /// The smaller of the two is taken off both without a trade, and
+    /// whichever is left over carries on.
+    DecrementBoth,
+}
+
+/// Highest protocol fee the admin can set.
+pub const MAX_PROTOCOL_FEE_BPS: u16 = 1_000;
+
+impl ProgramConfig {
+    pub fn new(admin: Pubkey) -> Self {
+        ProgramConfig {
+            admin,
+            paused: false,
+            creator_allowlist: Vec::new(),
+            treasury: admin,
+            creator_bond: 0,
+            protocol_fee_bps: 0,
+            referral_share_bps: 0,
+            cashout_haircut_bps: 0,
+            self_trade_policy: SelfTradePolicy::default(),
+            mint_allowlist: Vec::new(),
+            min_confirmations: 0,
+            max_active_events_per_creator: 0,
+        }
+    }
+
+    pub fn is_creator_allowed(&self, creator: &Pubkey) -> bool {
+        self.creator_allowlist.is_empty() || self.creator_allowlist.binary_search(creator).is_ok()
+    }
+
+    pub fn is_mint_allowed(&self, mint: &Pubkey) -> bool {
+        self.mint_allowlist.is_empty() || self.mint_allowlist.binary_search(mint).is_ok()
+    }
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct SetPausedParams {
+    pub paused: bool,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct CreatorParams {
+    pub creator: Pubkey,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct SetCreatorBondParams {
+    pub amount: u64,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct SetProtocolFeesParams {
+    pub protocol_fee_bps: u16,
+    pub referral_share_bps: u16,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct SetCashoutHaircutParams {
+    pub haircut_bps: u16,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct SetSelfTradePolicyParams {
+    pub policy: SelfTradePolicy,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct SetMinConfirmationsParams {
+    pub min_confirmations: u64,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct SetMaxActiveEventsParams {
+    pub max_active_events: u32,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct MintParams {
+    pub mint: Pubkey,
+}
+
+pub fn load_config(config_account: &AccountInfo<'_>) -> Result<ProgramConfig, ProgramError> {
+    storage::read(config_account).map_err(|_| ProgramError::UninitializedAccount)
+}
+
+pub fn store_config(
+    config_account: &AccountInfo<'_>,
+    config: &ProgramConfig,
+) -> Result<(), ProgramError> {
+    storage::write(config_account, config)?;
+    Ok(())
+}
+
+/// Loads the config and checks that `admin_account` is its signing admin.
+pub fn load_config_as_admin(
+    config_account: &AccountInfo<'_>,
+    admin_account: &AccountInfo<'_>,
+) -> Result<ProgramConfig, ProgramError> {
+    if !admin_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    ensure_distinct_accounts(&[config_account.key, admin_account.key])?;
+
+    let config = load_config(config_account)?;
+
+    if config.admin != *admin_account.key {
+        return Err(ProgramError::IncorrectAuthority);
+    }
+
+    Ok(config)
+}
+
+pub fn initialize_config(
+    config_account: &AccountInfo<'_>,
+    admin_account: &AccountInfo<'_>,
+    program_id: &Pubkey,
+) -> Result<(), ProgramError> {
+    if !admin_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    ensure_distinct_accounts(&[config_account.key, admin_account.key])?;
+
+    if !config_account.data_is_empty() {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    if config_account.owner != program_id {
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    store_config(config_account, &ProgramConfig::new(*admin_account.key))
+}
+
+pub fn set_paused(
+    config_account: &AccountInfo<'_>,
+    admin_account: &AccountInfo<'_>,
+    paused: bool,
+) -> Result<(), ProgramError> {
+    let mut config = load_config_as_admin(config_account, admin_account)?;
+
+    config.paused = paused;
+
+    msg!(
+        "Program paused set to {} at block height {}",
+        paused,
+        get_bitcoin_block_height()
+    );
+
+    store_config(config_account, &config)
+}
+
+/// Fails with `ProgramPaused` while the emergency switch is on.
+pub fn ensure_not_paused(config_account: &AccountInfo<'_>) -> Result<(), ProgramError> {
+    if load_config(config_account)?.paused {
+        return Err(PredictionMarketError::ProgramPaused.into());
+    }
+
+    Ok(())
+}
+
+pub fn add_creator(
+    config_account: &AccountInfo<'_>,
+    admin_account: &AccountInfo<'_>,
+    creator: Pubkey,
+) -> Result<(), ProgramError> {
+    let mut config = load_config_as_admin(config_account, admin_account)?;
+
+    if let Err(index) = config.creator_allowlist.binary_search(&creator) {
+        config.creator_allowlist.insert(index, creator);
+    }
+
+    store_config(config_account, &config)
+}
+
+pub fn remove_creator(
+    config_account: &AccountInfo<'_>,
+    admin_account: &AccountInfo<'_>,
+    creator: Pubkey,
+) -> Result<(), ProgramError> {
+    let mut config = load_config_as_admin(config_account, admin_account)?;
+
+    let index = config
+        .creator_allowlist
+        .binary_search(&creator)
+        .map_err(|_| ProgramError::InvalidArgument)?;
+    config.creator_allowlist.remove(index);
+
+    store_config(config_account, &config)
+}
+
+/// Allows events to take bets in `mint`. Events already created keep their
+/// mint either way.
+pub fn add_mint(
+    config_account: &AccountInfo<'_>,
+    admin_account: &AccountInfo<'_>,
+    mint: Pubkey,
+) -> Result<(), ProgramError> {
+    let mut config = load_config_as_admin(config_account, admin_account)?;
+
+    if let Err(index) = config.mint_allowlist.binary_search(&mint) {
+        config.mint_allowlist.insert(index, mint);
+    }
+
+    store_config(config_account, &config)
+}
+
+pub fn remove_mint(
+    config_account: &AccountInfo<'_>,
+    admin_account: &AccountInfo<'_>,
+    mint: Pubkey,
+) -> Result<(), ProgramError> {
+    let mut config = load_config_as_admin(config_account, admin_account)?;
+
+    let index = config
+        .mint_allowlist
+        .binary_search(&mint)
+        .map_err(|_| ProgramError::InvalidArgument)?;
+    config.mint_allowlist.remove(index);
+
+    store_config(config_account, &config)
+}
+
+/// Sets the bond required of events created from now on; existing events
+/// keep the bond they were created with.
+pub fn set_creator_bond(
+    config_account: &AccountInfo<'_>,
+    admin_account: &AccountInfo<'_>,
+    amount: u64,
+) -> Result<(), ProgramError> {
+    let mut config = load_config_as_admin(config_account, admin_account)?;
+
+    config.creator_bond = amount;
+
+    store_config(config_account, &config)
+}
+
+/// Sets the fee charged on buys from now on and the share of it referrers
+/// earn.
+pub fn set_protocol_fees(
+    config_account: &AccountInfo<'_>,
+    admin_account: &AccountInfo<'_>,
+    protocol_fee_bps: u16,
+    referral_share_bps: u16,
+) -> Result<(), ProgramError> {
+    let mut config = load_config_as_admin(config_account, admin_account)?;
+
+    if protocol_fee_bps > MAX_PROTOCOL_FEE_BPS {
+        return Err(PredictionMarketError::ProtocolFeeTooHigh.into());
+    }
+
+    if referral_share_bps as u64 > BPS_DENOMINATOR {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    config.protocol_fee_bps = protocol_fee_bps;
+    config.referral_share_bps = referral_share_bps;
+
+    store_config(config_account, &config)
+}
+
+pub fn set_cashout_haircut(
+    config_account: &AccountInfo<'_>,
+    admin_account: &AccountInfo<'_>,
+    haircut_bps: u16,
+) -> Result<(), ProgramError> {
+    let mut config = load_config_as_admin(config_account, admin_account)?;
+
+    if haircut_bps as u64 > BPS_DENOMINATOR {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    config.cashout_haircut_bps = haircut_bps;
+
+    store_config(config_account, &config)
+}
+
+/// Sets how market orders from now on treat the taker's own resting orders.
+pub fn set_self_trade_policy(
+    config_account: &AccountInfo<'_>,
+    admin_account: &AccountInfo<'_>,
+    policy: SelfTradePolicy,
+) -> Result<(), ProgramError> {
+    let mut config = load_config_as_admin(config_account, admin_account)?;
+
+    config.self_trade_policy = policy;
+
+    store_config(config_account, &config)
+}
+
+/// Sets how many confirmations a utxo-funded bet waits for before it is
+/// credited. The crank reads it each time it runs, so bets already pending
+/// wait for the new value too.
+pub fn set_min_confirmations(
+    config_account: &AccountInfo<'_>,
+    admin_account: &AccountInfo<'_>,
+    min_confirmations: u64,
+) -> Result<(), ProgramError> {
+    let mut config = load_config_as_admin(config_account, admin_account)?;
+
+    config.min_confirmations = min_confirmations;
+
+    store_config(config_account, &config)
+}
+
+/// Caps how many events each creator may have open at once, see
+/// `registry::register`. Creators already past a lowered cap keep their
+/// events but can't create more until enough of them close.
+pub fn set_max_active_events(
+    config_account: &AccountInfo<'_>,
+    admin_account: &AccountInfo<'_>,
+    max_active_events: u32,
+) -> Result<(), ProgramError> {
+    let mut config = load_config_as_admin(config_account, admin_account)?;
+
+    config.max_active_events_per_creator = max_active_events;
+
+    store_config(config_account, &config)
+}
+
+/// Fails with `CreatorNotAllowed` when an allowlist is set and `creator` is
+/// not on it.
+pub fn ensure_creator_allowed(
+    config_account: &AccountInfo<'_>,
+    creator: &Pubkey,
+) -> Result<(), ProgramError> {
+    if !load_config(config_account)?.is_creator_allowed(creator) {
+        return Err(PredictionMarketError::CreatorNotAllowed.into());
+    }
+
+    Ok(())
+}
+
+/// Fails with `MintNotAllowed` when a mint allowlist is set and `mint` is not
+/// on it.
+pub fn ensure_mint_allowed(
+    config_account: &AccountInfo<'_>,
+    mint: &Pubkey,
+) -> Result<(), ProgramError> {
+    if !load_config(config_account)?.is_mint_allowed(mint) {
+        return Err(PredictionMarketError::MintNotAllowed.into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod creator_allowlist_tests {
+    use super::*;
+    use crate::process_create_event;
+    use crate::test_utils::{
+        config_account, event_account, event_params, token_account, TestAccount,
+    };
+
+    #[test]
+    fn test_allowlist_grows_sorted_and_shrinks_without_holes() {
+        let program_id = Pubkey::new_unique();
+        let mut admin = TestAccount::new(&program_id, &[]).signer();
+        let mut config = config_account(&program_id, &admin.key(), false);
+        let empty_len = config.data().len();
+        let creators = [
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        ];
+
+        for creator in creators.iter().rev() {
+            add_creator(&config.info(), &admin.info(), *creator).unwrap();
+        }
+        let stored = ProgramConfig::try_from_slice(config.data()).unwrap();
+        assert_eq!(stored.creator_allowlist, creators.to_vec());
+        assert_eq!(config.data().len(), empty_len + 3 * 32);
+
+        remove_creator(&config.info(), &admin.info(), creators[1]).unwrap();
+        let stored = ProgramConfig::try_from_slice(config.data()).unwrap();
+        assert_eq!(stored.creator_allowlist, vec![creators[0], creators[2]]);
+        assert_eq!(config.data().len(), empty_len + 2 * 32);
+    }
+
+    #[test]
+    fn test_only_listed_creators_can_create_once_populated() {
+        let program_id = Pubkey::new_unique();
+        let mut admin = TestAccount::new(&program_id, &[]).signer();
+        let mut config = config_account(&program_id, &admin.key(), false);
+        let mut token = token_account(&program_id, &[]);
+        let mut listed = TestAccount::new(&program_id, &[]).signer();
+        let mut unlisted = TestAccount::new(&program_id, &[]).signer();
+
+        // an empty allowlist is open to everyone
+        process_create_event(
+            &program_id,
+            &event_account(&program_id, &[1; 32]).info(),
+            &unlisted.info(),
+            &token.info(),
+            &config.info(),
+            event_params([1; 32], 100, 2),
+        )
+        .unwrap();
+
+        add_creator(&config.info(), &admin.info(), listed.key()).unwrap();
+
+        let rejected = process_create_event(
+            &program_id,
+            &event_account(&program_id, &[2; 32]).info(),
+            &unlisted.info(),
+            &token.info(),
+            &config.info(),
+            event_params([2; 32], 100, 2),
+        );
+        assert_eq!(
+            rejected,
+            Err(PredictionMarketError::CreatorNotAllowed.into())
+        );
+
+        process_create_event(
+            &program_id,
+            &event_account(&program_id, &[3; 32]).info(),
+            &listed.info(),
+            &token.info(),
+            &config.info(),
+            event_params([3; 32], 100, 2),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_removing_unknown_creator_fails() {
+        let program_id = Pubkey::new_unique();
+        let mut admin = TestAccount::new(&program_id, &[]).signer();
+        let mut config = config_account(&program_id, &admin.key(), false);
+
+        let result = remove_creator(&config.info(), &admin.info(), Pubkey::new_unique());
+
+        assert_eq!(result, Err(ProgramError::InvalidArgument));
+    }
+}
+
+#[cfg(test)]
+mod mint_allowlist_tests {
+    use super::*;
+    use crate::test_utils::{
+        config_account, event_account, event_params, token_account, Market, TestAccount, TEST_MINT,
+    };
+    use crate::types::PredictionEventParams;
+    use crate::{
+        process_activate_event, process_create_event, process_instruction, process_resolve_event,
+    };
+
+    const OTHER_MINT: Pubkey = Pubkey([0x6d; 32]);
+
+    fn set_mint(
+        program_id: &Pubkey,
+        config: &mut TestAccount,
+        admin: &mut TestAccount,
+        opcode: u8,
+        mint: Pubkey,
+    ) -> Result<(), ProgramError> {
+        let mut data = vec![opcode];
+        data.extend(borsh::to_vec(&MintParams { mint }).unwrap());
+        process_instruction(program_id, &[config.info(), admin.info()], &data)
+    }
+
+    fn create(
+        program_id: &Pubkey,
+        config: &mut TestAccount,
+        token: &mut TestAccount,
+        unique_id: [u8; 32],
+    ) -> Result<Market, ProgramError> {
+        let mut event = event_account(program_id, &unique_id);
+        let mut creator = TestAccount::new(program_id, &[]).signer();
+        let params = PredictionEventParams {
+            mint: token.key(),
+            ..event_params(unique_id, 100, 2)
+        };
+        process_create_event(
+            program_id,
+            &event.info(),
+            &creator.info(),
+            &token.info(),
+            &config.info(),
+            params,
+        )?;
+        process_activate_event(program_id, &event.info(), &creator.info(), unique_id)?;
+
+        // the market trades on copies of the token and config accounts
+        Ok(Market {
+            unique_id,
+            event,
+            creator,
+            token: TestAccount::new(program_id, token.data()).with_key(token.key()),
+            config: TestAccount::new(program_id, config.data()),
+        })
+    }
+
+    #[test]
+    fn test_events_take_bets_only_in_their_own_mint() {
+        let program_id = Pubkey::new_unique();
+        let mut admin = TestAccount::new(&program_id, &[]).signer();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut config = config_account(&program_id, &admin.key(), false);
+        let balances = [(alice.key(), 1_000)];
+        let mut bango = token_account(&program_id, &balances);
+        let mut other = token_account(&program_id, &balances).with_key(OTHER_MINT);
+        set_mint(&program_id, &mut config, &mut admin, 58, TEST_MINT).unwrap();
+        set_mint(&program_id, &mut config, &mut admin, 58, OTHER_MINT).unwrap();
+
+        let mut in_bango = create(&program_id, &mut config, &mut bango, [62; 32]).unwrap();
+        let mut in_other = create(&program_id, &mut config, &mut other, [63; 32]).unwrap();
+        in_bango.buy(&mut alice, 0, 100).unwrap();
+        in_other.buy(&mut alice, 0, 40).unwrap();
+        assert_eq!(in_bango.balance(&alice), 900);
+        assert_eq!(in_other.balance(&alice), 960);
+
+        // each market's token account swapped for the other's mint
+        std::mem::swap(&mut in_bango.token, &mut in_other.token);
+        let wrong_mint: ProgramError = PredictionMarketError::MintMismatch.into();
+        assert_eq!(in_bango.buy(&mut alice, 0, 10), Err(wrong_mint.clone()));
+        assert_eq!(in_other.sell(&mut alice, 0, 10), Err(wrong_mint.clone()));
+        process_resolve_event(
+            &program_id,
+            &in_other.event.info(),
+            &in_other.creator.info(),
+            [63; 32],
+            0,
+        )
+        .unwrap();
+        assert_eq!(in_other.claim(&mut alice), Err(wrong_mint));
+
+        std::mem::swap(&mut in_bango.token, &mut in_other.token);
+        in_other.claim(&mut alice).unwrap();
+        assert_eq!(in_other.balance(&alice), 1_000);
+    }
+
+    #[test]
+    fn test_only_listed_mints_back_new_events() {
+        let program_id = Pubkey::new_unique();
+        let mut admin = TestAccount::new(&program_id, &[]).signer();
+        let mut config = config_account(&program_id, &admin.key(), false);
+        let mut other = token_account(&program_id, &[]).with_key(OTHER_MINT);
+        set_mint(&program_id, &mut config, &mut admin, 58, TEST_MINT).unwrap();
+
+        assert_eq!(
+            create(&program_id, &mut config, &mut other, [62; 32]).err(),
+            Some(PredictionMarketError::MintNotAllowed.into())
+        );
+
+        // an empty allowlist takes any mint
+        set_mint(&program_id, &mut config, &mut admin, 59, TEST_MINT).unwrap();
+        assert!(load_config(&config.info())
+            .unwrap()
+            .mint_allowlist
+            .is_empty());
+        create(&program_id, &mut config, &mut other, [62; 32]).unwrap();
+        assert_eq!(
+            set_mint(&program_id, &mut config, &mut admin, 59, TEST_MINT),
+            Err(ProgramError::InvalidArgument)
+        );
+    }
+}
@@ -1,8 +1,32 @@
+use arch_program::program_error::ProgramError;
 use borsh::{BorshDeserialize, BorshSerialize};
 
-#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+/// Token errors are reported above this base so their codes never collide
+/// with `PredictionMarketError`.
+pub const FUNGIBLE_TOKEN_ERROR_BASE: u32 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, BorshSerialize, BorshDeserialize)]
 pub enum FungibleTokenError {
     InsufficientBalance,
     MintOver,
     NotEnoughRemainingMintableTokens,
-}
\ No newline at end of file
+    BatchTooLarge,
+    ZeroAmount,
+    DuplicateRecipient,
+    BalanceNotEmpty,
+    InvalidMultisig,
+    DuplicateSigner,
+    MultisigThresholdNotMet,
+    MintAddressMismatch,
+    TransferFeeTooHigh,
+    NonceAlreadyProcessed,
+    NotBtcBacked,
+    BtcBackedMint,
+    ReserveMismatch,
+}
+
+impl From<FungibleTokenError> for ProgramError {
+    fn from(e: FungibleTokenError) -> Self {
+        ProgramError::Custom(FUNGIBLE_TOKEN_ERROR_BASE + e as u32)
+    }
+}
@@ -0,0 +1,582 @@
+//! Layout of the event account: an index of `(unique_id, offset, len)`
+//! entries followed by the borsh bytes of every event, in index order.
+//! Handlers that touch a single event read and splice only that event's
+//! bytes instead of decoding the whole `Predictions` blob. Events are created
+//! at the address derived from their id, see `ensure_event_address`. An
+//! account holds at most `MAX_EVENTS_PER_ACCOUNT` of them, and growth the
+//! runtime refuses fails with `AccountTooSmall`.
+use arch_program::{account::AccountInfo, msg, program_error::ProgramError};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::storage;
+use crate::types::{derive_event_address, PredictionEvent, PredictionMarketError, Predictions};
+
+// `total_predictions` and the entry count
+const HEADER_LEN: usize = 8;
+const ENTRY_LEN: usize = 40;
+
+/// Largest event account `decode_predictions` will decode whole. Decoding
+/// every event costs compute in proportion to the account, so past this
+/// handlers fail with `AccountTooLarge` up front rather than running out of
+/// budget halfway through; the per-event `load_event` has no such limit.
+pub const MAX_DECODED_ACCOUNT_LEN: usize = 64 * 1024;
+
+/// Most events one event account holds. New events go to the account derived
+/// from their id, so only accounts that already hold several reach it; past
+/// it creation fails with `EventCapacityReached` up front instead of once the
+/// account can't grow.
+pub const MAX_EVENTS_PER_ACCOUNT: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct EventIndexEntry {
+    pub unique_id: [u8; 32],
+    // from the end of the index
+    pub offset: u32,
+    pub len: u32,
+}
+
+#[derive(Debug, Clone, Default, BorshSerialize, BorshDeserialize)]
+pub struct EventIndex {
+    pub total_predictions: u32,
+    pub entries: Vec<EventIndexEntry>,
+}
+
+impl EventIndex {
+    fn serialized_len(&self) -> usize {
+        HEADER_LEN + self.entries.len() * ENTRY_LEN
+    }
+
+    fn position(&self, unique_id: &[u8; 32]) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| entry.unique_id == *unique_id)
+    }
+
+    fn write(&self, data: &mut [u8]) -> Result<(), ProgramError> {
+        self.serialize(&mut &mut data[..self.serialized_len()])
+            .map_err(|e| ProgramError::BorshIoError(e.to_string()))
+    }
+}
+
+/// Fails with `EventCapacityReached` once `event_account` holds
+/// `MAX_EVENTS_PER_ACCOUNT` events.
+pub fn ensure_capacity(event_account: &AccountInfo<'_>) -> Result<(), ProgramError> {
+    let held = read_index(&event_account.try_borrow_data()?)?.entries.len();
+    if held >= MAX_EVENTS_PER_ACCOUNT {
+        msg!(
+            "Event account holds {} events already, use an id that derives a fresh one",
+            held
+        );
+        return Err(PredictionMarketError::EventCapacityReached.into());
+    }
+
+    Ok(())
+}
+
+/// Reads the index; an empty account holds no events.
+pub fn read_index(data: &[u8]) -> Result<EventIndex, ProgramError> {
+    if data.is_empty() {
+        return Ok(EventIndex::default());
+    }
+
+    EventIndex::deserialize(&mut &data[..]).map_err(|_| ProgramError::InvalidAccountData)
+}
+
+fn event_bytes<'d>(
+    data: &'d [u8],
+    index: &EventIndex,
+    entry: &EventIndexEntry,
+) -> Result<&'d [u8], ProgramError> {
+    let start = index.serialized_len() + entry.offset as usize;
+    data.get(start..start + entry.len as usize)
+        .ok_or(ProgramError::InvalidAccountData)
+}
+
+fn decode_event(bytes: &[u8]) -> Result<PredictionEvent, ProgramError> {
+    PredictionEvent::try_from_slice(bytes).map_err(|_| ProgramError::InvalidAccountData)
+}
+
+/// Decodes every event, in the order they were created.
+pub fn decode_predictions(data: &[u8]) -> Result<Predictions, ProgramError> {
+    if data.len() > MAX_DECODED_ACCOUNT_LEN {
+        return Err(PredictionMarketError::AccountTooLarge.into());
+    }
+
+    let index = read_index(data)?;
+
+    let predictions = index
+        .entries
+        .iter()
+        .map(|entry| decode_event(event_bytes(data, &index, entry)?))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Predictions {
+        total_predictions: index.total_predictions,
+        predictions,
+    })
+}
+
+/// Rewrites the whole account from `predictions`, resizing it once.
+pub fn store_predictions(
+    event_account: &AccountInfo<'_>,
+    predictions: &Predictions,
+) -> Result<(), ProgramError> {
+    let mut index = EventIndex {
+        total_predictions: predictions.total_predictions,
+        entries: Vec::with_capacity(predictions.predictions.len()),
+    };
+    let mut offset = 0usize;
+    for event in &predictions.predictions {
+        let len =
+            borsh::object_length(event).map_err(|e| ProgramError::BorshIoError(e.to_string()))?;
+        index.entries.push(EventIndexEntry {
+            unique_id: event.unique_id,
+            offset: offset as u32,
+            len: len as u32,
+        });
+        offset += len;
+    }
+
+    let total_len = index.serialized_len() + offset;
+    if event_account.data_len() != total_len {
+        storage::realloc(event_account, total_len, false)?;
+    }
+    storage::ensure_len(event_account, total_len)?;
+
+    let mut data = event_account.try_borrow_mut_data()?;
+    index.write(&mut data)?;
+    let mut writer: &mut [u8] = &mut data[index.serialized_len()..total_len];
+    for event in &predictions.predictions {
+        event
+            .serialize(&mut writer)
+            .map_err(|e| ProgramError::BorshIoError(e.to_string()))?;
+    }
+
+    Ok(())
+}
+
+/// Fails with `EventAddressMismatch` unless `event_account` sits at the
+/// address derived for `unique_id`, see `derive_event_address`. Every
+/// handler acting on one event checks this, through `load_event` or
+/// directly; they check the account is owned by the program first, so its
+/// owner is the program id. The crank and sweep take no single event and
+/// accept any account.
+pub fn ensure_event_address(
+    event_account: &AccountInfo<'_>,
+    unique_id: &[u8; 32],
+) -> Result<(), ProgramError> {
+    if *event_account.key != derive_event_address(event_account.owner, unique_id) {
+        return Err(PredictionMarketError::EventAddressMismatch.into());
+    }
+
+    Ok(())
+}
+
+/// Decodes only the event with `unique_id`.
+pub fn load_event(
+    event_account: &AccountInfo<'_>,
+    unique_id: &[u8; 32],
+) -> Result<PredictionEvent, ProgramError> {
+    ensure_event_address(event_account, unique_id)?;
+
+    let data = event_account.try_borrow_data()?;
+    let index = read_index(&data)?;
+
+    let position = index
+        .position(unique_id)
+        .ok_or(PredictionMarketError::EventNotFound)?;
+
+    decode_event(event_bytes(&data, &index, &index.entries[position])?)
+}
+
+/// Writes `event` over its stored bytes, or appends it if its id is new.
+/// Only the event's own region is rewritten; the events after it are shifted
+/// and their offsets updated, the ones before it are left untouched.
+pub fn store_event(
+    event_account: &AccountInfo<'_>,
+    event: &PredictionEvent,
+) -> Result<(), ProgramError> {
+    let new_len =
+        borsh::object_length(event).map_err(|e| ProgramError::BorshIoError(e.to_string()))?;
+
+    let old_total = event_account.data_len();
+    let mut index = read_index(&event_account.try_borrow_data()?)?;
+    // an empty account doesn't carry a header yet
+    let old_index_len = if old_total == 0 {
+        0
+    } else {
+        index.serialized_len()
+    };
+
+    let (offset, old_len) = match index.position(&event.unique_id) {
+        Some(position) => {
+            let old_len = index.entries[position].len;
+            index.entries[position].len = new_len as u32;
+            for later in &mut index.entries[position + 1..] {
+                later.offset = later.offset - old_len + new_len as u32;
+            }
+            (index.entries[position].offset as usize, old_len as usize)
+        }
+        None => {
+            let offset = old_total - old_index_len;
+            index.entries.push(EventIndexEntry {
+                unique_id: event.unique_id,
+                offset: offset as u32,
+                len: new_len as u32,
+            });
+            (offset, 0)
+        }
+    };
+
+    let new_index_len = index.serialized_len();
+    let new_total = old_total - old_index_len - old_len + new_index_len + new_len;
+    let old_tail = old_index_len + offset + old_len;
+    let new_tail = new_index_len + offset + new_len;
+
+    if new_total > old_total {
+        storage::realloc(event_account, new_total, false)?;
+    }
+    storage::ensure_len(event_account, new_total)?;
+
+    {
+        let mut data = event_account.try_borrow_mut_data()?;
+        // the tail first: only an append moves the head, and it has no tail
+        data.copy_within(old_tail..old_total, new_tail);
+        data.copy_within(old_index_len..old_index_len + offset, new_index_len);
+        index.write(&mut data)?;
+        event
+            .serialize(&mut &mut data[new_index_len + offset..new_tail])
+            .map_err(|e| ProgramError::BorshIoError(e.to_string()))?;
+    }
+
+    if new_total < old_total {
+        event_account.realloc(new_total, false)?;
+    }
+
+    Ok(())
+}
+
+/// Drops the event with `unique_id`: the events after it shift back over its
+/// bytes and the account shrinks by them and by its index entry.
+pub fn remove_event(
+    event_account: &AccountInfo<'_>,
+    unique_id: &[u8; 32],
+) -> Result<(), ProgramError> {
+    let old_total = event_account.data_len();
+    let mut index = read_index(&event_account.try_borrow_data()?)?;
+    let old_index_len = index.serialized_len();
+
+    let position = index
+        .position(unique_id)
+        .ok_or(PredictionMarketError::EventNotFound)?;
+    let removed = index.entries.remove(position);
+    for later in &mut index.entries[position..] {
+        later.offset -= removed.len;
+    }
+
+    let new_index_len = index.serialized_len();
+    let offset = removed.offset as usize;
+    let old_tail = old_index_len + offset + removed.len as usize;
+
+    {
+        let mut data = event_account.try_borrow_mut_data()?;
+        data.copy_within(old_index_len..old_index_len + offset, new_index_len);
+        data.copy_within(old_tail..old_total, new_index_len + offset);
+        index.write(&mut data)?;
+    }
+
+    event_account.realloc(old_total - ENTRY_LEN - removed.len as usize, false)
+}
+
+pub fn total_predictions(event_account: &AccountInfo<'_>) -> Result<u32, ProgramError> {
+    Ok(read_index(&event_account.try_borrow_data()?)?.total_predictions)
+}
+
+/// Overwrites the counter in the header, leaving everything else in place.
+pub fn set_total_predictions(
+    event_account: &AccountInfo<'_>,
+    total_predictions: u32,
+) -> Result<(), ProgramError> {
+    let mut data = event_account.try_borrow_mut_data()?;
+    let counter = data
+        .get_mut(..4)
+        .ok_or(ProgramError::UninitializedAccount)?;
+    counter.copy_from_slice(&total_predictions.to_le_bytes());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod event_store_tests {
+    use super::*;
+    use crate::test_utils::{
+        event_account, event_params, stack_event, token_account, Market, TestAccount,
+    };
+    use crate::types::derive_event_address;
+    use crate::{process_create_event, process_expire_unresolved_event};
+    use arch_program::pubkey::Pubkey;
+
+    // each event's bytes, keyed by position in the index
+    fn event_slices(data: &[u8]) -> Vec<Vec<u8>> {
+        let index = read_index(data).unwrap();
+        index
+            .entries
+            .iter()
+            .map(|entry| event_bytes(data, &index, entry).unwrap().to_vec())
+            .collect()
+    }
+
+    // events [0; 32] up to `count` in one account, which sits at the address
+    // of event `home` so handlers reach that one
+    fn market_with_events(program_id: &Pubkey, count: u8, home: u8) -> Market {
+        let mut market = Market::new(program_id, [home; 32], &[]);
+        let template = market.event_state();
+        market.event = event_account(program_id, &[home; 32]);
+        for i in 0..count {
+            let event = PredictionEvent {
+                unique_id: [i; 32],
+                ..template.clone()
+            };
+            stack_event(&mut market.event, event);
+        }
+        market
+    }
+
+    #[test]
+    fn test_bet_rewrites_only_the_targeted_event() {
+        let program_id = Pubkey::new_unique();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut market = market_with_events(&program_id, 50, 25);
+        market.token = token_account(&program_id, &[(better.key(), 100)]);
+        let before = event_slices(market.event.data());
+        let header_before = market.event.data()[..HEADER_LEN + 25 * ENTRY_LEN].to_vec();
+
+        market.buy(&mut better, 1, 40).unwrap();
+
+        let data = market.event.data();
+        let after = event_slices(data);
+        assert_eq!(after.len(), 50);
+        for (i, (old, new)) in before.iter().zip(&after).enumerate() {
+            if i == 25 {
+                assert_ne!(old, new);
+            } else {
+                assert_eq!(old, new, "event {} changed", i);
+            }
+        }
+        // offsets up to and including the modified entry stay put
+        assert_eq!(&data[..HEADER_LEN + 25 * ENTRY_LEN], &header_before[..]);
+        let index = read_index(data).unwrap();
+        let start = index.serialized_len();
+        let prefix_len = index.entries[25].offset as usize;
+        assert_eq!(&data[start..start + prefix_len], &before[..25].concat()[..]);
+
+        let event = load_event(&market.event.info(), &[25; 32]).unwrap();
+        assert_eq!(event.total_pool_amount, 40);
+    }
+
+    #[test]
+    fn test_shrinking_an_event_shifts_the_tail_back() {
+        let program_id = Pubkey::new_unique();
+        let mut market = market_with_events(&program_id, 3, 1);
+        let mut event = load_event(&market.event.info(), &[1; 32]).unwrap();
+        let original = event.clone();
+        let original_len = market.event.data().len();
+
+        event.claimed.insert(Pubkey::new_unique());
+        store_event(&market.event.info(), &event).unwrap();
+        assert_eq!(market.event.data().len(), original_len + 32);
+
+        store_event(&market.event.info(), &original).unwrap();
+        assert_eq!(market.event.data().len(), original_len);
+        let predictions = decode_predictions(market.event.data()).unwrap();
+        let ids: Vec<[u8; 32]> = predictions
+            .predictions
+            .iter()
+            .map(|p| p.unique_id)
+            .collect();
+        assert_eq!(ids, vec![[0; 32], [1; 32], [2; 32]]);
+        assert_eq!(predictions.total_predictions, 3);
+    }
+
+    #[test]
+    fn test_whole_account_round_trips_through_the_index() {
+        let program_id = Pubkey::new_unique();
+        // the home event is not among the four
+        let mut market = market_with_events(&program_id, 4, 9);
+        let predictions = decode_predictions(market.event.data()).unwrap();
+        let before = market.event.data().to_vec();
+        let mut copy = TestAccount::new(&program_id, &[]);
+
+        store_predictions(&copy.info(), &predictions).unwrap();
+
+        assert_eq!(copy.data(), &before[..]);
+        assert_eq!(
+            load_event(&market.event.info(), &[9; 32]).unwrap_err(),
+            PredictionMarketError::EventNotFound.into()
+        );
+    }
+
+    #[test]
+    fn test_storing_fewer_events_leaves_no_stale_tail() {
+        let program_id = Pubkey::new_unique();
+        let market = market_with_events(&program_id, 6, 0);
+        let mut predictions = decode_predictions(market.event.data()).unwrap();
+        let mut account = TestAccount::new(&program_id, &[]);
+        crate::helper_store_predictions(&account.info(), predictions.clone()).unwrap();
+        let large_len = account.data().len();
+
+        predictions.predictions.truncate(2);
+        crate::helper_store_predictions(&account.info(), predictions.clone()).unwrap();
+
+        let mut fresh = TestAccount::new(&program_id, &[]);
+        store_predictions(&fresh.info(), &predictions).unwrap();
+        assert!(account.data().len() < large_len);
+        assert_eq!(account.data(), fresh.data());
+        let stored = decode_predictions(account.data()).unwrap();
+        assert_eq!(stored.predictions.len(), 2);
+    }
+
+    fn create_home_event(program_id: &Pubkey, market: &mut Market) -> Result<(), ProgramError> {
+        process_create_event(
+            program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            &market.token.info(),
+            &market.config.info(),
+            event_params([200; 32], 500, 3),
+        )
+    }
+
+    #[test]
+    fn test_an_account_fills_up_to_the_cap() {
+        let program_id = Pubkey::new_unique();
+        let mut market = market_with_events(&program_id, MAX_EVENTS_PER_ACCOUNT as u8 - 1, 200);
+
+        create_home_event(&program_id, &mut market).unwrap();
+
+        let index = read_index(market.event.data()).unwrap();
+        assert_eq!(index.entries.len(), MAX_EVENTS_PER_ACCOUNT);
+        assert_eq!(index.entries.last().unwrap().unique_id, [200; 32]);
+    }
+
+    #[test]
+    fn test_creating_past_the_cap_fails_up_front() {
+        let program_id = Pubkey::new_unique();
+        let mut market = market_with_events(&program_id, MAX_EVENTS_PER_ACCOUNT as u8, 200);
+        let before = market.event.data().to_vec();
+
+        assert_eq!(
+            create_home_event(&program_id, &mut market),
+            Err(PredictionMarketError::EventCapacityReached.into())
+        );
+        assert_eq!(market.event.data(), &before[..]);
+    }
+
+    #[test]
+    fn test_growth_the_runtime_refuses_is_account_too_small() {
+        let program_id = Pubkey::new_unique();
+        let market = Market::new(&program_id, [1; 32], &[]);
+        let event = market.event_state();
+        // more in one instruction than an account may grow by
+        let predictions = Predictions {
+            total_predictions: 16,
+            predictions: (0..16)
+                .map(|i| PredictionEvent {
+                    unique_id: [i; 32],
+                    ..event.clone()
+                })
+                .collect(),
+        };
+        let mut account = TestAccount::new(&program_id, &[]);
+
+        assert_eq!(
+            store_predictions(&account.info(), &predictions),
+            Err(PredictionMarketError::AccountTooSmall.into())
+        );
+        assert!(account.data().is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_event_ids_are_rejected() {
+        let program_id = Pubkey::new_unique();
+        let mut market = market_with_events(&program_id, 2, 1);
+        let before = market.event.data().to_vec();
+
+        let result = process_create_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            &market.token.info(),
+            &market.config.info(),
+            event_params([1; 32], 500, 3),
+        );
+
+        assert_eq!(
+            result,
+            Err(PredictionMarketError::EventAlreadyExists.into())
+        );
+        assert_eq!(market.event.data(), &before[..]);
+    }
+
+    #[test]
+    fn test_oversized_account_fails_cleanly_before_decoding() {
+        let program_id = Pubkey::new_unique();
+        let market = market_with_events(&program_id, 2, 1);
+
+        // bytes past the last event are never read by the per-event path
+        let mut data = market.event.data().to_vec();
+        data.resize(MAX_DECODED_ACCOUNT_LEN + 1, 0);
+        let mut oversized = TestAccount::new(&program_id, &data)
+            .with_key(derive_event_address(&program_id, &[1; 32]));
+
+        assert_eq!(
+            process_expire_unresolved_event(&program_id, &oversized.info(), [1; 32]),
+            Err(PredictionMarketError::AccountTooLarge.into())
+        );
+        assert!(load_event(&oversized.info(), &[1; 32]).is_ok());
+    }
+
+    #[test]
+    fn test_event_address_derivation_is_stable() {
+        let program_id = Pubkey::new_unique();
+        let address = derive_event_address(&program_id, &[1; 32]);
+
+        assert_eq!(address, derive_event_address(&program_id, &[1; 32]));
+        assert_ne!(address, derive_event_address(&program_id, &[2; 32]));
+        assert_ne!(
+            address,
+            derive_event_address(&Pubkey::new_unique(), &[1; 32])
+        );
+        assert_eq!(Market::new(&program_id, [1; 32], &[]).event.key(), address);
+    }
+
+    #[test]
+    fn test_event_account_off_its_derived_address_is_rejected() {
+        let program_id = Pubkey::new_unique();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, [1; 32], &[(better.key(), 100)]);
+        let mismatch = Err(PredictionMarketError::EventAddressMismatch.into());
+
+        let mut stray = TestAccount::new(&program_id, &[]);
+        assert_eq!(
+            process_create_event(
+                &program_id,
+                &stray.info(),
+                &market.creator.info(),
+                &market.token.info(),
+                &market.config.info(),
+                event_params([2; 32], 100, 2),
+            ),
+            mismatch
+        );
+        assert!(stray.data().is_empty());
+
+        // the event's bytes copied to an account it doesn't belong at
+        market.event = TestAccount::new(&program_id, market.event.data());
+        assert_eq!(market.buy(&mut better, 0, 10), mismatch);
+        assert_eq!(
+            process_expire_unresolved_event(&program_id, &market.event.info(), [1; 32]),
+            mismatch
+        );
+    }
+}
@@ -0,0 +1,464 @@
+//! Logs for off-chain indexers. Every state change an indexer cares about is
+//! written as one line:
+//!
+//! ```text
+//! EVT:v<version>:<name>:<hex(borsh(payload))>
+//! ```
+//!
+//! `name` picks the payload struct below, which off-chain code decodes with
+//! these same borsh definitions (see [`decode_line`]). Fields are only ever
+//! appended to a payload; any other change bumps `EVENT_LOG_VERSION`. Lines
+//! are written as state changes, so only those of successful transactions
+//! count.
+#[cfg(not(test))]
+use arch_program::log::sol_log;
+use arch_program::pubkey::Pubkey;
+use borsh::{BorshDeserialize, BorshSerialize};
+
+#[cfg(test)]
+use crate::test_utils::sol_log;
+use crate::types::{BetType, EventStatus};
+
+pub const EVENT_LOG_PREFIX: &str = "EVT:";
+pub const EVENT_LOG_VERSION: u8 = 1;
+
+/// A payload that can be emitted as an `EVT:` line under `NAME`.
+pub trait EventLog: BorshSerialize {
+    const NAME: &'static str;
+}
+
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct EventCreatedLog {
+    pub unique_id: [u8; 32],
+    pub creator: Pubkey,
+    pub num_outcomes: u8,
+    pub expiry_timestamp: u32,
+    pub block_height: u64,
+}
+
+/// Any status change after creation: activation, closing (by the creator or
+/// on expiry) and cancellation. Resolution has its own log.
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct EventStatusLog {
+    pub unique_id: [u8; 32],
+    pub status: EventStatus,
+    pub block_height: u64,
+}
+
+/// Both buys and sells, told apart by `bet_type`.
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct BetPlacedLog {
+    pub unique_id: [u8; 32],
+    pub user: Pubkey,
+    pub outcome_id: u8,
+    pub amount: u64,
+    pub bet_type: BetType,
+    pub block_height: u64,
+    // tokens paid for a buy or refunded for a sell; `amount` in a pool market
+    pub cost: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct EventResolvedLog {
+    pub unique_id: [u8; 32],
+    // `None` is a void resolution
+    pub winning_outcome: Option<u8>,
+    pub total_pool_amount: u64,
+    pub block_height: u64,
+    // the clamped value a scalar event resolved to
+    pub scalar_value: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct ClaimedLog {
+    pub unique_id: [u8; 32],
+    pub user: Pubkey,
+    pub amount: u64,
+    pub block_height: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct OwnershipTransferredLog {
+    pub unique_id: [u8; 32],
+    pub previous_creator: Pubkey,
+    pub new_creator: Pubkey,
+    pub block_height: u64,
+}
+
+/// Part of a position handed to another user outside the pool.
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct PositionTransferredLog {
+    pub unique_id: [u8; 32],
+    pub from: Pubkey,
+    pub to: Pubkey,
+    pub outcome_id: u8,
+    pub amount: u64,
+    pub block_height: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct MintedLog {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub block_height: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct BurnedLog {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub block_height: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct TransferredLog {
+    pub from: Pubkey,
+    pub to: Pubkey,
+    pub amount: u64,
+    pub block_height: u64,
+}
+
+impl EventLog for EventCreatedLog {
+    const NAME: &'static str = "event_created";
+}
+
+impl EventLog for EventStatusLog {
+    const NAME: &'static str = "event_status";
+}
+
+impl EventLog for BetPlacedLog {
+    const NAME: &'static str = "bet_placed";
+}
+
+impl EventLog for EventResolvedLog {
+    const NAME: &'static str = "event_resolved";
+}
+
+impl EventLog for ClaimedLog {
+    const NAME: &'static str = "claimed";
+}
+
+impl EventLog for OwnershipTransferredLog {
+    const NAME: &'static str = "ownership_transferred";
+}
+
+impl EventLog for PositionTransferredLog {
+    const NAME: &'static str = "position_transferred";
+}
+
+impl EventLog for MintedLog {
+    const NAME: &'static str = "minted";
+}
+
+impl EventLog for BurnedLog {
+    const NAME: &'static str = "burned";
+}
+
+impl EventLog for TransferredLog {
+    const NAME: &'static str = "transferred";
+}
+
+pub fn log_line<T: EventLog>(log: &T) -> String {
+    let payload = borsh::to_vec(log).expect("log payloads serialize into a Vec");
+    format!(
+        "{}v{}:{}:{}",
+        EVENT_LOG_PREFIX,
+        EVENT_LOG_VERSION,
+        T::NAME,
+        hex::encode(payload)
+    )
+}
+
+pub fn emit<T: EventLog>(log: &T) {
+    sol_log(&log_line(log));
+}
+
+/// Decodes `line` as a `T`, or `None` if it is any other line, another
+/// version, or a malformed payload.
+pub fn decode_line<T: EventLog + BorshDeserialize>(line: &str) -> Option<T> {
+    let rest = line.strip_prefix(EVENT_LOG_PREFIX)?;
+    let (version, rest) = rest.split_once(':')?;
+    let (name, payload) = rest.split_once(':')?;
+
+    if version != format!("v{}", EVENT_LOG_VERSION) || name != T::NAME {
+        return None;
+    }
+
+    T::try_from_slice(&hex::decode(payload).ok()?).ok()
+}
+
+#[cfg(test)]
+mod events_tests {
+    use super::*;
+    use crate::test_utils::{
+        balance_of, config_account, event_account, event_params, set_block_height, take_logs,
+        token_account, Market, TestAccount,
+    };
+    use crate::transfer::batch_transfer_tokens;
+    use crate::{
+        mint::{burn_tokens, mint_tokens},
+        process_activate_event, process_cancel_event, process_close_event, process_create_event,
+        process_resolve_event, process_resolve_void,
+    };
+
+    const EVENT_ID: [u8; 32] = [0xab; 32];
+
+    fn event_lines() -> Vec<String> {
+        take_logs()
+            .into_iter()
+            .filter(|line| line.starts_with(EVENT_LOG_PREFIX))
+            .collect()
+    }
+
+    fn status(status: EventStatus, block_height: u64) -> EventStatusLog {
+        EventStatusLog {
+            unique_id: EVENT_ID,
+            status,
+            block_height,
+        }
+    }
+
+    #[test]
+    fn test_line_format_and_round_trip() {
+        let log = ClaimedLog {
+            unique_id: EVENT_ID,
+            user: Pubkey([1; 32]),
+            amount: 7,
+            block_height: 42,
+        };
+
+        let line = log_line(&log);
+
+        assert_eq!(
+            line,
+            format!(
+                "EVT:v1:claimed:{}",
+                hex::encode(borsh::to_vec(&log).unwrap())
+            )
+        );
+        assert_eq!(decode_line::<ClaimedLog>(&line), Some(log));
+        // a line is only decoded as the payload it names
+        assert_eq!(decode_line::<MintedLog>(&line), None);
+        assert_eq!(
+            decode_line::<ClaimedLog>(&line.replacen("v1", "v2", 1)),
+            None
+        );
+        assert_eq!(decode_line::<ClaimedLog>("EVT:v1:claimed:zz"), None);
+    }
+
+    #[test]
+    fn test_event_lifecycle_logs_round_trip() {
+        let program_id = Pubkey::new_unique();
+        let mut event_account = event_account(&program_id, &EVENT_ID);
+        let mut creator = TestAccount::new(&program_id, &[]).signer();
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+        let mut token = token_account(&program_id, &[]);
+        set_block_height(10);
+        take_logs();
+
+        process_create_event(
+            &program_id,
+            &event_account.info(),
+            &creator.info(),
+            &token.info(),
+            &config.info(),
+            event_params(EVENT_ID, 120, 2),
+        )
+        .unwrap();
+        process_activate_event(
+            &program_id,
+            &event_account.info(),
+            &creator.info(),
+            EVENT_ID,
+        )
+        .unwrap();
+        process_close_event(
+            &program_id,
+            &event_account.info(),
+            &creator.info(),
+            EVENT_ID,
+        )
+        .unwrap();
+
+        let lines = event_lines();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            decode_line::<EventCreatedLog>(&lines[0]),
+            Some(EventCreatedLog {
+                unique_id: EVENT_ID,
+                creator: creator.key(),
+                num_outcomes: 2,
+                expiry_timestamp: 120,
+                block_height: 10,
+            })
+        );
+        assert_eq!(
+            decode_line::<EventStatusLog>(&lines[1]),
+            Some(status(EventStatus::Active, 10))
+        );
+        assert_eq!(
+            decode_line::<EventStatusLog>(&lines[2]),
+            Some(status(EventStatus::Closed, 10))
+        );
+    }
+
+    #[test]
+    fn test_bets_resolution_and_claim_logs_round_trip() {
+        let program_id = Pubkey::new_unique();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(better.key(), 100)]);
+        set_block_height(20);
+        take_logs();
+
+        market.buy(&mut better, 1, 60).unwrap();
+        market.sell(&mut better, 1, 10).unwrap();
+        process_resolve_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+            1,
+        )
+        .unwrap();
+        market.claim(&mut better).unwrap();
+
+        let lines = event_lines();
+        assert_eq!(lines.len(), 4);
+        let bet = |amount, bet_type| BetPlacedLog {
+            unique_id: EVENT_ID,
+            user: better.key(),
+            outcome_id: 1,
+            amount,
+            bet_type,
+            block_height: 20,
+            cost: amount,
+        };
+        assert_eq!(
+            decode_line::<BetPlacedLog>(&lines[0]),
+            Some(bet(60, BetType::BUY))
+        );
+        assert_eq!(
+            decode_line::<BetPlacedLog>(&lines[1]),
+            Some(bet(10, BetType::SELL))
+        );
+        assert_eq!(
+            decode_line::<EventResolvedLog>(&lines[2]),
+            Some(EventResolvedLog {
+                unique_id: EVENT_ID,
+                winning_outcome: Some(1),
+                total_pool_amount: 50,
+                block_height: 20,
+                scalar_value: None,
+            })
+        );
+        assert_eq!(
+            decode_line::<ClaimedLog>(&lines[3]),
+            Some(ClaimedLog {
+                unique_id: EVENT_ID,
+                user: better.key(),
+                amount: 50,
+                block_height: 20,
+            })
+        );
+    }
+
+    #[test]
+    fn test_void_resolution_and_cancel_logs_round_trip() {
+        let program_id = Pubkey::new_unique();
+        let mut market = Market::new(&program_id, EVENT_ID, &[]);
+        let mut other = Market::new(&program_id, [0xcd; 32], &[]);
+        set_block_height(30);
+        take_logs();
+
+        process_resolve_void(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+        )
+        .unwrap();
+        process_cancel_event(
+            &program_id,
+            &other.event.info(),
+            &other.creator.info(),
+            [0xcd; 32],
+        )
+        .unwrap();
+
+        let lines = event_lines();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            decode_line::<EventResolvedLog>(&lines[0]),
+            Some(EventResolvedLog {
+                unique_id: EVENT_ID,
+                winning_outcome: None,
+                total_pool_amount: 0,
+                block_height: 30,
+                scalar_value: None,
+            })
+        );
+        assert_eq!(
+            decode_line::<EventStatusLog>(&lines[1]),
+            Some(EventStatusLog {
+                unique_id: [0xcd; 32],
+                status: EventStatus::Cancelled,
+                block_height: 30,
+            })
+        );
+    }
+
+    #[test]
+    fn test_token_movements_logs_round_trip() {
+        let program_id = Pubkey::new_unique();
+        let mut holder = TestAccount::new(&program_id, &[]).signer();
+        let mut token = token_account(&program_id, &[(holder.key(), 100)]);
+        let recipient = Pubkey::new_unique();
+        set_block_height(40);
+        take_logs();
+
+        mint_tokens(&token.info(), &holder.key(), 5, None).unwrap();
+        burn_tokens(&token.info(), &holder.info(), 3, None).unwrap();
+        batch_transfer_tokens(&token.info(), &holder.info(), &[(recipient, 2)]).unwrap();
+
+        let lines = event_lines();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            decode_line::<MintedLog>(&lines[0]),
+            Some(MintedLog {
+                user: holder.key(),
+                amount: 5,
+                block_height: 40,
+            })
+        );
+        assert_eq!(
+            decode_line::<BurnedLog>(&lines[1]),
+            Some(BurnedLog {
+                user: holder.key(),
+                amount: 3,
+                block_height: 40,
+            })
+        );
+        assert_eq!(
+            decode_line::<TransferredLog>(&lines[2]),
+            Some(TransferredLog {
+                from: holder.key(),
+                to: recipient,
+                amount: 2,
+                block_height: 40,
+            })
+        );
+        assert_eq!(balance_of(&token, &recipient), 2);
+    }
+
+    #[test]
+    fn test_failed_instruction_emits_nothing() {
+        let program_id = Pubkey::new_unique();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(better.key(), 5)]);
+        take_logs();
+
+        assert!(market.buy(&mut better, 0, 50).is_err());
+
+        assert!(event_lines().is_empty());
+    }
+}
@@ -1,35 +1,65 @@
-use std::{cell::RefMut, collections::HashMap};
+use std::{
+    cell::RefMut,
+    collections::{BTreeMap, BTreeSet, HashMap},
+};
 
+#[cfg(not(any(test, feature = "no-entrypoint")))]
 use arch_program::entrypoint;
+#[cfg(not(test))]
+use arch_program::program::{get_bitcoin_block_height, validate_utxo_ownership};
 use arch_program::{
-    account::AccountInfo,
-    bitcoin::{absolute::LockTime, amount, consensus, transaction::Version, Transaction},
-    entrypoint::ProgramResult,
-    helper::add_state_transition,
-    input_to_sign::InputToSign,
-    msg,
-    program::{
-        get_bitcoin_block_height, next_account_info, set_transaction_to_sign,
-        validate_utxo_ownership,
-    },
-    program_error::ProgramError,
-    pubkey::Pubkey,
-    transaction_to_sign::TransactionToSign,
-    utxo::UtxoMeta,
+    account::AccountInfo, entrypoint::ProgramResult, msg, program::next_account_info,
+    program_error::ProgramError, pubkey::Pubkey, utxo::UtxoMeta,
 };
-use borsh::{BorshDeserialize, BorshSerialize};
+use borsh::BorshDeserialize;
 
-use mint::{burn_tokens, initialize_mint, mint_tokens, InitializeMintInput};
-use token_account::initialize_balance_account;
-use transfer::{transfer_tokens, TransferInput};
+use events::{
+    BetPlacedLog, ClaimedLog, EventCreatedLog, EventResolvedLog, EventStatusLog,
+    OwnershipTransferredLog, PositionTransferredLog,
+};
+use mint::{
+    batch_mint_tokens, burn_balance, burn_tokens, close_balance, ensure_can_mint,
+    ensure_multisig_signed, get_balance, get_supply, initialize_mint, mint_tokens,
+    transfer_mint_authority, BatchMintInput, GetBalanceInput, InitializeMintInput,
+    TransferMintAuthorityInput, MAX_MINT_SIGNERS,
+};
+use orders::OrderAccounts;
+use referral::Referral;
+use stats::EventStats;
+#[cfg(test)]
+use test_utils::{get_bitcoin_block_height, validate_utxo_ownership};
+use transfer::{batch_transfer_tokens, move_balance, BatchTransferInput};
 use types::*;
 
+pub mod anchoring;
+pub mod archive;
+pub mod config;
 pub mod errors;
+pub mod event_store;
+pub mod events;
+pub mod math;
 pub mod mint;
+pub mod offers;
+pub mod orders;
+pub mod parlay;
+pub mod payout;
+pub mod query;
+pub mod referral;
+pub mod registry;
+pub mod scalar;
+pub mod stats;
+pub mod storage;
 pub mod token_account;
 pub mod transfer;
 pub mod types;
+pub mod user_limits;
+pub mod utxo_bets;
+pub mod wrapped_btc;
+
+#[cfg(test)]
+mod test_utils;
 
+#[cfg(not(any(test, feature = "no-entrypoint")))]
 entrypoint!(process_instruction);
 
 pub fn process_instruction(
@@ -37,13 +67,33 @@ pub fn process_instruction(
     accounts: &[AccountInfo],
     instruction_data: &[u8],
 ) -> ProgramResult {
-    msg!("Hello 1");
-
-    let function_number = instruction_data[0];
+    let function_number = *instruction_data
+        .first()
+        .ok_or(ProgramError::InvalidInstructionData)?;
 
     msg!("Function Called {}", function_number);
 
-    let account_iter = &mut accounts.clone().iter();
+    if let Some(expected) = expected_account_count(function_number) {
+        let max = expected + max_trailing_accounts(function_number);
+        if accounts.len() < expected || accounts.len() > max {
+            msg!(
+                "Instruction {} expects {} accounts, got {}",
+                function_number,
+                expected,
+                accounts.len()
+            );
+            return Err(PredictionMarketError::InvalidAccountCount.into());
+        }
+
+        if !allowed_while_paused(function_number) {
+            // every other instruction takes the program config as its last account
+            let config_account = &accounts[expected - 1];
+            ensure_program_owned(program_id, &[config_account])?;
+            config::ensure_not_paused(config_account)?;
+        }
+    }
+
+    let account_iter = &mut accounts.iter();
 
     match function_number {
         1 => {
@@ -52,14 +102,19 @@ pub fn process_instruction(
             let params = PredictionEventParams::try_from_slice(&instruction_data[1..])
                 .map_err(|_| ProgramError::InvalidInstructionData)?;
 
-            let res = process_create_event(
-                accounts,
-                params.unique_id,
-                params.expiry_timestamp,
-                params.num_outcomes,
-            );
+            let event_account = next_account_info(account_iter)?;
+            let creator_account = next_account_info(account_iter)?;
+            let token_account = next_account_info(account_iter)?;
+            let config_account = next_account_info(account_iter)?;
 
-            res
+            process_create_event(
+                program_id,
+                event_account,
+                creator_account,
+                token_account,
+                config_account,
+                params,
+            )
         }
 
         2 => {
@@ -68,9 +123,10 @@ pub fn process_instruction(
             let params = ClosePredictionEventParams::try_from_slice(&instruction_data[1..])
                 .map_err(|_| ProgramError::InvalidInstructionData)?;
 
-            let res = process_close_event(accounts, params.unique_id);
+            let event_account = next_account_info(account_iter)?;
+            let creator_account = next_account_info(account_iter)?;
 
-            res
+            process_close_event(program_id, event_account, creator_account, params.unique_id)
         }
 
         3 => {
@@ -79,9 +135,35 @@ pub fn process_instruction(
             let params = BetOnPredictionEventParams::try_from_slice(&instruction_data[1..])
                 .map_err(|_| ProgramError::InvalidInstructionData)?;
 
-            let res = process_buy_bet(accounts, params.unique_id, params.outcome_id, params.amount);
+            let mut accounts = BetAccounts {
+                event: next_account_info(account_iter)?,
+                token: next_account_info(account_iter)?,
+                better: next_account_info(account_iter)?,
+                limits: Some(next_account_info(account_iter)?),
+                config: next_account_info(account_iter)?,
+                referral: None,
+                orders: None,
+            };
+            if let Some(referrer) = params.referrer {
+                accounts.referral = Some(Referral {
+                    referrer,
+                    stats: next_account_info(account_iter)?,
+                });
+            }
+            accounts.orders = account_iter.next();
 
-            res
+            process_buy_bet(
+                program_id,
+                accounts,
+                params.unique_id,
+                params.outcome_id,
+                params.amount,
+                None,
+                SlippageLimits {
+                    min_expected_payout_bps: params.min_expected_payout_bps,
+                    max_cost: params.max_cost,
+                },
+            )
         }
 
         4 => {
@@ -90,35 +172,36 @@ pub fn process_instruction(
             let params = BetOnPredictionEventParams::try_from_slice(&instruction_data[1..])
                 .map_err(|_| ProgramError::InvalidInstructionData)?;
 
-            let res =
-                process_sell_bet(accounts, params.unique_id, params.outcome_id, params.amount);
+            let accounts = BetAccounts {
+                event: next_account_info(account_iter)?,
+                token: next_account_info(account_iter)?,
+                better: next_account_info(account_iter)?,
+                limits: None,
+                config: next_account_info(account_iter)?,
+                referral: None,
+                orders: account_iter.next(),
+            };
 
-            res
+            process_sell_bet(
+                program_id,
+                accounts,
+                params.unique_id,
+                params.outcome_id,
+                params.amount,
+            )
         }
 
         5 => {
-            /* -------------------------------------------------------------------------- */
-            /*                               INITIALIZE MINT                              */
-            /* -------------------------------------------------------------------------- */
-            // 1 Account : (owned by program, uninitialized)
-            msg!("Initializing Mint Account ");
-
-            if accounts.len() != 2 {
-                return Err(ProgramError::Custom(502));
-            }
-
-            let account = next_account_info(account_iter)?;
-
-            msg!("Initializing Mint Account 2");
-
+            msg!("Instruction: InitializeMint");
 
             let initialize_mint_input: InitializeMintInput =
                 borsh::from_slice(&instruction_data[1..])
                     .map_err(|_e| ProgramError::InvalidArgument)?;
 
-                    msg!("Initializing Mint Account 3");
-            initialize_mint(account, program_id, initialize_mint_input)?;
-            Ok(())
+            // owned by the program, not yet initialized
+            let account = next_account_info(account_iter)?;
+
+            initialize_mint(account, program_id, initialize_mint_input)
         }
 
         6 => {
@@ -127,21 +210,28 @@ pub fn process_instruction(
             /* -------------------------------------------------------------------------- */
             /*                                 MINT TOKENS                                */
             /* -------------------------------------------------------------------------- */
-            // 1 - Mint account ( owned by program and writable )
-            // 2 - Balance account ( owned by program and writable )
-            // 3 - Owner account( signer )
-            if accounts.len() != 2 {
-                return Err(ProgramError::Custom(502));
-            }
+            // 1 - Mint account ( owned by program and writable ), holds every balance
+            // 2 - Holder account, the signing mint authority unless the mint has a multisig
 
             let token_account = next_account_info(account_iter)?;
 
             let owner_account = next_account_info(account_iter)?;
 
+            ensure_distinct_accounts(&[token_account.key, owner_account.key])?;
+
+            // multisig signers trail the config account
+            ensure_multisig_signed(token_account, owner_account, &accounts[3..])?;
+            ensure_can_mint(token_account, owner_account)?;
+
             let mint_params: MintTokenParams = borsh::from_slice(&instruction_data[1..])
                 .map_err(|_e| ProgramError::InvalidArgument)?;
 
-            mint_tokens(token_account, owner_account.key, mint_params.amount)?;
+            mint_tokens(
+                token_account,
+                owner_account.key,
+                mint_params.amount,
+                mint_params.nonce,
+            )?;
 
             Ok(())
         }
@@ -152,274 +242,7522 @@ pub fn process_instruction(
             /* -------------------------------------------------------------------------- */
             /*                                 Burn TOKENS                                */
             /* -------------------------------------------------------------------------- */
-            // 1 - Mint account ( owned by program and writable )
-            // 2 - Balance account ( owned by program and writable )
-            // 3 - Owner account( signer )
-            if accounts.len() != 2 {
-                return Err(ProgramError::Custom(502));
-            }
+            // 1 - Mint account ( owned by program and writable ), holds every balance
+            // 2 - Holder account, signing for the balance it burns
 
             let token_account = next_account_info(account_iter)?;
 
             let owner_account = next_account_info(account_iter)?;
 
+            if !owner_account.is_signer {
+                return Err(ProgramError::MissingRequiredSignature);
+            }
+
+            ensure_distinct_accounts(&[token_account.key, owner_account.key])?;
+
+            // multisig signers trail the config account
+            ensure_multisig_signed(token_account, owner_account, &accounts[3..])?;
+
             let mint_params: MintTokenParams = borsh::from_slice(&instruction_data[1..])
                 .map_err(|_e| ProgramError::InvalidArgument)?;
 
-            burn_tokens(token_account, owner_account.key, mint_params.amount)?;
+            burn_tokens(
+                token_account,
+                owner_account,
+                mint_params.amount,
+                mint_params.nonce,
+            )?;
 
             Ok(())
         }
 
-        _ => Err(ProgramError::BorshIoError(String::from(
-            "Invalid function call",
-        ))),
-    }
-}
+        8 => {
+            msg!("Instruction: Bet on Event Buy backed by UTXO");
 
-pub fn process_create_event(
-    accounts: &[AccountInfo],
-    unique_id: [u8; 32],
-    expiry_timestamp: u32,
-    num_outcomes: u8,
-) -> Result<(), ProgramError> {
-    let accounts_iter = &mut accounts.iter();
-    let event_account = next_account_info(accounts_iter)?;
-    let creator_account = next_account_info(accounts_iter)?;
+            let params = UtxoBackedBetParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
 
-    msg!(
-        "Hello1 {}, {}",
-        creator_account.is_signer,
-        creator_account.is_executable
-    );
-    if !creator_account.is_signer {
-        return Err(ProgramError::MissingRequiredSignature);
-    }
+            let accounts = BetAccounts {
+                event: next_account_info(account_iter)?,
+                token: next_account_info(account_iter)?,
+                better: next_account_info(account_iter)?,
+                limits: Some(next_account_info(account_iter)?),
+                config: next_account_info(account_iter)?,
+                referral: None,
+                orders: None,
+            };
 
-    let mut outcomes = Vec::new();
-    for i in 0..num_outcomes {
-        outcomes.push(Outcome {
-            id: i,
-            total_amount: 0,
-            bets: HashMap::new(),
-        });
-    }
+            process_buy_bet(
+                program_id,
+                accounts,
+                params.unique_id,
+                params.outcome_id,
+                params.amount,
+                Some(&params.utxo),
+                SlippageLimits {
+                    min_expected_payout_bps: params.min_expected_payout_bps,
+                    max_cost: params.max_cost,
+                },
+            )
+        }
 
-    let event = PredictionEvent {
-        unique_id: unique_id,
-        creator: creator_account.key.clone(),
-        expiry_timestamp: expiry_timestamp,
-        outcomes: outcomes,
-        total_pool_amount: 0,
-        status: EventStatus::Active,
-        winning_outcome: None,
-    };
+        9 => {
+            msg!("Instruction: ResolveEvent");
 
-    let data = event_account.try_borrow_mut_data()?;
+            let params = ResolveEventParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
 
-    // fetch all events data
-    let mut predictions_data = helper_deserialize_predictions(data)?;
+            let event_account = next_account_info(account_iter)?;
+            let resolver_account = next_account_info(account_iter)?;
 
-    predictions_data.predictions.push(event);
-    predictions_data.total_predictions += 1;
+            process_resolve_event(
+                program_id,
+                event_account,
+                resolver_account,
+                params.unique_id,
+                params.winning_outcome,
+            )
+        }
 
-    helper_store_predictions(event_account, predictions_data)
-}
+        10 => {
+            msg!("Instruction: ClaimWinnings");
 
-pub fn process_close_event(
-    accounts: &[AccountInfo],
-    unique_id: [u8; 32],
-) -> Result<(), ProgramError> {
-    let accounts_iter = &mut accounts.iter();
-    let event_account = next_account_info(accounts_iter)?;
-    let creator_account = next_account_info(accounts_iter)?;
+            let params = ClaimWinningsParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
 
-    if !creator_account.is_signer {
-        return Err(ProgramError::MissingRequiredSignature);
-    }
+            let event_account = next_account_info(account_iter)?;
+            let token_account = next_account_info(account_iter)?;
+            let winner_account = next_account_info(account_iter)?;
 
-    let data = event_account.try_borrow_mut_data()?;
-    let mut predictions_data = helper_deserialize_predictions(data)?;
+            process_claim_winnings(
+                program_id,
+                event_account,
+                token_account,
+                winner_account,
+                params.unique_id,
+                params.settle_on_bitcoin,
+            )
+        }
 
-    let index = predictions_data
-        .predictions
-        .iter()
-        .position(|x| x.unique_id == unique_id)
-        .unwrap();
+        11 => {
+            msg!("Instruction: InitializeConfig");
 
-    predictions_data.predictions[index].status = EventStatus::Closed;
-    predictions_data.total_predictions -= 1;
+            let config_account = next_account_info(account_iter)?;
+            let admin_account = next_account_info(account_iter)?;
 
-    helper_store_predictions(event_account, predictions_data)
-}
+            config::initialize_config(config_account, admin_account, program_id)
+        }
 
-pub fn helper_deserialize_predictions(
-    data: RefMut<'_, &mut [u8]>,
-) -> Result<Predictions, ProgramError> {
-    msg!("Total bytes: {}", data.len());
-    let predictions_data = if data.len() > 0 {
-        Predictions::try_from_slice(&data).map_err(|e| {
-            msg!("Error: Failed to deserialize event data {}", e.to_string());
-            ProgramError::BorshIoError(String::from("Error: Failed to deserialize event data"))
-        })?
-    } else {
-        Predictions {
-            total_predictions: 0,
-            predictions: Vec::new(),
+        12 => {
+            msg!("Instruction: SetPaused");
+
+            let params = config::SetPausedParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let config_account = next_account_info(account_iter)?;
+            let admin_account = next_account_info(account_iter)?;
+
+            config::set_paused(config_account, admin_account, params.paused)
         }
-    };
 
-    Ok(predictions_data)
-}
+        13 => {
+            msg!("Instruction: BatchMint");
 
-pub fn helper_store_predictions(
-    event_account: &AccountInfo<'_>,
-    predictions_data: Predictions,
-) -> Result<(), ProgramError> {
-    let serialized_data = borsh::to_vec(&predictions_data)
-        .map_err(|_| ProgramError::BorshIoError(String::from("Serailization failed")))?;
-    let required_len = serialized_data.len();
-    msg!("Serlized data length {}", required_len);
+            let token_account = next_account_info(account_iter)?;
+            let authority_account = next_account_info(account_iter)?;
 
-    if event_account.data_len() < required_len {
-        event_account.realloc(required_len, false)?;
-    }
+            let batch_params: BatchMintInput = borsh::from_slice(&instruction_data[1..])
+                .map_err(|_e| ProgramError::InvalidArgument)?;
 
-    msg!("account size {}", event_account.data_len());
+            ensure_multisig_signed(token_account, authority_account, &accounts[3..])?;
 
-    event_account.data.borrow_mut()[..required_len].copy_from_slice(&serialized_data);
+            batch_mint_tokens(token_account, authority_account, &batch_params.recipients)
+        }
 
-    Ok(())
-}
+        14 => {
+            msg!("Instruction: AddCreator");
 
-pub fn process_buy_bet(
-    accounts: &[AccountInfo],
-    unique_id: [u8; 32],
-    outcome_id: u8,
-    amount: u64,
-) -> Result<(), ProgramError> {
-    let accounts_iter = &mut accounts.iter();
-    let event_account = next_account_info(accounts_iter)?;
-    let token_account = next_account_info(accounts_iter)?;
-    let better_account = next_account_info(accounts_iter)?;
+            let params = config::CreatorParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
 
-    if !better_account.is_signer {
-        return Err(ProgramError::MissingRequiredSignature);
-    }
+            let config_account = next_account_info(account_iter)?;
+            let admin_account = next_account_info(account_iter)?;
 
-    let mut events = Predictions::try_from_slice(&event_account.data.borrow())
-        .map_err(|_| ProgramError::BorshIoError(String::from("No event exists")))?;
+            config::add_creator(config_account, admin_account, params.creator)
+        }
 
-    let event = events
-        .predictions
-        .iter_mut()
-        .find(|p| p.unique_id == unique_id)
-        .unwrap();
+        15 => {
+            msg!("Instruction: RemoveCreator");
 
-    if event.status != EventStatus::Active {
-        return Err(ProgramError::BorshIoError(String::from("Event is closed.")));
-    }
+            let params = config::CreatorParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
 
-    let bet = Bet {
-        user: better_account.key.clone(),
-        event_id: event.unique_id,
-        outcome_id,
-        amount,
-        timestamp: get_bitcoin_block_height() as i64,
-        bet_type: BetType::BUY,
-    };
+            let config_account = next_account_info(account_iter)?;
+            let admin_account = next_account_info(account_iter)?;
 
-    let outcome = event
-        .outcomes
-        .iter_mut()
-        .find(|outcome| outcome.id == outcome_id)
-        .unwrap();
+            config::remove_creator(config_account, admin_account, params.creator)
+        }
 
-    let bets: Option<&mut Vec<Bet>> = outcome.bets.get_mut(&better_account.key);
+        16 => {
+            msg!("Instruction: BatchTransfer");
 
-    // if let Some(bets) = bets {
-    //     // You now have `bets`, which is a mutable reference to `Vec<Bet>`
-    //     bets.push(bet);
-    // } else {
-    //     outcome
-    //         .bets
-    //         .entry(better_account.key.clone())
-    //         .or_insert_with(Vec::new)
-    //         .push(bet);
-    // }
+            let token_account = next_account_info(account_iter)?;
+            let sender_account = next_account_info(account_iter)?;
 
-    // event
-    //     .serialize(&mut *event_account.data.borrow_mut())
-    //     .map_err(|_| ProgramError::InvalidAccountData)?;
+            let batch_params: BatchTransferInput = borsh::from_slice(&instruction_data[1..])
+                .map_err(|_e| ProgramError::InvalidArgument)?;
 
-    burn_tokens(token_account, better_account.key, amount).unwrap();
+            batch_transfer_tokens(token_account, sender_account, &batch_params.recipients)
+        }
 
-    Ok(())
-}
+        17 => {
+            msg!("Instruction: ChangeResolver");
 
-pub fn process_sell_bet(
-    accounts: &[AccountInfo],
-    unique_id: [u8; 32],
-    outcome_id: u8,
-    amount: u64,
-) -> Result<(), ProgramError> {
-    let accounts_iter = &mut accounts.iter();
-    let event_account = next_account_info(accounts_iter)?;
-    let token_account = next_account_info(accounts_iter)?;
-    let better_account = next_account_info(accounts_iter)?;
+            let params = ChangeResolverParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
 
-    if !better_account.is_signer {
-        return Err(ProgramError::MissingRequiredSignature);
-    }
+            let event_account = next_account_info(account_iter)?;
+            let creator_account = next_account_info(account_iter)?;
 
-    let mut events = Predictions::try_from_slice(&event_account.data.borrow())
-        .map_err(|_| ProgramError::BorshIoError(String::from("No event exists")))?;
+            process_change_resolver(
+                program_id,
+                event_account,
+                creator_account,
+                params.unique_id,
+                params.resolver,
+            )
+        }
 
-    let event = events
-        .predictions
-        .iter_mut()
-        .find(|p| p.unique_id == unique_id)
-        .unwrap();
+        18 => {
+            msg!("Instruction: CancelEvent");
 
-    if event.status != EventStatus::Active {
-        return Err(ProgramError::BorshIoError(String::from("Event is closed.")));
-    }
+            let params = ClosePredictionEventParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
 
-    let bet = Bet {
-        user: better_account.key.clone(),
-        event_id: event.unique_id,
-        outcome_id,
-        amount,
-        timestamp: get_bitcoin_block_height() as i64,
-        bet_type: BetType::SELL,
-    };
-    msg!("Sell Bet");
+            let event_account = next_account_info(account_iter)?;
+            let creator_account = next_account_info(account_iter)?;
 
-    let outcome = event
-        .outcomes
-        .iter_mut()
-        .find(|outcome| outcome.id == outcome_id)
-        .unwrap();
+            process_cancel_event(program_id, event_account, creator_account, params.unique_id)
+        }
+
+        19 => {
+            msg!("Instruction: CloseBalance");
 
-    let bets: Option<&mut Vec<Bet>> = outcome.bets.get_mut(&better_account.key);
+            let token_account = next_account_info(account_iter)?;
+            let holder_account = next_account_info(account_iter)?;
 
-    // if let Some(bets) = bets {
-    //     // You now have `bets`, which is a mutable reference to `Vec<Bet>`
-    //     bets.push(bet);
-    // } else {
-    //     outcome
-    //         .bets
-    //         .entry(better_account.key.clone())
-    //         .or_insert_with(Vec::new)
-    //         .push(bet);
-    // }
+            close_balance(token_account, holder_account)
+        }
 
-    // event
-    //     .serialize(&mut *event_account.data.borrow_mut())
-    //     .map_err(|_| ProgramError::InvalidAccountData)?;
+        20 => {
+            msg!("Instruction: VoteResolution");
 
+            let params = VoteResolutionParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
 
-    mint_tokens(token_account, better_account.key, amount).unwrap();
+            let event_account = next_account_info(account_iter)?;
+            let member_account = next_account_info(account_iter)?;
 
-    Ok(())
+            process_vote_resolution(
+                program_id,
+                event_account,
+                member_account,
+                params.unique_id,
+                params.winning_outcome,
+            )
+        }
+
+        21 => {
+            msg!("Instruction: ActivateEvent");
+
+            let params = ClosePredictionEventParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+            let creator_account = next_account_info(account_iter)?;
+
+            process_activate_event(program_id, event_account, creator_account, params.unique_id)
+        }
+
+        22 => {
+            msg!("Instruction: ExpireUnresolvedEvent");
+
+            let params = ClosePredictionEventParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+
+            process_expire_unresolved_event(program_id, event_account, params.unique_id)
+        }
+
+        23 => {
+            msg!("Instruction: CrankCloseExpired");
+
+            let params = CrankCloseExpiredParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+
+            process_crank_close_expired(program_id, event_account, &params.unique_ids)
+        }
+
+        24 => {
+            msg!("Instruction: ResolveVoid");
+
+            let params = ClosePredictionEventParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+            let resolver_account = next_account_info(account_iter)?;
+
+            process_resolve_void(
+                program_id,
+                event_account,
+                resolver_account,
+                params.unique_id,
+            )
+        }
+
+        25 => {
+            msg!("Instruction: GetBalance");
+
+            let token_account = next_account_info(account_iter)?;
+
+            let balance_params = GetBalanceInput::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            get_balance(token_account, &balance_params.holder).map(|_| ())
+        }
+
+        26 => {
+            msg!("Instruction: GetSupply");
+
+            let token_account = next_account_info(account_iter)?;
+
+            get_supply(token_account).map(|_| ())
+        }
+
+        27 => {
+            msg!("Instruction: SweepExpired");
+
+            let params = SweepExpiredParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+
+            process_sweep_expired(program_id, event_account, params.offset, params.limit)
+                .map(|_| ())
+        }
+
+        28 => {
+            msg!("Instruction: QueryEvent");
+
+            let params = QueryEventParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+
+            query::process_query_event(program_id, event_account, params.unique_id).map(|_| ())
+        }
+
+        29 => {
+            msg!("Instruction: QueryUserPosition");
+
+            let params = QueryUserPositionParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+
+            query::process_query_user_position(
+                program_id,
+                event_account,
+                params.unique_id,
+                &params.user,
+            )
+            .map(|_| ())
+        }
+
+        30 => {
+            msg!("Instruction: QueryOdds");
+
+            let params = QueryOddsParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+
+            query::process_query_odds(program_id, event_account, params.unique_id).map(|_| ())
+        }
+
+        31 => {
+            msg!("Instruction: TransferEventOwnership");
+
+            let params = TransferEventOwnershipParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+            let creator_account = next_account_info(account_iter)?;
+
+            process_transfer_event_ownership(
+                program_id,
+                event_account,
+                creator_account,
+                params.unique_id,
+                params.new_creator,
+            )
+        }
+
+        32 => {
+            msg!("Instruction: TransferMintAuthority");
+
+            let token_account = next_account_info(account_iter)?;
+            let authority_account = next_account_info(account_iter)?;
+
+            let params = TransferMintAuthorityInput::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            transfer_mint_authority(token_account, authority_account, params.new_owner)
+        }
+
+        33 => {
+            msg!("Instruction: CompactSettledEvent");
+
+            let params = ClosePredictionEventParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+
+            process_compact_settled_event(program_id, event_account, params.unique_id)
+        }
+
+        34 => {
+            msg!("Instruction: SweepUnclaimed");
+
+            let params = ClosePredictionEventParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+            let token_account = next_account_info(account_iter)?;
+            let config_account = next_account_info(account_iter)?;
+
+            process_sweep_unclaimed(
+                program_id,
+                event_account,
+                token_account,
+                config_account,
+                params.unique_id,
+            )
+            .map(|_| ())
+        }
+
+        35 => {
+            msg!("Instruction: DisputeResolution");
+
+            let params = ClosePredictionEventParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+            let disputer_account = next_account_info(account_iter)?;
+            let config_account = next_account_info(account_iter)?;
+
+            process_dispute_resolution(
+                program_id,
+                event_account,
+                disputer_account,
+                config_account,
+                params.unique_id,
+            )
+        }
+
+        36 => {
+            msg!("Instruction: SetCreatorBond");
+
+            let params = config::SetCreatorBondParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let config_account = next_account_info(account_iter)?;
+            let admin_account = next_account_info(account_iter)?;
+
+            config::set_creator_bond(config_account, admin_account, params.amount)
+        }
+
+        37 => {
+            msg!("Instruction: ReclaimCreatorBond");
+
+            let params = ClosePredictionEventParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+            let token_account = next_account_info(account_iter)?;
+            let creator_account = next_account_info(account_iter)?;
+
+            process_reclaim_creator_bond(
+                program_id,
+                event_account,
+                token_account,
+                creator_account,
+                params.unique_id,
+            )
+        }
+
+        38 => {
+            msg!("Instruction: SetProtocolFees");
+
+            let params = config::SetProtocolFeesParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let config_account = next_account_info(account_iter)?;
+            let admin_account = next_account_info(account_iter)?;
+
+            config::set_protocol_fees(
+                config_account,
+                admin_account,
+                params.protocol_fee_bps,
+                params.referral_share_bps,
+            )
+        }
+
+        39 => {
+            msg!("Instruction: ClaimReferralRewards");
+
+            let stats_account = next_account_info(account_iter)?;
+            let token_account = next_account_info(account_iter)?;
+            let referrer_account = next_account_info(account_iter)?;
+
+            referral::process_claim_referral_rewards(
+                program_id,
+                stats_account,
+                token_account,
+                referrer_account,
+            )
+            .map(|_| ())
+        }
+
+        40 => {
+            msg!("Instruction: AutoResolve");
+
+            let params = ClosePredictionEventParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+
+            process_auto_resolve(program_id, event_account, params.unique_id)
+        }
+
+        41 => {
+            msg!("Instruction: CashoutPosition");
+
+            let params = CashoutPositionParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let accounts = BetAccounts {
+                event: next_account_info(account_iter)?,
+                token: next_account_info(account_iter)?,
+                better: next_account_info(account_iter)?,
+                limits: None,
+                config: next_account_info(account_iter)?,
+                referral: None,
+                orders: None,
+            };
+
+            process_cashout_position(
+                program_id,
+                accounts,
+                params.unique_id,
+                params.outcome_id,
+                params.amount,
+            )
+        }
+
+        42 => {
+            msg!("Instruction: SetCashoutHaircut");
+
+            let params = config::SetCashoutHaircutParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let config_account = next_account_info(account_iter)?;
+            let admin_account = next_account_info(account_iter)?;
+
+            config::set_cashout_haircut(config_account, admin_account, params.haircut_bps)
+        }
+
+        43 => {
+            msg!("Instruction: PreviewPayout");
+
+            let params = PreviewPayoutParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+            let user_account = next_account_info(account_iter)?;
+
+            query::process_preview_payout(
+                program_id,
+                event_account,
+                user_account,
+                params.unique_id,
+                params.outcome_id,
+            )
+            .map(|_| ())
+        }
+
+        44 => {
+            msg!("Instruction: PlaceLimitOrder");
+
+            let params = PlaceLimitOrderParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let accounts = OrderAccounts {
+                event: next_account_info(account_iter)?,
+                orders: next_account_info(account_iter)?,
+                token: next_account_info(account_iter)?,
+                owner: next_account_info(account_iter)?,
+                config: next_account_info(account_iter)?,
+            };
+
+            orders::process_place_limit_order(program_id, accounts, params).map(|_| ())
+        }
+
+        45 => {
+            msg!("Instruction: CancelLimitOrder");
+
+            let params = CancelLimitOrderParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let accounts = OrderAccounts {
+                event: next_account_info(account_iter)?,
+                orders: next_account_info(account_iter)?,
+                token: next_account_info(account_iter)?,
+                owner: next_account_info(account_iter)?,
+                config: next_account_info(account_iter)?,
+            };
+
+            orders::process_cancel_limit_order(program_id, accounts, params.order_id)
+        }
+
+        46 => {
+            msg!("Instruction: PruneEvent");
+
+            let params = ClosePredictionEventParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+            let token_account = next_account_info(account_iter)?;
+            let config_account = next_account_info(account_iter)?;
+
+            process_prune_event(
+                program_id,
+                event_account,
+                token_account,
+                config_account,
+                params.unique_id,
+            )
+        }
+
+        47 => {
+            msg!("Instruction: SetSelfTradePolicy");
+
+            let params = config::SetSelfTradePolicyParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let config_account = next_account_info(account_iter)?;
+            let admin_account = next_account_info(account_iter)?;
+
+            config::set_self_trade_policy(config_account, admin_account, params.policy)
+        }
+
+        48 => {
+            msg!("Instruction: PlaceParlay");
+
+            let params = PlaceParlayParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let parlay_account = next_account_info(account_iter)?;
+            let token_account = next_account_info(account_iter)?;
+            let owner_account = next_account_info(account_iter)?;
+            let config_account = next_account_info(account_iter)?;
+
+            parlay::process_place_parlay(
+                program_id,
+                parlay_account,
+                token_account,
+                owner_account,
+                config_account,
+                &accounts[4..],
+                params,
+            )
+        }
+
+        49 => {
+            msg!("Instruction: SettleParlay");
+
+            let parlay_account = next_account_info(account_iter)?;
+            let token_account = next_account_info(account_iter)?;
+            let config_account = next_account_info(account_iter)?;
+
+            parlay::process_settle_parlay(
+                program_id,
+                parlay_account,
+                token_account,
+                config_account,
+                &accounts[3..],
+            )
+            .map(|_| ())
+        }
+
+        50 => {
+            msg!("Instruction: TakeScalarPosition");
+
+            let params = ScalarPositionParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+            let token_account = next_account_info(account_iter)?;
+            let better_account = next_account_info(account_iter)?;
+            let config_account = next_account_info(account_iter)?;
+
+            scalar::process_take_scalar_position(
+                program_id,
+                event_account,
+                token_account,
+                better_account,
+                config_account,
+                params,
+            )
+        }
+
+        51 => {
+            msg!("Instruction: ResolveScalar");
+
+            let params = ResolveScalarParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+            let resolver_account = next_account_info(account_iter)?;
+
+            process_resolve_scalar(
+                program_id,
+                event_account,
+                resolver_account,
+                params.unique_id,
+                params.value,
+            )
+        }
+
+        52 => {
+            msg!("Instruction: TransferPosition");
+
+            let params = TransferPositionParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+            let holder_account = next_account_info(account_iter)?;
+
+            process_transfer_position(program_id, event_account, holder_account, params)
+        }
+
+        53 => {
+            msg!("Instruction: OfferPosition");
+
+            let params = OfferPositionParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+            let seller_account = next_account_info(account_iter)?;
+            let config_account = next_account_info(account_iter)?;
+
+            offers::process_offer_position(
+                program_id,
+                event_account,
+                seller_account,
+                config_account,
+                params,
+            )
+            .map(|_| ())
+        }
+
+        54 => {
+            msg!("Instruction: AcceptOffer");
+
+            let params = AcceptOfferParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+            let token_account = next_account_info(account_iter)?;
+            let buyer_account = next_account_info(account_iter)?;
+            let config_account = next_account_info(account_iter)?;
+
+            offers::process_accept_offer(
+                program_id,
+                event_account,
+                token_account,
+                buyer_account,
+                config_account,
+                params,
+            )
+        }
+
+        55 => {
+            msg!("Instruction: CancelOffer");
+
+            let params = CancelOfferParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+            let seller_account = next_account_info(account_iter)?;
+            let config_account = next_account_info(account_iter)?;
+
+            offers::process_cancel_offer(
+                program_id,
+                event_account,
+                seller_account,
+                config_account,
+                params,
+            )
+        }
+
+        56 => {
+            msg!("Instruction: UserBetHistory");
+
+            let params = UserBetHistoryParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            query::process_user_bet_history(program_id, accounts, &params.user, params.offset)
+                .map(|_| ())
+        }
+
+        57 => {
+            msg!("Instruction: SetUserLimit");
+
+            let limit = user_limits::UserLimit::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let limits_account = next_account_info(account_iter)?;
+            let user_account = next_account_info(account_iter)?;
+
+            user_limits::process_set_user_limit(program_id, limits_account, user_account, limit)
+        }
+
+        58 => {
+            msg!("Instruction: AddMint");
+
+            let params = config::MintParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let config_account = next_account_info(account_iter)?;
+            let admin_account = next_account_info(account_iter)?;
+
+            config::add_mint(config_account, admin_account, params.mint)
+        }
+
+        59 => {
+            msg!("Instruction: RemoveMint");
+
+            let params = config::MintParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let config_account = next_account_info(account_iter)?;
+            let admin_account = next_account_info(account_iter)?;
+
+            config::remove_mint(config_account, admin_account, params.mint)
+        }
+
+        60 => {
+            msg!("Instruction: BuyBetWithUtxo");
+
+            let params = UtxoBetParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+            let better_account = next_account_info(account_iter)?;
+            let limits_account = next_account_info(account_iter)?;
+            let config_account = next_account_info(account_iter)?;
+
+            utxo_bets::process_buy_bet_with_utxo(
+                program_id,
+                event_account,
+                better_account,
+                limits_account,
+                config_account,
+                params,
+            )
+        }
+
+        61 => {
+            msg!("Instruction: SettleOnBitcoin");
+
+            let params = SettleOnBitcoinParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+            let authority_account = next_account_info(account_iter)?;
+            let config_account = next_account_info(account_iter)?;
+
+            payout::process_settle_on_bitcoin(
+                program_id,
+                event_account,
+                authority_account,
+                config_account,
+                params,
+            )
+        }
+
+        62 => {
+            msg!("Instruction: ListEventsByStatus");
+
+            let params = ListEventsByStatusParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            query::process_list_events_by_status(
+                program_id,
+                accounts,
+                params.status,
+                params.offset,
+                params.limit,
+            )
+            .map(|_| ())
+        }
+
+        63 => {
+            msg!("Instruction: RefundOnBitcoin");
+
+            let params = RefundOnBitcoinParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+            let authority_account = next_account_info(account_iter)?;
+            let config_account = next_account_info(account_iter)?;
+
+            payout::process_refund_on_bitcoin(
+                program_id,
+                event_account,
+                authority_account,
+                config_account,
+                params,
+            )
+        }
+
+        64 => {
+            msg!("Instruction: SetMinConfirmations");
+
+            let params = config::SetMinConfirmationsParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let config_account = next_account_info(account_iter)?;
+            let admin_account = next_account_info(account_iter)?;
+
+            config::set_min_confirmations(config_account, admin_account, params.min_confirmations)
+        }
+
+        65 => {
+            msg!("Instruction: ConfirmPendingBets");
+
+            let params = ConfirmPendingBetsParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+            let config_account = next_account_info(account_iter)?;
+
+            utxo_bets::process_confirm_pending_bets(
+                program_id,
+                event_account,
+                config_account,
+                params.unique_id,
+            )
+        }
+
+        66 => {
+            msg!("Instruction: BumpSettlementFee");
+
+            let params = BumpSettlementFeeParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+            let authority_account = next_account_info(account_iter)?;
+            let config_account = next_account_info(account_iter)?;
+
+            payout::process_bump_settlement_fee(
+                program_id,
+                event_account,
+                authority_account,
+                config_account,
+                params,
+            )
+        }
+
+        67 => {
+            msg!("Instruction: DepositBtc");
+
+            let params = wrapped_btc::DepositBtcParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let mint_account = next_account_info(account_iter)?;
+            let depositor_account = next_account_info(account_iter)?;
+            let config_account = next_account_info(account_iter)?;
+
+            wrapped_btc::process_deposit_btc(
+                program_id,
+                mint_account,
+                depositor_account,
+                config_account,
+                params,
+            )
+        }
+
+        68 => {
+            msg!("Instruction: WithdrawBtc");
+
+            let params = wrapped_btc::WithdrawBtcParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let mint_account = next_account_info(account_iter)?;
+            let holder_account = next_account_info(account_iter)?;
+            let config_account = next_account_info(account_iter)?;
+
+            wrapped_btc::process_withdraw_btc(
+                program_id,
+                mint_account,
+                holder_account,
+                config_account,
+                params,
+            )
+        }
+
+        69 => {
+            msg!("Instruction: ResolveMultiple");
+
+            let params = ResolveMultipleParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+            let resolver_account = next_account_info(account_iter)?;
+
+            process_resolve_multiple(
+                program_id,
+                event_account,
+                resolver_account,
+                params.unique_id,
+                &params.winning_outcomes,
+            )
+        }
+
+        70 => {
+            msg!("Instruction: QueryRegistry");
+
+            let params = QueryRegistryParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let registry_account = next_account_info(account_iter)?;
+
+            query::process_query_registry(
+                program_id,
+                registry_account,
+                params.offset,
+                params.limit,
+                params.status_filter,
+            )
+            .map(|_| ())
+        }
+
+        71 => {
+            msg!("Instruction: SetMaxActiveEvents");
+
+            let params = config::SetMaxActiveEventsParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let config_account = next_account_info(account_iter)?;
+            let admin_account = next_account_info(account_iter)?;
+
+            config::set_max_active_events(config_account, admin_account, params.max_active_events)
+        }
+
+        72 => {
+            msg!("Instruction: QueryEventsByCreator");
+
+            let params = QueryEventsByCreatorParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let registry_account = next_account_info(account_iter)?;
+
+            query::process_query_events_by_creator(
+                program_id,
+                registry_account,
+                &params.creator,
+                params.offset,
+                params.limit,
+            )
+            .map(|_| ())
+        }
+
+        73 => {
+            msg!("Instruction: QueryEventStats");
+
+            let params = QueryEventStatsParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+
+            query::process_query_event_stats(program_id, event_account, params.unique_id)
+                .map(|_| ())
+        }
+
+        74 => {
+            msg!("Instruction: ArchiveEvent");
+
+            let params = ClosePredictionEventParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let event_account = next_account_info(account_iter)?;
+            let token_account = next_account_info(account_iter)?;
+            let config_account = next_account_info(account_iter)?;
+            let archive_account = next_account_info(account_iter)?;
+
+            process_archive_event(
+                program_id,
+                event_account,
+                token_account,
+                config_account,
+                archive_account,
+                params.unique_id,
+            )
+        }
+
+        75 => {
+            msg!("Instruction: QueryArchivedEvent");
+
+            let params = QueryEventParams::try_from_slice(&instruction_data[1..])
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+
+            let archive_account = next_account_info(account_iter)?;
+
+            query::process_query_archived_event(program_id, archive_account, params.unique_id)
+                .map(|_| ())
+        }
+
+        _ => Err(ProgramError::BorshIoError(String::from(
+            "Invalid function call",
+        ))),
+    }?;
+
+    // the registry, when passed, follows the fixed accounts
+    if registry::tracks(function_number) {
+        let registry_account =
+            expected_account_count(function_number).and_then(|count| accounts.get(count));
+        if function_number == 1 {
+            // [event, creator, token, config]
+            registry::register(program_id, registry_account, &accounts[0], &accounts[3])?;
+        } else if let Some(registry_account) = registry_account {
+            registry::sync(program_id, registry_account, &accounts[0])?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Fails with `AccountAliasing` when the same account is passed in more than
+/// one role. Every role holds its own borrow, so aliases would either fail to
+/// borrow or overwrite each other's data.
+pub(crate) fn ensure_distinct_accounts(keys: &[&Pubkey]) -> Result<(), ProgramError> {
+    for (i, key) in keys.iter().enumerate() {
+        if keys[i + 1..].contains(key) {
+            msg!("Account {} is passed in more than one role", key);
+            return Err(PredictionMarketError::AccountAliasing.into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Fails with `IllegalOwner` unless every account is owned by this program,
+/// so state can't be spoofed with a look-alike account.
+pub(crate) fn ensure_program_owned<'a>(
+    program_id: &Pubkey,
+    accounts: &[&AccountInfo<'a>],
+) -> Result<(), ProgramError> {
+    if accounts.iter().any(|account| account.owner != program_id) {
+        return Err(ProgramError::IllegalOwner);
+    }
+
+    Ok(())
+}
+
+/// Exact number of accounts each instruction takes, `None` for unknown
+/// opcodes. Instructions not `allowed_while_paused` end with the config.
+pub fn expected_account_count(function_number: u8) -> Option<usize> {
+    match function_number {
+        // [event, creator, token, config]
+        1 => Some(4),
+        // [event, creator | resolver | member | disputer | holder | seller, config]
+        2 | 9 | 17 | 18 | 20 | 21 | 24 | 31 | 35 | 51 | 52 | 53 | 55 | 61 | 63 | 66 => Some(3),
+        // [event, resolver, config]
+        69 => Some(3),
+        // [event, token, better, better's limits, config]
+        3 | 8 => Some(5),
+        // [event, token, better | buyer, config]
+        4 | 41 | 50 | 54 => Some(4),
+        // [mint, config]
+        5 => Some(2),
+        // [mint, holder | authority | sender | depositor, config]
+        6 | 7 | 13 | 16 | 19 | 32 | 67 | 68 => Some(3),
+        // [event, token, winner | creator, config]
+        10 | 37 => Some(4),
+        // [referral stats, token, referrer, config]
+        39 => Some(4),
+        // [user limits, user, config]
+        57 => Some(3),
+        // [event, better, better's limits, config]
+        60 => Some(4),
+        // [event, token, config]
+        34 | 46 => Some(3),
+        // [event, token, config, archive]
+        74 => Some(4),
+        // [config, admin]
+        11 | 12 | 14 | 15 | 36 | 38 | 42 | 47 | 58 | 59 | 64 | 71 => Some(2),
+        // [event, config]
+        22 | 23 | 27 | 33 | 40 | 65 => Some(2),
+        // [mint]
+        25 | 26 => Some(1),
+        // [event], history and listings take more events after it
+        28..=30 | 56 | 62 | 73 => Some(1),
+        // [registry | archive]
+        70 | 72 | 75 => Some(1),
+        // [event, user]
+        43 => Some(2),
+        // [event, order book, token, owner, config]
+        44 | 45 => Some(5),
+        // [parlay, token, owner, config]
+        48 => Some(4),
+        // [parlay, token, config]
+        49 => Some(3),
+        _ => None,
+    }
+}
+
+/// Accounts an instruction accepts after its fixed accounts: mints, burns and
+/// batch mints collect the signatures of a multisig mint authority this way,
+/// a referred buy takes the referral stats account, buys and sells take the
+/// event's order book last, parlays take the event account of each leg, and
+/// a bet history or event listing the further event accounts it reads, and
+/// instructions that create an event or change its status the registry.
+pub fn max_trailing_accounts(function_number: u8) -> usize {
+    match function_number {
+        3 => 2,
+        4 => 1,
+        6 | 7 | 13 => MAX_MINT_SIGNERS,
+        48 | 49 => parlay::MAX_PARLAY_LEGS,
+        56 | 62 => query::MAX_HISTORY_EVENT_ACCOUNTS - 1,
+        f if registry::tracks(f) => 1,
+        _ => 0,
+    }
+}
+
+/// Instructions that keep working while the program is paused: the admin's
+/// config instructions, so it can unpause, the read-only queries, and every
+/// way out of a market (closing, resolving, claiming, refunding, cancelling
+/// orders and offers, withdrawing), so funds are never trapped. Only creating
+/// events, buying, selling, minting and transferring stop.
+pub fn allowed_while_paused(function_number: u8) -> bool {
+    let admin = matches!(
+        function_number,
+        11 | 12 | 14 | 15 | 36 | 38 | 42 | 47 | 58 | 59 | 64 | 71
+    );
+    let query = matches!(
+        function_number,
+        25 | 26 | 28..=30 | 43 | 56 | 62 | 70 | 72 | 73 | 75
+    );
+    let exit = matches!(
+        function_number,
+        2 | 9 | 10 | 17..=20 | 22..=24 | 27 | 33..=35 | 37 | 39 | 40 | 45 | 46 | 49 | 51 | 55
+            | 61 | 63 | 66 | 68 | 69 | 74
+    );
+    admin || query || exit
+}
+
+/// Creates an event, locking the configured creator bond from the creator's
+/// balance in `token_account`, which must be the event's mint, into the
+/// event's escrow.
+pub fn process_create_event<'a>(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'a>,
+    creator_account: &AccountInfo<'a>,
+    token_account: &AccountInfo<'a>,
+    config_account: &AccountInfo<'a>,
+    params: PredictionEventParams,
+) -> Result<(), ProgramError> {
+    ensure_distinct_accounts(&[
+        event_account.key,
+        creator_account.key,
+        token_account.key,
+        config_account.key,
+    ])?;
+
+    ensure_program_owned(program_id, &[event_account, token_account, config_account])?;
+
+    if !creator_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    event_store::ensure_event_address(event_account, &params.unique_id)?;
+
+    config::ensure_creator_allowed(config_account, creator_account.key)?;
+
+    if *token_account.key != params.mint {
+        return Err(PredictionMarketError::MintMismatch.into());
+    }
+    config::ensure_mint_allowed(config_account, &params.mint)?;
+
+    let creator_bond = config::load_config(config_account)?.creator_bond;
+    if token_balance(token_account, creator_account.key)? < creator_bond {
+        return Err(PredictionMarketError::InsufficientBond.into());
+    }
+    let decimals = storage::read::<mint::TokenMintDetails>(token_account)?.decimals;
+
+    let committee = params.committee.map(ResolutionCommittee::new).transpose()?;
+    params.market_type.validate()?;
+
+    // committees and auto-resolution pick an outcome, which a scalar market
+    // doesn't have
+    let is_scalar = matches!(params.market_type, MarketType::Scalar { .. });
+    if is_scalar && (committee.is_some() || params.auto_resolve) {
+        return Err(PredictionMarketError::InvalidMarketType.into());
+    }
+    let num_outcomes = if is_scalar { 0 } else { params.num_outcomes };
+    validate_labels(&params.labels, num_outcomes)?;
+
+    if params.creator_fee_bps > math::MAX_CREATOR_FEE_BPS {
+        return Err(PredictionMarketError::CreatorFeeTooHigh.into());
+    }
+
+    let mut outcomes = Vec::new();
+    for i in 0..num_outcomes {
+        outcomes.push(Outcome {
+            id: i,
+            total_amount: 0,
+            bets: HashMap::new(),
+        });
+    }
+    validate_outcome_ids(&outcomes)?;
+
+    let event = PredictionEvent {
+        unique_id: params.unique_id,
+        creator: *creator_account.key,
+        resolver: params.resolver,
+        mint: params.mint,
+        decimals,
+        expiry_timestamp: params.expiry_timestamp,
+        resolution_deadline: params.resolution_deadline,
+        claim_deadline_blocks: params.claim_deadline_blocks,
+        dispute_window: params.dispute_window,
+        dispute_until: None,
+        dispute_deadline: None,
+        freeze_blocks_before_expiry: params.freeze_blocks_before_expiry,
+        creator_bond,
+        creator_fee_bps: params.creator_fee_bps,
+        auto_resolve: params.auto_resolve,
+        max_bet_per_user: params.max_bet_per_user,
+        max_pool: params.max_pool,
+        max_outcome_exposure: params.max_outcome_exposure,
+        min_participants: params.min_participants,
+        market_type: params.market_type,
+        category: params.category,
+        outcomes,
+        labels: params.labels,
+        total_pool_amount: 0,
+        status: EventStatus::Created,
+        winning_outcome: None,
+        co_winners: Vec::new(),
+        committee,
+        claimed: BTreeSet::new(),
+        net_paid: BTreeMap::new(),
+        cost_basis: BTreeMap::new(),
+        open_parlays: 0,
+        scalar_exposure: BTreeMap::new(),
+        scalar_value: None,
+        offers: BTreeMap::new(),
+        next_offer_id: 0,
+        escrowed_utxos: Vec::new(),
+        utxo_stake: 0,
+        utxo_stakes: BTreeMap::new(),
+        pending_utxo_bets: Vec::new(),
+        bitcoin_payout: None,
+        stats: EventStats::default(),
+    };
+
+    // storing under a taken id would overwrite that event
+    match event_store::load_event(event_account, &event.unique_id) {
+        Ok(_) => return Err(PredictionMarketError::EventAlreadyExists.into()),
+        Err(e) if e != PredictionMarketError::EventNotFound.into() => return Err(e),
+        Err(_) => {}
+    }
+    event_store::ensure_capacity(event_account)?;
+
+    let total_predictions = event_store::total_predictions(event_account)?;
+    event_store::store_event(event_account, &event)?;
+    event_store::set_total_predictions(event_account, total_predictions + 1)?;
+
+    if creator_bond > 0 {
+        let escrow = escrow_address(event_account.key, &event.unique_id);
+        move_balance(token_account, creator_account.key, &escrow, creator_bond)?;
+    }
+
+    let height = get_bitcoin_block_height();
+    events::emit(&EventCreatedLog {
+        unique_id: event.unique_id,
+        creator: event.creator,
+        num_outcomes,
+        expiry_timestamp: event.expiry_timestamp,
+        block_height: height,
+    });
+
+    anchoring::anchor_state_transition(event_account)
+}
+
+/// Opens a freshly created event for betting. Only its creator may do so.
+pub fn process_activate_event(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'_>,
+    creator_account: &AccountInfo<'_>,
+    unique_id: [u8; 32],
+) -> Result<(), ProgramError> {
+    ensure_distinct_accounts(&[event_account.key, creator_account.key])?;
+
+    ensure_program_owned(program_id, &[event_account])?;
+    event_store::ensure_event_address(event_account, &unique_id)?;
+
+    if !creator_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let data = event_account.try_borrow_mut_data()?;
+    let mut predictions_data = helper_deserialize_predictions(data)?;
+
+    let event = predictions_data
+        .predictions
+        .iter_mut()
+        .find(|x| x.unique_id == unique_id)
+        .ok_or(PredictionMarketError::EventNotFound)?;
+
+    if event.creator != *creator_account.key {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let height = get_bitcoin_block_height();
+    event.transition_to(EventStatus::Active)?;
+    events::emit(&EventStatusLog {
+        unique_id,
+        status: EventStatus::Active,
+        block_height: height,
+    });
+
+    helper_store_predictions(event_account, predictions_data)
+}
+
+pub fn process_close_event(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'_>,
+    creator_account: &AccountInfo<'_>,
+    unique_id: [u8; 32],
+) -> Result<(), ProgramError> {
+    ensure_distinct_accounts(&[event_account.key, creator_account.key])?;
+
+    ensure_program_owned(program_id, &[event_account])?;
+
+    if !creator_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut event = event_store::load_event(event_account, &unique_id)?;
+
+    if event.creator != *creator_account.key {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let height = get_bitcoin_block_height();
+    event.transition_to(EventStatus::Closed)?;
+    events::emit(&EventStatusLog {
+        unique_id,
+        status: EventStatus::Closed,
+        block_height: height,
+    });
+
+    event_store::store_event(event_account, &event)?;
+
+    anchoring::anchor_state_transition(event_account)
+}
+
+pub fn process_resolve_event(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'_>,
+    resolver_account: &AccountInfo<'_>,
+    unique_id: [u8; 32],
+    winning_outcome: u8,
+) -> Result<(), ProgramError> {
+    resolve_event(
+        program_id,
+        event_account,
+        resolver_account,
+        unique_id,
+        &[winning_outcome],
+        None,
+    )
+}
+
+/// Resolves the event to several winning outcomes at once, for markets with
+/// more than one right answer. Their stakes share the pool as one: a bettor
+/// is paid on their stake across all of them. Fails with `InvalidOutcome`
+/// for an empty list or one naming an outcome twice.
+pub fn process_resolve_multiple(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'_>,
+    resolver_account: &AccountInfo<'_>,
+    unique_id: [u8; 32],
+    winning_outcomes: &[u8],
+) -> Result<(), ProgramError> {
+    let distinct: BTreeSet<&u8> = winning_outcomes.iter().collect();
+    if winning_outcomes.is_empty() || distinct.len() != winning_outcomes.len() {
+        return Err(PredictionMarketError::InvalidOutcome.into());
+    }
+
+    resolve_event(
+        program_id,
+        event_account,
+        resolver_account,
+        unique_id,
+        winning_outcomes,
+        None,
+    )
+}
+
+/// Resolves the event with no winner. Every bettor can then claim back their
+/// net stake instead of a share of the pool.
+pub fn process_resolve_void(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'_>,
+    resolver_account: &AccountInfo<'_>,
+    unique_id: [u8; 32],
+) -> Result<(), ProgramError> {
+    resolve_event(
+        program_id,
+        event_account,
+        resolver_account,
+        unique_id,
+        &[],
+        None,
+    )
+}
+
+/// Resolves a scalar event to `value`, clamped to the event's bounds, which
+/// splits its pool between the long and the short side. `ResolveVoid` voids
+/// a scalar event as any other.
+pub fn process_resolve_scalar(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'_>,
+    resolver_account: &AccountInfo<'_>,
+    unique_id: [u8; 32],
+    value: i64,
+) -> Result<(), ProgramError> {
+    resolve_event(
+        program_id,
+        event_account,
+        resolver_account,
+        unique_id,
+        &[],
+        Some(value),
+    )
+}
+
+fn resolve_event(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'_>,
+    resolver_account: &AccountInfo<'_>,
+    unique_id: [u8; 32],
+    winning_outcomes: &[u8],
+    scalar_value: Option<i64>,
+) -> Result<(), ProgramError> {
+    ensure_distinct_accounts(&[event_account.key, resolver_account.key])?;
+
+    ensure_program_owned(program_id, &[event_account])?;
+    event_store::ensure_event_address(event_account, &unique_id)?;
+
+    if !resolver_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let data = event_account.try_borrow_mut_data()?;
+    let mut predictions_data = helper_deserialize_predictions(data)?;
+
+    let event = predictions_data
+        .predictions
+        .iter_mut()
+        .find(|x| x.unique_id == unique_id)
+        .ok_or(PredictionMarketError::EventNotFound)?;
+
+    if event.committee.is_some() {
+        return Err(PredictionMarketError::CommitteeVoteRequired.into());
+    }
+
+    if event.resolution_authority() != *resolver_account.key {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    for id in winning_outcomes {
+        if !event.outcomes.iter().any(|o| o.id == *id) {
+            return Err(PredictionMarketError::InvalidOutcome.into());
+        }
+    }
+
+    event.scalar_value = match (event.market_type, scalar_value) {
+        (
+            MarketType::Scalar {
+                lower_bound,
+                upper_bound,
+            },
+            value,
+        ) => value.map(|value| value.clamp(lower_bound, upper_bound)),
+        (_, Some(_)) => return Err(PredictionMarketError::InvalidMarketType.into()),
+        (_, None) => None,
+    };
+
+    let height = get_bitcoin_block_height();
+    event.resolve(winning_outcomes.first().copied(), height)?;
+    // a resolution voided for too few bettors has no winners at all
+    if event.winning_outcome.is_some() {
+        event.co_winners = winning_outcomes[1..].to_vec();
+    }
+
+    events::emit(&EventResolvedLog {
+        unique_id,
+        winning_outcome: event.winning_outcome,
+        total_pool_amount: event.total_pool_amount,
+        block_height: height,
+        scalar_value: event.scalar_value,
+    });
+
+    helper_store_predictions(event_account, predictions_data)?;
+
+    anchoring::anchor_state_transition(event_account)
+}
+
+/// Resolves an event that opted into auto-resolution in favour of the
+/// outcome holding the most stake. Anyone may call it once the event has
+/// expired. A tie for the most stake, an empty pool included, voids the event
+/// by cancelling it. Either way, doing so past the resolution deadline
+/// forfeits the creator's bond, as a late resolution does.
+pub fn process_auto_resolve(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'_>,
+    unique_id: [u8; 32],
+) -> Result<(), ProgramError> {
+    ensure_program_owned(program_id, &[event_account])?;
+
+    let mut event = event_store::load_event(event_account, &unique_id)?;
+
+    if !event.auto_resolve {
+        return Err(PredictionMarketError::AutoResolveNotEnabled.into());
+    }
+
+    let height = get_bitcoin_block_height();
+    if height < event.expiry_timestamp as u64 {
+        return Err(PredictionMarketError::EventNotExpired.into());
+    }
+
+    let most_stake = event.outcomes.iter().map(|o| o.total_amount).max();
+    let mut leaders = event
+        .outcomes
+        .iter()
+        .filter(|o| Some(o.total_amount) == most_stake);
+    let winner = match (leaders.next(), leaders.next()) {
+        (Some(leader), None) => Some(leader.id),
+        _ => None,
+    };
+
+    match winner {
+        Some(winning_outcome) => {
+            event.resolve(Some(winning_outcome), height)?;
+            events::emit(&EventResolvedLog {
+                unique_id,
+                winning_outcome: event.winning_outcome,
+                total_pool_amount: event.total_pool_amount,
+                block_height: height,
+                scalar_value: None,
+            });
+        }
+        None => {
+            if height > event.resolution_deadline_height() {
+                event.forfeit_creator_bond();
+            }
+            event.transition_to(EventStatus::Cancelled)?;
+            events::emit(&EventStatusLog {
+                unique_id,
+                status: EventStatus::Cancelled,
+                block_height: height,
+            });
+            msg!("No outcome holds the most stake alone, event cancelled");
+        }
+    }
+
+    event_store::store_event(event_account, &event)?;
+
+    anchoring::anchor_state_transition(event_account)
+}
+
+/// Records a committee member's vote and resolves the event as soon as one
+/// outcome reaches the committee's threshold.
+pub fn process_vote_resolution(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'_>,
+    member_account: &AccountInfo<'_>,
+    unique_id: [u8; 32],
+    winning_outcome: u8,
+) -> Result<(), ProgramError> {
+    ensure_distinct_accounts(&[event_account.key, member_account.key])?;
+
+    ensure_program_owned(program_id, &[event_account])?;
+    event_store::ensure_event_address(event_account, &unique_id)?;
+
+    if !member_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let data = event_account.try_borrow_mut_data()?;
+    let mut predictions_data = helper_deserialize_predictions(data)?;
+
+    let event = predictions_data
+        .predictions
+        .iter_mut()
+        .find(|x| x.unique_id == unique_id)
+        .ok_or(PredictionMarketError::EventNotFound)?;
+
+    event.ensure_can_transition(EventStatus::Resolved)?;
+
+    if !event.outcomes.iter().any(|o| o.id == winning_outcome) {
+        return Err(PredictionMarketError::InvalidOutcome.into());
+    }
+
+    let committee = event
+        .committee
+        .as_mut()
+        .ok_or(PredictionMarketError::InvalidCommittee)?;
+
+    if !committee.is_member(member_account.key) {
+        return Err(PredictionMarketError::NotCommitteeMember.into());
+    }
+
+    let resolved = committee.record_vote(*member_account.key, winning_outcome);
+    if let Some(outcome) = resolved {
+        msg!("Committee threshold reached for outcome {}", outcome);
+        let height = get_bitcoin_block_height();
+        event.resolve(Some(outcome), height)?;
+
+        events::emit(&EventResolvedLog {
+            unique_id,
+            winning_outcome: event.winning_outcome,
+            total_pool_amount: event.total_pool_amount,
+            block_height: height,
+            scalar_value: None,
+        });
+    }
+
+    helper_store_predictions(event_account, predictions_data)?;
+
+    // a vote short of the threshold changes no status
+    match resolved {
+        Some(_) => anchoring::anchor_state_transition(event_account),
+        None => Ok(()),
+    }
+}
+
+/// Lets the program admin challenge a resolution while its dispute window is
+/// open. The event goes back to awaiting resolution: its winner and any
+/// committee votes are cleared, and the resolver (or the committee) resolves
+/// it again, which opens a new dispute window. It has `resolution_deadline`
+/// blocks from the dispute to do so before anyone can expire the event.
+pub fn process_dispute_resolution<'a>(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'a>,
+    disputer_account: &AccountInfo<'a>,
+    config_account: &AccountInfo<'a>,
+    unique_id: [u8; 32],
+) -> Result<(), ProgramError> {
+    ensure_distinct_accounts(&[event_account.key, disputer_account.key, config_account.key])?;
+
+    ensure_program_owned(program_id, &[event_account, config_account])?;
+
+    config::load_config_as_admin(config_account, disputer_account)?;
+
+    let mut event = event_store::load_event(event_account, &unique_id)?;
+
+    event.ensure_can_transition(EventStatus::Disputed)?;
+
+    let height = get_bitcoin_block_height();
+    if !event.in_dispute_window(height) {
+        return Err(PredictionMarketError::DisputeWindowClosed.into());
+    }
+
+    event.transition_to(EventStatus::Disputed)?;
+    event.winning_outcome = None;
+    event.co_winners.clear();
+    event.dispute_until = None;
+    event.dispute_deadline = Some(height + event.resolution_deadline as u64);
+    if let Some(committee) = event.committee.as_mut() {
+        committee.votes.clear();
+    }
+
+    event_store::store_event(event_account, &event)?;
+
+    events::emit(&EventStatusLog {
+        unique_id,
+        status: EventStatus::Disputed,
+        block_height: height,
+    });
+
+    Ok(())
+}
+
+pub fn process_cancel_event(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'_>,
+    creator_account: &AccountInfo<'_>,
+    unique_id: [u8; 32],
+) -> Result<(), ProgramError> {
+    ensure_distinct_accounts(&[event_account.key, creator_account.key])?;
+
+    ensure_program_owned(program_id, &[event_account])?;
+    event_store::ensure_event_address(event_account, &unique_id)?;
+
+    if !creator_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let data = event_account.try_borrow_mut_data()?;
+    let mut predictions_data = helper_deserialize_predictions(data)?;
+
+    let event = predictions_data
+        .predictions
+        .iter_mut()
+        .find(|x| x.unique_id == unique_id)
+        .ok_or(PredictionMarketError::EventNotFound)?;
+
+    if event.creator != *creator_account.key {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // a disputed event is resolved again or expires, the creator can't
+    // walk away from the dispute
+    if event.status == EventStatus::Disputed {
+        return Err(PredictionMarketError::InvalidStatusTransition.into());
+    }
+
+    let height = get_bitcoin_block_height();
+    event.transition_to(EventStatus::Cancelled)?;
+    events::emit(&EventStatusLog {
+        unique_id,
+        status: EventStatus::Cancelled,
+        block_height: height,
+    });
+
+    helper_store_predictions(event_account, predictions_data)?;
+
+    anchoring::anchor_state_transition(event_account)
+}
+
+/// Closes every listed event that is still `Active` past its expiry. Anyone
+/// may crank; ids that are unknown or don't qualify are skipped, so a crank
+/// with nothing to do succeeds without touching the account.
+///
+/// There is no fee pool yet, so crankers are not paid.
+pub fn process_crank_close_expired(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'_>,
+    unique_ids: &[[u8; 32]],
+) -> Result<(), ProgramError> {
+    ensure_program_owned(program_id, &[event_account])?;
+
+    if unique_ids.len() > MAX_CRANK_EVENTS {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let data = event_account.try_borrow_mut_data()?;
+    let mut predictions_data = helper_deserialize_predictions(data)?;
+
+    let height = get_bitcoin_block_height();
+    let mut closed = 0u32;
+
+    for event in predictions_data
+        .predictions
+        .iter_mut()
+        .filter(|x| unique_ids.contains(&x.unique_id))
+    {
+        if close_if_expired(event, height)? {
+            closed += 1;
+        }
+    }
+
+    msg!("Crank closed {} expired events", closed);
+
+    if closed == 0 {
+        return Ok(());
+    }
+
+    helper_store_predictions(event_account, predictions_data)?;
+
+    anchoring::anchor_state_transition(event_account)
+}
+
+/// Closes the `Active` events past their expiry among the `limit` events
+/// starting at `offset`, in storage order. Returns the offset to resume from,
+/// which equals the number of events once the sweep has covered them all.
+///
+/// Closing stops betting only; refunds still go through resolution or
+/// cancellation. Like the crank, sweeping is unpaid.
+pub fn process_sweep_expired(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'_>,
+    offset: u32,
+    limit: u8,
+) -> Result<u32, ProgramError> {
+    ensure_program_owned(program_id, &[event_account])?;
+
+    if limit as usize > MAX_CRANK_EVENTS {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let data = event_account.try_borrow_mut_data()?;
+    let mut predictions_data = helper_deserialize_predictions(data)?;
+
+    let start = (offset as usize).min(predictions_data.predictions.len());
+    let end = (start + limit as usize).min(predictions_data.predictions.len());
+
+    let height = get_bitcoin_block_height();
+    let mut closed = 0u32;
+
+    for event in &mut predictions_data.predictions[start..end] {
+        if close_if_expired(event, height)? {
+            closed += 1;
+        }
+    }
+
+    msg!(
+        "Sweep closed {} expired events, resume at offset {}",
+        closed,
+        end
+    );
+
+    if closed > 0 {
+        helper_store_predictions(event_account, predictions_data)?;
+        anchoring::anchor_state_transition(event_account)?;
+    }
+
+    Ok(end as u32)
+}
+
+// shared by the crank and the sweep
+fn close_if_expired(event: &mut PredictionEvent, height: u64) -> Result<bool, ProgramError> {
+    if event.status != EventStatus::Active || height < event.expiry_timestamp as u64 {
+        return Ok(false);
+    }
+
+    event.transition_to(EventStatus::Closed)?;
+    events::emit(&EventStatusLog {
+        unique_id: event.unique_id,
+        status: EventStatus::Closed,
+        block_height: height,
+    });
+
+    Ok(true)
+}
+
+/// Hands the event to `new_creator`, who from then on holds every creator
+/// right: activating, closing, cancelling, changing the resolver and, when no
+/// resolver is set, resolving. Only the current creator may do so, and only
+/// while the event can still change; resolved and cancelled events keep the
+/// creator they ended with.
+pub fn process_transfer_event_ownership(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'_>,
+    creator_account: &AccountInfo<'_>,
+    unique_id: [u8; 32],
+    new_creator: Pubkey,
+) -> Result<(), ProgramError> {
+    ensure_distinct_accounts(&[event_account.key, creator_account.key])?;
+
+    ensure_program_owned(program_id, &[event_account])?;
+
+    if !creator_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut event = event_store::load_event(event_account, &unique_id)?;
+
+    if event.creator != *creator_account.key {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if matches!(
+        event.status,
+        EventStatus::Resolved | EventStatus::Cancelled | EventStatus::Settled
+    ) {
+        return Err(PredictionMarketError::EventFinalized.into());
+    }
+
+    let height = get_bitcoin_block_height();
+    event.creator = new_creator;
+    event_store::store_event(event_account, &event)?;
+
+    events::emit(&OwnershipTransferredLog {
+        unique_id,
+        previous_creator: *creator_account.key,
+        new_creator,
+        block_height: height,
+    });
+
+    Ok(())
+}
+
+/// Cancels an event nobody resolved in time, forfeiting the creator's bond.
+/// Anyone may call it once the block height is past the event's resolution
+/// deadline, which for a disputed event is the one its dispute set.
+pub fn process_expire_unresolved_event(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'_>,
+    unique_id: [u8; 32],
+) -> Result<(), ProgramError> {
+    ensure_program_owned(program_id, &[event_account])?;
+    event_store::ensure_event_address(event_account, &unique_id)?;
+
+    let data = event_account.try_borrow_mut_data()?;
+    let mut predictions_data = helper_deserialize_predictions(data)?;
+
+    let event = predictions_data
+        .predictions
+        .iter_mut()
+        .find(|x| x.unique_id == unique_id)
+        .ok_or(PredictionMarketError::EventNotFound)?;
+
+    let height = get_bitcoin_block_height();
+    if height <= event.resolution_deadline_height() {
+        return Err(PredictionMarketError::ResolutionDeadlineNotReached.into());
+    }
+
+    event.transition_to(EventStatus::Cancelled)?;
+    event.forfeit_creator_bond();
+    events::emit(&EventStatusLog {
+        unique_id,
+        status: EventStatus::Cancelled,
+        block_height: height,
+    });
+
+    msg!("Event expired unresolved, cancelled");
+
+    helper_store_predictions(event_account, predictions_data)?;
+
+    anchoring::anchor_state_transition(event_account)
+}
+
+/// Pays the creator's bond back out of escrow once the event is resolved or
+/// cancelled without forfeiting it, that is resolved in time or cancelled by
+/// the creator.
+pub fn process_reclaim_creator_bond<'a>(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'a>,
+    token_account: &AccountInfo<'a>,
+    creator_account: &AccountInfo<'a>,
+    unique_id: [u8; 32],
+) -> Result<(), ProgramError> {
+    ensure_distinct_accounts(&[event_account.key, token_account.key, creator_account.key])?;
+
+    ensure_program_owned(program_id, &[event_account, token_account])?;
+
+    if !creator_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut event = event_store::load_event(event_account, &unique_id)?;
+
+    event.ensure_mint(token_account.key)?;
+
+    if event.creator != *creator_account.key {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    // an event settled on bitcoin leaves the bond in escrow
+    if !matches!(
+        event.status,
+        EventStatus::Resolved | EventStatus::Cancelled | EventStatus::Settled
+    ) {
+        return Err(PredictionMarketError::EventNotResolved.into());
+    }
+
+    let bond = event.creator_bond;
+    if bond == 0 {
+        return Err(PredictionMarketError::BondNotReturnable.into());
+    }
+
+    event.creator_bond = 0;
+    event_store::store_event(event_account, &event)?;
+
+    msg!("Returning a creator bond of {}", bond);
+
+    let escrow = escrow_address(event_account.key, &unique_id);
+    move_balance(token_account, &escrow, creator_account.key, bond)
+}
+
+/// Lets the creator hand resolution to another key (or take it back with
+/// `None`) up until the event expires.
+pub fn process_change_resolver(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'_>,
+    creator_account: &AccountInfo<'_>,
+    unique_id: [u8; 32],
+    resolver: Option<Pubkey>,
+) -> Result<(), ProgramError> {
+    ensure_distinct_accounts(&[event_account.key, creator_account.key])?;
+
+    ensure_program_owned(program_id, &[event_account])?;
+    event_store::ensure_event_address(event_account, &unique_id)?;
+
+    if !creator_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let data = event_account.try_borrow_mut_data()?;
+    let mut predictions_data = helper_deserialize_predictions(data)?;
+
+    let event = predictions_data
+        .predictions
+        .iter_mut()
+        .find(|x| x.unique_id == unique_id)
+        .ok_or(PredictionMarketError::EventNotFound)?;
+
+    if event.creator != *creator_account.key {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let height = get_bitcoin_block_height();
+    if height >= event.expiry_timestamp as u64 {
+        return Err(PredictionMarketError::EventExpired.into());
+    }
+
+    event.resolver = resolver;
+
+    helper_store_predictions(event_account, predictions_data)
+}
+
+pub fn process_claim_winnings<'a>(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'a>,
+    token_account: &AccountInfo<'a>,
+    winner_account: &AccountInfo<'a>,
+    unique_id: [u8; 32],
+    settle_on_bitcoin: bool,
+) -> Result<(), ProgramError> {
+    ensure_distinct_accounts(&[event_account.key, token_account.key, winner_account.key])?;
+
+    ensure_program_owned(program_id, &[event_account, token_account])?;
+
+    if !winner_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut event = event_store::load_event(event_account, &unique_id)?;
+
+    event.ensure_mint(token_account.key)?;
+
+    if event.claimed.contains(winner_account.key) {
+        return Err(PredictionMarketError::AlreadyClaimed.into());
+    }
+
+    let height = get_bitcoin_block_height();
+    if event.in_dispute_window(height) {
+        return Err(PredictionMarketError::DisputeWindowOpen.into());
+    }
+
+    // settling a parlay pays into or out of the pool its winners share
+    if event.status == EventStatus::Resolved && event.winning_outcome.is_some() {
+        event.ensure_no_open_parlays()?;
+    }
+
+    let payout = claimable_amount(&event, winner_account.key)?;
+
+    msg!("Paying out {} to {}", payout, winner_account.key);
+
+    // the creator fee is income, not proceeds of a position
+    let creator_fee = if *winner_account.key == event.creator {
+        event.creator_fee()
+    } else {
+        0
+    };
+    event.realize_claim(winner_account.key, payout.saturating_sub(creator_fee))?;
+
+    // persist the claim before paying so the same user cannot be paid twice
+    event.claimed.insert(*winner_account.key);
+    event_store::store_event(event_account, &event)?;
+
+    events::emit(&ClaimedLog {
+        unique_id,
+        user: *winner_account.key,
+        amount: payout,
+        block_height: height,
+    });
+
+    let escrow = escrow_address(event_account.key, &unique_id);
+
+    if settle_on_bitcoin {
+        // the payout leaves as sats, so its tokens leave the escrow for good
+        burn_balance(token_account, &escrow, payout)?;
+        payout::settle_claim_on_bitcoin(event_account, winner_account, payout)
+    } else {
+        move_balance(token_account, &escrow, winner_account.key, payout)
+    }
+}
+
+/// Drops the bets, payment records, cost basis and claim set of a settled
+/// event so its storage shrinks back to the totals. Anyone may compact once
+/// every payout has been claimed, or regardless once the claim deadline has
+/// passed; what is left unclaimed then stays in the escrow until it is swept.
+/// Claims after compaction find no position and fail with
+/// `NoWinningPosition`, so clearing `claimed` cannot let anyone claim twice.
+pub fn process_compact_settled_event(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'_>,
+    unique_id: [u8; 32],
+) -> Result<(), ProgramError> {
+    ensure_program_owned(program_id, &[event_account])?;
+
+    let mut event = event_store::load_event(event_account, &unique_id)?;
+
+    if !matches!(
+        event.status,
+        EventStatus::Resolved | EventStatus::Cancelled | EventStatus::Settled
+    ) {
+        return Err(PredictionMarketError::EventNotResolved.into());
+    }
+
+    let height = get_bitcoin_block_height();
+    if height <= event.claim_deadline_height() {
+        ensure_no_outstanding_claims(&event)?;
+    }
+
+    for outcome in &mut event.outcomes {
+        outcome.bets.clear();
+    }
+    event.net_paid.clear();
+    event.cost_basis.clear();
+    event.scalar_exposure.clear();
+    event.offers.clear();
+    event.claimed.clear();
+
+    event_store::store_event(event_account, &event)
+}
+
+/// Removes a settled event from the event account altogether, shrinking the
+/// account by its bytes. Anyone may prune once nobody is owed anything from
+/// it: every payout has been claimed, or the event was swept, and its escrow
+/// is empty, so no winnings, bond or rounding dust is stranded. The event's
+/// id is free again afterwards.
+pub fn process_prune_event<'a>(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'a>,
+    token_account: &AccountInfo<'a>,
+    config_account: &AccountInfo<'a>,
+    unique_id: [u8; 32],
+) -> Result<(), ProgramError> {
+    ensure_distinct_accounts(&[event_account.key, token_account.key, config_account.key])?;
+
+    ensure_program_owned(program_id, &[event_account, token_account, config_account])?;
+
+    let event = event_store::load_event(event_account, &unique_id)?;
+    ensure_nothing_owed(event_account, token_account, &event)?;
+
+    event_store::remove_event(event_account, &unique_id)?;
+
+    msg!("Pruned event");
+
+    Ok(())
+}
+
+/// Moves a settled event out of the event account into the archive,
+/// shrinking the event account by its bytes as pruning does, see
+/// `process_prune_event` for when that is allowed. `QueryArchivedEvent`
+/// reads it from there.
+pub fn process_archive_event<'a>(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'a>,
+    token_account: &AccountInfo<'a>,
+    config_account: &AccountInfo<'a>,
+    archive_account: &AccountInfo<'a>,
+    unique_id: [u8; 32],
+) -> Result<(), ProgramError> {
+    ensure_distinct_accounts(&[
+        event_account.key,
+        token_account.key,
+        config_account.key,
+        archive_account.key,
+    ])?;
+
+    ensure_program_owned(program_id, &[event_account, token_account, config_account])?;
+    archive::ensure_archive(program_id, archive_account)?;
+
+    let event = event_store::load_event(event_account, &unique_id)?;
+    ensure_nothing_owed(event_account, token_account, &event)?;
+
+    archive::append(archive_account, &event)?;
+    event_store::remove_event(event_account, &unique_id)?;
+
+    msg!("Archived event");
+
+    Ok(())
+}
+
+// an event may leave its event account once it is over and nobody is owed
+// anything from it
+fn ensure_nothing_owed(
+    event_account: &AccountInfo<'_>,
+    token_account: &AccountInfo<'_>,
+    event: &PredictionEvent,
+) -> Result<(), ProgramError> {
+    event.ensure_mint(token_account.key)?;
+
+    if !matches!(
+        event.status,
+        EventStatus::Resolved | EventStatus::Cancelled | EventStatus::Settled
+    ) {
+        return Err(PredictionMarketError::EventNotResolved.into());
+    }
+
+    ensure_no_outstanding_claims(event)?;
+    event.ensure_no_open_parlays()?;
+
+    let escrow = escrow_address(event_account.key, &event.unique_id);
+    let escrowed = token_balance(token_account, &escrow)?;
+    if escrowed > 0 {
+        msg!("{} is still in the escrow", escrowed);
+        return Err(PredictionMarketError::EscrowNotEmpty.into());
+    }
+
+    Ok(())
+}
+
+// a participant is owed something until they claim it, as long as claiming
+// would pay them, and so is a seller until they cancel their offer
+fn ensure_no_outstanding_claims(event: &PredictionEvent) -> Result<(), ProgramError> {
+    let outstanding = event
+        .participants()
+        .iter()
+        .filter(|user| !event.claimed.contains(user) && claimable_amount(event, user).is_ok())
+        .count()
+        + event.offers.len();
+
+    if outstanding > 0 {
+        msg!("{} claims are still outstanding", outstanding);
+        return Err(PredictionMarketError::ClaimsOutstanding.into());
+    }
+
+    Ok(())
+}
+
+/// What `user` is owed from a settled event: their share of the pool if
+/// they backed a winner, their stake back if the event is void, and for
+/// the creator the creator fee. Stakes a utxo backs are left out, being paid
+/// on bitcoin. Fails with `NoWinningPosition` when they are owed nothing, or
+/// `UtxoStakeLocked` when a utxo backs all they would be owed. Claims already
+/// made are not taken into account.
+fn claimable_amount(event: &PredictionEvent, user: &Pubkey) -> Result<u64, ProgramError> {
+    if let MarketType::Scalar { .. } = event.market_type {
+        return scalar::claimable_amount(event, user);
+    }
+
+    // a winnerless resolution or a cancellation refunds stakes instead
+    let is_void = match event.status {
+        EventStatus::Resolved => event.winning_outcome.is_none(),
+        EventStatus::Cancelled => true,
+        EventStatus::Settled => return Err(PredictionMarketError::ClaimWindowClosed.into()),
+        _ => return Err(PredictionMarketError::EventNotResolved.into()),
+    };
+
+    // what a utxo backs is paid back on bitcoin, never out of the escrow
+    let nothing_owed = |winners_only: bool| {
+        let utxo_backed = event.outcomes.iter().any(|outcome| {
+            (!winners_only || event.is_winner(outcome.id))
+                && event.utxo_position(user, outcome.id) > 0
+        });
+        if utxo_backed {
+            PredictionMarketError::UtxoStakeLocked
+        } else {
+            PredictionMarketError::NoWinningPosition
+        }
+    };
+
+    if is_void {
+        let stake = event
+            .outcomes
+            .iter()
+            .map(|outcome| event.token_stake(outcome, user))
+            .fold(0u64, u64::saturating_add);
+
+        if stake == 0 {
+            return Err(nothing_owed(false).into());
+        }
+
+        // LMSR shares weren't all bought at the same price, so what was
+        // paid for them is refunded instead, scaled down if sells at a
+        // profit left the pool short
+        let refund = match event.market_type {
+            MarketType::ParimutuelPool | MarketType::Scalar { .. } => stake,
+            MarketType::Lmsr { .. } => {
+                let paid = event.net_paid.get(user).copied().unwrap_or(0);
+                let total_paid = event
+                    .net_paid
+                    .values()
+                    .fold(0u64, |total, paid| total.saturating_add(*paid));
+                let refundable = event.total_pool_amount.min(total_paid);
+                if total_paid == 0 {
+                    0
+                } else {
+                    math::mul_div(paid, refundable, total_paid)
+                        .ok_or(ProgramError::ArithmeticOverflow)?
+                }
+            }
+        };
+
+        if refund == 0 {
+            return Err(PredictionMarketError::NoWinningPosition.into());
+        }
+
+        Ok(refund)
+    } else {
+        let winning_index = event
+            .winning_outcome
+            .and_then(|id| event.outcome_index(id))
+            .ok_or(PredictionMarketError::EventNotResolved)?;
+
+        // stakes on any of several winners pay out together
+        let stake = event
+            .outcomes
+            .iter()
+            .filter(|outcome| event.is_winner(outcome.id))
+            .map(|outcome| event.token_stake(outcome, user))
+            .fold(0u64, u64::saturating_add);
+        let fee = if *user == event.creator {
+            event.creator_fee()
+        } else {
+            0
+        };
+
+        if stake == 0 && fee == 0 {
+            return Err(nothing_owed(true).into());
+        }
+
+        math::winning_payout(event, winning_index, stake)
+            .and_then(|payout| payout.checked_add(fee))
+            .ok_or(ProgramError::ArithmeticOverflow)
+    }
+}
+
+/// Moves whatever is still in a settled event's escrow to the treasury once
+/// the claim deadline has passed, and marks the event `Settled` so later
+/// claims fail with `ClaimWindowClosed`. Anyone may sweep. The amount is read
+/// from the escrow balance, not derived from the pool and the claims made.
+pub fn process_sweep_unclaimed<'a>(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'a>,
+    token_account: &AccountInfo<'a>,
+    config_account: &AccountInfo<'a>,
+    unique_id: [u8; 32],
+) -> Result<u64, ProgramError> {
+    ensure_distinct_accounts(&[event_account.key, token_account.key, config_account.key])?;
+
+    ensure_program_owned(program_id, &[event_account, token_account, config_account])?;
+
+    let mut event = event_store::load_event(event_account, &unique_id)?;
+
+    event.ensure_mint(token_account.key)?;
+    event.ensure_can_transition(EventStatus::Settled)?;
+    event.ensure_no_open_parlays()?;
+
+    let height = get_bitcoin_block_height();
+    if height <= event.claim_deadline_height() {
+        return Err(PredictionMarketError::ClaimDeadlineNotReached.into());
+    }
+
+    event.transition_to(EventStatus::Settled)?;
+    // a bond not taken back by now goes with the rest
+    event.forfeit_creator_bond();
+    event_store::store_event(event_account, &event)?;
+
+    events::emit(&EventStatusLog {
+        unique_id,
+        status: EventStatus::Settled,
+        block_height: height,
+    });
+
+    let escrow = escrow_address(event_account.key, &unique_id);
+    let residual = token_balance(token_account, &escrow)?;
+
+    msg!("Sweeping {} unclaimed to the treasury", residual);
+
+    if residual > 0 {
+        let treasury = config::load_config(config_account)?.treasury;
+        move_balance(token_account, &escrow, &treasury, residual)?;
+    }
+
+    Ok(residual)
+}
+
+fn token_balance(token_account: &AccountInfo<'_>, holder: &Pubkey) -> Result<u64, ProgramError> {
+    let token = storage::read::<mint::TokenMintDetails>(token_account)?;
+
+    Ok(token.balances.get(holder).copied().unwrap_or(0))
+}
+
+/// While bets are open every staked token sits in the event's escrow next
+/// to the creator's bond, so the escrow balance must equal the two. Stakes
+/// funded by utxos hold no tokens and are left out.
+pub(crate) fn ensure_escrow_backs_pool(
+    token_account: &AccountInfo<'_>,
+    escrow: &Pubkey,
+    event: &PredictionEvent,
+) -> Result<(), ProgramError> {
+    let escrowed = token_balance(token_account, escrow)?;
+    let expected = event.total_pool_amount - event.utxo_stake + event.creator_bond;
+    if escrowed != expected {
+        msg!(
+            "Escrow holds {} but the pool and bond are {}",
+            escrowed,
+            expected
+        );
+        return Err(PredictionMarketError::EscrowMismatch.into());
+    }
+
+    Ok(())
+}
+
+pub fn helper_deserialize_predictions(
+    data: RefMut<'_, &mut [u8]>,
+) -> Result<Predictions, ProgramError> {
+    msg!("Total bytes: {}", data.len());
+    event_store::decode_predictions(&data).map_err(|e| {
+        if e == PredictionMarketError::AccountTooLarge.into() {
+            msg!("Error: Event account too large to decode whole, use per-event instructions");
+            return e;
+        }
+        msg!("Error: Failed to deserialize event data {}", e);
+        ProgramError::BorshIoError(String::from("Error: Failed to deserialize event data"))
+    })
+}
+
+pub fn helper_store_predictions(
+    event_account: &AccountInfo<'_>,
+    predictions_data: Predictions,
+) -> Result<(), ProgramError> {
+    event_store::store_predictions(event_account, &predictions_data)
+}
+
+/// Accounts of a buy or sell, in instruction order.
+#[derive(Clone, Copy)]
+pub struct BetAccounts<'b, 'a> {
+    pub event: &'b AccountInfo<'a>,
+    // the mint holding every balance, including the event's escrow
+    pub token: &'b AccountInfo<'a>,
+    pub better: &'b AccountInfo<'a>,
+    // buys only, the limits the better set on themselves, see `user_limits`
+    pub limits: Option<&'b AccountInfo<'a>>,
+    pub config: &'b AccountInfo<'a>,
+    // buys only, who referred the better and where that is recorded
+    pub referral: Option<Referral<'b, 'a>>,
+    // the event's order book, crossed before the pool
+    pub orders: Option<&'b AccountInfo<'a>>,
+}
+
+pub fn process_buy_bet(
+    program_id: &Pubkey,
+    accounts: BetAccounts<'_, '_>,
+    unique_id: [u8; 32],
+    outcome_id: u8,
+    amount: u64,
+    backing_utxo: Option<&UtxoMeta>,
+    slippage: SlippageLimits,
+) -> Result<(), ProgramError> {
+    let BetAccounts {
+        event: event_account,
+        token: token_account,
+        better: better_account,
+        limits: limits_account,
+        config: config_account,
+        referral,
+        orders: orders_account,
+    } = accounts;
+    let limits_account = limits_account.ok_or(ProgramError::NotEnoughAccountKeys)?;
+
+    // referring yourself earns nothing
+    let referral = referral.filter(|referral| referral.referrer != *better_account.key);
+
+    let mut keys = vec![
+        event_account.key,
+        token_account.key,
+        better_account.key,
+        limits_account.key,
+        config_account.key,
+    ];
+    keys.extend(referral.map(|referral| referral.stats.key));
+    keys.extend(orders_account.map(|orders| orders.key));
+    ensure_distinct_accounts(&keys)?;
+
+    ensure_program_owned(
+        program_id,
+        &[event_account, token_account, limits_account, config_account],
+    )?;
+    if let Some(referral) = referral {
+        ensure_program_owned(program_id, &[referral.stats])?;
+    }
+    if let Some(orders) = orders_account {
+        ensure_program_owned(program_id, &[orders])?;
+    }
+
+    if !better_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    config::ensure_not_paused(config_account)?;
+    let config = config::load_config(config_account)?;
+
+    // A bet backed by Bitcoin must reference a utxo the better actually controls
+    if let Some(utxo) = backing_utxo {
+        if !validate_utxo_ownership(utxo, better_account.key) {
+            msg!("Utxo {} is not owned by the better", utxo);
+            return Err(PredictionMarketError::UtxoOwnershipNotProven.into());
+        }
+    }
+
+    let mut event = event_store::load_event(event_account, &unique_id)?;
+
+    if event.status != EventStatus::Active {
+        return Err(PredictionMarketError::EventNotActive.into());
+    }
+
+    let height = get_bitcoin_block_height();
+    event.ensure_not_frozen(height)?;
+    event.ensure_mint(token_account.key)?;
+
+    // amounts are in the mint's smallest unit, of which a bet needs one
+    if amount == 0 {
+        return Err(PredictionMarketError::InvalidBetAmount.into());
+    }
+
+    event.ensure_within_bet_limit(better_account.key, amount)?;
+    user_limits::ensure_bet_allowed(
+        limits_account,
+        better_account.key,
+        event.user_stake(better_account.key).saturating_add(amount),
+        height,
+    )?;
+
+    let outcome_index = event
+        .outcome_index(outcome_id)
+        .ok_or(PredictionMarketError::InvalidOutcome)?;
+
+    // resting asks that beat the pool fill first, the pool takes the rest
+    let mut book = orders_account
+        .map(|orders| orders::load_book(orders, event_account.key, &unique_id))
+        .transpose()?;
+    let matched = match &mut book {
+        Some(book) => book.match_market_order(
+            outcome_id,
+            OrderSide::Buy,
+            better_account.key,
+            amount,
+            orders::pool_price_bps(&event, outcome_index),
+            config.self_trade_policy,
+        )?,
+        None => orders::Matched::default(),
+    };
+    // self-trade prevention can cancel part of the buy, or all of it
+    let bought = amount - matched.withheld;
+    if bought == 0 {
+        return settle_unexecuted(accounts, event, outcome_index, book, &matched, height);
+    }
+    let pool_amount = bought - matched.filled();
+    let fill_cost = matched
+        .fills
+        .iter()
+        .try_fold(0u64, |total, fill| total.checked_add(fill.tokens))
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    // a pool bet stakes `amount` itself, an LMSR bet buys `amount` shares
+    let pool_cost = match event.market_type {
+        MarketType::ParimutuelPool | MarketType::Scalar { .. } => pool_amount,
+        MarketType::Lmsr { b } => {
+            math::lmsr_buy_cost(&event.outcome_quantities(), outcome_index, pool_amount, b)
+                .ok_or(ProgramError::ArithmeticOverflow)?
+        }
+    };
+    event.ensure_within_pool_cap(pool_cost)?;
+    event.ensure_within_outcome_exposure(outcome_index, pool_amount)?;
+    let cost = pool_cost
+        .checked_add(fill_cost)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    // what the bet pays if it wins, priced after the bet itself
+    let expected_payout = match event.market_type {
+        MarketType::ParimutuelPool | MarketType::Scalar { .. } => {
+            let outcome_total = event.outcomes[outcome_index]
+                .total_amount
+                .saturating_add(pool_amount);
+            let pool = event.total_pool_amount.saturating_add(pool_amount);
+            let fee = math::creator_fee(pool, outcome_total, event.creator_fee_bps);
+            math::mul_div(bought, pool - fee, outcome_total.max(1))
+                .ok_or(ProgramError::ArithmeticOverflow)?
+        }
+        MarketType::Lmsr { .. } => bought,
+    };
+    // the protocol fee is charged on top of the cost and never enters the pool
+    let (protocol_fee, referral_reward) =
+        referral::split_protocol_fee(cost, config.protocol_fee_bps, config.referral_share_bps);
+    let referral_reward = if referral.is_some() {
+        referral_reward
+    } else {
+        0
+    };
+    slippage.check(cost + protocol_fee, expected_payout)?;
+
+    let bet = Bet {
+        user: *better_account.key,
+        event_id: event.unique_id,
+        outcome_id,
+        amount: bought,
+        timestamp: height as i64,
+        bet_type: BetType::BUY,
+    };
+
+    // take the stake first so a failed transfer never leaves a recorded bet behind
+    let escrow = escrow_address(event_account.key, &unique_id);
+    move_balance(token_account, better_account.key, &escrow, pool_cost)?;
+    orders::settle_buy_fills(
+        token_account,
+        &mut event,
+        outcome_id,
+        better_account.key,
+        &matched.fills,
+    )?;
+    if let Some(orders) = orders_account {
+        orders::settle_released(
+            token_account,
+            orders,
+            &mut event,
+            outcome_index,
+            &matched.released,
+            height,
+        )?;
+    }
+
+    if protocol_fee > referral_reward {
+        move_balance(
+            token_account,
+            better_account.key,
+            &config.treasury,
+            protocol_fee - referral_reward,
+        )?;
+    }
+    if let Some(referral) = referral {
+        if referral_reward > 0 {
+            let vault = referral::vault_address(referral.stats.key);
+            move_balance(token_account, better_account.key, &vault, referral_reward)?;
+        }
+        referral::credit_referrer(referral, token_account.key, cost, referral_reward)?;
+    }
+
+    let outcome = &mut event.outcomes[outcome_index];
+    outcome.total_amount += pool_amount;
+    outcome
+        .bets
+        .entry(*better_account.key)
+        .or_default()
+        .push(bet);
+    event.total_pool_amount += pool_cost;
+    if let MarketType::Lmsr { .. } = event.market_type {
+        *event.net_paid.entry(*better_account.key).or_default() += cost;
+    }
+    event
+        .cost_basis
+        .entry((*better_account.key, outcome_id))
+        .or_default()
+        .buy(cost)?;
+    event.stats.record_buy(better_account.key, cost, height);
+
+    ensure_escrow_backs_pool(token_account, &escrow, &event)?;
+
+    event_store::store_event(event_account, &event)?;
+    store_filled_book(orders_account, book, &matched)?;
+
+    events::emit(&BetPlacedLog {
+        unique_id,
+        user: *better_account.key,
+        outcome_id,
+        amount: bought,
+        bet_type: BetType::BUY,
+        block_height: height,
+        cost,
+    });
+
+    Ok(())
+}
+
+pub fn process_sell_bet(
+    program_id: &Pubkey,
+    accounts: BetAccounts<'_, '_>,
+    unique_id: [u8; 32],
+    outcome_id: u8,
+    amount: u64,
+) -> Result<(), ProgramError> {
+    exit_position(program_id, accounts, unique_id, outcome_id, amount, false)
+}
+
+/// Exits a position before resolution at `math::cashout_value`, the
+/// configured haircut staying in the pool for the remaining bets.
+pub fn process_cashout_position(
+    program_id: &Pubkey,
+    accounts: BetAccounts<'_, '_>,
+    unique_id: [u8; 32],
+    outcome_id: u8,
+    amount: u64,
+) -> Result<(), ProgramError> {
+    exit_position(program_id, accounts, unique_id, outcome_id, amount, true)
+}
+
+// stores an order book a market order crossed; one it didn't cross is left
+// as it was, so a buy or sell never creates a book
+fn store_filled_book(
+    orders_account: Option<&AccountInfo<'_>>,
+    book: Option<orders::OrderBook>,
+    matched: &orders::Matched,
+) -> Result<(), ProgramError> {
+    if let (Some(orders), Some(book)) = (orders_account, book) {
+        if matched.touched_book() {
+            storage::write(orders, &book)?;
+        }
+    }
+
+    Ok(())
+}
+
+// finishes a buy or sell self-trade prevention cancelled whole: only the
+// taker's orders it took off the book change
+fn settle_unexecuted(
+    accounts: BetAccounts<'_, '_>,
+    mut event: PredictionEvent,
+    outcome_index: usize,
+    book: Option<orders::OrderBook>,
+    matched: &orders::Matched,
+    height: u64,
+) -> Result<(), ProgramError> {
+    msg!("Self-trade prevention cancelled the whole order");
+
+    if let Some(orders) = accounts.orders {
+        orders::settle_released(
+            accounts.token,
+            orders,
+            &mut event,
+            outcome_index,
+            &matched.released,
+            height,
+        )?;
+    }
+
+    event_store::store_event(accounts.event, &event)?;
+    store_filled_book(accounts.orders, book, matched)
+}
+
+// shared by sells and cashouts, which differ only in what the position is
+// paid out at
+fn exit_position(
+    program_id: &Pubkey,
+    accounts: BetAccounts<'_, '_>,
+    unique_id: [u8; 32],
+    outcome_id: u8,
+    amount: u64,
+    cashout: bool,
+) -> Result<(), ProgramError> {
+    let BetAccounts {
+        event: event_account,
+        token: token_account,
+        better: better_account,
+        config: config_account,
+        orders: orders_account,
+        ..
+    } = accounts;
+
+    // a cashout is priced against the pool alone
+    let orders_account = orders_account.filter(|_| !cashout);
+
+    let mut keys = vec![
+        event_account.key,
+        token_account.key,
+        better_account.key,
+        config_account.key,
+    ];
+    keys.extend(orders_account.map(|orders| orders.key));
+    ensure_distinct_accounts(&keys)?;
+
+    ensure_program_owned(program_id, &[event_account, token_account, config_account])?;
+    if let Some(orders) = orders_account {
+        ensure_program_owned(program_id, &[orders])?;
+    }
+
+    if !better_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    config::ensure_not_paused(config_account)?;
+    let config = config::load_config(config_account)?;
+
+    let mut event = event_store::load_event(event_account, &unique_id)?;
+
+    if event.status != EventStatus::Active {
+        return Err(PredictionMarketError::EventNotActive.into());
+    }
+
+    let height = get_bitcoin_block_height();
+    event.ensure_not_frozen(height)?;
+    event.ensure_mint(token_account.key)?;
+
+    msg!("{}", if cashout { "Cashout" } else { "Sell Bet" });
+
+    let outcome_index = event
+        .outcome_index(outcome_id)
+        .ok_or(PredictionMarketError::InvalidOutcome)?;
+
+    // resting bids that beat the pool fill first, the pool takes the rest
+    let mut book = orders_account
+        .map(|orders| orders::load_book(orders, event_account.key, &unique_id))
+        .transpose()?;
+    let matched = match &mut book {
+        Some(book) => book.match_market_order(
+            outcome_id,
+            OrderSide::Sell,
+            better_account.key,
+            amount,
+            orders::pool_price_bps(&event, outcome_index),
+            config.self_trade_policy,
+        )?,
+        None => orders::Matched::default(),
+    };
+    // self-trade prevention can cancel part of the sell, or all of it
+    let sold = amount - matched.withheld;
+    if sold == 0 {
+        return settle_unexecuted(accounts, event, outcome_index, book, &matched, height);
+    }
+    let filled = matched.filled();
+    let pool_amount = sold - filled;
+
+    // priced on the event as it stands before the position shrinks
+    let pool_refund = if cashout {
+        math::cashout_value(&event, outcome_index, sold, config.cashout_haircut_bps)
+    } else {
+        match event.market_type {
+            MarketType::ParimutuelPool | MarketType::Scalar { .. } => Some(pool_amount),
+            MarketType::Lmsr { b } => {
+                math::lmsr_sell_refund(&event.outcome_quantities(), outcome_index, pool_amount, b)
+            }
+        }
+    }
+    .ok_or(PredictionMarketError::InsufficientPosition)?;
+    let refund = matched
+        .fills
+        .iter()
+        .try_fold(pool_refund, |total, fill| total.checked_add(fill.tokens))
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    // only what the seller actually holds on this outcome can be sold back;
+    // what the book bought changes hands rather than leaving the outcome
+    event.ensure_token_backed(outcome_index, better_account.key, sold)?;
+    let held = net_stake(&event.outcomes[outcome_index], better_account.key);
+    event.outcomes[outcome_index].sell_position(better_account.key, sold)?;
+    event.outcomes[outcome_index].total_amount += filled;
+    event
+        .cost_basis
+        .entry((*better_account.key, outcome_id))
+        .or_default()
+        .dispose(sold, held, refund)?;
+
+    let escrow = escrow_address(event_account.key, &unique_id);
+    move_balance(token_account, &escrow, better_account.key, pool_refund)?;
+    if let Some(orders) = orders_account {
+        orders::settle_sell_fills(
+            token_account,
+            orders,
+            &mut event,
+            outcome_index,
+            better_account.key,
+            &matched.fills,
+            height,
+        )?;
+        orders::settle_released(
+            token_account,
+            orders,
+            &mut event,
+            outcome_index,
+            &matched.released,
+            height,
+        )?;
+    }
+
+    event.total_pool_amount = event.total_pool_amount.saturating_sub(pool_refund);
+    if let Some(paid) = event.net_paid.get_mut(better_account.key) {
+        *paid = paid.saturating_sub(refund);
+    }
+    event.stats.record_sell(better_account.key, refund, height);
+
+    ensure_escrow_backs_pool(token_account, &escrow, &event)?;
+
+    event_store::store_event(event_account, &event)?;
+    store_filled_book(orders_account, book, &matched)?;
+
+    events::emit(&BetPlacedLog {
+        unique_id,
+        user: *better_account.key,
+        outcome_id,
+        amount: sold,
+        bet_type: BetType::SELL,
+        block_height: height,
+        cost: refund,
+    });
+
+    Ok(())
+}
+
+/// Hands `amount` of the signing holder's position on `outcome_id` to
+/// `recipient` while the event is active, outside the pool: no tokens move
+/// and the outcome and pool totals stay as they are. The cost basis of the
+/// shares, and in an LMSR market what was paid for them, go with them, so
+/// the recipient claims on them as if they had bought them. The recipient's
+/// bet limit applies.
+pub fn process_transfer_position(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'_>,
+    holder_account: &AccountInfo<'_>,
+    params: TransferPositionParams,
+) -> Result<(), ProgramError> {
+    ensure_distinct_accounts(&[event_account.key, holder_account.key])?;
+
+    ensure_program_owned(program_id, &[event_account])?;
+
+    if !holder_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let holder = *holder_account.key;
+    let recipient = params.recipient;
+    if recipient == holder {
+        return Err(PredictionMarketError::SelfTransfer.into());
+    }
+
+    let mut event = event_store::load_event(event_account, &params.unique_id)?;
+
+    if event.status != EventStatus::Active {
+        return Err(PredictionMarketError::EventNotActive.into());
+    }
+
+    let outcome_index = event
+        .outcome_index(params.outcome_id)
+        .ok_or(PredictionMarketError::InvalidOutcome)?;
+
+    event.ensure_within_bet_limit(&recipient, params.amount)?;
+    event.ensure_token_backed(outcome_index, &holder, params.amount)?;
+    let height = get_bitcoin_block_height();
+
+    let outcome = &mut event.outcomes[outcome_index];
+    let held = net_stake(outcome, &holder);
+    outcome.withdraw_position(&holder, params.amount)?;
+    outcome.deposit_position(Bet {
+        user: recipient,
+        event_id: params.unique_id,
+        outcome_id: params.outcome_id,
+        amount: params.amount,
+        timestamp: height as i64,
+        bet_type: BetType::BUY,
+    });
+
+    let basis = event
+        .cost_basis
+        .entry((holder, params.outcome_id))
+        .or_default()
+        .release(params.amount, held);
+    event
+        .cost_basis
+        .entry((recipient, params.outcome_id))
+        .or_default()
+        .buy(basis)?;
+    if let Some(paid) = event.net_paid.get_mut(&holder) {
+        let moved = basis.min(*paid);
+        *paid -= moved;
+        *event.net_paid.entry(recipient).or_default() += moved;
+    }
+
+    event_store::store_event(event_account, &event)?;
+
+    events::emit(&PositionTransferredLog {
+        unique_id: params.unique_id,
+        from: holder,
+        to: recipient,
+        outcome_id: params.outcome_id,
+        amount: params.amount,
+        block_height: height,
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod utxo_backed_bet_tests {
+    use super::*;
+    use crate::test_utils::{
+        balance_of, config_account, create_event, load_predictions, register_utxo_owner,
+        token_account, user_limits_account, TestAccount,
+    };
+
+    const EVENT_ID: [u8; 32] = [7; 32];
+
+    #[test]
+    fn test_buy_bet_with_owned_utxo() {
+        let program_id = Pubkey::new_unique();
+        let (mut event_account, _) = create_event(&program_id, EVENT_ID, 100, 2);
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = token_account(&program_id, &[(better.key(), 1_000)]);
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+        let utxo = UtxoMeta::from([1; 32], 0);
+        register_utxo_owner(utxo.clone(), better.key());
+
+        let mut limits = user_limits_account(&program_id, &better.key());
+        process_buy_bet(
+            &program_id,
+            BetAccounts {
+                event: &event_account.info(),
+                token: &token_account.info(),
+                better: &better.info(),
+                limits: Some(&limits.info()),
+                config: &config.info(),
+                referral: None,
+                orders: None,
+            },
+            EVENT_ID,
+            1,
+            250,
+            Some(&utxo),
+            SlippageLimits::default(),
+        )
+        .unwrap();
+
+        let predictions = load_predictions(&event_account);
+        let event = &predictions.predictions[0];
+        assert_eq!(event.total_pool_amount, 250);
+        assert_eq!(event.outcomes[1].bets[&better.key()][0].amount, 250);
+        assert_eq!(balance_of(&token_account, &better.key()), 750);
+    }
+
+    #[test]
+    fn test_buy_bet_with_foreign_utxo_is_rejected() {
+        let program_id = Pubkey::new_unique();
+        let (mut event_account, _) = create_event(&program_id, EVENT_ID, 100, 2);
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = token_account(&program_id, &[(better.key(), 1_000)]);
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+        let utxo = UtxoMeta::from([2; 32], 1);
+        register_utxo_owner(utxo.clone(), Pubkey::new_unique());
+        let event_bytes = event_account.data().to_vec();
+
+        let mut limits = user_limits_account(&program_id, &better.key());
+        let result = process_buy_bet(
+            &program_id,
+            BetAccounts {
+                event: &event_account.info(),
+                token: &token_account.info(),
+                better: &better.info(),
+                limits: Some(&limits.info()),
+                config: &config.info(),
+                referral: None,
+                orders: None,
+            },
+            EVENT_ID,
+            1,
+            250,
+            Some(&utxo),
+            SlippageLimits::default(),
+        );
+
+        assert_eq!(
+            result,
+            Err(PredictionMarketError::UtxoOwnershipNotProven.into())
+        );
+        assert_eq!(event_account.data(), &event_bytes[..]);
+        assert_eq!(balance_of(&token_account, &better.key()), 1_000);
+    }
+}
+
+#[cfg(test)]
+mod pause_tests {
+    use super::*;
+    use crate::test_utils::{
+        balance_of, config_account, create_event, token_account, user_limits_account, Market,
+        TestAccount,
+    };
+
+    const EVENT_ID: [u8; 32] = [9; 32];
+
+    fn set_paused(paused: bool) -> Vec<u8> {
+        let mut data = vec![12];
+        data.extend(borsh::to_vec(&config::SetPausedParams { paused }).unwrap());
+        data
+    }
+
+    #[test]
+    fn test_bets_rejected_while_paused() {
+        let program_id = Pubkey::new_unique();
+        let (mut event_account, _) = create_event(&program_id, EVENT_ID, 100, 2);
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = token_account(&program_id, &[(better.key(), 1_000)]);
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), true);
+
+        let mut limits = user_limits_account(&program_id, &better.key());
+        let buy = process_buy_bet(
+            &program_id,
+            BetAccounts {
+                event: &event_account.info(),
+                token: &token_account.info(),
+                better: &better.info(),
+                limits: Some(&limits.info()),
+                config: &config.info(),
+                referral: None,
+                orders: None,
+            },
+            EVENT_ID,
+            0,
+            100,
+            None,
+            SlippageLimits::default(),
+        );
+        let sell = process_sell_bet(
+            &program_id,
+            BetAccounts {
+                event: &event_account.info(),
+                token: &token_account.info(),
+                better: &better.info(),
+                limits: None,
+                config: &config.info(),
+                referral: None,
+                orders: None,
+            },
+            EVENT_ID,
+            0,
+            100,
+        );
+
+        assert_eq!(buy, Err(PredictionMarketError::ProgramPaused.into()));
+        assert_eq!(sell, Err(PredictionMarketError::ProgramPaused.into()));
+        assert_eq!(balance_of(&token_account, &better.key()), 1_000);
+    }
+
+    #[test]
+    fn test_every_mutation_rejected_while_paused() {
+        let program_id = Pubkey::new_unique();
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), true);
+
+        for function_number in 1..=75 {
+            if allowed_while_paused(function_number) {
+                continue;
+            }
+            let count = expected_account_count(function_number).unwrap();
+            let mut others: Vec<TestAccount> = (1..count)
+                .map(|_| TestAccount::new(&program_id, &[]).signer())
+                .collect();
+            let mut infos: Vec<AccountInfo> = others.iter_mut().map(|a| a.info()).collect();
+            infos.push(config.info());
+
+            assert_eq!(
+                process_instruction(&program_id, &infos, &[function_number]),
+                Err(PredictionMarketError::ProgramPaused.into()),
+                "opcode {} while paused",
+                function_number
+            );
+        }
+    }
+
+    #[test]
+    fn test_mutations_resume_after_unpause_and_reads_never_stop() {
+        let program_id = Pubkey::new_unique();
+        let mut admin = TestAccount::new(&program_id, &[]).signer();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut limits = user_limits_account(&program_id, &better.key());
+        let mut market = Market::new(&program_id, EVENT_ID, &[(better.key(), 1_000)]);
+        market.config = config_account(&program_id, &admin.key(), false);
+        let mut buy = vec![3];
+        buy.extend(
+            borsh::to_vec(&BetOnPredictionEventParams {
+                unique_id: EVENT_ID,
+                outcome_id: 0,
+                amount: 100,
+                min_expected_payout_bps: 0,
+                max_cost: 0,
+                referrer: None,
+            })
+            .unwrap(),
+        );
+
+        process_instruction(
+            &program_id,
+            &[market.config.info(), admin.info()],
+            &set_paused(true),
+        )
+        .unwrap();
+
+        let bought = process_instruction(
+            &program_id,
+            &[
+                market.event.info(),
+                market.token.info(),
+                better.info(),
+                limits.info(),
+                market.config.info(),
+            ],
+            &buy,
+        );
+        assert_eq!(bought, Err(PredictionMarketError::ProgramPaused.into()));
+        let mut get_balance = vec![25];
+        get_balance.extend(better.key().0);
+        process_instruction(&program_id, &[market.token.info()], &get_balance).unwrap();
+
+        process_instruction(
+            &program_id,
+            &[market.config.info(), admin.info()],
+            &set_paused(false),
+        )
+        .unwrap();
+        process_instruction(
+            &program_id,
+            &[
+                market.event.info(),
+                market.token.info(),
+                better.info(),
+                limits.info(),
+                market.config.info(),
+            ],
+            &buy,
+        )
+        .unwrap();
+
+        assert_eq!(market.balance(&better), 900);
+    }
+
+    #[test]
+    fn test_resolve_and_claim_allowed_while_paused() {
+        let program_id = Pubkey::new_unique();
+        let mut admin = TestAccount::new(&program_id, &[]).signer();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(better.key(), 1_000)]);
+        market.config = config_account(&program_id, &admin.key(), false);
+        market.buy(&mut better, 0, 400).unwrap();
+        let mut close = vec![2];
+        close.extend(EVENT_ID);
+        let mut resolve = vec![9];
+        resolve.extend(
+            borsh::to_vec(&ResolveEventParams {
+                unique_id: EVENT_ID,
+                winning_outcome: 0,
+            })
+            .unwrap(),
+        );
+        let mut claim = vec![10];
+        claim.extend(
+            borsh::to_vec(&ClaimWinningsParams {
+                unique_id: EVENT_ID,
+                settle_on_bitcoin: false,
+            })
+            .unwrap(),
+        );
+
+        process_instruction(
+            &program_id,
+            &[market.config.info(), admin.info()],
+            &set_paused(true),
+        )
+        .unwrap();
+        for data in [&close, &resolve] {
+            process_instruction(
+                &program_id,
+                &[
+                    market.event.info(),
+                    market.creator.info(),
+                    market.config.info(),
+                ],
+                data,
+            )
+            .unwrap();
+        }
+        process_instruction(
+            &program_id,
+            &[
+                market.event.info(),
+                market.token.info(),
+                better.info(),
+                market.config.info(),
+            ],
+            &claim,
+        )
+        .unwrap();
+
+        assert_eq!(market.event_state().status, EventStatus::Resolved);
+        assert!(market.balance(&better) > 600);
+    }
+
+    #[test]
+    fn test_unpause_reenables_betting() {
+        let program_id = Pubkey::new_unique();
+        let (mut event_account, _) = create_event(&program_id, EVENT_ID, 100, 2);
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = token_account(&program_id, &[(better.key(), 1_000)]);
+        let mut admin = TestAccount::new(&program_id, &[]).signer();
+        let mut config = config_account(&program_id, &admin.key(), true);
+
+        config::set_paused(&config.info(), &admin.info(), false).unwrap();
+        let mut limits = user_limits_account(&program_id, &better.key());
+        process_buy_bet(
+            &program_id,
+            BetAccounts {
+                event: &event_account.info(),
+                token: &token_account.info(),
+                better: &better.info(),
+                limits: Some(&limits.info()),
+                config: &config.info(),
+                referral: None,
+                orders: None,
+            },
+            EVENT_ID,
+            0,
+            100,
+            None,
+            SlippageLimits::default(),
+        )
+        .unwrap();
+
+        assert_eq!(balance_of(&token_account, &better.key()), 900);
+    }
+
+    #[test]
+    fn test_only_admin_can_pause() {
+        let program_id = Pubkey::new_unique();
+        let mut impostor = TestAccount::new(&program_id, &[]).signer();
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+
+        let result = config::set_paused(&config.info(), &impostor.info(), true);
+
+        assert_eq!(result, Err(ProgramError::IncorrectAuthority));
+    }
+}
+
+#[cfg(test)]
+mod resolver_tests {
+    use super::*;
+    use crate::test_utils::{
+        config_account, event_account, event_params, load_predictions, set_block_height,
+        token_account, TestAccount,
+    };
+
+    const EVENT_ID: [u8; 32] = [11; 32];
+
+    fn create_with_resolver(
+        program_id: &Pubkey,
+        resolver: Option<Pubkey>,
+    ) -> (TestAccount, TestAccount) {
+        let mut event_account = event_account(program_id, &EVENT_ID);
+        let mut creator = TestAccount::new(program_id, &[]).signer();
+        let mut config = config_account(program_id, &Pubkey::new_unique(), false);
+        let mut token = token_account(program_id, &[]);
+        let mut params = event_params(EVENT_ID, 100, 2);
+        params.resolver = resolver;
+        process_create_event(
+            program_id,
+            &event_account.info(),
+            &creator.info(),
+            &token.info(),
+            &config.info(),
+            params,
+        )
+        .unwrap();
+        process_activate_event(program_id, &event_account.info(), &creator.info(), EVENT_ID)
+            .unwrap();
+        (event_account, creator)
+    }
+
+    #[test]
+    fn test_designated_resolver_resolves_instead_of_creator() {
+        let program_id = Pubkey::new_unique();
+        let mut oracle = TestAccount::new(&program_id, &[]).signer();
+        let (mut event_account, mut creator) =
+            create_with_resolver(&program_id, Some(oracle.key()));
+
+        let by_creator = process_resolve_event(
+            &program_id,
+            &event_account.info(),
+            &creator.info(),
+            EVENT_ID,
+            1,
+        );
+        assert_eq!(by_creator, Err(ProgramError::MissingRequiredSignature));
+
+        process_resolve_event(
+            &program_id,
+            &event_account.info(),
+            &oracle.info(),
+            EVENT_ID,
+            1,
+        )
+        .unwrap();
+
+        let event = &load_predictions(&event_account).predictions[0];
+        assert_eq!(event.status, EventStatus::Resolved);
+        assert_eq!(event.winning_outcome, Some(1));
+    }
+
+    #[test]
+    fn test_creator_resolves_when_no_resolver_set() {
+        let program_id = Pubkey::new_unique();
+        let (mut event_account, mut creator) = create_with_resolver(&program_id, None);
+
+        process_resolve_event(
+            &program_id,
+            &event_account.info(),
+            &creator.info(),
+            EVENT_ID,
+            0,
+        )
+        .unwrap();
+
+        assert_eq!(
+            load_predictions(&event_account).predictions[0].status,
+            EventStatus::Resolved
+        );
+    }
+
+    #[test]
+    fn test_creator_can_still_cancel_with_resolver_set() {
+        let program_id = Pubkey::new_unique();
+        let (mut event_account, mut creator) =
+            create_with_resolver(&program_id, Some(Pubkey::new_unique()));
+
+        process_cancel_event(
+            &program_id,
+            &event_account.info(),
+            &creator.info(),
+            EVENT_ID,
+        )
+        .unwrap();
+
+        assert_eq!(
+            load_predictions(&event_account).predictions[0].status,
+            EventStatus::Cancelled
+        );
+    }
+
+    #[test]
+    fn test_change_resolver_only_before_expiry() {
+        let program_id = Pubkey::new_unique();
+        let new_oracle = Pubkey::new_unique();
+        let (mut event_account, mut creator) = create_with_resolver(&program_id, None);
+
+        set_block_height(99);
+        process_change_resolver(
+            &program_id,
+            &event_account.info(),
+            &creator.info(),
+            EVENT_ID,
+            Some(new_oracle),
+        )
+        .unwrap();
+        assert_eq!(
+            load_predictions(&event_account).predictions[0].resolver,
+            Some(new_oracle)
+        );
+
+        set_block_height(100);
+        let late = process_change_resolver(
+            &program_id,
+            &event_account.info(),
+            &creator.info(),
+            EVENT_ID,
+            None,
+        );
+        assert_eq!(late, Err(PredictionMarketError::EventExpired.into()));
+    }
+}
+
+#[cfg(test)]
+mod committee_tests {
+    use super::*;
+    use crate::test_utils::{
+        config_account, event_account, event_params, load_predictions, token_account, TestAccount,
+    };
+
+    const EVENT_ID: [u8; 32] = [12; 32];
+
+    fn create_with_committee(
+        program_id: &Pubkey,
+        members: &[Pubkey],
+        threshold: u8,
+    ) -> (TestAccount, Result<(), ProgramError>) {
+        let mut event_account = event_account(program_id, &EVENT_ID);
+        let mut creator = TestAccount::new(program_id, &[]).signer();
+        let mut config = config_account(program_id, &Pubkey::new_unique(), false);
+        let mut token = token_account(program_id, &[]);
+        let mut params = event_params(EVENT_ID, 100, 3);
+        params.committee = Some(CommitteeParams {
+            members: members.to_vec(),
+            threshold,
+        });
+        let result = process_create_event(
+            program_id,
+            &event_account.info(),
+            &creator.info(),
+            &token.info(),
+            &config.info(),
+            params,
+        )
+        .and_then(|_| {
+            process_activate_event(program_id, &event_account.info(), &creator.info(), EVENT_ID)
+        });
+        (event_account, result)
+    }
+
+    fn committee(program_id: &Pubkey, size: usize) -> Vec<TestAccount> {
+        (0..size)
+            .map(|_| TestAccount::new(program_id, &[]).signer())
+            .collect()
+    }
+
+    fn vote(
+        event_account: &mut TestAccount,
+        member: &mut TestAccount,
+        outcome: u8,
+    ) -> Result<(), ProgramError> {
+        let program_id = event_account.owner;
+        process_vote_resolution(
+            &program_id,
+            &event_account.info(),
+            &member.info(),
+            EVENT_ID,
+            outcome,
+        )
+    }
+
+    #[test]
+    fn test_event_resolves_once_threshold_matches() {
+        let program_id = Pubkey::new_unique();
+        let mut members = committee(&program_id, 3);
+        let keys: Vec<Pubkey> = members.iter().map(|m| m.key()).collect();
+        let (mut event_account, created) = create_with_committee(&program_id, &keys, 2);
+        created.unwrap();
+
+        vote(&mut event_account, &mut members[0], 1).unwrap();
+        vote(&mut event_account, &mut members[1], 2).unwrap();
+        assert_eq!(
+            load_predictions(&event_account).predictions[0].status,
+            EventStatus::Active
+        );
+
+        vote(&mut event_account, &mut members[2], 1).unwrap();
+
+        let event = &load_predictions(&event_account).predictions[0];
+        assert_eq!(event.status, EventStatus::Resolved);
+        assert_eq!(event.winning_outcome, Some(1));
+        assert_eq!(event.committee.as_ref().unwrap().votes.len(), 3);
+    }
+
+    #[test]
+    fn test_revote_overwrites_instead_of_counting_twice() {
+        let program_id = Pubkey::new_unique();
+        let mut members = committee(&program_id, 3);
+        let keys: Vec<Pubkey> = members.iter().map(|m| m.key()).collect();
+        let (mut event_account, created) = create_with_committee(&program_id, &keys, 2);
+        created.unwrap();
+
+        vote(&mut event_account, &mut members[0], 0).unwrap();
+        vote(&mut event_account, &mut members[0], 1).unwrap();
+        vote(&mut event_account, &mut members[0], 1).unwrap();
+
+        let event = &load_predictions(&event_account).predictions[0];
+        assert_eq!(event.status, EventStatus::Active);
+        assert_eq!(
+            event.committee.as_ref().unwrap().votes,
+            vec![CommitteeVote {
+                member: keys[0],
+                outcome_id: 1
+            }]
+        );
+
+        // the earlier vote for outcome 0 no longer counts
+        vote(&mut event_account, &mut members[1], 0).unwrap();
+        assert_eq!(
+            load_predictions(&event_account).predictions[0].status,
+            EventStatus::Active
+        );
+    }
+
+    #[test]
+    fn test_votes_after_resolution_and_from_outsiders_are_rejected() {
+        let program_id = Pubkey::new_unique();
+        let mut members = committee(&program_id, 2);
+        let mut outsider = TestAccount::new(&program_id, &[]).signer();
+        let keys: Vec<Pubkey> = members.iter().map(|m| m.key()).collect();
+        let (mut event_account, created) = create_with_committee(&program_id, &keys, 1);
+        created.unwrap();
+
+        assert_eq!(
+            vote(&mut event_account, &mut outsider, 0),
+            Err(PredictionMarketError::NotCommitteeMember.into())
+        );
+
+        vote(&mut event_account, &mut members[0], 0).unwrap();
+
+        assert_eq!(
+            vote(&mut event_account, &mut members[1], 1),
+            Err(PredictionMarketError::EventAlreadyResolved.into())
+        );
+    }
+
+    #[test]
+    fn test_single_resolver_path_is_closed_for_committee_events() {
+        let program_id = Pubkey::new_unique();
+        let mut members = committee(&program_id, 1);
+        let (mut event_account, created) =
+            create_with_committee(&program_id, &[members[0].key()], 1);
+        created.unwrap();
+
+        let result = process_resolve_event(
+            &program_id,
+            &event_account.info(),
+            &members[0].info(),
+            EVENT_ID,
+            0,
+        );
+
+        assert_eq!(
+            result,
+            Err(PredictionMarketError::CommitteeVoteRequired.into())
+        );
+    }
+
+    #[test]
+    fn test_malformed_committees_are_rejected() {
+        let program_id = Pubkey::new_unique();
+        let member = Pubkey::new_unique();
+        let oversized: Vec<Pubkey> = (0..MAX_COMMITTEE_MEMBERS + 1)
+            .map(|_| Pubkey::new_unique())
+            .collect();
+
+        for (members, threshold) in [
+            (vec![], 0),
+            (vec![member], 0),
+            (vec![member], 2),
+            (vec![member, member], 1),
+            (oversized, 1),
+        ] {
+            let (_, result) = create_with_committee(&program_id, &members, threshold);
+            assert_eq!(result, Err(PredictionMarketError::InvalidCommittee.into()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod lifecycle_tests {
+    use super::*;
+    use crate::test_utils::{
+        config_account, create_event, event_account, event_params, load_predictions, stack_event,
+        token_account, TestAccount,
+    };
+
+    const EVENT_ID: [u8; 32] = [13; 32];
+
+    #[test]
+    fn test_cancelled_event_cannot_be_resolved() {
+        let program_id = Pubkey::new_unique();
+        let (mut event_account, mut creator) = create_event(&program_id, EVENT_ID, 100, 2);
+
+        process_cancel_event(
+            &program_id,
+            &event_account.info(),
+            &creator.info(),
+            EVENT_ID,
+        )
+        .unwrap();
+        let result = process_resolve_event(
+            &program_id,
+            &event_account.info(),
+            &creator.info(),
+            EVENT_ID,
+            0,
+        );
+
+        assert_eq!(
+            result,
+            Err(PredictionMarketError::InvalidStatusTransition.into())
+        );
+        assert_eq!(
+            load_predictions(&event_account).predictions[0].status,
+            EventStatus::Cancelled
+        );
+    }
+
+    #[test]
+    fn test_total_predictions_counts_every_event_created() {
+        let program_id = Pubkey::new_unique();
+        let (other, mut creator) = create_event(&program_id, [2; 32], 100, 2);
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+        let mut token = token_account(&program_id, &[]);
+        // an account already holding two events
+        let mut event_account = event_account(&program_id, &[1; 32]);
+        for id in [[2; 32], [3; 32]] {
+            let event = PredictionEvent {
+                unique_id: id,
+                ..load_predictions(&other).predictions[0].clone()
+            };
+            stack_event(&mut event_account, event);
+        }
+
+        process_create_event(
+            &program_id,
+            &event_account.info(),
+            &creator.info(),
+            &token.info(),
+            &config.info(),
+            event_params([1; 32], 100, 2),
+        )
+        .unwrap();
+        process_activate_event(&program_id, &event_account.info(), &creator.info(), [1; 32])
+            .unwrap();
+
+        process_close_event(&program_id, &event_account.info(), &creator.info(), [1; 32]).unwrap();
+
+        let predictions = load_predictions(&event_account);
+        assert_eq!(predictions.total_predictions, 3);
+        assert_eq!(predictions.predictions.len(), 3);
+    }
+
+    #[test]
+    fn test_only_the_creator_can_close() {
+        let program_id = Pubkey::new_unique();
+        let (mut event_account, _creator) = create_event(&program_id, EVENT_ID, 100, 2);
+        let mut stranger = TestAccount::new(&program_id, &[]).signer();
+
+        let result = process_close_event(
+            &program_id,
+            &event_account.info(),
+            &stranger.info(),
+            EVENT_ID,
+        );
+
+        assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+        assert_eq!(
+            load_predictions(&event_account).predictions[0].status,
+            EventStatus::Active
+        );
+    }
+
+    #[test]
+    fn test_closed_event_resolves_but_cannot_close_again() {
+        let program_id = Pubkey::new_unique();
+        let (mut event_account, mut creator) = create_event(&program_id, EVENT_ID, 100, 2);
+
+        process_close_event(
+            &program_id,
+            &event_account.info(),
+            &creator.info(),
+            EVENT_ID,
+        )
+        .unwrap();
+        let again = process_close_event(
+            &program_id,
+            &event_account.info(),
+            &creator.info(),
+            EVENT_ID,
+        );
+        assert_eq!(
+            again,
+            Err(PredictionMarketError::InvalidStatusTransition.into())
+        );
+
+        process_resolve_event(
+            &program_id,
+            &event_account.info(),
+            &creator.info(),
+            EVENT_ID,
+            1,
+        )
+        .unwrap();
+        let cancel = process_cancel_event(
+            &program_id,
+            &event_account.info(),
+            &creator.info(),
+            EVENT_ID,
+        );
+        assert_eq!(
+            cancel,
+            Err(PredictionMarketError::EventAlreadyResolved.into())
+        );
+    }
+}
+
+#[cfg(test)]
+mod activation_tests {
+    use super::*;
+    use crate::test_utils::{
+        config_account, event_account, event_params, load_predictions, token_account,
+        user_limits_account, TestAccount,
+    };
+
+    const EVENT_ID: [u8; 32] = [14; 32];
+
+    #[test]
+    fn test_bets_rejected_until_creator_activates() {
+        let program_id = Pubkey::new_unique();
+        let mut event_account = event_account(&program_id, &EVENT_ID);
+        let mut creator = TestAccount::new(&program_id, &[]).signer();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+        let mut token = token_account(&program_id, &[(better.key(), 100)]);
+
+        process_create_event(
+            &program_id,
+            &event_account.info(),
+            &creator.info(),
+            &token.info(),
+            &config.info(),
+            event_params(EVENT_ID, 100, 2),
+        )
+        .unwrap();
+        assert_eq!(
+            load_predictions(&event_account).predictions[0].status,
+            EventStatus::Created
+        );
+
+        let mut limits = user_limits_account(&program_id, &better.key());
+        let early = process_buy_bet(
+            &program_id,
+            BetAccounts {
+                event: &event_account.info(),
+                token: &token.info(),
+                better: &better.info(),
+                limits: Some(&limits.info()),
+                config: &config.info(),
+                referral: None,
+                orders: None,
+            },
+            EVENT_ID,
+            0,
+            10,
+            None,
+            SlippageLimits::default(),
+        );
+        assert_eq!(early, Err(PredictionMarketError::EventNotActive.into()));
+
+        process_activate_event(
+            &program_id,
+            &event_account.info(),
+            &creator.info(),
+            EVENT_ID,
+        )
+        .unwrap();
+
+        let mut limits = user_limits_account(&program_id, &better.key());
+        process_buy_bet(
+            &program_id,
+            BetAccounts {
+                event: &event_account.info(),
+                token: &token.info(),
+                better: &better.info(),
+                limits: Some(&limits.info()),
+                config: &config.info(),
+                referral: None,
+                orders: None,
+            },
+            EVENT_ID,
+            0,
+            10,
+            None,
+            SlippageLimits::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            load_predictions(&event_account).predictions[0].total_pool_amount,
+            10
+        );
+    }
+
+    #[test]
+    fn test_only_creator_activates_and_only_once() {
+        let program_id = Pubkey::new_unique();
+        let mut event_account = event_account(&program_id, &EVENT_ID);
+        let mut creator = TestAccount::new(&program_id, &[]).signer();
+        let mut stranger = TestAccount::new(&program_id, &[]).signer();
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+        let mut token = token_account(&program_id, &[]);
+
+        process_create_event(
+            &program_id,
+            &event_account.info(),
+            &creator.info(),
+            &token.info(),
+            &config.info(),
+            event_params(EVENT_ID, 100, 2),
+        )
+        .unwrap();
+
+        let by_stranger = process_activate_event(
+            &program_id,
+            &event_account.info(),
+            &stranger.info(),
+            EVENT_ID,
+        );
+        assert_eq!(by_stranger, Err(ProgramError::MissingRequiredSignature));
+
+        process_activate_event(
+            &program_id,
+            &event_account.info(),
+            &creator.info(),
+            EVENT_ID,
+        )
+        .unwrap();
+        let again = process_activate_event(
+            &program_id,
+            &event_account.info(),
+            &creator.info(),
+            EVENT_ID,
+        );
+        assert_eq!(
+            again,
+            Err(PredictionMarketError::InvalidStatusTransition.into())
+        );
+    }
+}
+
+#[cfg(test)]
+mod resolution_deadline_tests {
+    use super::*;
+    use crate::test_utils::{
+        config_account, event_account, event_params, load_predictions, set_block_height,
+        token_account, TestAccount,
+    };
+
+    const EVENT_ID: [u8; 32] = [15; 32];
+
+    // expires at 100, resolvable up to and including block 110
+    fn create_with_deadline(program_id: &Pubkey) -> (TestAccount, TestAccount) {
+        let mut event_account = event_account(program_id, &EVENT_ID);
+        let mut creator = TestAccount::new(program_id, &[]).signer();
+        let mut config = config_account(program_id, &Pubkey::new_unique(), false);
+        let mut token = token_account(program_id, &[]);
+        let mut params = event_params(EVENT_ID, 100, 2);
+        params.resolution_deadline = 10;
+        process_create_event(
+            program_id,
+            &event_account.info(),
+            &creator.info(),
+            &token.info(),
+            &config.info(),
+            params,
+        )
+        .unwrap();
+        process_activate_event(program_id, &event_account.info(), &creator.info(), EVENT_ID)
+            .unwrap();
+        (event_account, creator)
+    }
+
+    #[test]
+    fn test_expire_one_block_early_is_rejected() {
+        let program_id = Pubkey::new_unique();
+        let (mut event_account, _) = create_with_deadline(&program_id);
+
+        set_block_height(110);
+        let result = process_expire_unresolved_event(&program_id, &event_account.info(), EVENT_ID);
+
+        assert_eq!(
+            result,
+            Err(PredictionMarketError::ResolutionDeadlineNotReached.into())
+        );
+        assert_eq!(
+            load_predictions(&event_account).predictions[0].status,
+            EventStatus::Active
+        );
+    }
+
+    #[test]
+    fn test_expire_one_block_late_cancels() {
+        let program_id = Pubkey::new_unique();
+        let (mut event_account, _) = create_with_deadline(&program_id);
+
+        set_block_height(111);
+        process_expire_unresolved_event(&program_id, &event_account.info(), EVENT_ID).unwrap();
+
+        assert_eq!(
+            load_predictions(&event_account).predictions[0].status,
+            EventStatus::Cancelled
+        );
+    }
+
+    #[test]
+    fn test_resolved_event_cannot_be_expired() {
+        let program_id = Pubkey::new_unique();
+        let (mut event_account, mut creator) = create_with_deadline(&program_id);
+
+        process_resolve_event(
+            &program_id,
+            &event_account.info(),
+            &creator.info(),
+            EVENT_ID,
+            0,
+        )
+        .unwrap();
+        set_block_height(111);
+        let result = process_expire_unresolved_event(&program_id, &event_account.info(), EVENT_ID);
+
+        assert_eq!(
+            result,
+            Err(PredictionMarketError::EventAlreadyResolved.into())
+        );
+    }
+}
+
+#[cfg(test)]
+mod crank_tests {
+    use super::*;
+    use crate::test_utils::{create_event, load_predictions, set_block_height, stack_event};
+
+    #[test]
+    fn test_crank_closes_only_expired_active_events() {
+        let program_id = Pubkey::new_unique();
+        let (mut event_account, _) = create_event(&program_id, [1; 32], 100, 2);
+        let template = load_predictions(&event_account).predictions[0].clone();
+        for (id, expiry, status) in [
+            ([2; 32], 100, EventStatus::Cancelled),
+            ([3; 32], 200, EventStatus::Active),
+        ] {
+            let event = PredictionEvent {
+                unique_id: id,
+                expiry_timestamp: expiry,
+                status,
+                ..template.clone()
+            };
+            stack_event(&mut event_account, event);
+        }
+
+        set_block_height(150);
+        process_crank_close_expired(
+            &program_id,
+            &event_account.info(),
+            &[[1; 32], [2; 32], [3; 32]],
+        )
+        .unwrap();
+
+        let predictions = load_predictions(&event_account);
+        let statuses: Vec<EventStatus> = predictions.predictions.iter().map(|p| p.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                EventStatus::Closed,
+                EventStatus::Cancelled,
+                EventStatus::Active
+            ]
+        );
+        // closing leaves the count of events created alone
+        assert_eq!(predictions.total_predictions, 3);
+    }
+
+    #[test]
+    fn test_crank_with_nothing_to_close_is_a_noop() {
+        let program_id = Pubkey::new_unique();
+        let (mut event_account, _) = create_event(&program_id, [1; 32], 100, 2);
+        let before = event_account.data().to_vec();
+
+        set_block_height(99);
+        process_crank_close_expired(&program_id, &event_account.info(), &[[1; 32], [9; 32]])
+            .unwrap();
+        process_crank_close_expired(&program_id, &event_account.info(), &[]).unwrap();
+
+        assert_eq!(event_account.data(), &before[..]);
+    }
+
+    #[test]
+    fn test_crank_rejects_oversized_batches() {
+        let program_id = Pubkey::new_unique();
+        let (mut event_account, _) = create_event(&program_id, [1; 32], 100, 2);
+
+        let result = process_crank_close_expired(
+            &program_id,
+            &event_account.info(),
+            &[[0; 32]; MAX_CRANK_EVENTS + 1],
+        );
+
+        assert_eq!(result, Err(ProgramError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_sweep_closes_expired_events_across_resumed_calls() {
+        let program_id = Pubkey::new_unique();
+        let (mut event_account, _) = create_event(&program_id, [0; 32], 100, 2);
+        let template = load_predictions(&event_account).predictions[0].clone();
+        // alternating expired and live events, one expired one left unactivated
+        for i in 1..5u8 {
+            let event = PredictionEvent {
+                unique_id: [i; 32],
+                expiry_timestamp: if i % 2 == 0 { 100 } else { 300 },
+                status: if i == 4 {
+                    EventStatus::Created
+                } else {
+                    EventStatus::Active
+                },
+                ..template.clone()
+            };
+            stack_event(&mut event_account, event);
+        }
+
+        set_block_height(200);
+        let next = process_sweep_expired(&program_id, &event_account.info(), 0, 2).unwrap();
+        assert_eq!(next, 2);
+        let next = process_sweep_expired(&program_id, &event_account.info(), next, 2).unwrap();
+        assert_eq!(next, 4);
+        let next = process_sweep_expired(&program_id, &event_account.info(), next, 2).unwrap();
+        assert_eq!(next, 5);
+
+        let statuses: Vec<EventStatus> = load_predictions(&event_account)
+            .predictions
+            .iter()
+            .map(|p| p.status)
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                EventStatus::Closed,
+                EventStatus::Active,
+                EventStatus::Closed,
+                EventStatus::Active,
+                EventStatus::Created,
+            ]
+        );
+
+        // past the end there is nothing left to do
+        let before = event_account.data().to_vec();
+        let next = process_sweep_expired(&program_id, &event_account.info(), 9, 2).unwrap();
+        assert_eq!(next, 5);
+        assert_eq!(event_account.data(), &before[..]);
+    }
+
+    #[test]
+    fn test_sweep_rejects_oversized_limits() {
+        let program_id = Pubkey::new_unique();
+        let (mut event_account, _) = create_event(&program_id, [1; 32], 100, 2);
+
+        let result = process_sweep_expired(
+            &program_id,
+            &event_account.info(),
+            0,
+            MAX_CRANK_EVENTS as u8 + 1,
+        );
+
+        assert_eq!(result, Err(ProgramError::InvalidArgument));
+    }
+}
+
+#[cfg(test)]
+mod bet_validation_tests {
+    use super::*;
+    use crate::test_utils::{
+        balance_of, config_account, create_event, load_predictions, token_account,
+        user_limits_account, TestAccount,
+    };
+
+    const EVENT_ID: [u8; 32] = [16; 32];
+
+    #[test]
+    fn test_bet_on_unknown_outcome_is_rejected() {
+        let program_id = Pubkey::new_unique();
+        let (mut event_account, _) = create_event(&program_id, EVENT_ID, 100, 2);
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut token = token_account(&program_id, &[(better.key(), 100)]);
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+
+        let mut limits = user_limits_account(&program_id, &better.key());
+        let result = process_buy_bet(
+            &program_id,
+            BetAccounts {
+                event: &event_account.info(),
+                token: &token.info(),
+                better: &better.info(),
+                limits: Some(&limits.info()),
+                config: &config.info(),
+                referral: None,
+                orders: None,
+            },
+            EVENT_ID,
+            2,
+            10,
+            None,
+            SlippageLimits::default(),
+        );
+
+        assert_eq!(result, Err(PredictionMarketError::InvalidOutcome.into()));
+        assert_eq!(
+            load_predictions(&event_account).predictions[0].total_pool_amount,
+            0
+        );
+        assert_eq!(balance_of(&token, &better.key()), 100);
+    }
+}
+
+#[cfg(test)]
+mod void_resolution_tests {
+    use super::*;
+    use crate::test_utils::{Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [17; 32];
+
+    #[test]
+    fn test_void_market_refunds_each_net_contribution() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(
+            &program_id,
+            EVENT_ID,
+            &[(alice.key(), 100), (bob.key(), 100)],
+        );
+
+        market.buy(&mut alice, 0, 30).unwrap();
+        market.buy(&mut alice, 1, 10).unwrap();
+        market.buy(&mut bob, 1, 50).unwrap();
+        market.sell(&mut bob, 1, 20).unwrap();
+        assert_eq!(market.balance(&alice), 60);
+        assert_eq!(market.balance(&bob), 70);
+
+        process_resolve_void(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+        )
+        .unwrap();
+        let event = market.event_state();
+        assert_eq!(event.status, EventStatus::Resolved);
+        assert_eq!(event.winning_outcome, None);
+
+        market.claim(&mut alice).unwrap();
+        market.claim(&mut bob).unwrap();
+
+        assert_eq!(market.balance(&alice), 100);
+        assert_eq!(market.balance(&bob), 100);
+    }
+
+    #[test]
+    fn test_cancelled_market_refunds_stakes() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(alice.key(), 100)]);
+
+        market.buy(&mut alice, 1, 40).unwrap();
+        process_cancel_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+        )
+        .unwrap();
+        market.claim(&mut alice).unwrap();
+
+        assert_eq!(market.balance(&alice), 100);
+    }
+
+    #[test]
+    fn test_void_claim_without_bets_fails() {
+        let program_id = Pubkey::new_unique();
+        let mut stranger = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[]);
+
+        process_resolve_void(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+        )
+        .unwrap();
+
+        assert_eq!(
+            market.claim(&mut stranger),
+            Err(PredictionMarketError::NoWinningPosition.into())
+        );
+    }
+
+    #[test]
+    fn test_only_resolution_authority_can_void() {
+        let program_id = Pubkey::new_unique();
+        let mut stranger = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[]);
+
+        let result = process_resolve_void(
+            &program_id,
+            &market.event.info(),
+            &stranger.info(),
+            EVENT_ID,
+        );
+
+        assert_eq!(result, Err(ProgramError::MissingRequiredSignature));
+        assert_eq!(market.event_state().status, EventStatus::Active);
+    }
+}
+
+#[cfg(test)]
+mod multiple_winner_tests {
+    use super::*;
+    use crate::test_utils::{event_params, Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [81; 32];
+
+    fn three_way_market(program_id: &Pubkey, balances: &[(Pubkey, u64)]) -> Market {
+        Market::with_params(program_id, event_params(EVENT_ID, 100, 3), balances)
+    }
+
+    fn resolve(program_id: &Pubkey, market: &mut Market, winners: &[u8]) -> ProgramResult {
+        process_resolve_multiple(
+            program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+            winners,
+        )
+    }
+
+    #[test]
+    fn test_winners_split_the_pool_by_combined_stake() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut carol = TestAccount::new(&program_id, &[]).signer();
+        let mut market = three_way_market(
+            &program_id,
+            &[(alice.key(), 100), (bob.key(), 100), (carol.key(), 100)],
+        );
+
+        market.buy(&mut alice, 0, 30).unwrap();
+        market.buy(&mut bob, 1, 20).unwrap();
+        market.buy(&mut carol, 2, 50).unwrap();
+        resolve(&program_id, &mut market, &[0, 1]).unwrap();
+
+        let event = market.event_state();
+        assert_eq!(event.winning_outcome, Some(0));
+        assert_eq!(event.co_winners, vec![1]);
+        assert_eq!(event.winning_total(), 50);
+
+        market.claim(&mut alice).unwrap();
+        market.claim(&mut bob).unwrap();
+        assert_eq!(market.balance(&alice), 130);
+        assert_eq!(market.balance(&bob), 120);
+        assert_eq!(
+            market.claim(&mut carol),
+            Err(PredictionMarketError::NoWinningPosition.into())
+        );
+    }
+
+    #[test]
+    fn test_bettor_on_several_winners_claims_both_stakes() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = three_way_market(&program_id, &[(alice.key(), 100), (bob.key(), 100)]);
+
+        market.buy(&mut alice, 0, 30).unwrap();
+        market.buy(&mut alice, 1, 20).unwrap();
+        market.buy(&mut bob, 1, 25).unwrap();
+        market.buy(&mut bob, 2, 25).unwrap();
+        resolve(&program_id, &mut market, &[1, 0]).unwrap();
+
+        // 75 of the 100 pool backed a winner; alice holds 50 of it
+        assert_eq!(
+            claimable_amount(&market.event_state(), &alice.key()),
+            Ok(66)
+        );
+        market.claim(&mut alice).unwrap();
+        market.claim(&mut bob).unwrap();
+        assert_eq!(market.balance(&alice), 116);
+        assert_eq!(market.balance(&bob), 83);
+    }
+
+    #[test]
+    fn test_rejects_empty_duplicate_or_unknown_winners() {
+        let program_id = Pubkey::new_unique();
+        let mut market = three_way_market(&program_id, &[]);
+
+        for winners in [&[][..], &[0, 0], &[1, 3]] {
+            assert_eq!(
+                resolve(&program_id, &mut market, winners),
+                Err(PredictionMarketError::InvalidOutcome.into())
+            );
+        }
+        assert_eq!(market.event_state().status, EventStatus::Active);
+    }
+}
+
+#[cfg(test)]
+mod block_height_read_tests {
+    use super::*;
+    use crate::test_utils::{
+        create_event, load_predictions, set_block_height, stack_event, take_block_height_reads,
+        token_account, Market, TestAccount,
+    };
+
+    const EVENT_ID: [u8; 32] = [82; 32];
+
+    // runs `instruction` and returns how often it read the block height
+    fn reads(instruction: impl FnOnce() -> ProgramResult) -> u32 {
+        take_block_height_reads();
+        instruction().unwrap();
+        take_block_height_reads()
+    }
+
+    #[test]
+    fn test_betting_reads_the_height_once() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(alice.key(), 100)]);
+
+        assert_eq!(reads(|| market.buy(&mut alice, 0, 40)), 1);
+        assert_eq!(reads(|| market.sell(&mut alice, 0, 10)), 1);
+    }
+
+    #[test]
+    fn test_resolving_and_claiming_read_the_height_once() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(alice.key(), 100)]);
+        market.buy(&mut alice, 0, 40).unwrap();
+        set_block_height(100);
+
+        let resolve = || {
+            process_resolve_event(
+                &program_id,
+                &market.event.info(),
+                &market.creator.info(),
+                EVENT_ID,
+                0,
+            )
+        };
+        assert_eq!(reads(resolve), 1);
+        assert_eq!(reads(|| market.claim(&mut alice)), 1);
+    }
+
+    #[test]
+    fn test_batch_transfer_reads_the_height_once() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut token = token_account(&program_id, &[(alice.key(), 100)]);
+        let recipients: Vec<(Pubkey, u64)> = (0..5).map(|_| (Pubkey::new_unique(), 10)).collect();
+
+        let transfer =
+            || transfer::batch_transfer_tokens(&token.info(), &alice.info(), &recipients);
+        assert_eq!(reads(transfer), 1);
+    }
+
+    #[test]
+    fn test_crank_and_sweep_read_the_height_once() {
+        let program_id = Pubkey::new_unique();
+        let (mut event_account, _) = create_event(&program_id, EVENT_ID, 100, 2);
+        let template = load_predictions(&event_account).predictions[0].clone();
+        for id in 1..=4 {
+            stack_event(
+                &mut event_account,
+                PredictionEvent {
+                    unique_id: [id; 32],
+                    ..template.clone()
+                },
+            );
+        }
+        let ids: Vec<[u8; 32]> = (1..=2).map(|id| [id; 32]).collect();
+        set_block_height(100);
+
+        let crank = || process_crank_close_expired(&program_id, &event_account.info(), &ids);
+        assert_eq!(reads(crank), 1);
+        let sweep = || {
+            process_sweep_expired(&program_id, &event_account.info(), 0, 5).map(|resume| {
+                assert_eq!(resume, 5);
+            })
+        };
+        assert_eq!(reads(sweep), 1);
+        assert!(load_predictions(&event_account)
+            .predictions
+            .iter()
+            .all(|event| event.status == EventStatus::Closed));
+    }
+}
+
+#[cfg(test)]
+mod clock_tests {
+    use super::*;
+    use crate::test_utils::{event_params, set_block_height, Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [83; 32];
+
+    #[test]
+    fn test_one_event_through_every_window() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        // expires at 100, frozen from 90 and disputable up to 110
+        let params = PredictionEventParams {
+            freeze_blocks_before_expiry: 10,
+            dispute_window: 10,
+            ..event_params(EVENT_ID, 100, 2)
+        };
+        let mut market =
+            Market::with_params(&program_id, params, &[(alice.key(), 100), (bob.key(), 100)]);
+
+        set_block_height(89);
+        market.buy(&mut alice, 0, 50).unwrap();
+        market.buy(&mut bob, 1, 50).unwrap();
+
+        set_block_height(90);
+        assert_eq!(
+            market.buy(&mut bob, 1, 10),
+            Err(PredictionMarketError::BettingFrozen.into())
+        );
+
+        set_block_height(100);
+        process_resolve_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+            0,
+        )
+        .unwrap();
+
+        set_block_height(110);
+        assert_eq!(
+            market.claim(&mut alice),
+            Err(PredictionMarketError::DisputeWindowOpen.into())
+        );
+
+        set_block_height(111);
+        market.claim(&mut alice).unwrap();
+        assert_eq!(market.balance(&alice), 150);
+
+        let sweep = |market: &mut Market| {
+            process_sweep_unclaimed(
+                &program_id,
+                &market.event.info(),
+                &market.token.info(),
+                &market.config.info(),
+                EVENT_ID,
+            )
+        };
+        let deadline = market.event_state().claim_deadline_height();
+        set_block_height(deadline);
+        assert_eq!(
+            sweep(&mut market),
+            Err(PredictionMarketError::ClaimDeadlineNotReached.into())
+        );
+
+        set_block_height(deadline + 1);
+        assert_eq!(sweep(&mut market), Ok(0));
+        assert_eq!(market.event_state().status, EventStatus::Settled);
+    }
+}
+
+#[cfg(test)]
+mod sell_position_tests {
+    use super::*;
+    use crate::test_utils::{set_block_height, Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [18; 32];
+
+    #[test]
+    fn test_sell_is_bounded_by_net_position() {
+        let program_id = Pubkey::new_unique();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(better.key(), 100)]);
+
+        market.buy(&mut better, 0, 100).unwrap();
+        market.sell(&mut better, 0, 60).unwrap();
+
+        assert_eq!(
+            market.sell(&mut better, 0, 50),
+            Err(PredictionMarketError::InsufficientPosition.into())
+        );
+        assert_eq!(market.balance(&better), 60);
+        assert_eq!(market.event_state().outcomes[0].total_amount, 40);
+    }
+
+    #[test]
+    fn test_partial_sells_reduce_the_oldest_buys_first() {
+        let program_id = Pubkey::new_unique();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(better.key(), 100)]);
+        set_block_height(1);
+        market.buy(&mut better, 0, 30).unwrap();
+        set_block_height(2);
+        market.buy(&mut better, 0, 50).unwrap();
+
+        market.sell(&mut better, 0, 40).unwrap();
+
+        let event = market.event_state();
+        let bets = &event.outcomes[0].bets[&better.key()];
+        assert_eq!(bets.len(), 1);
+        assert_eq!((bets[0].amount, bets[0].timestamp), (40, 2));
+        assert_eq!(event.outcomes[0].total_amount, 40);
+        assert_eq!(event.total_pool_amount, 40);
+        assert_eq!(market.balance(&better), 60);
+
+        market.sell(&mut better, 0, 15).unwrap();
+
+        let event = market.event_state();
+        assert_eq!(event.outcomes[0].bets[&better.key()][0].amount, 25);
+        assert_eq!(event.total_pool_amount, 25);
+    }
+
+    #[test]
+    fn test_full_exit_clears_the_position() {
+        let program_id = Pubkey::new_unique();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(better.key(), 100)]);
+        market.buy(&mut better, 0, 30).unwrap();
+        market.buy(&mut better, 0, 20).unwrap();
+
+        market.sell(&mut better, 0, 50).unwrap();
+
+        let event = market.event_state();
+        assert!(!event.outcomes[0].bets.contains_key(&better.key()));
+        assert_eq!(event.outcomes[0].total_amount, 0);
+        assert_eq!(event.total_pool_amount, 0);
+        assert_eq!(market.balance(&better), 100);
+        assert_eq!(
+            market.sell(&mut better, 0, 1),
+            Err(PredictionMarketError::InsufficientPosition.into())
+        );
+    }
+
+    #[test]
+    fn test_recorded_sells_settle_before_new_ones() {
+        let mut outcome = Outcome {
+            id: 0,
+            total_amount: 50,
+            bets: HashMap::new(),
+        };
+        let user = Pubkey::new_unique();
+        let bet = |amount, bet_type| Bet {
+            user,
+            event_id: EVENT_ID,
+            outcome_id: 0,
+            amount,
+            timestamp: 0,
+            bet_type,
+        };
+        outcome.bets.insert(
+            user,
+            vec![
+                bet(40, BetType::BUY),
+                bet(30, BetType::SELL),
+                bet(40, BetType::BUY),
+            ],
+        );
+
+        outcome.sell_position(&user, 20).unwrap();
+
+        let bets = &outcome.bets[&user];
+        assert_eq!(bets.len(), 1);
+        assert_eq!((bets[0].amount, &bets[0].bet_type), (30, &BetType::BUY));
+        assert_eq!(net_stake(&outcome, &user), 30);
+        assert_eq!(outcome.total_amount, 30);
+    }
+
+    #[test]
+    fn test_claim_pays_on_the_net_stake_after_buy_sell_buy() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut carol = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(
+            &program_id,
+            EVENT_ID,
+            &[(alice.key(), 100), (bob.key(), 100), (carol.key(), 100)],
+        );
+
+        // alice bought 60 in total but holds only 50 of it
+        market.buy(&mut alice, 0, 40).unwrap();
+        market.sell(&mut alice, 0, 10).unwrap();
+        market.buy(&mut alice, 0, 20).unwrap();
+        market.buy(&mut carol, 0, 50).unwrap();
+        market.buy(&mut bob, 1, 50).unwrap();
+        let event = market.event_state();
+        assert_eq!(net_stake(&event.outcomes[0], &alice.key()), 50);
+
+        process_resolve_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+            0,
+        )
+        .unwrap();
+        market.claim(&mut alice).unwrap();
+        market.claim(&mut carol).unwrap();
+
+        // each holds half of the winning side, so each is paid half the pool of 150
+        assert_eq!(market.balance(&alice), 50 + 75);
+        assert_eq!(market.balance(&carol), 50 + 75);
+    }
+
+    #[test]
+    fn test_position_on_one_outcome_cannot_be_sold_on_another() {
+        let program_id = Pubkey::new_unique();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(better.key(), 100)]);
+
+        market.buy(&mut better, 0, 100).unwrap();
+
+        assert_eq!(
+            market.sell(&mut better, 1, 1),
+            Err(PredictionMarketError::InsufficientPosition.into())
+        );
+    }
+}
+
+#[cfg(test)]
+mod claim_tracking_tests {
+    use super::*;
+    use crate::test_utils::{set_block_height, Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [19; 32];
+
+    // alice backs the winning outcome 0, bob and carol the losing one
+    fn settled_market(
+        program_id: &Pubkey,
+        alice: &mut TestAccount,
+        bob: &mut TestAccount,
+        carol: &mut TestAccount,
+    ) -> Market {
+        let mut market = Market::new(
+            program_id,
+            EVENT_ID,
+            &[(alice.key(), 100), (bob.key(), 100), (carol.key(), 100)],
+        );
+        market.buy(alice, 0, 50).unwrap();
+        market.buy(bob, 1, 30).unwrap();
+        market.buy(carol, 1, 20).unwrap();
+        process_resolve_event(
+            program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+            0,
+        )
+        .unwrap();
+        market
+    }
+
+    fn compact(program_id: &Pubkey, market: &mut Market) -> Result<(), ProgramError> {
+        process_compact_settled_event(program_id, &market.event.info(), EVENT_ID)
+    }
+
+    #[test]
+    fn test_second_claim_is_rejected() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(
+            &program_id,
+            EVENT_ID,
+            &[(alice.key(), 100), (bob.key(), 100)],
+        );
+
+        market.buy(&mut alice, 0, 50).unwrap();
+        market.buy(&mut bob, 1, 50).unwrap();
+        process_resolve_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+            0,
+        )
+        .unwrap();
+
+        market.claim(&mut alice).unwrap();
+        assert_eq!(market.balance(&alice), 150);
+
+        assert_eq!(
+            market.claim(&mut alice),
+            Err(PredictionMarketError::AlreadyClaimed.into())
+        );
+        assert_eq!(market.balance(&alice), 150);
+        assert!(market.event_state().claimed.contains(&alice.key()));
+    }
+
+    #[test]
+    fn test_refund_can_only_be_claimed_once() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(alice.key(), 100)]);
+
+        market.buy(&mut alice, 0, 50).unwrap();
+        process_resolve_void(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+        )
+        .unwrap();
+
+        market.claim(&mut alice).unwrap();
+        assert_eq!(
+            market.claim(&mut alice),
+            Err(PredictionMarketError::AlreadyClaimed.into())
+        );
+        assert_eq!(market.balance(&alice), 100);
+    }
+
+    #[test]
+    fn test_compaction_waits_for_every_winner() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut carol = TestAccount::new(&program_id, &[]).signer();
+        let mut market = settled_market(&program_id, &mut alice, &mut bob, &mut carol);
+        set_block_height(100);
+        let before = market.event.data().to_vec();
+
+        assert_eq!(
+            compact(&program_id, &mut market),
+            Err(PredictionMarketError::ClaimsOutstanding.into())
+        );
+        assert_eq!(market.event.data(), &before[..]);
+
+        // losers have nothing to claim, so the winner alone holds it up
+        market.claim(&mut alice).unwrap();
+        compact(&program_id, &mut market).unwrap();
+
+        let state = market.event_state();
+        assert!(state.claimed.is_empty());
+        assert!(state.outcomes.iter().all(|outcome| outcome.bets.is_empty()));
+        assert_eq!(state.total_pool_amount, 100);
+        assert!(market.event.data().len() < before.len());
+    }
+
+    #[test]
+    fn test_claims_after_compaction_pay_nothing() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut carol = TestAccount::new(&program_id, &[]).signer();
+        let mut market = settled_market(&program_id, &mut alice, &mut bob, &mut carol);
+        set_block_height(100);
+        market.claim(&mut alice).unwrap();
+        compact(&program_id, &mut market).unwrap();
+
+        assert_eq!(
+            market.claim(&mut alice),
+            Err(PredictionMarketError::NoWinningPosition.into())
+        );
+        assert_eq!(
+            market.claim(&mut bob),
+            Err(PredictionMarketError::NoWinningPosition.into())
+        );
+        assert_eq!(market.balance(&alice), 150);
+    }
+
+    #[test]
+    fn test_compaction_after_the_claim_deadline_drops_unclaimed_payouts() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut carol = TestAccount::new(&program_id, &[]).signer();
+        let mut market = settled_market(&program_id, &mut alice, &mut bob, &mut carol);
+        let deadline = market.event_state().claim_deadline_height();
+
+        set_block_height(deadline);
+        assert_eq!(
+            compact(&program_id, &mut market),
+            Err(PredictionMarketError::ClaimsOutstanding.into())
+        );
+
+        set_block_height(deadline + 1);
+        compact(&program_id, &mut market).unwrap();
+        assert_eq!(
+            market.claim(&mut alice),
+            Err(PredictionMarketError::NoWinningPosition.into())
+        );
+    }
+
+    #[test]
+    fn test_unsettled_events_cannot_be_compacted() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(alice.key(), 100)]);
+        market.buy(&mut alice, 0, 50).unwrap();
+        set_block_height(u64::MAX);
+
+        assert_eq!(
+            compact(&program_id, &mut market),
+            Err(PredictionMarketError::EventNotResolved.into())
+        );
+    }
+}
+
+#[cfg(test)]
+mod bet_atomicity_tests {
+    use super::*;
+    use crate::test_utils::{Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [20; 32];
+
+    #[test]
+    fn test_failed_burn_leaves_event_untouched() {
+        let program_id = Pubkey::new_unique();
+        let mut broke = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(broke.key(), 0)]);
+        let event_before = market.event.data().to_vec();
+
+        let result = market.buy(&mut broke, 0, 10);
+
+        assert!(result.is_err());
+        assert_eq!(market.event.data(), &event_before[..]);
+        assert_eq!(market.balance(&broke), 0);
+    }
+
+    #[test]
+    fn test_unknown_holder_buy_leaves_event_untouched() {
+        let program_id = Pubkey::new_unique();
+        let mut stranger = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[]);
+        let event_before = market.event.data().to_vec();
+
+        assert!(market.buy(&mut stranger, 1, 1).is_err());
+        assert_eq!(market.event.data(), &event_before[..]);
+    }
+
+    #[test]
+    fn test_rejected_sell_mints_nothing() {
+        let program_id = Pubkey::new_unique();
+        let mut seller = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(seller.key(), 0)]);
+        let event_before = market.event.data().to_vec();
+        let token_before = market.token.data().to_vec();
+
+        assert_eq!(
+            market.sell(&mut seller, 0, 10),
+            Err(PredictionMarketError::InsufficientPosition.into())
+        );
+        assert_eq!(market.event.data(), &event_before[..]);
+        assert_eq!(market.token.data(), &token_before[..]);
+    }
+}
+
+#[cfg(test)]
+mod escrow_tests {
+    use super::*;
+    use crate::mint::TokenMintDetails;
+    use crate::test_utils::{balance_of, Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [21; 32];
+
+    fn total_tokens(market: &Market) -> u64 {
+        TokenMintDetails::try_from_slice(market.token.data())
+            .unwrap()
+            .balances
+            .values()
+            .sum()
+    }
+
+    #[test]
+    fn test_pool_is_escrowed_and_supply_is_constant() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(
+            &program_id,
+            EVENT_ID,
+            &[(alice.key(), 100), (bob.key(), 100)],
+        );
+        let escrow = escrow_address(&market.event.key(), &EVENT_ID);
+
+        market.buy(&mut alice, 0, 60).unwrap();
+        market.buy(&mut bob, 1, 40).unwrap();
+        market.sell(&mut alice, 0, 20).unwrap();
+
+        assert_eq!(balance_of(&market.token, &escrow), 80);
+        assert_eq!(market.event_state().total_pool_amount, 80);
+        assert_eq!(total_tokens(&market), 200);
+
+        process_resolve_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+            0,
+        )
+        .unwrap();
+        market.claim(&mut alice).unwrap();
+
+        assert_eq!(market.balance(&alice), 140);
+        assert_eq!(balance_of(&market.token, &escrow), 0);
+        assert_eq!(total_tokens(&market), 200);
+    }
+
+    #[test]
+    fn test_buy_is_rejected_when_escrow_does_not_match_pool() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(alice.key(), 100)]);
+        let escrow = escrow_address(&market.event.key(), &EVENT_ID);
+
+        // tokens sent straight to the escrow are not part of any bet
+        let mut token = TokenMintDetails::try_from_slice(market.token.data()).unwrap();
+        token.balances.insert(escrow, 5);
+        market.token.set_data(&borsh::to_vec(&token).unwrap());
+
+        assert_eq!(
+            market.buy(&mut alice, 0, 10),
+            Err(PredictionMarketError::EscrowMismatch.into())
+        );
+    }
+
+    #[test]
+    fn test_escrow_is_distinct_per_event() {
+        let event_account = Pubkey::new_unique();
+
+        assert_ne!(
+            escrow_address(&event_account, &[1; 32]),
+            escrow_address(&event_account, &[2; 32])
+        );
+        assert_ne!(
+            escrow_address(&event_account, &[1; 32]),
+            escrow_address(&Pubkey::new_unique(), &[1; 32])
+        );
+    }
+}
+
+#[cfg(test)]
+mod account_count_tests {
+    use super::*;
+    use crate::test_utils::TestAccount;
+
+    fn run_with_accounts(function_number: u8, count: usize) -> ProgramResult {
+        let program_id = Pubkey::new_unique();
+        let mut accounts: Vec<TestAccount> = (0..count)
+            .map(|_| TestAccount::new(&program_id, &[]))
+            .collect();
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(|a| a.info()).collect();
+        process_instruction(&program_id, &infos, &[function_number])
+    }
+
+    #[test]
+    fn test_empty_instruction_data_is_rejected() {
+        let program_id = Pubkey::new_unique();
+
+        assert_eq!(
+            process_instruction(&program_id, &[], &[]),
+            Err(ProgramError::InvalidInstructionData)
+        );
+    }
+
+    #[test]
+    fn test_every_opcode_requires_its_exact_account_count() {
+        let invalid_count = Err(PredictionMarketError::InvalidAccountCount.into());
+
+        for function_number in 1..=75 {
+            let expected = expected_account_count(function_number).unwrap();
+
+            assert_eq!(
+                run_with_accounts(function_number, expected - 1),
+                invalid_count,
+                "opcode {} with too few accounts",
+                function_number
+            );
+            let max = expected + max_trailing_accounts(function_number);
+            assert_eq!(
+                run_with_accounts(function_number, max + 1),
+                invalid_count,
+                "opcode {} with too many accounts",
+                function_number
+            );
+            assert_ne!(
+                run_with_accounts(function_number, expected),
+                invalid_count,
+                "opcode {} with the right accounts",
+                function_number
+            );
+        }
+    }
+
+    #[test]
+    fn test_unknown_opcode_has_no_account_count() {
+        assert_eq!(expected_account_count(0), None);
+        assert_eq!(expected_account_count(76), None);
+    }
+}
+
+#[cfg(test)]
+mod aliasing_tests {
+    use super::*;
+    use crate::test_utils::{user_limits_account, Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [22; 32];
+
+    #[test]
+    fn test_buy_with_better_as_token_account_is_rejected() {
+        let program_id = Pubkey::new_unique();
+        let better = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(better.key(), 100)]);
+        let mut token = market.token.info();
+        token.is_signer = true;
+        let before = market.event.data().to_vec();
+
+        let result = process_buy_bet(
+            &program_id,
+            BetAccounts {
+                event: &market.event.info(),
+                token: &token.clone(),
+                better: &token,
+                limits: Some(&user_limits_account(&program_id, token.key).info()),
+                config: &market.config.info(),
+                referral: None,
+                orders: None,
+            },
+            EVENT_ID,
+            0,
+            10,
+            None,
+            SlippageLimits::default(),
+        );
+
+        assert_eq!(result, Err(PredictionMarketError::AccountAliasing.into()));
+        assert_eq!(market.event.data(), &before[..]);
+        assert_eq!(market.balance(&better), 100);
+    }
+
+    #[test]
+    fn test_sell_with_event_as_token_account_is_rejected() {
+        let program_id = Pubkey::new_unique();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(better.key(), 100)]);
+        market.buy(&mut better, 0, 50).unwrap();
+        let event = market.event.info();
+
+        let result = process_sell_bet(
+            &program_id,
+            BetAccounts {
+                event: &event.clone(),
+                token: &event,
+                better: &better.info(),
+                limits: None,
+                config: &market.config.info(),
+                referral: None,
+                orders: None,
+            },
+            EVENT_ID,
+            0,
+            50,
+        );
+
+        assert_eq!(result, Err(PredictionMarketError::AccountAliasing.into()));
+    }
+}
+
+#[cfg(test)]
+mod ownership_tests {
+    use super::*;
+    use crate::test_utils::{config_account, event_params, token_account, Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [23; 32];
+
+    #[test]
+    fn test_create_event_in_foreign_account_is_rejected() {
+        let program_id = Pubkey::new_unique();
+        let mut event_account = TestAccount::new(&Pubkey::new_unique(), &[]);
+        let mut creator = TestAccount::new(&program_id, &[]).signer();
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+        let mut token = token_account(&program_id, &[]);
+
+        let result = process_create_event(
+            &program_id,
+            &event_account.info(),
+            &creator.info(),
+            &token.info(),
+            &config.info(),
+            event_params(EVENT_ID, 100, 2),
+        );
+
+        assert_eq!(result, Err(ProgramError::IllegalOwner));
+        assert!(event_account.data().is_empty());
+    }
+
+    #[test]
+    fn test_spoofed_event_account_cannot_be_resolved() {
+        let program_id = Pubkey::new_unique();
+        let market = Market::new(&program_id, EVENT_ID, &[]);
+        // a look-alike carrying the same bytes but owned by someone else
+        let mut spoofed = TestAccount::new(&Pubkey::new_unique(), market.event.data());
+        let mut resolver = TestAccount::new(&program_id, &[]).signer();
+
+        let result =
+            process_resolve_event(&program_id, &spoofed.info(), &resolver.info(), EVENT_ID, 0);
+
+        assert_eq!(result, Err(ProgramError::IllegalOwner));
+    }
+
+    #[test]
+    fn test_buy_against_foreign_token_or_config_is_rejected() {
+        let program_id = Pubkey::new_unique();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(better.key(), 100)]);
+
+        market.token.owner = Pubkey::new_unique();
+        assert_eq!(
+            market.buy(&mut better, 0, 10),
+            Err(ProgramError::IllegalOwner)
+        );
+
+        market.token.owner = program_id;
+        market.config.owner = Pubkey::new_unique();
+        assert_eq!(
+            market.buy(&mut better, 0, 10),
+            Err(ProgramError::IllegalOwner)
+        );
+
+        market.config.owner = program_id;
+        market.buy(&mut better, 0, 10).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod mint_binding_tests {
+    use super::*;
+    use crate::test_utils::{token_account, Market, TestAccount, TEST_MINT};
+
+    const EVENT_ID: [u8; 32] = [31; 32];
+
+    #[test]
+    fn test_event_is_bound_to_its_creation_mint() {
+        let program_id = Pubkey::new_unique();
+        let market = Market::new(&program_id, EVENT_ID, &[]);
+
+        assert_eq!(market.event_state().mint, TEST_MINT);
+    }
+
+    #[test]
+    fn test_bets_against_another_mint_are_rejected() {
+        let program_id = Pubkey::new_unique();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(better.key(), 100)]);
+        market.buy(&mut better, 0, 40).unwrap();
+
+        let balances = [(better.key(), 100)];
+        let mut rogue = token_account(&program_id, &balances).with_key(Pubkey::new_unique());
+        std::mem::swap(&mut market.token, &mut rogue);
+        let event_before = market.event.data().to_vec();
+
+        assert_eq!(
+            market.buy(&mut better, 0, 10),
+            Err(PredictionMarketError::MintMismatch.into())
+        );
+        assert_eq!(
+            market.sell(&mut better, 0, 10),
+            Err(PredictionMarketError::MintMismatch.into())
+        );
+        assert_eq!(market.event.data(), &event_before[..]);
+        assert_eq!(market.balance(&better), 100);
+    }
+}
+
+#[cfg(test)]
+mod account_order_tests {
+    use super::*;
+    use crate::test_utils::{
+        balance_of, config_account, event_account, event_params, load_predictions, token_account,
+        user_limits_account, TestAccount,
+    };
+    use borsh::BorshSerialize;
+
+    const EVENT_ID: [u8; 32] = [32; 32];
+
+    fn instruction<T: BorshSerialize>(function_number: u8, params: &T) -> Vec<u8> {
+        let mut data = vec![function_number];
+        data.extend(borsh::to_vec(params).unwrap());
+        data
+    }
+
+    fn bet(amount: u64) -> BetOnPredictionEventParams {
+        BetOnPredictionEventParams {
+            unique_id: EVENT_ID,
+            outcome_id: 0,
+            amount,
+            min_expected_payout_bps: 0,
+            max_cost: 0,
+            referrer: None,
+        }
+    }
+
+    #[test]
+    fn test_event_lifecycle_in_documented_account_order() {
+        let program_id = Pubkey::new_unique();
+        let mut event = event_account(&program_id, &EVENT_ID);
+        let mut creator = TestAccount::new(&program_id, &[]).signer();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut limits = user_limits_account(&program_id, &better.key());
+        let mut token = token_account(&program_id, &[(better.key(), 100)]);
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+        let id = ClosePredictionEventParams {
+            unique_id: EVENT_ID,
+        };
+
+        // [event, creator, token, config]
+        process_instruction(
+            &program_id,
+            &[event.info(), creator.info(), token.info(), config.info()],
+            &instruction(1, &event_params(EVENT_ID, 100, 2)),
+        )
+        .unwrap();
+        // [event, creator, config]
+        process_instruction(
+            &program_id,
+            &[event.info(), creator.info(), config.info()],
+            &instruction(21, &id),
+        )
+        .unwrap();
+        // [event, token, better, better's limits, config]
+        process_instruction(
+            &program_id,
+            &[
+                event.info(),
+                token.info(),
+                better.info(),
+                limits.info(),
+                config.info(),
+            ],
+            &instruction(3, &bet(60)),
+        )
+        .unwrap();
+        // [event, token, better, config]
+        process_instruction(
+            &program_id,
+            &[event.info(), token.info(), better.info(), config.info()],
+            &instruction(4, &bet(20)),
+        )
+        .unwrap();
+        // [event, creator, config]
+        process_instruction(
+            &program_id,
+            &[event.info(), creator.info(), config.info()],
+            &instruction(2, &id),
+        )
+        .unwrap();
+        // [event, resolver, config]
+        let resolve = ResolveEventParams {
+            unique_id: EVENT_ID,
+            winning_outcome: 0,
+        };
+        process_instruction(
+            &program_id,
+            &[event.info(), creator.info(), config.info()],
+            &instruction(9, &resolve),
+        )
+        .unwrap();
+        // [event, token, winner, config]
+        let claim = ClaimWinningsParams {
+            unique_id: EVENT_ID,
+            settle_on_bitcoin: false,
+        };
+        process_instruction(
+            &program_id,
+            &[event.info(), token.info(), better.info(), config.info()],
+            &instruction(10, &claim),
+        )
+        .unwrap();
+
+        let state = &load_predictions(&event).predictions[0];
+        assert_eq!(state.status, EventStatus::Resolved);
+        assert!(state.claimed.contains(&better.key()));
+        assert_eq!(balance_of(&token, &better.key()), 100);
+    }
+
+    #[test]
+    fn test_swapped_roles_are_rejected() {
+        let program_id = Pubkey::new_unique();
+        let mut event = event_account(&program_id, &EVENT_ID);
+        let mut creator = TestAccount::new(&program_id, &[]).signer();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut token = token_account(&program_id, &[(better.key(), 100)]);
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+
+        // the config can't stand in for the event account
+        assert!(process_instruction(
+            &program_id,
+            &[config.info(), creator.info(), token.info(), event.info()],
+            &instruction(1, &event_params(EVENT_ID, 100, 2)),
+        )
+        .is_err());
+
+        process_instruction(
+            &program_id,
+            &[event.info(), creator.info(), token.info(), config.info()],
+            &instruction(1, &event_params(EVENT_ID, 100, 2)),
+        )
+        .unwrap();
+        process_instruction(
+            &program_id,
+            &[event.info(), creator.info(), config.info()],
+            &instruction(
+                21,
+                &ClosePredictionEventParams {
+                    unique_id: EVENT_ID,
+                },
+            ),
+        )
+        .unwrap();
+        let before = event.data().to_vec();
+
+        // better and token swapped
+        assert!(process_instruction(
+            &program_id,
+            &[event.info(), better.info(), token.info(), config.info()],
+            &instruction(3, &bet(10)),
+        )
+        .is_err());
+        assert_eq!(event.data(), &before[..]);
+        assert_eq!(balance_of(&token, &better.key()), 100);
+    }
+}
+
+#[cfg(test)]
+mod bet_limit_tests {
+    use super::*;
+    use crate::test_utils::{event_params, Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [33; 32];
+
+    fn limited_market(program_id: &Pubkey, limit: u64, betters: &[&TestAccount]) -> Market {
+        let balances: Vec<(Pubkey, u64)> = betters.iter().map(|b| (b.key(), 1_000)).collect();
+        let params = PredictionEventParams {
+            max_bet_per_user: Some(limit),
+            ..event_params(EVENT_ID, 100, 2)
+        };
+        Market::with_params(program_id, params, &balances)
+    }
+
+    #[test]
+    fn test_bets_under_and_at_the_limit_are_accepted() {
+        let program_id = Pubkey::new_unique();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut market = limited_market(&program_id, 100, &[&better]);
+
+        market.buy(&mut better, 0, 60).unwrap();
+        // stakes on every outcome count towards the same limit
+        market.buy(&mut better, 1, 40).unwrap();
+
+        assert_eq!(market.event_state().total_pool_amount, 100);
+        assert_eq!(market.balance(&better), 900);
+    }
+
+    #[test]
+    fn test_bet_over_the_limit_is_rejected() {
+        let program_id = Pubkey::new_unique();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut other = TestAccount::new(&program_id, &[]).signer();
+        let mut market = limited_market(&program_id, 100, &[&better, &other]);
+        market.buy(&mut better, 0, 70).unwrap();
+        let event_before = market.event.data().to_vec();
+
+        assert_eq!(
+            market.buy(&mut better, 1, 31),
+            Err(PredictionMarketError::BetLimitExceeded.into())
+        );
+        assert_eq!(market.event.data(), &event_before[..]);
+        assert_eq!(market.balance(&better), 930);
+
+        // the limit is per user
+        market.buy(&mut other, 1, 100).unwrap();
+    }
+
+    #[test]
+    fn test_sold_stake_frees_up_the_limit() {
+        let program_id = Pubkey::new_unique();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut market = limited_market(&program_id, 100, &[&better]);
+        market.buy(&mut better, 0, 100).unwrap();
+
+        market.sell(&mut better, 0, 30).unwrap();
+
+        market.buy(&mut better, 0, 30).unwrap();
+        assert_eq!(
+            market.buy(&mut better, 0, 1),
+            Err(PredictionMarketError::BetLimitExceeded.into())
+        );
+    }
+
+    #[test]
+    fn test_no_limit_by_default() {
+        let program_id = Pubkey::new_unique();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(better.key(), 1_000_000)]);
+
+        market.buy(&mut better, 0, 1_000_000).unwrap();
+
+        assert_eq!(market.event_state().max_bet_per_user, None);
+    }
+}
+
+#[cfg(test)]
+mod pool_cap_tests {
+    use super::*;
+    use crate::test_utils::{event_params, Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [35; 32];
+
+    fn capped_market(program_id: &Pubkey, cap: u64, betters: &[&TestAccount]) -> Market {
+        let balances: Vec<(Pubkey, u64)> = betters.iter().map(|b| (b.key(), 1_000)).collect();
+        let params = PredictionEventParams {
+            max_pool: Some(cap),
+            ..event_params(EVENT_ID, 100, 2)
+        };
+        Market::with_params(program_id, params, &balances)
+    }
+
+    #[test]
+    fn test_bets_filling_the_pool_exactly_are_accepted() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = capped_market(&program_id, 100, &[&alice, &bob]);
+
+        market.buy(&mut alice, 0, 70).unwrap();
+        market.buy(&mut bob, 1, 30).unwrap();
+
+        assert_eq!(market.event_state().total_pool_amount, 100);
+    }
+
+    #[test]
+    fn test_bet_past_the_cap_is_rejected_whole() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = capped_market(&program_id, 100, &[&alice, &bob]);
+        market.buy(&mut alice, 0, 70).unwrap();
+        let event_before = market.event.data().to_vec();
+
+        assert_eq!(
+            market.buy(&mut bob, 1, 31),
+            Err(PredictionMarketError::PoolCapExceeded.into())
+        );
+        assert_eq!(market.event.data(), &event_before[..]);
+        assert_eq!(market.balance(&bob), 1_000);
+    }
+
+    #[test]
+    fn test_sells_make_room_under_the_cap() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = capped_market(&program_id, 100, &[&alice]);
+        market.buy(&mut alice, 0, 100).unwrap();
+
+        market.sell(&mut alice, 0, 40).unwrap();
+
+        market.buy(&mut alice, 1, 40).unwrap();
+        assert_eq!(market.event_state().total_pool_amount, 100);
+    }
+}
+
+#[cfg(test)]
+mod exposure_cap_tests {
+    use super::*;
+    use crate::test_utils::{event_params, Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [59; 32];
+
+    fn exposure_market(program_id: &Pubkey, cap: u64, betters: &[&TestAccount]) -> Market {
+        let balances: Vec<(Pubkey, u64)> = betters.iter().map(|b| (b.key(), 1_000)).collect();
+        let params = PredictionEventParams {
+            max_outcome_exposure: cap,
+            ..event_params(EVENT_ID, 100, 2)
+        };
+        Market::with_params(program_id, params, &balances)
+    }
+
+    #[test]
+    fn test_bet_past_an_outcomes_cap_is_rejected_whole() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = exposure_market(&program_id, 100, &[&alice, &bob]);
+        market.buy(&mut alice, 0, 70).unwrap();
+        let event_before = market.event.data().to_vec();
+
+        assert_eq!(
+            market.buy(&mut bob, 0, 31),
+            Err(PredictionMarketError::ExposureCapReached.into())
+        );
+        assert_eq!(market.event.data(), &event_before[..]);
+        assert_eq!(market.balance(&bob), 1_000);
+
+        // up to the cap exactly, and the other outcome has a cap of its own
+        market.buy(&mut bob, 0, 30).unwrap();
+        market.buy(&mut bob, 1, 100).unwrap();
+        let event = market.event_state();
+        assert_eq!(event.outcomes[0].total_amount, 100);
+        assert_eq!(event.outcomes[1].total_amount, 100);
+    }
+
+    #[test]
+    fn test_zero_cap_is_unlimited() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = exposure_market(&program_id, 0, &[&alice]);
+
+        market.buy(&mut alice, 0, 1_000).unwrap();
+
+        assert_eq!(market.event_state().outcomes[0].total_amount, 1_000);
+    }
+}
+
+#[cfg(test)]
+mod outcome_label_tests {
+    use super::*;
+    use crate::query::process_query_event;
+    use crate::test_utils::{
+        config_account, event_account, event_params, token_account, Market, TestAccount,
+    };
+
+    const EVENT_ID: [u8; 32] = [60; 32];
+
+    fn labelled(labels: &[&str]) -> PredictionEventParams {
+        PredictionEventParams {
+            labels: labels.iter().map(|l| l.to_string()).collect(),
+            ..event_params(EVENT_ID, 100, 2)
+        }
+    }
+
+    #[test]
+    fn test_one_label_per_outcome_is_kept_and_queried() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market =
+            Market::with_params(&program_id, labelled(&["Yes", "No"]), &[(alice.key(), 100)]);
+
+        // betting still goes by outcome id
+        market.buy(&mut alice, 1, 40).unwrap();
+
+        let summary = process_query_event(&program_id, &market.event.info(), EVENT_ID).unwrap();
+        assert_eq!(summary.outcome_labels, vec!["Yes", "No"]);
+        assert_eq!(summary.outcome_totals, vec![0, 40]);
+    }
+
+    #[test]
+    fn test_mismatched_or_oversized_labels_are_rejected() {
+        let program_id = Pubkey::new_unique();
+        let too_long = "x".repeat(MAX_LABEL_LEN + 1);
+
+        for labels in [
+            vec!["Yes"],
+            vec!["Yes", "No", "Maybe"],
+            vec!["Yes", ""],
+            vec!["Yes", too_long.as_str()],
+        ] {
+            let mut event = event_account(&program_id, &EVENT_ID);
+            let mut creator = TestAccount::new(&program_id, &[]).signer();
+            let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+            let mut token = token_account(&program_id, &[]);
+
+            assert_eq!(
+                process_create_event(
+                    &program_id,
+                    &event.info(),
+                    &creator.info(),
+                    &token.info(),
+                    &config.info(),
+                    labelled(&labels),
+                ),
+                Err(PredictionMarketError::InvalidOutcomeLabels.into()),
+                "{:?}",
+                labels
+            );
+            assert!(event.data().is_empty());
+        }
+
+        // a label of exactly the limit is fine, as is no labels at all
+        let at_limit = "x".repeat(MAX_LABEL_LEN);
+        assert_eq!(validate_labels(&["Yes".into(), at_limit], 2), Ok(()));
+        assert_eq!(validate_labels(&[], 2), Ok(()));
+    }
+}
+
+#[cfg(test)]
+mod min_participants_tests {
+    use super::*;
+    use crate::test_utils::{event_params, Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [67; 32];
+
+    // alice on outcome 0, `others` bettors on outcome 1, resolved to 0
+    fn resolve_with(
+        program_id: &Pubkey,
+        min_participants: u32,
+        others: usize,
+    ) -> (Market, TestAccount, Vec<TestAccount>) {
+        let mut alice = TestAccount::new(program_id, &[]).signer();
+        let mut others: Vec<TestAccount> = (0..others)
+            .map(|_| TestAccount::new(program_id, &[]).signer())
+            .collect();
+        let balances: Vec<(Pubkey, u64)> = others
+            .iter()
+            .chain([&alice])
+            .map(|better| (better.key(), 100))
+            .collect();
+        let params = PredictionEventParams {
+            min_participants,
+            ..event_params(EVENT_ID, 100, 2)
+        };
+        let mut market = Market::with_params(program_id, params, &balances);
+        market.buy(&mut alice, 0, 30).unwrap();
+        // betting on both outcomes still counts once
+        market.buy(&mut alice, 1, 10).unwrap();
+        for better in &mut others {
+            market.buy(better, 1, 20).unwrap();
+        }
+
+        process_resolve_event(
+            program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+            0,
+        )
+        .unwrap();
+        (market, alice, others)
+    }
+
+    #[test]
+    fn test_below_the_minimum_resolves_void() {
+        let program_id = Pubkey::new_unique();
+        let (mut market, mut alice, mut others) = resolve_with(&program_id, 3, 1);
+
+        let event = market.event_state();
+        assert_eq!(event.bettor_count(), 2);
+        assert_eq!(event.status, EventStatus::Resolved);
+        assert_eq!(event.winning_outcome, None);
+
+        // every stake is refunded
+        market.claim(&mut alice).unwrap();
+        market.claim(&mut others[0]).unwrap();
+        assert_eq!(market.balance(&alice), 100);
+        assert_eq!(market.balance(&others[0]), 100);
+    }
+
+    #[test]
+    fn test_at_the_minimum_resolves_normally() {
+        let program_id = Pubkey::new_unique();
+        let (mut market, mut alice, mut others) = resolve_with(&program_id, 3, 2);
+
+        assert_eq!(market.event_state().bettor_count(), 3);
+        assert_eq!(market.event_state().winning_outcome, Some(0));
+
+        market.claim(&mut alice).unwrap();
+        assert_eq!(market.balance(&alice), 60 + 80);
+        assert_eq!(
+            market.claim(&mut others[0]),
+            Err(PredictionMarketError::NoWinningPosition.into())
+        );
+    }
+}
+
+#[cfg(test)]
+mod lmsr_tests {
+    use super::*;
+    use crate::test_utils::{
+        config_account, event_account, event_params, token_account, Market, TestAccount,
+    };
+
+    const EVENT_ID: [u8; 32] = [38; 32];
+    const B: u64 = 1_000;
+
+    fn lmsr_market(program_id: &Pubkey, betters: &[&TestAccount]) -> Market {
+        let balances: Vec<(Pubkey, u64)> = betters.iter().map(|b| (b.key(), 10_000)).collect();
+        let params = PredictionEventParams {
+            market_type: MarketType::Lmsr { b: B },
+            ..event_params(EVENT_ID, 100, 2)
+        };
+        Market::with_params(program_id, params, &balances)
+    }
+
+    fn resolve(program_id: &Pubkey, market: &mut Market, outcome: u8) {
+        process_resolve_event(
+            program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+            outcome,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_buy_debits_the_lmsr_price_of_the_shares() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = lmsr_market(&program_id, &[&alice]);
+
+        // b·(ln(1 + e) - ln2) = 620.11
+        market.buy(&mut alice, 0, 1_000).unwrap();
+
+        let event = market.event_state();
+        assert_eq!(market.balance(&alice), 10_000 - 621);
+        assert_eq!(event.total_pool_amount, 621);
+        assert_eq!(event.outcomes[0].total_amount, 1_000);
+        assert_eq!(net_stake(&event.outcomes[0], &alice.key()), 1_000);
+
+        // the favourite got dearer, the other outcome cheaper
+        market.buy(&mut alice, 1, 1_000).unwrap();
+        assert_eq!(market.balance(&alice), 10_000 - 621 - 380);
+    }
+
+    #[test]
+    fn test_selling_refunds_the_lmsr_price_without_profit() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = lmsr_market(&program_id, &[&alice]);
+        market.buy(&mut alice, 0, 1_000).unwrap();
+
+        market.sell(&mut alice, 0, 400).unwrap();
+        market.sell(&mut alice, 0, 600).unwrap();
+
+        let event = market.event_state();
+        assert!(market.balance(&alice) <= 10_000);
+        assert_eq!(event.outcomes[0].total_amount, 0);
+        assert_eq!(event.total_pool_amount, 10_000 - market.balance(&alice));
+    }
+
+    #[test]
+    fn test_winning_shares_pay_a_token_each_as_far_as_the_pool_reaches() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = lmsr_market(&program_id, &[&alice, &bob]);
+        market.buy(&mut alice, 0, 1_000).unwrap();
+        market.buy(&mut bob, 1, 1_000).unwrap();
+        assert_eq!(market.event_state().total_pool_amount, 1_001);
+        resolve(&program_id, &mut market, 0);
+
+        market.claim(&mut alice).unwrap();
+
+        assert_eq!(market.balance(&alice), 10_000 - 621 + 1_000);
+
+        // alone on the winning side, the pool only covers part of the shares
+        let mut carol = TestAccount::new(&program_id, &[]).signer();
+        let mut market = lmsr_market(&program_id, &[&carol]);
+        market.buy(&mut carol, 0, 1_000).unwrap();
+        resolve(&program_id, &mut market, 0);
+
+        market.claim(&mut carol).unwrap();
+
+        assert_eq!(market.balance(&carol), 10_000);
+    }
+
+    #[test]
+    fn test_void_event_refunds_what_was_paid() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = lmsr_market(&program_id, &[&alice, &bob]);
+        market.buy(&mut alice, 0, 1_000).unwrap();
+        market.buy(&mut bob, 1, 1_000).unwrap();
+        process_resolve_void(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+        )
+        .unwrap();
+
+        market.claim(&mut alice).unwrap();
+        market.claim(&mut bob).unwrap();
+
+        assert_eq!(market.balance(&alice), 10_000);
+        assert_eq!(market.balance(&bob), 10_000);
+    }
+
+    #[test]
+    fn test_invalid_liquidity_is_rejected() {
+        let program_id = Pubkey::new_unique();
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+        let mut token = token_account(&program_id, &[]);
+
+        for b in [0, math::MAX_LMSR_B + 1] {
+            let mut event_account = event_account(&program_id, &EVENT_ID);
+            let mut creator = TestAccount::new(&program_id, &[]).signer();
+            let params = PredictionEventParams {
+                market_type: MarketType::Lmsr { b },
+                ..event_params(EVENT_ID, 100, 2)
+            };
+
+            assert_eq!(
+                process_create_event(
+                    &program_id,
+                    &event_account.info(),
+                    &creator.info(),
+                    &token.info(),
+                    &config.info(),
+                    params,
+                ),
+                Err(PredictionMarketError::InvalidMarketType.into())
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod ownership_transfer_tests {
+    use super::*;
+    use crate::test_utils::{Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [39; 32];
+
+    fn transfer(
+        program_id: &Pubkey,
+        event: &mut TestAccount,
+        signer: &mut TestAccount,
+        to: Pubkey,
+    ) -> ProgramResult {
+        process_transfer_event_ownership(program_id, &event.info(), &signer.info(), EVENT_ID, to)
+    }
+
+    #[test]
+    fn test_new_owner_resolves_and_old_one_cannot() {
+        let program_id = Pubkey::new_unique();
+        let mut dao = TestAccount::new(&program_id, &[]).signer();
+        let Market {
+            mut event,
+            creator: mut old_creator,
+            ..
+        } = Market::new(&program_id, EVENT_ID, &[]);
+
+        transfer(&program_id, &mut event, &mut old_creator, dao.key()).unwrap();
+
+        assert_eq!(
+            process_resolve_event(&program_id, &event.info(), &old_creator.info(), EVENT_ID, 1),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        let back = old_creator.key();
+        assert_eq!(
+            transfer(&program_id, &mut event, &mut old_creator, back),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        process_resolve_event(&program_id, &event.info(), &dao.info(), EVENT_ID, 1).unwrap();
+
+        let resolved = event_store::load_event(&event.info(), &EVENT_ID).unwrap();
+        assert_eq!(resolved.creator, dao.key());
+        assert_eq!(resolved.winning_outcome, Some(1));
+    }
+
+    #[test]
+    fn test_only_the_signing_creator_can_transfer() {
+        let program_id = Pubkey::new_unique();
+        let mut market = Market::new(&program_id, EVENT_ID, &[]);
+        let mut stranger = TestAccount::new(&program_id, &[]).signer();
+        let mut unsigned_creator =
+            TestAccount::new(&program_id, &[]).with_key(market.creator.key());
+
+        for signer in [&mut stranger, &mut unsigned_creator] {
+            assert_eq!(
+                transfer(&program_id, &mut market.event, signer, Pubkey::new_unique()),
+                Err(ProgramError::MissingRequiredSignature)
+            );
+        }
+    }
+
+    #[test]
+    fn test_finalized_events_keep_their_creator() {
+        let program_id = Pubkey::new_unique();
+        let mut market = Market::new(&program_id, EVENT_ID, &[]);
+        process_resolve_void(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+        )
+        .unwrap();
+
+        assert_eq!(
+            transfer(
+                &program_id,
+                &mut market.event,
+                &mut market.creator,
+                Pubkey::new_unique()
+            ),
+            Err(PredictionMarketError::EventFinalized.into())
+        );
+    }
+}
+
+#[cfg(test)]
+mod slippage_tests {
+    use super::*;
+    use crate::math::lmsr_buy_cost;
+    use crate::test_utils::{event_params, Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [40; 32];
+
+    fn min_payout(bps: u64) -> SlippageLimits {
+        SlippageLimits {
+            min_expected_payout_bps: bps,
+            max_cost: 0,
+        }
+    }
+
+    // 100 on each side: another 100 on outcome 0 would pay 300 back, 1.5x
+    fn balanced_market(
+        program_id: &Pubkey,
+        alice: &mut TestAccount,
+        bob: &mut TestAccount,
+    ) -> Market {
+        let mut market = Market::new(
+            program_id,
+            EVENT_ID,
+            &[(alice.key(), 1_000), (bob.key(), 1_000)],
+        );
+        market.buy(alice, 0, 100).unwrap();
+        market.buy(bob, 1, 100).unwrap();
+        market
+    }
+
+    #[test]
+    fn test_bet_moved_by_an_earlier_bet_is_rejected() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = balanced_market(&program_id, &mut alice, &mut bob);
+
+        // bob lands first on the same side, diluting alice to 400 / 300
+        market.buy(&mut bob, 0, 100).unwrap();
+        let event_before = market.event.data().to_vec();
+
+        assert_eq!(
+            market.buy_with_slippage(&mut alice, 0, 100, min_payout(15_000)),
+            Err(PredictionMarketError::SlippageExceeded.into())
+        );
+        assert_eq!(market.event.data(), &event_before[..]);
+        assert_eq!(market.balance(&alice), 900);
+    }
+
+    #[test]
+    fn test_bet_within_its_tolerance_goes_through() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = balanced_market(&program_id, &mut alice, &mut bob);
+
+        market.buy(&mut bob, 0, 100).unwrap();
+
+        market
+            .buy_with_slippage(&mut alice, 0, 100, min_payout(13_000))
+            .unwrap();
+        assert_eq!(market.event_state().outcomes[0].total_amount, 300);
+    }
+
+    #[test]
+    fn test_lmsr_bet_is_bounded_by_max_cost() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let params = PredictionEventParams {
+            market_type: MarketType::Lmsr { b: 1_000 },
+            ..event_params(EVENT_ID, 100, 2)
+        };
+        let mut market = Market::with_params(
+            &program_id,
+            params,
+            &[(alice.key(), 10_000), (bob.key(), 10_000)],
+        );
+        let quote =
+            lmsr_buy_cost(&market.event_state().outcome_quantities(), 0, 500, 1_000).unwrap();
+        let limit = SlippageLimits {
+            min_expected_payout_bps: 0,
+            max_cost: quote,
+        };
+
+        market.buy(&mut bob, 0, 500).unwrap();
+        assert_eq!(
+            market.buy_with_slippage(&mut alice, 0, 500, limit),
+            Err(PredictionMarketError::SlippageExceeded.into())
+        );
+
+        market.sell(&mut bob, 0, 500).unwrap();
+        market.buy_with_slippage(&mut alice, 0, 500, limit).unwrap();
+        assert_eq!(market.balance(&alice), 10_000 - quote);
+    }
+
+    #[test]
+    fn test_pool_bet_cost_is_unmoved_by_earlier_bets() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = balanced_market(&program_id, &mut alice, &mut bob);
+        let limit = SlippageLimits {
+            min_expected_payout_bps: 0,
+            max_cost: 100,
+        };
+
+        // a pool bet costs its stake however the odds moved, so `max_cost`
+        // only ever rejects a stake above it
+        market.buy(&mut bob, 0, 500).unwrap();
+        market.buy_with_slippage(&mut alice, 0, 100, limit).unwrap();
+        assert_eq!(
+            market.buy_with_slippage(&mut alice, 0, 101, limit),
+            Err(PredictionMarketError::SlippageExceeded.into())
+        );
+        assert_eq!(market.balance(&alice), 800);
+    }
+}
+
+#[cfg(test)]
+mod sweep_unclaimed_tests {
+    use super::*;
+    use crate::test_utils::{balance_of, set_block_height, Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [41; 32];
+
+    // outcome 0 wins a pool of 10 backed 1 : 2, so alice is owed 3 and bob 6,
+    // leaving 1 of rounding dust in escrow
+    fn resolved_market(
+        program_id: &Pubkey,
+        alice: &mut TestAccount,
+        bob: &mut TestAccount,
+        carol: &mut TestAccount,
+    ) -> Market {
+        let mut market = Market::new(
+            program_id,
+            EVENT_ID,
+            &[(alice.key(), 100), (bob.key(), 100), (carol.key(), 100)],
+        );
+        market.buy(alice, 0, 1).unwrap();
+        market.buy(bob, 0, 2).unwrap();
+        market.buy(carol, 1, 7).unwrap();
+        process_resolve_event(
+            program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+            0,
+        )
+        .unwrap();
+        market
+    }
+
+    fn sweep(program_id: &Pubkey, market: &mut Market) -> Result<u64, ProgramError> {
+        process_sweep_unclaimed(
+            program_id,
+            &market.event.info(),
+            &market.token.info(),
+            &market.config.info(),
+            EVENT_ID,
+        )
+    }
+
+    fn treasury(market: &mut Market) -> Pubkey {
+        config::load_config(&market.config.info()).unwrap().treasury
+    }
+
+    #[test]
+    fn test_claims_before_the_deadline_succeed() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut carol = TestAccount::new(&program_id, &[]).signer();
+        let mut market = resolved_market(&program_id, &mut alice, &mut bob, &mut carol);
+        set_block_height(market.event_state().claim_deadline_height());
+
+        assert_eq!(
+            sweep(&program_id, &mut market),
+            Err(PredictionMarketError::ClaimDeadlineNotReached.into())
+        );
+
+        market.claim(&mut alice).unwrap();
+        assert_eq!(market.balance(&alice), 102);
+    }
+
+    #[test]
+    fn test_claims_after_the_sweep_fail() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut carol = TestAccount::new(&program_id, &[]).signer();
+        let mut market = resolved_market(&program_id, &mut alice, &mut bob, &mut carol);
+        set_block_height(market.event_state().claim_deadline_height() + 1);
+
+        sweep(&program_id, &mut market).unwrap();
+
+        assert_eq!(market.event_state().status, EventStatus::Settled);
+        assert_eq!(
+            market.claim(&mut bob),
+            Err(PredictionMarketError::ClaimWindowClosed.into())
+        );
+        assert_eq!(market.balance(&bob), 98);
+        assert_eq!(
+            sweep(&program_id, &mut market),
+            Err(PredictionMarketError::InvalidStatusTransition.into())
+        );
+    }
+
+    #[test]
+    fn test_sweep_moves_exactly_the_residual() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut carol = TestAccount::new(&program_id, &[]).signer();
+        let mut market = resolved_market(&program_id, &mut alice, &mut bob, &mut carol);
+        market.claim(&mut alice).unwrap();
+
+        // tokens sent to the escrow directly aren't in any stored total
+        let escrow = escrow_address(&market.event.key(), &EVENT_ID);
+        move_balance(&market.token.info(), &carol.key(), &escrow, 5).unwrap();
+        set_block_height(market.event_state().claim_deadline_height() + 1);
+
+        // bob's unclaimed 6, the dust and the stray 5
+        assert_eq!(sweep(&program_id, &mut market), Ok(12));
+
+        let treasury = treasury(&mut market);
+        assert_eq!(balance_of(&market.token, &treasury), 12);
+        assert_eq!(balance_of(&market.token, &escrow), 0);
+    }
+}
+
+#[cfg(test)]
+mod dispute_tests {
+    use super::*;
+    use crate::test_utils::{event_params, set_block_height, Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [42; 32];
+
+    // expiry 100 and a 10 block window: disputable up to height 110
+    fn resolved_market(
+        program_id: &Pubkey,
+        alice: &mut TestAccount,
+        bob: &mut TestAccount,
+    ) -> Market {
+        let params = PredictionEventParams {
+            dispute_window: 10,
+            ..event_params(EVENT_ID, 100, 2)
+        };
+        let mut market =
+            Market::with_params(program_id, params, &[(alice.key(), 100), (bob.key(), 100)]);
+        market.buy(alice, 0, 50).unwrap();
+        market.buy(bob, 1, 50).unwrap();
+        set_block_height(100);
+        process_resolve_event(
+            program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+            0,
+        )
+        .unwrap();
+        market
+    }
+
+    fn admin_of(market: &mut Market) -> TestAccount {
+        let admin = config::load_config(&market.config.info()).unwrap().admin;
+        TestAccount::new(&market.event.owner, &[])
+            .signer()
+            .with_key(admin)
+    }
+
+    fn dispute(
+        program_id: &Pubkey,
+        market: &mut Market,
+        disputer: &mut TestAccount,
+    ) -> Result<(), ProgramError> {
+        process_dispute_resolution(
+            program_id,
+            &market.event.info(),
+            &disputer.info(),
+            &market.config.info(),
+            EVENT_ID,
+        )
+    }
+
+    #[test]
+    fn test_claims_wait_for_the_dispute_window() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = resolved_market(&program_id, &mut alice, &mut bob);
+
+        set_block_height(110);
+        assert_eq!(
+            market.claim(&mut alice),
+            Err(PredictionMarketError::DisputeWindowOpen.into())
+        );
+
+        set_block_height(111);
+        market.claim(&mut alice).unwrap();
+        assert_eq!(market.balance(&alice), 150);
+    }
+
+    #[test]
+    fn test_dispute_reopens_resolution() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = resolved_market(&program_id, &mut alice, &mut bob);
+        let mut admin = admin_of(&mut market);
+
+        set_block_height(105);
+        dispute(&program_id, &mut market, &mut admin).unwrap();
+
+        let state = market.event_state();
+        assert_eq!(state.status, EventStatus::Disputed);
+        assert_eq!(state.winning_outcome, None);
+        set_block_height(200);
+        assert_eq!(
+            market.claim(&mut alice),
+            Err(PredictionMarketError::EventNotResolved.into())
+        );
+
+        // the new resolution gets a window of its own
+        process_resolve_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+            1,
+        )
+        .unwrap();
+        assert_eq!(market.event_state().dispute_until, Some(210));
+        assert_eq!(
+            market.claim(&mut bob),
+            Err(PredictionMarketError::DisputeWindowOpen.into())
+        );
+
+        set_block_height(211);
+        market.claim(&mut bob).unwrap();
+        assert_eq!(market.balance(&bob), 150);
+    }
+
+    #[test]
+    fn test_disputed_event_expires_once_not_resolved_again_in_time() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = resolved_market(&program_id, &mut alice, &mut bob);
+        let mut admin = admin_of(&mut market);
+
+        set_block_height(105);
+        dispute(&program_id, &mut market, &mut admin).unwrap();
+        // the event's resolution deadline is 0 blocks, counted from the dispute
+        assert_eq!(market.event_state().resolution_deadline_height(), 105);
+        assert_eq!(
+            process_cancel_event(
+                &program_id,
+                &market.event.info(),
+                &market.creator.info(),
+                EVENT_ID
+            ),
+            Err(PredictionMarketError::InvalidStatusTransition.into())
+        );
+        assert_eq!(
+            process_expire_unresolved_event(&program_id, &market.event.info(), EVENT_ID),
+            Err(PredictionMarketError::ResolutionDeadlineNotReached.into())
+        );
+
+        set_block_height(106);
+        process_expire_unresolved_event(&program_id, &market.event.info(), EVENT_ID).unwrap();
+
+        assert_eq!(market.event_state().status, EventStatus::Cancelled);
+        market.claim(&mut alice).unwrap();
+        assert_eq!(market.balance(&alice), 100);
+    }
+
+    #[test]
+    fn test_only_the_admin_disputes_and_only_in_the_window() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = resolved_market(&program_id, &mut alice, &mut bob);
+        let mut admin = admin_of(&mut market);
+
+        assert_eq!(
+            dispute(&program_id, &mut market, &mut bob),
+            Err(ProgramError::IncorrectAuthority)
+        );
+
+        set_block_height(111);
+        assert_eq!(
+            dispute(&program_id, &mut market, &mut admin),
+            Err(PredictionMarketError::DisputeWindowClosed.into())
+        );
+        assert_eq!(market.event_state().status, EventStatus::Resolved);
+    }
+}
+
+#[cfg(test)]
+mod creator_bond_tests {
+    use super::*;
+    use crate::test_utils::{
+        balance_of, event_account, event_params, load_predictions, set_block_height, token_account,
+        TestAccount,
+    };
+
+    const EVENT_ID: [u8; 32] = [43; 32];
+    const BOND: u64 = 30;
+
+    struct Bonded {
+        event: TestAccount,
+        creator: TestAccount,
+        token: TestAccount,
+        config: TestAccount,
+    }
+
+    impl Bonded {
+        fn bond(&self) -> u64 {
+            load_predictions(&self.event).predictions[0].creator_bond
+        }
+
+        fn reclaim(&mut self, program_id: &Pubkey) -> Result<(), ProgramError> {
+            process_reclaim_creator_bond(
+                program_id,
+                &self.event.info(),
+                &self.token.info(),
+                &self.creator.info(),
+                EVENT_ID,
+            )
+        }
+    }
+
+    // expires at height 100 with no grace, so resolving at 100 is in time
+    fn bonded_event(program_id: &Pubkey) -> Bonded {
+        let mut event = event_account(program_id, &EVENT_ID);
+        let mut creator = TestAccount::new(program_id, &[]).signer();
+        let mut token = token_account(program_id, &[(creator.key(), 50)]);
+        let mut config = config::ProgramConfig::new(Pubkey::new_unique());
+        config.creator_bond = BOND;
+        let mut config = TestAccount::new(program_id, &borsh::to_vec(&config).unwrap());
+
+        process_create_event(
+            program_id,
+            &event.info(),
+            &creator.info(),
+            &token.info(),
+            &config.info(),
+            event_params(EVENT_ID, 100, 2),
+        )
+        .unwrap();
+        process_activate_event(program_id, &event.info(), &creator.info(), EVENT_ID).unwrap();
+
+        Bonded {
+            event,
+            creator,
+            token,
+            config,
+        }
+    }
+
+    #[test]
+    fn test_creation_locks_the_bond_in_escrow() {
+        let program_id = Pubkey::new_unique();
+        let bonded = bonded_event(&program_id);
+
+        let escrow = escrow_address(&bonded.event.key(), &EVENT_ID);
+        assert_eq!(bonded.bond(), BOND);
+        assert_eq!(balance_of(&bonded.token, &bonded.creator.key()), 20);
+        assert_eq!(balance_of(&bonded.token, &escrow), BOND);
+    }
+
+    #[test]
+    fn test_creator_without_the_bond_cannot_create() {
+        let program_id = Pubkey::new_unique();
+        let mut bonded = bonded_event(&program_id);
+        let mut event = event_account(&program_id, &EVENT_ID);
+        let mut poor = TestAccount::new(&program_id, &[]).signer();
+
+        assert_eq!(
+            process_create_event(
+                &program_id,
+                &event.info(),
+                &poor.info(),
+                &bonded.token.info(),
+                &bonded.config.info(),
+                event_params(EVENT_ID, 100, 2),
+            ),
+            Err(PredictionMarketError::InsufficientBond.into())
+        );
+    }
+
+    #[test]
+    fn test_admin_sets_the_bond() {
+        let program_id = Pubkey::new_unique();
+        let mut admin = TestAccount::new(&program_id, &[]).signer();
+        let mut config = TestAccount::new(
+            &program_id,
+            &borsh::to_vec(&config::ProgramConfig::new(admin.key())).unwrap(),
+        );
+        let mut data = vec![36];
+        data.extend(borsh::to_vec(&config::SetCreatorBondParams { amount: BOND }).unwrap());
+
+        let mut stranger = TestAccount::new(&program_id, &[]).signer();
+        assert_eq!(
+            process_instruction(&program_id, &[config.info(), stranger.info()], &data),
+            Err(ProgramError::IncorrectAuthority)
+        );
+
+        process_instruction(&program_id, &[config.info(), admin.info()], &data).unwrap();
+        assert_eq!(
+            config::load_config(&config.info()).unwrap().creator_bond,
+            BOND
+        );
+    }
+
+    #[test]
+    fn test_timely_resolution_returns_the_bond() {
+        let program_id = Pubkey::new_unique();
+        let mut bonded = bonded_event(&program_id);
+        set_block_height(100);
+
+        // not until the event is resolved
+        assert_eq!(
+            bonded.reclaim(&program_id),
+            Err(PredictionMarketError::EventNotResolved.into())
+        );
+
+        process_resolve_event(
+            &program_id,
+            &bonded.event.info(),
+            &bonded.creator.info(),
+            EVENT_ID,
+            0,
+        )
+        .unwrap();
+        bonded.reclaim(&program_id).unwrap();
+
+        let escrow = escrow_address(&bonded.event.key(), &EVENT_ID);
+        assert_eq!(balance_of(&bonded.token, &bonded.creator.key()), 50);
+        assert_eq!(balance_of(&bonded.token, &escrow), 0);
+        assert_eq!(
+            bonded.reclaim(&program_id),
+            Err(PredictionMarketError::BondNotReturnable.into())
+        );
+    }
+
+    #[test]
+    fn test_late_resolution_forfeits_the_bond() {
+        let program_id = Pubkey::new_unique();
+        let mut bonded = bonded_event(&program_id);
+        set_block_height(101);
+
+        process_resolve_event(
+            &program_id,
+            &bonded.event.info(),
+            &bonded.creator.info(),
+            EVENT_ID,
+            0,
+        )
+        .unwrap();
+
+        assert_eq!(bonded.bond(), 0);
+        assert_eq!(
+            bonded.reclaim(&program_id),
+            Err(PredictionMarketError::BondNotReturnable.into())
+        );
+    }
+
+    #[test]
+    fn test_expired_event_bond_is_swept_to_the_treasury() {
+        let program_id = Pubkey::new_unique();
+        let mut bonded = bonded_event(&program_id);
+        set_block_height(101);
+
+        process_expire_unresolved_event(&program_id, &bonded.event.info(), EVENT_ID).unwrap();
+        assert_eq!(
+            bonded.reclaim(&program_id),
+            Err(PredictionMarketError::BondNotReturnable.into())
+        );
+
+        let deadline = load_predictions(&bonded.event).predictions[0].claim_deadline_height();
+        set_block_height(deadline + 1);
+        let swept = process_sweep_unclaimed(
+            &program_id,
+            &bonded.event.info(),
+            &bonded.token.info(),
+            &bonded.config.info(),
+            EVENT_ID,
+        );
+
+        assert_eq!(swept, Ok(BOND));
+        let treasury = config::load_config(&bonded.config.info()).unwrap().treasury;
+        assert_eq!(balance_of(&bonded.token, &treasury), BOND);
+    }
+}
+
+#[cfg(test)]
+mod creator_fee_tests {
+    use super::*;
+    use crate::test_utils::{
+        balance_of, config_account, event_account, event_params, token_account, Market, Rng,
+        TestAccount,
+    };
+
+    const EVENT_ID: [u8; 32] = [44; 32];
+
+    fn fee_market(program_id: &Pubkey, fee_bps: u16, balances: &[(Pubkey, u64)]) -> Market {
+        let params = PredictionEventParams {
+            creator_fee_bps: fee_bps,
+            ..event_params(EVENT_ID, 100, 2)
+        };
+        Market::with_params(program_id, params, balances)
+    }
+
+    fn resolve(program_id: &Pubkey, market: &mut Market, winning_outcome: u8) {
+        process_resolve_event(
+            program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+            winning_outcome,
+        )
+        .unwrap();
+    }
+
+    fn claim_as_creator(program_id: &Pubkey, market: &mut Market) -> Result<(), ProgramError> {
+        process_claim_winnings(
+            program_id,
+            &market.event.info(),
+            &market.token.info(),
+            &market.creator.info(),
+            EVENT_ID,
+            false,
+        )
+    }
+
+    #[test]
+    fn test_fee_above_the_cap_is_rejected() {
+        let program_id = Pubkey::new_unique();
+        let mut event = event_account(&program_id, &EVENT_ID);
+        let mut creator = TestAccount::new(&program_id, &[]).signer();
+        let mut token = token_account(&program_id, &[]);
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+        let params = PredictionEventParams {
+            creator_fee_bps: math::MAX_CREATOR_FEE_BPS + 1,
+            ..event_params(EVENT_ID, 100, 2)
+        };
+
+        assert_eq!(
+            process_create_event(
+                &program_id,
+                &event.info(),
+                &creator.info(),
+                &token.info(),
+                &config.info(),
+                params,
+            ),
+            Err(PredictionMarketError::CreatorFeeTooHigh.into())
+        );
+    }
+
+    #[test]
+    fn test_creator_takes_the_fee_before_winners_split_the_pool() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = fee_market(&program_id, 500, &[(alice.key(), 100), (bob.key(), 100)]);
+        market.buy(&mut alice, 0, 30).unwrap();
+        market.buy(&mut bob, 1, 70).unwrap();
+        resolve(&program_id, &mut market, 0);
+
+        // 5% of the losing 70 is 3.5, rounded down
+        assert_eq!(market.event_state().creator_fee(), 3);
+        claim_as_creator(&program_id, &mut market).unwrap();
+        market.claim(&mut alice).unwrap();
+
+        let escrow = escrow_address(&market.event.key(), &EVENT_ID);
+        assert_eq!(balance_of(&market.token, &market.creator.key()), 3);
+        assert_eq!(market.balance(&alice), 70 + 97);
+        assert_eq!(balance_of(&market.token, &escrow), 0);
+    }
+
+    #[test]
+    fn test_without_a_fee_the_creator_is_owed_nothing() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = fee_market(&program_id, 0, &[(alice.key(), 100), (bob.key(), 100)]);
+        market.buy(&mut alice, 0, 30).unwrap();
+        market.buy(&mut bob, 1, 70).unwrap();
+        resolve(&program_id, &mut market, 0);
+
+        assert_eq!(
+            claim_as_creator(&program_id, &mut market),
+            Err(PredictionMarketError::NoWinningPosition.into())
+        );
+        market.claim(&mut alice).unwrap();
+        assert_eq!(market.balance(&alice), 70 + 100);
+    }
+
+    #[test]
+    fn test_fee_payouts_and_dust_add_up_to_the_pool() {
+        let program_id = Pubkey::new_unique();
+        let mut rng = Rng(0xfee);
+
+        for _ in 0..100 {
+            let fee_bps = rng.below(math::MAX_CREATOR_FEE_BPS as u64 + 1) as u16;
+            let mut betters: Vec<TestAccount> = (0..2 + rng.below(5))
+                .map(|_| TestAccount::new(&program_id, &[]).signer())
+                .collect();
+            let balances: Vec<(Pubkey, u64)> =
+                betters.iter().map(|better| (better.key(), 1_000)).collect();
+            let mut market = fee_market(&program_id, fee_bps, &balances);
+
+            for better in &mut betters {
+                let outcome_id = rng.below(2) as u8;
+                market.buy(better, outcome_id, 1 + rng.below(999)).unwrap();
+            }
+            resolve(&program_id, &mut market, rng.below(2) as u8);
+
+            let event = market.event_state();
+            let winner = &event.outcomes[event.winning_outcome.unwrap() as usize];
+            let losing = event.total_pool_amount - winner.total_amount;
+            assert_eq!(event.creator_fee(), losing * fee_bps as u64 / 10_000);
+
+            let payouts: u64 = betters
+                .iter()
+                .map(|better| claimable_amount(&event, &better.key()).unwrap_or(0))
+                .sum();
+            let paid = event.creator_fee() + payouts;
+            assert!(paid <= event.total_pool_amount);
+
+            // each winner loses less than one token to rounding, unless
+            // nobody backed the winner and the rest is left to sweep
+            let dust = event.total_pool_amount - paid;
+            assert!(winner.total_amount == 0 || dust < winner.bets.len() as u64);
+        }
+    }
+}
+
+#[cfg(test)]
+mod auto_resolve_tests {
+    use super::*;
+    use crate::test_utils::{event_params, set_block_height, Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [46; 32];
+
+    // expires at height 100
+    fn auto_market(program_id: &Pubkey, balances: &[(Pubkey, u64)]) -> Market {
+        let params = PredictionEventParams {
+            auto_resolve: true,
+            ..event_params(EVENT_ID, 100, 2)
+        };
+        Market::with_params(program_id, params, balances)
+    }
+
+    fn auto_resolve(program_id: &Pubkey, market: &mut Market) -> ProgramResult {
+        let mut data = vec![40];
+        data.extend(
+            borsh::to_vec(&ClosePredictionEventParams {
+                unique_id: EVENT_ID,
+            })
+            .unwrap(),
+        );
+        process_instruction(
+            program_id,
+            &[market.event.info(), market.config.info()],
+            &data,
+        )
+    }
+
+    #[test]
+    fn test_clear_majority_wins_after_expiry() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = auto_market(&program_id, &[(alice.key(), 100), (bob.key(), 100)]);
+        market.buy(&mut alice, 0, 30).unwrap();
+        market.buy(&mut bob, 1, 70).unwrap();
+
+        set_block_height(99);
+        assert_eq!(
+            auto_resolve(&program_id, &mut market),
+            Err(PredictionMarketError::EventNotExpired.into())
+        );
+
+        set_block_height(100);
+        auto_resolve(&program_id, &mut market).unwrap();
+
+        let event = market.event_state();
+        assert_eq!(event.status, EventStatus::Resolved);
+        assert_eq!(event.winning_outcome, Some(1));
+        market.claim(&mut bob).unwrap();
+        assert_eq!(market.balance(&bob), 30 + 100);
+    }
+
+    #[test]
+    fn test_tie_for_the_most_stake_voids_the_event() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = auto_market(&program_id, &[(alice.key(), 100), (bob.key(), 100)]);
+        market.buy(&mut alice, 0, 50).unwrap();
+        market.buy(&mut bob, 1, 50).unwrap();
+        set_block_height(100);
+
+        auto_resolve(&program_id, &mut market).unwrap();
+
+        let event = market.event_state();
+        assert_eq!(event.status, EventStatus::Cancelled);
+        assert_eq!(event.winning_outcome, None);
+        market.claim(&mut alice).unwrap();
+        assert_eq!(market.balance(&alice), 100);
+    }
+
+    #[test]
+    fn test_events_must_opt_in() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(alice.key(), 100)]);
+        market.buy(&mut alice, 0, 50).unwrap();
+        set_block_height(100);
+
+        assert_eq!(
+            auto_resolve(&program_id, &mut market),
+            Err(PredictionMarketError::AutoResolveNotEnabled.into())
+        );
+        assert_eq!(market.event_state().status, EventStatus::Active);
+    }
+}
+
+#[cfg(test)]
+mod cashout_tests {
+    use super::*;
+    use crate::test_utils::{Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [47; 32];
+
+    // 100 on each outcome and a 10% cashout haircut
+    fn cashout_market(
+        program_id: &Pubkey,
+        alice: &mut TestAccount,
+        bob: &mut TestAccount,
+    ) -> Market {
+        let mut market = Market::new(
+            program_id,
+            EVENT_ID,
+            &[(alice.key(), 100), (bob.key(), 100)],
+        );
+        let mut config = config::ProgramConfig::new(Pubkey::new_unique());
+        config.cashout_haircut_bps = 1_000;
+        market.config = TestAccount::new(program_id, &borsh::to_vec(&config).unwrap());
+        market.buy(alice, 0, 100).unwrap();
+        market.buy(bob, 1, 100).unwrap();
+        market
+    }
+
+    fn cashout(
+        program_id: &Pubkey,
+        market: &mut Market,
+        better: &mut TestAccount,
+        amount: u64,
+    ) -> ProgramResult {
+        let mut data = vec![41];
+        data.extend(
+            borsh::to_vec(&CashoutPositionParams {
+                unique_id: EVENT_ID,
+                outcome_id: 0,
+                amount,
+            })
+            .unwrap(),
+        );
+        process_instruction(
+            program_id,
+            &[
+                market.event.info(),
+                market.token.info(),
+                better.info(),
+                market.config.info(),
+            ],
+            &data,
+        )
+    }
+
+    #[test]
+    fn test_haircut_stays_in_the_pool() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = cashout_market(&program_id, &mut alice, &mut bob);
+
+        cashout(&program_id, &mut market, &mut alice, 50).unwrap();
+
+        let event = market.event_state();
+        assert_eq!(market.balance(&alice), 45);
+        assert_eq!(event.outcomes[0].total_amount, 50);
+        assert_eq!(event.total_pool_amount, 155);
+
+        // what alice kept on outcome 0 now wins the pool, haircut included
+        process_resolve_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+            0,
+        )
+        .unwrap();
+        market.claim(&mut alice).unwrap();
+        assert_eq!(market.balance(&alice), 45 + 155);
+    }
+
+    #[test]
+    fn test_cashout_is_bounded_by_net_position() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = cashout_market(&program_id, &mut alice, &mut bob);
+        market.sell(&mut alice, 0, 40).unwrap();
+
+        assert_eq!(
+            cashout(&program_id, &mut market, &mut alice, 61),
+            Err(PredictionMarketError::InsufficientPosition.into())
+        );
+        // bob holds nothing on outcome 0
+        assert_eq!(
+            cashout(&program_id, &mut market, &mut bob, 1),
+            Err(PredictionMarketError::InsufficientPosition.into())
+        );
+        assert_eq!(market.event_state().total_pool_amount, 160);
+    }
+
+    #[test]
+    fn test_cashout_is_disabled_once_closed_or_resolved() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = cashout_market(&program_id, &mut alice, &mut bob);
+
+        process_close_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+        )
+        .unwrap();
+        assert_eq!(
+            cashout(&program_id, &mut market, &mut alice, 10),
+            Err(PredictionMarketError::EventNotActive.into())
+        );
+
+        process_resolve_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+            0,
+        )
+        .unwrap();
+        assert_eq!(
+            cashout(&program_id, &mut market, &mut alice, 10),
+            Err(PredictionMarketError::EventNotActive.into())
+        );
+        assert_eq!(market.balance(&alice), 0);
+    }
+}
+
+#[cfg(test)]
+mod cost_basis_tests {
+    use super::*;
+    use crate::test_utils::{Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [48; 32];
+
+    #[test]
+    fn test_claim_realizes_the_winning_position() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(
+            &program_id,
+            EVENT_ID,
+            &[(alice.key(), 100), (bob.key(), 100)],
+        );
+        market.buy(&mut alice, 0, 30).unwrap();
+        market.buy(&mut bob, 1, 50).unwrap();
+        market.buy(&mut alice, 0, 20).unwrap();
+        market.sell(&mut alice, 0, 10).unwrap();
+
+        let event = market.event_state();
+        assert_eq!(
+            event.cost_basis[&(alice.key(), 0)],
+            CostBasis {
+                cost_basis_total: 40,
+                realized_pnl: 0,
+            }
+        );
+
+        process_resolve_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+            0,
+        )
+        .unwrap();
+        market.claim(&mut alice).unwrap();
+
+        // the whole pool of 90 went to alice's 40
+        let event = market.event_state();
+        assert_eq!(
+            event.cost_basis[&(alice.key(), 0)],
+            CostBasis {
+                cost_basis_total: 0,
+                realized_pnl: 50,
+            }
+        );
+        assert_eq!(
+            event.cost_basis[&(bob.key(), 1)],
+            CostBasis {
+                cost_basis_total: 50,
+                realized_pnl: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_void_refund_realizes_nothing() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(alice.key(), 100)]);
+        market.buy(&mut alice, 0, 30).unwrap();
+        market.buy(&mut alice, 1, 20).unwrap();
+
+        process_cancel_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+        )
+        .unwrap();
+        market.claim(&mut alice).unwrap();
+
+        let event = market.event_state();
+        for outcome_id in [0, 1] {
+            assert_eq!(
+                event.cost_basis[&(alice.key(), outcome_id)],
+                CostBasis::default()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod prune_tests {
+    use super::*;
+    use crate::test_utils::{load_predictions, stack_event, Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [50; 32];
+    const OTHER_ID: [u8; 32] = [51; 32];
+
+    // EVENT_ID with alice's 30 on outcome 0, followed by an untouched OTHER_ID
+    fn two_event_market(program_id: &Pubkey, alice: &mut TestAccount) -> Market {
+        let mut market = Market::new(program_id, EVENT_ID, &[(alice.key(), 100)]);
+        let other = PredictionEvent {
+            unique_id: OTHER_ID,
+            ..market.event_state()
+        };
+        market.buy(alice, 0, 30).unwrap();
+        stack_event(&mut market.event, other);
+        market
+    }
+
+    fn resolve(program_id: &Pubkey, market: &mut Market, winning_outcome: u8) {
+        process_resolve_event(
+            program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+            winning_outcome,
+        )
+        .unwrap();
+    }
+
+    fn prune(program_id: &Pubkey, market: &mut Market) -> ProgramResult {
+        process_prune_event(
+            program_id,
+            &market.event.info(),
+            &market.token.info(),
+            &market.config.info(),
+            EVENT_ID,
+        )
+    }
+
+    #[test]
+    fn test_pruning_a_settled_event_keeps_the_others() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = two_event_market(&program_id, &mut alice);
+        resolve(&program_id, &mut market, 0);
+        market.claim(&mut alice).unwrap();
+        let pruned = event_store::load_event(&market.event.info(), &EVENT_ID).unwrap();
+        let other = load_predictions(&market.event).predictions[1].clone();
+        let len_before = market.event.data().len();
+
+        prune(&program_id, &mut market).unwrap();
+
+        let predictions = load_predictions(&market.event);
+        assert_eq!(predictions.predictions.len(), 1);
+        assert_eq!(
+            borsh::to_vec(&predictions.predictions[0]).unwrap(),
+            borsh::to_vec(&other).unwrap()
+        );
+        // the event's bytes and its index entry
+        assert_eq!(
+            market.event.data().len(),
+            len_before - borsh::object_length(&pruned).unwrap() - 40
+        );
+        assert_eq!(
+            event_store::load_event(&market.event.info(), &EVENT_ID).unwrap_err(),
+            PredictionMarketError::EventNotFound.into()
+        );
+    }
+
+    #[test]
+    fn test_events_still_owing_something_are_kept() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = two_event_market(&program_id, &mut alice);
+
+        assert_eq!(
+            prune(&program_id, &mut market),
+            Err(PredictionMarketError::EventNotResolved.into())
+        );
+
+        resolve(&program_id, &mut market, 0);
+        assert_eq!(
+            prune(&program_id, &mut market),
+            Err(PredictionMarketError::ClaimsOutstanding.into())
+        );
+        assert_eq!(load_predictions(&market.event).predictions.len(), 2);
+    }
+
+    #[test]
+    fn test_an_unswept_escrow_blocks_pruning() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = two_event_market(&program_id, &mut alice);
+
+        // nobody backed the winner, so nobody can claim the 30
+        resolve(&program_id, &mut market, 1);
+
+        assert_eq!(
+            prune(&program_id, &mut market),
+            Err(PredictionMarketError::EscrowNotEmpty.into())
+        );
+    }
+}
+
+#[cfg(test)]
+mod archive_tests {
+    use super::*;
+    use crate::archive::{derive_archive_address, load_archived, record_count};
+    use crate::test_utils::{load_predictions, stack_event, take_return_data, Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [84; 32];
+    const OTHER_IDS: [[u8; 32]; 2] = [[85; 32], [86; 32]];
+
+    // EVENT_ID with alice's 30 on outcome 0, followed by two untouched events
+    fn three_event_market(program_id: &Pubkey, alice: &mut TestAccount) -> Market {
+        let mut market = Market::new(program_id, EVENT_ID, &[(alice.key(), 100)]);
+        let others = OTHER_IDS.map(|unique_id| PredictionEvent {
+            unique_id,
+            ..market.event_state()
+        });
+        market.buy(alice, 0, 30).unwrap();
+        for other in others {
+            stack_event(&mut market.event, other);
+        }
+        market
+    }
+
+    fn settle(program_id: &Pubkey, market: &mut Market, alice: &mut TestAccount) {
+        process_resolve_event(
+            program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+            0,
+        )
+        .unwrap();
+        market.claim(alice).unwrap();
+    }
+
+    fn archive_account(program_id: &Pubkey) -> TestAccount {
+        TestAccount::new(program_id, &[]).with_key(derive_archive_address(program_id))
+    }
+
+    fn archive(
+        program_id: &Pubkey,
+        market: &mut Market,
+        archive: &mut TestAccount,
+    ) -> ProgramResult {
+        process_archive_event(
+            program_id,
+            &market.event.info(),
+            &market.token.info(),
+            &market.config.info(),
+            &archive.info(),
+            EVENT_ID,
+        )
+    }
+
+    #[test]
+    fn test_archived_and_remaining_events_all_decode() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = three_event_market(&program_id, &mut alice);
+        settle(&program_id, &mut market, &mut alice);
+        let archived = event_store::load_event(&market.event.info(), &EVENT_ID).unwrap();
+        let others = load_predictions(&market.event).predictions[1..].to_vec();
+        let summary = query::process_query_event(&program_id, &market.event.info(), EVENT_ID);
+        let len_before = market.event.data().len();
+        let mut archive_account = archive_account(&program_id);
+
+        archive(&program_id, &mut market, &mut archive_account).unwrap();
+
+        let predictions = load_predictions(&market.event);
+        assert_eq!(
+            borsh::to_vec(&predictions.predictions).unwrap(),
+            borsh::to_vec(&others).unwrap()
+        );
+        assert_eq!(
+            market.event.data().len(),
+            len_before - borsh::object_length(&archived).unwrap() - 40
+        );
+        assert_eq!(record_count(archive_account.data()), Ok(1));
+        assert_eq!(
+            borsh::to_vec(&load_archived(archive_account.data(), &EVENT_ID).unwrap()).unwrap(),
+            borsh::to_vec(&archived).unwrap()
+        );
+
+        let mut instruction = vec![75];
+        instruction.extend_from_slice(&EVENT_ID);
+        let mut archive_info = archive_account.info();
+        archive_info.is_writable = false;
+        process_instruction(&program_id, &[archive_info], &instruction).unwrap();
+        assert_eq!(
+            query::EventSummary::try_from_slice(&take_return_data().unwrap()).ok(),
+            summary.ok()
+        );
+    }
+
+    #[test]
+    fn test_events_still_owing_something_stay_put() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = three_event_market(&program_id, &mut alice);
+        let mut archive_account = archive_account(&program_id);
+
+        assert_eq!(
+            archive(&program_id, &mut market, &mut archive_account),
+            Err(PredictionMarketError::EventNotResolved.into())
+        );
+        assert_eq!(load_predictions(&market.event).predictions.len(), 3);
+        assert!(archive_account.data().is_empty());
+    }
+
+    #[test]
+    fn test_only_the_derived_archive_is_written() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = three_event_market(&program_id, &mut alice);
+        settle(&program_id, &mut market, &mut alice);
+        let mut impostor = TestAccount::new(&program_id, &[]);
+
+        assert_eq!(
+            archive(&program_id, &mut market, &mut impostor),
+            Err(PredictionMarketError::ArchiveAddressMismatch.into())
+        );
+        assert_eq!(load_predictions(&market.event).predictions.len(), 3);
+    }
+}
+
+#[cfg(test)]
+mod position_transfer_tests {
+    use super::*;
+    use crate::test_utils::{Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [56; 32];
+
+    fn transfer(
+        market: &mut Market,
+        holder: &mut TestAccount,
+        outcome_id: u8,
+        amount: u64,
+        recipient: Pubkey,
+    ) -> ProgramResult {
+        let program_id = market.event.owner;
+        process_transfer_position(
+            &program_id,
+            &market.event.info(),
+            &holder.info(),
+            TransferPositionParams {
+                unique_id: EVENT_ID,
+                outcome_id,
+                amount,
+                recipient,
+            },
+        )
+    }
+
+    #[test]
+    fn test_recipient_claims_the_transferred_position() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut carol = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(
+            &program_id,
+            EVENT_ID,
+            &[(alice.key(), 100), (carol.key(), 100)],
+        );
+        market.buy(&mut alice, 0, 100).unwrap();
+        market.buy(&mut carol, 1, 100).unwrap();
+
+        transfer(&mut market, &mut alice, 0, 60, bob.key()).unwrap();
+
+        let event = market.event_state();
+        assert_eq!(net_stake(&event.outcomes[0], &alice.key()), 40);
+        assert_eq!(net_stake(&event.outcomes[0], &bob.key()), 60);
+        assert_eq!(event.outcomes[0].total_amount, 100);
+        assert_eq!(event.total_pool_amount, 200);
+        assert_eq!(event.cost_basis[&(bob.key(), 0)].cost_basis_total, 60);
+        assert_eq!(market.balance(&alice), 0);
+        assert_eq!(market.balance(&bob), 0);
+
+        process_resolve_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+            0,
+        )
+        .unwrap();
+        market.claim(&mut alice).unwrap();
+        market.claim(&mut bob).unwrap();
+        assert_eq!(market.balance(&alice), 80);
+        assert_eq!(market.balance(&bob), 120);
+    }
+
+    #[test]
+    fn test_transfer_is_bounded_by_net_position() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let bob = Pubkey::new_unique();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(alice.key(), 100)]);
+        market.buy(&mut alice, 0, 100).unwrap();
+        market.sell(&mut alice, 0, 30).unwrap();
+
+        assert_eq!(
+            transfer(&mut market, &mut alice, 0, 71, bob),
+            Err(PredictionMarketError::InsufficientPosition.into())
+        );
+        assert_eq!(
+            transfer(&mut market, &mut alice, 1, 1, bob),
+            Err(PredictionMarketError::InsufficientPosition.into())
+        );
+        transfer(&mut market, &mut alice, 0, 70, bob).unwrap();
+        assert!(!market.event_state().outcomes[0]
+            .bets
+            .contains_key(&alice.key()));
+    }
+
+    #[test]
+    fn test_transfer_to_self_or_off_an_active_event_fails() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let bob = Pubkey::new_unique();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(alice.key(), 100)]);
+        market.buy(&mut alice, 0, 100).unwrap();
+
+        let alice_key = alice.key();
+        assert_eq!(
+            transfer(&mut market, &mut alice, 0, 10, alice_key),
+            Err(PredictionMarketError::SelfTransfer.into())
+        );
+
+        process_cancel_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+        )
+        .unwrap();
+        assert_eq!(
+            transfer(&mut market, &mut alice, 0, 10, bob),
+            Err(PredictionMarketError::EventNotActive.into())
+        );
+    }
+}
+
+#[cfg(test)]
+mod freeze_window_tests {
+    use super::*;
+    use crate::test_utils::{event_params, set_block_height, Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [58; 32];
+
+    // expires at height 100, frozen from 90
+    fn frozen_market(program_id: &Pubkey, alice: &TestAccount) -> Market {
+        let params = PredictionEventParams {
+            freeze_blocks_before_expiry: 10,
+            ..event_params(EVENT_ID, 100, 2)
+        };
+        Market::with_params(program_id, params, &[(alice.key(), 100)])
+    }
+
+    #[test]
+    fn test_bets_one_block_outside_the_window_go_through() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = frozen_market(&program_id, &alice);
+
+        set_block_height(89);
+        market.buy(&mut alice, 0, 30).unwrap();
+        market.sell(&mut alice, 0, 10).unwrap();
+
+        assert_eq!(market.event_state().total_pool_amount, 20);
+    }
+
+    #[test]
+    fn test_bets_one_block_inside_the_window_are_frozen() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = frozen_market(&program_id, &alice);
+        market.buy(&mut alice, 0, 30).unwrap();
+        let frozen = Err(PredictionMarketError::BettingFrozen.into());
+
+        set_block_height(90);
+        assert_eq!(market.buy(&mut alice, 0, 10), frozen);
+        assert_eq!(market.sell(&mut alice, 0, 10), frozen);
+        assert_eq!(market.balance(&alice), 70);
+
+        // the event still closes, resolves and pays out
+        process_close_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+        )
+        .unwrap();
+        set_block_height(100);
+        process_resolve_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+            0,
+        )
+        .unwrap();
+        market.claim(&mut alice).unwrap();
+        assert_eq!(market.balance(&alice), 100);
+    }
 }
@@ -0,0 +1,709 @@
+//! Odds shared by the program and its clients, so every frontend quotes the
+//! same prices, and the LMSR market maker's cost function. All arithmetic is
+//! integer, so every validator computes the same results.
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::types::{MarketType, PredictionEvent};
+
+/// Scale of the fixed-point values below: `FIXED_POINT_ONE` is 1.0.
+pub const FIXED_POINT_ONE: u64 = 1_000_000_000;
+pub const BPS_DENOMINATOR: u64 = 10_000;
+
+/// Scale of the LMSR's internal fixed point, finer than `FIXED_POINT_ONE` so
+/// the cost function keeps its precision across large quantities.
+pub const LMSR_SCALE: u128 = 1_000_000_000_000_000_000;
+/// Largest LMSR liquidity parameter accepted, the range `lmsr_cost` keeps
+/// within one token of the exact cost.
+pub const MAX_LMSR_B: u64 = 100_000_000_000_000;
+/// Highest creator fee an event can be created with.
+pub const MAX_CREATOR_FEE_BPS: u16 = 500;
+const LN_2: u128 = 693_147_180_559_945_309;
+// e^-42 is below one unit of `LMSR_SCALE`
+const EXP_NEG_CUTOFF: u128 = 42 * LMSR_SCALE;
+
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct OddsEntry {
+    pub outcome_id: u8,
+    // the outcome's share of the pool, in basis points
+    pub pool_share_bps: u16,
+    // what one unit staked on the outcome pays if it wins, scaled by
+    // `FIXED_POINT_ONE`. Zero for an outcome nobody backs, and saturates at
+    // `u64::MAX` when the pool dwarfs the outcome's stake.
+    pub implied_payout_per_unit_fp: u64,
+}
+
+/// The creator's cut of a resolved pool: `fee_bps` of what was staked on the
+/// losing outcomes, rounded down.
+pub fn creator_fee(total_pool: u64, winning_total: u64, fee_bps: u16) -> u64 {
+    let losing = total_pool.saturating_sub(winning_total) as u128;
+    (losing * fee_bps as u128 / BPS_DENOMINATOR as u128) as u64
+}
+
+/// `a * b / divisor` rounded down. The product is taken in `u128`, so only a
+/// quotient past `u64::MAX` fails, and so does a zero `divisor`.
+pub fn mul_div(a: u64, b: u64, divisor: u64) -> Option<u64> {
+    let quotient = (a as u128 * b as u128).checked_div(divisor as u128)?;
+    u64::try_from(quotient).ok()
+}
+
+/// `mul_div` rounded up.
+pub fn mul_div_up(a: u64, b: u64, divisor: u64) -> Option<u64> {
+    let product = a as u128 * b as u128;
+    let quotient = product.checked_div(divisor as u128)?;
+    let remainder = product % divisor as u128;
+    u64::try_from(quotient + (remainder > 0) as u128).ok()
+}
+
+/// What `amount` of outcome `index` pays if that outcome wins, as the event
+/// stands. Claims pay out by the same rule. Once the event is resolved to
+/// several outcomes, stakes on any of them share the pool as one, so
+/// `amount` may be a stake across them all. `None` if the payout doesn't fit
+/// a `u64`, which a stake no larger than the outcome's total never hits.
+pub fn winning_payout(event: &PredictionEvent, index: usize, amount: u64) -> Option<u64> {
+    let outcome = &event.outcomes[index];
+    let outcome_total = if event.is_winner(outcome.id) {
+        event.winning_total()
+    } else {
+        outcome.total_amount
+    };
+    if outcome_total == 0 {
+        return Some(0);
+    }
+
+    let pool = event.total_pool_amount
+        - creator_fee(
+            event.total_pool_amount,
+            outcome_total,
+            event.creator_fee_bps,
+        );
+
+    // an LMSR share pays one token, as far as the pool reaches
+    let paid_out = match event.market_type {
+        MarketType::ParimutuelPool | MarketType::Scalar { .. } => pool,
+        MarketType::Lmsr { .. } => pool.min(outcome_total),
+    };
+
+    mul_div(amount, paid_out, outcome_total)
+}
+
+/// What cashing out `amount` of outcome `index` pays before resolution: the
+/// position's current value less `haircut_bps`, rounded down. A pool stake is
+/// valued at par, an LMSR position at what selling it to the market maker
+/// returns. Capped at `winning_payout`, so cashing out never beats holding a
+/// winner. `None` past the outstanding shares of an LMSR outcome, or if the
+/// value doesn't fit a `u64`.
+pub fn cashout_value(
+    event: &PredictionEvent,
+    index: usize,
+    amount: u64,
+    haircut_bps: u16,
+) -> Option<u64> {
+    let value = match event.market_type {
+        MarketType::ParimutuelPool | MarketType::Scalar { .. } => amount,
+        MarketType::Lmsr { b } => lmsr_sell_refund(&event.outcome_quantities(), index, amount, b)?,
+    };
+
+    let kept = BPS_DENOMINATOR.saturating_sub(haircut_bps as u64) as u128;
+    let value = (value as u128 * kept / BPS_DENOMINATOR as u128) as u64;
+
+    Some(value.min(winning_payout(event, index, amount)?))
+}
+
+/// Odds of every outcome, in outcome order. In a pool market they come from
+/// the current pool, following the same pro-rata rule as claims: the pool
+/// less the creator fee split across the winning outcome's stakes. In an LMSR market the
+/// share is the outcome's marginal price and the payout is per token spent
+/// at that price.
+pub fn compute_odds(event: &PredictionEvent) -> Vec<OddsEntry> {
+    if let MarketType::Lmsr { b } = event.market_type {
+        return lmsr_odds(event, b);
+    }
+
+    let total_pool = event.total_pool_amount as u128;
+
+    event
+        .outcomes
+        .iter()
+        .map(|outcome| {
+            let outcome_total = outcome.total_amount as u128;
+
+            // an empty pool or outcome quotes zero rather than dividing by it
+            let pool_share_bps = (outcome_total * BPS_DENOMINATOR as u128)
+                .checked_div(total_pool)
+                .unwrap_or(0);
+            let fee = creator_fee(
+                event.total_pool_amount,
+                outcome.total_amount,
+                event.creator_fee_bps,
+            ) as u128;
+            let payout_fp = ((total_pool - fee) * FIXED_POINT_ONE as u128)
+                .checked_div(outcome_total)
+                .unwrap_or(0);
+
+            OddsEntry {
+                outcome_id: outcome.id,
+                pool_share_bps: pool_share_bps.min(BPS_DENOMINATOR as u128) as u16,
+                implied_payout_per_unit_fp: payout_fp.min(u64::MAX as u128) as u64,
+            }
+        })
+        .collect()
+}
+
+fn lmsr_odds(event: &PredictionEvent, b: u64) -> Vec<OddsEntry> {
+    let weights = lmsr_weights(&event.outcome_quantities(), b);
+    let total: u128 = weights.iter().sum();
+
+    event
+        .outcomes
+        .iter()
+        .zip(weights)
+        .map(|(outcome, weight)| {
+            let price = weight * LMSR_SCALE / total;
+            let payout_fp = (FIXED_POINT_ONE as u128 * LMSR_SCALE)
+                .checked_div(price)
+                .unwrap_or(u128::MAX);
+
+            OddsEntry {
+                outcome_id: outcome.id,
+                pool_share_bps: (price * BPS_DENOMINATOR as u128 / LMSR_SCALE) as u16,
+                implied_payout_per_unit_fp: payout_fp.min(u64::MAX as u128) as u64,
+            }
+        })
+        .collect()
+}
+
+/// e^-x, with `x` and the result scaled by `LMSR_SCALE`. Splits off powers of
+/// two, x = k·ln2 + r, and sums the Taylor series of e^r for r < ln2. The
+/// result is within a few units of `LMSR_SCALE` of the exact value.
+pub fn exp_neg(x: u128) -> u128 {
+    if x >= EXP_NEG_CUTOFF {
+        return 0;
+    }
+
+    let k = x / LN_2;
+    let r = x - k * LN_2;
+
+    let mut term = LMSR_SCALE;
+    let mut exp_r = LMSR_SCALE;
+    for n in 1..=40 {
+        term = term * r / LMSR_SCALE / n;
+        if term == 0 {
+            break;
+        }
+        exp_r += term;
+    }
+
+    (LMSR_SCALE * LMSR_SCALE / exp_r) >> k
+}
+
+/// ln(y) for y >= 1, both scaled by `LMSR_SCALE`. Splits off powers of two,
+/// y = 2^k·m, and sums ln m = 2·atanh((m-1)/(m+1)), which converges fast for
+/// m in [1, 2). The result is within a few units of `LMSR_SCALE` of the exact
+/// value.
+pub fn ln(y: u128) -> u128 {
+    debug_assert!(y >= LMSR_SCALE);
+
+    let mut k = 0u32;
+    while y >> (k + 1) >= LMSR_SCALE {
+        k += 1;
+    }
+    let m = y >> k;
+
+    let z = (m - LMSR_SCALE) * LMSR_SCALE / (m + LMSR_SCALE);
+    let z_squared = z * z / LMSR_SCALE;
+    let mut term = z;
+    let mut sum = 0;
+    let mut n = 1;
+    while term > 0 {
+        sum += term / n;
+        term = term * z_squared / LMSR_SCALE;
+        n += 2;
+    }
+
+    k as u128 * LN_2 + 2 * sum
+}
+
+// e^((q_i - max q) / b) for every outcome, scaled by `LMSR_SCALE`
+fn lmsr_weights(quantities: &[u64], b: u64) -> Vec<u128> {
+    let max = quantities.iter().copied().max().unwrap_or(0);
+    quantities
+        .iter()
+        .map(|q| exp_neg((max - q) as u128 * LMSR_SCALE / b as u128))
+        .collect()
+}
+
+/// The LMSR cost function C(q) = b·ln(Σ e^(q_i/b)), in tokens scaled by
+/// `LMSR_SCALE`. Evaluated as max q + b·ln(Σ e^((q_i - max q)/b)) so nothing
+/// overflows; the error stays below one token for b up to `MAX_LMSR_B` and
+/// up to 255 outcomes.
+pub fn lmsr_cost(quantities: &[u64], b: u64) -> u128 {
+    let max = quantities.iter().copied().max().unwrap_or(0);
+    let sum: u128 = lmsr_weights(quantities, b).iter().sum();
+
+    max as u128 * LMSR_SCALE + b as u128 * ln(sum)
+}
+
+/// Tokens charged for `shares` more of outcome `index`: C(q') - C(q), rounded
+/// up, so at least one token. `None` if the quantity would overflow.
+pub fn lmsr_buy_cost(quantities: &[u64], index: usize, shares: u64, b: u64) -> Option<u64> {
+    let mut after = quantities.to_vec();
+    after[index] = after[index].checked_add(shares)?;
+
+    let cost = lmsr_cost(&after, b).saturating_sub(lmsr_cost(quantities, b));
+    // any share costs something, even where the price is below the precision
+    let cost = cost.div_ceil(LMSR_SCALE).max((shares > 0) as u128);
+    u64::try_from(cost).ok()
+}
+
+/// Tokens refunded for `shares` fewer of outcome `index`: C(q) - C(q'),
+/// rounded down. Since the cost of a buy rounds the other way over the very
+/// same two values, selling what was just bought never returns more than it
+/// cost. `None` if fewer than `shares` are outstanding.
+pub fn lmsr_sell_refund(quantities: &[u64], index: usize, shares: u64, b: u64) -> Option<u64> {
+    let mut after = quantities.to_vec();
+    after[index] = after[index].checked_sub(shares)?;
+
+    let refund = lmsr_cost(quantities, b).saturating_sub(lmsr_cost(&after, b));
+    u64::try_from(refund / LMSR_SCALE).ok()
+}
+
+/// Where `value`, clamped to the bounds, falls between them: the share of a
+/// scalar pool the long side takes, scaled by `FIXED_POINT_ONE` and rounded
+/// down. 0 at or below `lower_bound`, all of it at or above `upper_bound`.
+pub fn scalar_long_share_fp(value: i64, lower_bound: i64, upper_bound: i64) -> u64 {
+    let value = value.clamp(lower_bound, upper_bound);
+    let above = (value as i128 - lower_bound as i128) as u128;
+    let range = (upper_bound as i128 - lower_bound as i128) as u128;
+    (above * FIXED_POINT_ONE as u128 / range) as u64
+}
+
+/// What a scalar stake of `exposure` pays once the event resolved to
+/// `value`: its side's interpolated share of the pool, split pro rata across
+/// the side's stakes. A side nobody took leaves its share to the other.
+/// `None` if the payout doesn't fit a `u64`.
+pub fn scalar_payout(event: &PredictionEvent, exposure: i64, value: i64) -> Option<u64> {
+    let MarketType::Scalar {
+        lower_bound,
+        upper_bound,
+    } = event.market_type
+    else {
+        return Some(0);
+    };
+
+    let (long, short) = event.scalar_totals();
+    let long_share_fp = match (long, short) {
+        (0, _) => 0,
+        (_, 0) => FIXED_POINT_ONE,
+        _ => scalar_long_share_fp(value, lower_bound, upper_bound),
+    } as u128;
+    let (side_share_fp, side_total) = if exposure > 0 {
+        (long_share_fp, long)
+    } else {
+        (FIXED_POINT_ONE as u128 - long_share_fp, short)
+    };
+
+    if side_total == 0 {
+        return Some(0);
+    }
+
+    // the share is at most `FIXED_POINT_ONE`, so the side's pool fits a u64
+    let side_pool = mul_div(
+        event.total_pool_amount,
+        side_share_fp as u64,
+        FIXED_POINT_ONE,
+    )?;
+    mul_div(side_pool, exposure.unsigned_abs(), side_total)
+}
+
+#[cfg(test)]
+mod math_tests {
+    use super::*;
+    use crate::process_resolve_event;
+    use crate::test_utils::{Market, Rng, TestAccount};
+    use arch_program::pubkey::Pubkey;
+
+    // an event whose outcomes hold exactly `totals`
+    fn event_with_totals(totals: &[u64]) -> PredictionEvent {
+        let program_id = Pubkey::new_unique();
+        let mut event = Market::new(&program_id, [36; 32], &[]).event_state();
+        let template = event.outcomes[0].clone();
+        event.outcomes = totals
+            .iter()
+            .enumerate()
+            .map(|(id, total)| {
+                let mut outcome = template.clone();
+                outcome.id = id as u8;
+                outcome.total_amount = *total;
+                outcome
+            })
+            .collect();
+        event.total_pool_amount = totals.iter().sum();
+        event
+    }
+
+    fn odds(outcome_id: u8, pool_share_bps: u16, implied_payout_per_unit_fp: u64) -> OddsEntry {
+        OddsEntry {
+            outcome_id,
+            pool_share_bps,
+            implied_payout_per_unit_fp,
+        }
+    }
+
+    #[test]
+    fn test_odds_of_a_balanced_pool() {
+        assert_eq!(
+            compute_odds(&event_with_totals(&[300, 100])),
+            vec![odds(0, 7_500, 1_333_333_333), odds(1, 2_500, 4_000_000_000)]
+        );
+    }
+
+    #[test]
+    fn test_odds_round_down() {
+        assert_eq!(
+            compute_odds(&event_with_totals(&[1, 1, 1])),
+            vec![
+                odds(0, 3_333, 3_000_000_000),
+                odds(1, 3_333, 3_000_000_000),
+                odds(2, 3_333, 3_000_000_000),
+            ]
+        );
+        assert_eq!(
+            compute_odds(&event_with_totals(&[2, 1])),
+            vec![odds(0, 6_666, 1_500_000_000), odds(1, 3_333, 3_000_000_000)]
+        );
+    }
+
+    #[test]
+    fn test_outcomes_without_liquidity() {
+        assert_eq!(
+            compute_odds(&event_with_totals(&[0, 50])),
+            vec![odds(0, 0, 0), odds(1, 10_000, 1_000_000_000)]
+        );
+        assert_eq!(
+            compute_odds(&event_with_totals(&[0, 0])),
+            vec![odds(0, 0, 0), odds(1, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn test_extreme_skews() {
+        // 1_000_001 * 10^12 / 10^6 still fits
+        assert_eq!(
+            compute_odds(&event_with_totals(&[1_000_000, 1_000_000_000_000])),
+            vec![
+                odds(0, 0, 1_000_001_000_000_000),
+                odds(1, 9_999, 1_000_001_000),
+            ]
+        );
+        // (10^12 + 1) * 10^9 does not
+        assert_eq!(
+            compute_odds(&event_with_totals(&[1, 1_000_000_000_000])),
+            vec![odds(0, 0, u64::MAX), odds(1, 9_999, 1_000_000_000)]
+        );
+    }
+
+    #[test]
+    fn test_odds_match_the_claim_payout() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let balances = [(alice.key(), 100), (bob.key(), 100)];
+        let mut market = Market::new(&program_id, [36; 32], &balances);
+        market.buy(&mut alice, 0, 25).unwrap();
+        market.buy(&mut bob, 1, 75).unwrap();
+        let entry = compute_odds(&market.event_state())[0].clone();
+        process_resolve_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            [36; 32],
+            0,
+        )
+        .unwrap();
+
+        market.claim(&mut alice).unwrap();
+
+        let quoted = entry.implied_payout_per_unit_fp * 25 / FIXED_POINT_ONE;
+        assert_eq!(market.balance(&alice), 75 + quoted);
+    }
+
+    #[test]
+    fn test_mul_div_only_fails_past_u64() {
+        assert_eq!(mul_div(u64::MAX, u64::MAX, u64::MAX), Some(u64::MAX));
+        assert_eq!(mul_div(u64::MAX, 3, 4), Some(u64::MAX / 4 * 3 + 2));
+        assert_eq!(mul_div(u64::MAX, 2, 1), None);
+        assert_eq!(mul_div(1, 1, 0), None);
+
+        assert_eq!(mul_div_up(7, 3, 2), Some(11));
+        assert_eq!(mul_div_up(8, 3, 2), Some(12));
+        assert_eq!(mul_div_up(u64::MAX, 1, 1), Some(u64::MAX));
+        assert_eq!(mul_div_up(u64::MAX, 3, 2), None);
+        assert_eq!(mul_div_up(1, 1, 0), None);
+    }
+
+    #[test]
+    fn test_payouts_whose_product_overflows_u64() {
+        // 3e9 * 7e9 is past u64::MAX, the payout itself is not
+        let (stake, pool) = (3_000_000_000u64, 7_000_000_000);
+        assert!(stake.checked_mul(pool).is_none());
+        let event = event_with_totals(&[stake, pool - stake]);
+        assert_eq!(winning_payout(&event, 0, stake), Some(pool));
+        assert_eq!(winning_payout(&event, 0, stake / 3), Some(pool / 3));
+
+        // the same through a claim
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let balances = [(alice.key(), stake), (bob.key(), pool - stake)];
+        let mut market = Market::new(&program_id, [36; 32], &balances);
+        market.buy(&mut alice, 0, stake).unwrap();
+        market.buy(&mut bob, 1, pool - stake).unwrap();
+        process_resolve_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            [36; 32],
+            0,
+        )
+        .unwrap();
+
+        market.claim(&mut alice).unwrap();
+        assert_eq!(market.balance(&alice), pool);
+    }
+
+    fn assert_close(actual: u128, expected: u128, tolerance: u128) {
+        assert!(
+            actual.abs_diff(expected) <= tolerance,
+            "{} is not within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_exp_neg_and_ln_fixtures() {
+        assert_eq!(exp_neg(0), LMSR_SCALE);
+        assert_close(exp_neg(LMSR_SCALE), 367_879_441_171_442_321, 10);
+        assert_close(exp_neg(LN_2), LMSR_SCALE / 2, 10);
+        assert_close(exp_neg(10 * LMSR_SCALE), 45_399_929_762_484, 10);
+        assert_eq!(exp_neg(EXP_NEG_CUTOFF), 0);
+
+        assert_eq!(ln(LMSR_SCALE), 0);
+        assert_close(ln(2 * LMSR_SCALE), LN_2, 10);
+        assert_close(ln(10 * LMSR_SCALE), 2_302_585_092_994_045_684, 10);
+        assert_close(ln(255 * LMSR_SCALE), 5_541_263_545_158_426_146, 100);
+    }
+
+    #[test]
+    fn test_lmsr_cost_fixtures() {
+        let b = 1_000_000;
+
+        // C(0, 0) = b·ln2
+        assert_close(lmsr_cost(&[0, 0], b), b as u128 * LN_2, LMSR_SCALE / 1_000);
+        // b·(ln(1 + e) - ln2) = 620_114.50...
+        assert_eq!(lmsr_buy_cost(&[0, 0], 0, b, b), Some(620_115));
+        assert_eq!(lmsr_sell_refund(&[b, 0], 0, b, b), Some(620_114));
+        // a skewed book charges close to a full token per share for the favourite
+        assert_eq!(lmsr_buy_cost(&[40 * b, 0], 0, 1_000, b), Some(1_000));
+        assert_eq!(lmsr_buy_cost(&[40 * b, 0], 1, 1_000, b), Some(1));
+        assert_eq!(lmsr_buy_cost(&[u64::MAX, 0], 0, 1, b), None);
+        assert_eq!(lmsr_sell_refund(&[5, 0], 0, 6, b), None);
+    }
+
+    #[test]
+    fn test_lmsr_costs_match_floating_point() {
+        let mut rng = Rng(0x5eed);
+
+        for _ in 0..200 {
+            let b = 1 + rng.below(1_000_000_000);
+            let quantities: Vec<u64> = (0..2 + rng.below(6)).map(|_| rng.below(10 * b)).collect();
+            let index = rng.below(quantities.len() as u64) as usize;
+            let shares = 1 + rng.below(b);
+
+            let cost_f64 = |q: &[u64]| {
+                let max = *q.iter().max().unwrap() as f64;
+                max + b as f64
+                    * q.iter()
+                        .map(|q| ((*q as f64 - max) / b as f64).exp())
+                        .sum::<f64>()
+                        .ln()
+            };
+            let mut after = quantities.clone();
+            after[index] += shares;
+            let expected = cost_f64(&after) - cost_f64(&quantities);
+
+            let cost = lmsr_buy_cost(&quantities, index, shares, b).unwrap();
+            assert!(
+                (cost as f64 - expected).abs() <= 1.0,
+                "cost {} vs {} for {:?} + {} on {} with b = {}",
+                cost,
+                expected,
+                quantities,
+                shares,
+                index,
+                b
+            );
+        }
+    }
+
+    #[test]
+    fn test_lmsr_buy_then_sell_never_profits() {
+        let mut rng = Rng(0xb0b);
+
+        for _ in 0..1_000 {
+            let b = 1 + rng.below(MAX_LMSR_B);
+            let quantities: Vec<u64> = (0..2 + rng.below(8)).map(|_| rng.below(20 * b)).collect();
+            let index = rng.below(quantities.len() as u64) as usize;
+            let shares = 1 + rng.below(5 * b);
+
+            let cost = lmsr_buy_cost(&quantities, index, shares, b).unwrap();
+            let mut after = quantities.clone();
+            after[index] += shares;
+            let refund = lmsr_sell_refund(&after, index, shares, b).unwrap();
+
+            assert!(
+                refund <= cost,
+                "bought for {} and sold for {} at {:?}, b = {}",
+                cost,
+                refund,
+                quantities,
+                b
+            );
+        }
+    }
+
+    #[test]
+    fn test_lmsr_round_trips_never_profit() {
+        let mut rng = Rng(0xa11ce);
+
+        for _ in 0..200 {
+            let b = 1 + rng.below(1_000_000);
+            let mut quantities: Vec<u64> =
+                (0..2 + rng.below(4)).map(|_| rng.below(5 * b)).collect();
+            let mut held = vec![0u64; quantities.len()];
+            let mut paid = 0u64;
+            let mut returned = 0u64;
+
+            for _ in 0..1 + rng.below(10) {
+                let index = rng.below(quantities.len() as u64) as usize;
+                let shares = 1 + rng.below(2 * b);
+                paid += lmsr_buy_cost(&quantities, index, shares, b).unwrap();
+                quantities[index] += shares;
+                held[index] += shares;
+            }
+            // unwind in whatever order, in arbitrary slices
+            while held.iter().any(|h| *h > 0) {
+                let index = rng.below(held.len() as u64) as usize;
+                if held[index] == 0 {
+                    continue;
+                }
+                let shares = 1 + rng.below(held[index]);
+                returned += lmsr_sell_refund(&quantities, index, shares, b).unwrap();
+                quantities[index] -= shares;
+                held[index] -= shares;
+            }
+
+            assert!(returned <= paid, "paid {} and got back {}", paid, returned);
+        }
+    }
+
+    #[test]
+    fn test_lmsr_odds_are_marginal_prices() {
+        let mut event = event_with_totals(&[0, 0]);
+        event.market_type = MarketType::Lmsr { b: 1_000 };
+
+        assert_eq!(
+            compute_odds(&event),
+            vec![odds(0, 5_000, 2_000_000_000), odds(1, 5_000, 2_000_000_000)]
+        );
+
+        // e / (1 + e) = 0.731058...
+        event.outcomes[0].total_amount = 1_000;
+        let odds = compute_odds(&event);
+        assert_eq!(odds[0].pool_share_bps, 7_310);
+        assert_eq!(odds[1].pool_share_bps, 2_689);
+        assert_eq!(odds[0].implied_payout_per_unit_fp, 1_367_879_441);
+    }
+
+    #[test]
+    fn test_cashout_never_beats_the_winning_payout() {
+        let mut rng = Rng(0xca5);
+
+        for round in 0..400 {
+            let totals: Vec<u64> = (0..2 + rng.below(4)).map(|_| rng.below(10_000)).collect();
+            let mut event = event_with_totals(&totals);
+            event.creator_fee_bps = rng.below(MAX_CREATOR_FEE_BPS as u64 + 1) as u16;
+            // every other round is an LMSR book whose pool may fall short of it
+            if round % 2 == 1 {
+                event.market_type = MarketType::Lmsr {
+                    b: 1 + rng.below(10_000),
+                };
+                event.total_pool_amount = rng.below(20_000);
+            }
+
+            let index = rng.below(totals.len() as u64) as usize;
+            let amount = rng.below(totals[index] + 1);
+            let haircut_bps = rng.below(BPS_DENOMINATOR + 1) as u16;
+
+            let Some(value) = cashout_value(&event, index, amount, haircut_bps) else {
+                continue;
+            };
+            assert!(value <= winning_payout(&event, index, amount).unwrap());
+
+            // a pool stake is worth at least itself if it wins, so the cap
+            // never binds there
+            if event.market_type == MarketType::ParimutuelPool {
+                let kept = BPS_DENOMINATOR - haircut_bps as u64;
+                assert_eq!(value, amount * kept / BPS_DENOMINATOR);
+            }
+        }
+    }
+
+    #[test]
+    fn test_scalar_long_share_interpolates_between_the_bounds() {
+        let half = FIXED_POINT_ONE / 2;
+        assert_eq!(scalar_long_share_fp(-50, -100, 0), half);
+        assert_eq!(scalar_long_share_fp(25, 0, 100), FIXED_POINT_ONE / 4);
+        // the bounds themselves, and values past them, clamp
+        assert_eq!(scalar_long_share_fp(0, 0, 100), 0);
+        assert_eq!(scalar_long_share_fp(-20, 0, 100), 0);
+        assert_eq!(scalar_long_share_fp(100, 0, 100), FIXED_POINT_ONE);
+        assert_eq!(scalar_long_share_fp(150, 0, 100), FIXED_POINT_ONE);
+        // the full i64 range doesn't overflow
+        assert_eq!(
+            scalar_long_share_fp(i64::MAX, i64::MIN, i64::MAX),
+            FIXED_POINT_ONE
+        );
+        assert_eq!(scalar_long_share_fp(0, i64::MIN, i64::MAX), half);
+    }
+
+    #[test]
+    fn test_scalar_payout_splits_the_pool_pro_rata_within_a_side() {
+        let mut event = event_with_totals(&[]);
+        event.market_type = MarketType::Scalar {
+            lower_bound: 0,
+            upper_bound: 100,
+        };
+        let (alice, bob, carol) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        event
+            .scalar_exposure
+            .extend([(alice, 30), (bob, 10), (carol, -60)]);
+        event.total_pool_amount = 100;
+
+        // at 40 the longs share 40 in a 3:1 ratio and carol takes the 60
+        assert_eq!(scalar_payout(&event, 30, 40), Some(30));
+        assert_eq!(scalar_payout(&event, 10, 40), Some(10));
+        assert_eq!(scalar_payout(&event, -60, 40), Some(60));
+        assert_eq!(scalar_payout(&event, 0, 40), Some(0));
+
+        // with nobody short the longs take the pool whatever the value
+        event.scalar_exposure.remove(&carol);
+        event.total_pool_amount = 40;
+        assert_eq!(scalar_payout(&event, 30, 0), Some(30));
+    }
+}
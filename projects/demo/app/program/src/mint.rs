@@ -1,8 +1,70 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
-use arch_program::{account::AccountInfo, msg, program_error::ProgramError, pubkey::Pubkey};
+use arch_program::{
+    account::AccountInfo,
+    bitcoin::hashes::{sha256, Hash, HashEngine},
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
 use borsh::{BorshDeserialize, BorshSerialize};
 
+use crate::{
+    ensure_distinct_accounts,
+    errors::FungibleTokenError,
+    events::{self, BurnedLog, MintedLog},
+    get_bitcoin_block_height,
+    math::BPS_DENOMINATOR,
+    storage,
+    wrapped_btc::ReserveUtxo,
+};
+
+/// Upper bound on recipients per `BatchMint`, keeps the instruction within
+/// compute limits.
+pub const MAX_BATCH_MINT_RECIPIENTS: usize = 32;
+
+/// `owner` of a mint whose authority was renounced. It matches no signer, so
+/// nothing that needs the mint authority can run on that mint again.
+pub const RENOUNCED_MINT_AUTHORITY: [u8; 32] = [0; 32];
+
+/// Upper bound on the keys of a `MintMultisig`, and so on the signer accounts
+/// trailing a mint or burn.
+pub const MAX_MINT_SIGNERS: usize = 11;
+
+/// Highest transfer fee a mint can charge.
+pub const MAX_TRANSFER_FEE_BPS: u16 = 1_000;
+
+/// How many of the latest mint and burn nonces a mint remembers. A nonce
+/// older than that is forgotten and would be accepted again, so clients must
+/// not resubmit that far behind.
+pub const MAX_PROCESSED_NONCES: usize = 128;
+
+/// `threshold` of `signers` must sign every mint and burn of a mint that has
+/// one. `owner` keeps the right to hand the authority over.
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct MintMultisig {
+    pub signers: Vec<[u8; 32]>,
+    pub threshold: u8,
+}
+
+impl MintMultisig {
+    fn validate(&self) -> Result<(), FungibleTokenError> {
+        let mut unique_signers = self.signers.clone();
+        unique_signers.sort();
+        unique_signers.dedup();
+
+        if unique_signers.len() != self.signers.len()
+            || self.signers.len() > MAX_MINT_SIGNERS
+            || self.threshold == 0
+            || self.threshold as usize > self.signers.len()
+        {
+            return Err(FungibleTokenError::InvalidMultisig);
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
 pub struct TokenMintDetails {
     owner: [u8; 32],
@@ -14,6 +76,18 @@ pub struct TokenMintDetails {
     token_metadata: HashMap<String, [u8; 32]>,
 
     pub balances: HashMap<Pubkey, u64>,
+    // `None` leaves mints and burns unrestricted, as single-owner mints are
+    pub multisig: Option<MintMultisig>,
+    // cut of every `batch_transfer_tokens` transfer, credited to
+    // `fee_collector`'s balance above; 0 for none
+    pub transfer_fee_bps: u16,
+    pub fee_collector: Pubkey,
+    // nonces of the latest mints and burns, oldest first, at most
+    // `MAX_PROCESSED_NONCES`
+    pub processed_nonces: VecDeque<[u8; 32]>,
+    // wrapped-BTC mints only: the utxos backing every token, one per satoshi;
+    // `None` for a mint that isn't backed, see `wrapped_btc`
+    pub btc_reserve: Option<Vec<ReserveUtxo>>,
 }
 
 impl TokenMintDetails {
@@ -31,8 +105,45 @@ impl TokenMintDetails {
             decimals: input.decimals,
             token_metadata,
             balances: HashMap::new(),
+            multisig: input.multisig,
+            transfer_fee_bps: input.transfer_fee_bps,
+            fee_collector: input.fee_collector,
+            processed_nonces: VecDeque::new(),
+            btc_reserve: input.btc_backed.then(Vec::new),
         }
     }
+
+    /// Fails with `BtcBackedMint` for a wrapped-BTC mint, whose tokens are
+    /// only minted and burned against its reserve.
+    fn ensure_not_btc_backed(&self) -> Result<(), FungibleTokenError> {
+        if self.btc_reserve.is_some() {
+            return Err(FungibleTokenError::BtcBackedMint);
+        }
+
+        Ok(())
+    }
+
+    /// Records `nonce` as processed, forgetting the oldest one once
+    /// `MAX_PROCESSED_NONCES` are held. Fails with `NonceAlreadyProcessed` if
+    /// it is among those held.
+    pub fn record_nonce(&mut self, nonce: [u8; 32]) -> Result<(), FungibleTokenError> {
+        if self.processed_nonces.contains(&nonce) {
+            return Err(FungibleTokenError::NonceAlreadyProcessed);
+        }
+
+        if self.processed_nonces.len() == MAX_PROCESSED_NONCES {
+            self.processed_nonces.pop_front();
+        }
+        self.processed_nonces.push_back(nonce);
+        Ok(())
+    }
+
+    /// Fee withheld from a transfer of `amount`. It rounds down, so the
+    /// recipient keeps any fraction of a token and a transfer too small to
+    /// owe a whole token pays none.
+    pub fn transfer_fee(&self, amount: u64) -> u64 {
+        (amount as u128 * self.transfer_fee_bps as u128 / BPS_DENOMINATOR as u128) as u64
+    }
 }
 #[derive(Debug, Clone, BorshSerialize, BorshDeserialize, Eq, PartialEq)]
 pub enum MintStatus {
@@ -46,6 +157,10 @@ pub struct InitializeMintInput {
     supply: u64, // in lowest denomination
     ticker: String,
     decimals: u8,
+    multisig: Option<MintMultisig>,
+    transfer_fee_bps: u16,
+    fee_collector: Pubkey,
+    btc_backed: bool,
 }
 impl InitializeMintInput {
     pub fn new(owner: [u8; 32], supply: u64, ticker: String, decimals: u8) -> Self {
@@ -54,8 +169,62 @@ impl InitializeMintInput {
             supply,
             ticker,
             decimals,
+            multisig: None,
+            transfer_fee_bps: 0,
+            fee_collector: Pubkey::default(),
+            btc_backed: false,
         }
     }
+
+    pub fn with_multisig(mut self, multisig: MintMultisig) -> Self {
+        self.multisig = Some(multisig);
+        self
+    }
+
+    pub fn with_transfer_fee(mut self, transfer_fee_bps: u16, fee_collector: Pubkey) -> Self {
+        self.transfer_fee_bps = transfer_fee_bps;
+        self.fee_collector = fee_collector;
+        self
+    }
+
+    /// Makes the mint wrapped BTC: one token per satoshi deposited, see
+    /// `wrapped_btc`.
+    pub fn btc_backed(mut self) -> Self {
+        self.btc_backed = true;
+        self
+    }
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct BatchMintInput {
+    pub recipients: Vec<(Pubkey, u64)>,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct TransferMintAuthorityInput {
+    /// `None` renounces the authority for good.
+    pub new_owner: Option<[u8; 32]>,
+}
+
+fn ensure_mint_authority(
+    token: &TokenMintDetails,
+    authority_account: &AccountInfo<'_>,
+) -> Result<(), ProgramError> {
+    if token.owner == RENOUNCED_MINT_AUTHORITY || token.owner != authority_account.key.serialize() {
+        return Err(ProgramError::IncorrectAuthority);
+    }
+
+    Ok(())
+}
+
+/// Address of the mint account for `ticker`, so a client can find a token
+/// from its ticker alone. No signer can hold it.
+pub fn derive_mint_address(program_id: &Pubkey, ticker: &str) -> Pubkey {
+    let mut engine = sha256::Hash::engine();
+    engine.input(b"mint");
+    engine.input(&program_id.0);
+    engine.input(ticker.as_bytes());
+    Pubkey(sha256::Hash::from_engine(engine).to_byte_array())
 }
 
 pub(crate) fn initialize_mint(
@@ -71,72 +240,177 @@ pub(crate) fn initialize_mint(
         return Err(ProgramError::IllegalOwner);
     }
 
+    if *account.key != derive_mint_address(program_id, &mint_input.ticker) {
+        return Err(FungibleTokenError::MintAddressMismatch.into());
+    }
+
+    if let Some(multisig) = &mint_input.multisig {
+        multisig.validate()?;
+    }
+
+    if mint_input.transfer_fee_bps > MAX_TRANSFER_FEE_BPS {
+        return Err(FungibleTokenError::TransferFeeTooHigh.into());
+    }
+
     let mint_initial_details =
         TokenMintDetails::new(mint_input, MintStatus::Ongoing, HashMap::new());
 
-    let serialized_mint_details = borsh::to_vec(&mint_initial_details)
-        .map_err(|e| ProgramError::BorshIoError(e.to_string()))?;
+    storage::write(account, &mint_initial_details)?;
+
+    Ok(())
+}
+
+/// Fails unless `threshold` distinct keys of the mint's multisig signed among
+/// `signers`. Signers outside the multisig don't count; the same key twice is
+/// rejected. Mints without a multisig keep the single-owner path instead:
+/// `owner_account` must sign, and any `signers` are ignored.
+pub(crate) fn ensure_multisig_signed(
+    token_account: &AccountInfo<'_>,
+    owner_account: &AccountInfo<'_>,
+    signers: &[AccountInfo<'_>],
+) -> Result<(), ProgramError> {
+    let token = storage::read::<TokenMintDetails>(token_account)?;
+
+    let Some(multisig) = token.multisig else {
+        if !owner_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        return Ok(());
+    };
+
+    let keys: Vec<&Pubkey> = signers.iter().map(|signer| signer.key).collect();
+    ensure_distinct_accounts(&keys).map_err(|_| FungibleTokenError::DuplicateSigner)?;
+
+    let approvals = signers
+        .iter()
+        .filter(|signer| signer.is_signer && multisig.signers.contains(&signer.key.serialize()))
+        .count();
+
+    if approvals < multisig.threshold as usize {
+        msg!(
+            "{} of {} required mint signatures",
+            approvals,
+            multisig.threshold
+        );
+        return Err(FungibleTokenError::MultisigThresholdNotMet.into());
+    }
+
+    Ok(())
+}
+
+/// Checks `owner_account` may mint on `token_account`: minting must still be
+/// ongoing, and on a mint without a multisig `owner_account` must be its
+/// authority, so nothing is minted once the authority is renounced. The
+/// signatures are checked by `ensure_multisig_signed`.
+pub(crate) fn ensure_can_mint(
+    token_account: &AccountInfo<'_>,
+    owner_account: &AccountInfo<'_>,
+) -> Result<(), ProgramError> {
+    let token = storage::read::<TokenMintDetails>(token_account)?;
 
-    if !serialized_mint_details.is_empty() {
-        account.realloc(serialized_mint_details.len(), true)?;
+    if token.status != MintStatus::Ongoing {
+        return Err(FungibleTokenError::MintOver.into());
     }
 
-    account
-        .data
-        .try_borrow_mut()
-        .map_err(|_e| ProgramError::AccountBorrowFailed)?
-        .copy_from_slice(&serialized_mint_details);
+    if token.multisig.is_none() {
+        ensure_mint_authority(&token, owner_account)?;
+    }
 
     Ok(())
 }
 
+/// Mints `amount` to `mint_address`. A `nonce` is recorded in the same
+/// write, see `TokenMintDetails::record_nonce`.
 pub(crate) fn mint_tokens(
     token_account: &AccountInfo<'_>,
     mint_address: &Pubkey,
     amount: u64,
+    nonce: Option<[u8; 32]>,
 ) -> Result<(), ProgramError> {
-    let mut token = TokenMintDetails::try_from_slice(&token_account.data.borrow_mut())
-        .map_err(|_| ProgramError::InvalidAccountData)?;
+    let mut token = storage::read::<TokenMintDetails>(token_account)?;
+
+    if let Some(nonce) = nonce {
+        token.record_nonce(nonce)?;
+    }
+
+    token.ensure_not_btc_backed()?;
+
+    let new_circulating_supply = token
+        .circulating_supply
+        .checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    if new_circulating_supply > token.supply {
+        return Err(FungibleTokenError::NotEnoughRemainingMintableTokens.into());
+    }
+    token.circulating_supply = new_circulating_supply;
 
     let token_balance = token.balances.get(mint_address);
 
     match token_balance {
         Some(balance) => {
-            token
-                .balances
-                .insert(mint_address.clone(), *balance + amount);
+            token.balances.insert(*mint_address, *balance + amount);
         }
         None => {
-            token.balances.insert(mint_address.clone(), amount);
+            token.balances.insert(*mint_address, amount);
         }
     }
 
-    let serialized_mint_details =
-        borsh::to_vec(&token).map_err(|e| ProgramError::BorshIoError(e.to_string()))?;
+    storage::write(token_account, &token)?;
 
-
-    if token_account.data_len() < serialized_mint_details.len() {
-        token_account.realloc(serialized_mint_details.len(), true)?;
-    }
-
-    token_account
-        .data
-        .try_borrow_mut()
-        .map_err(|_e| ProgramError::AccountBorrowFailed)?
-        .copy_from_slice(&serialized_mint_details);
+    events::emit(&MintedLog {
+        user: *mint_address,
+        amount,
+        block_height: get_bitcoin_block_height(),
+    });
 
     Ok(())
 }
 
 
 
+/// Burns `amount` of the holder's own balance; only the holder can, so
+/// `holder_account` must sign. A `nonce` is recorded in the same write, see
+/// `TokenMintDetails::record_nonce`.
 pub(crate) fn burn_tokens(
+    token_account: &AccountInfo<'_>,
+    holder_account: &AccountInfo<'_>,
+    amount: u64,
+    nonce: Option<[u8; 32]>,
+) -> Result<(), ProgramError> {
+    if !holder_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    ensure_distinct_accounts(&[token_account.key, holder_account.key])?;
+
+    burn_from(token_account, holder_account.key, amount, nonce)
+}
+
+/// Burns `amount` of the balance held at `mint_address` without asking for a
+/// signature, for balances the program itself holds such as an event's
+/// escrow.
+pub(crate) fn burn_balance(
+    token_account: &AccountInfo<'_>,
+    mint_address: &Pubkey,
+    amount: u64,
+) -> Result<(), ProgramError> {
+    burn_from(token_account, mint_address, amount, None)
+}
+
+fn burn_from(
     token_account: &AccountInfo<'_>,
     mint_address: &Pubkey,
     amount: u64,
+    nonce: Option<[u8; 32]>,
 ) -> Result<(), ProgramError> {
-    let mut token = TokenMintDetails::try_from_slice(&token_account.data.borrow_mut())
-        .map_err(|_| ProgramError::InvalidAccountData)?;
+    let mut token = storage::read::<TokenMintDetails>(token_account)?;
+
+    if let Some(nonce) = nonce {
+        token.record_nonce(nonce)?;
+    }
+
+    token.ensure_not_btc_backed()?;
 
     let token_balance = token.balances.get(mint_address);
 
@@ -149,9 +423,8 @@ pub(crate) fn burn_tokens(
                 )));
             }
 
-            token
-                .balances
-                .insert(mint_address.clone(), *balance - amount);
+            token.balances.insert(*mint_address, *balance - amount);
+            token.circulating_supply = token.circulating_supply.saturating_sub(amount);
         }
         None => {
             return Err(ProgramError::BorshIoError(String::from(
@@ -160,19 +433,877 @@ pub(crate) fn burn_tokens(
         }
     }
 
-    let serialized_mint_details =
-        borsh::to_vec(&token).map_err(|e| ProgramError::BorshIoError(e.to_string()))?;
+    storage::write(token_account, &token)?;
+
+    events::emit(&BurnedLog {
+        user: *mint_address,
+        amount,
+        block_height: get_bitcoin_block_height(),
+    });
+
+    Ok(())
+}
+
+/// Mints to every recipient in one go. Authority and supply cap are checked
+/// for the whole batch before any balance changes, so it either fully applies
+/// or not at all. A mint with a multisig has no single authority; its
+/// signatures are checked by `ensure_multisig_signed`.
+pub(crate) fn batch_mint_tokens(
+    token_account: &AccountInfo<'_>,
+    authority_account: &AccountInfo<'_>,
+    recipients: &[(Pubkey, u64)],
+) -> Result<(), ProgramError> {
+    ensure_distinct_accounts(&[token_account.key, authority_account.key])?;
+
+    let mut token = storage::read::<TokenMintDetails>(token_account)?;
+
+    if token.multisig.is_none() {
+        if !authority_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        ensure_mint_authority(&token, authority_account)?;
+    }
+
+    if recipients.is_empty() || recipients.len() > MAX_BATCH_MINT_RECIPIENTS {
+        return Err(FungibleTokenError::BatchTooLarge.into());
+    }
+
+    if token.status != MintStatus::Ongoing {
+        return Err(FungibleTokenError::MintOver.into());
+    }
+
+    token.ensure_not_btc_backed()?;
+
+    let total = recipients
+        .iter()
+        .try_fold(0u64, |total, (_, amount)| total.checked_add(*amount))
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    let new_circulating_supply = token
+        .circulating_supply
+        .checked_add(total)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    if new_circulating_supply > token.supply {
+        return Err(FungibleTokenError::NotEnoughRemainingMintableTokens.into());
+    }
+
+    for (recipient, amount) in recipients {
+        *token.balances.entry(*recipient).or_insert(0) += amount;
+    }
+    token.circulating_supply = new_circulating_supply;
+
+    storage::write(token_account, &token)?;
+
+    let height = get_bitcoin_block_height();
+    for (recipient, amount) in recipients {
+        events::emit(&MintedLog {
+            user: *recipient,
+            amount: *amount,
+            block_height: height,
+        });
+    }
+
+    Ok(())
+}
+
+/// Hands the mint authority to `new_owner`, or renounces it when `None`.
+/// Only the current authority can do either.
+pub(crate) fn transfer_mint_authority(
+    token_account: &AccountInfo<'_>,
+    authority_account: &AccountInfo<'_>,
+    new_owner: Option<[u8; 32]>,
+) -> Result<(), ProgramError> {
+    if !authority_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    ensure_distinct_accounts(&[token_account.key, authority_account.key])?;
+
+    let mut token = storage::read::<TokenMintDetails>(token_account)?;
+
+    ensure_mint_authority(&token, authority_account)?;
+
+    token.owner = new_owner.unwrap_or(RENOUNCED_MINT_AUTHORITY);
 
+    storage::write(token_account, &token)?;
 
-    if token_account.data_len() < serialized_mint_details.len() {
-        token_account.realloc(serialized_mint_details.len(), true)?;
+    Ok(())
+}
+
+/// Drops a holder's emptied entry from `balances` and shrinks the mint account
+/// to match. Arch accounts hold no lamports, so the data space is what gets
+/// reclaimed.
+pub(crate) fn close_balance(
+    token_account: &AccountInfo<'_>,
+    holder_account: &AccountInfo<'_>,
+) -> Result<(), ProgramError> {
+    if !holder_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
     }
 
-    token_account
-        .data
-        .try_borrow_mut()
-        .map_err(|_e| ProgramError::AccountBorrowFailed)?
-        .copy_from_slice(&serialized_mint_details);
+    ensure_distinct_accounts(&[token_account.key, holder_account.key])?;
+
+    let mut token = storage::read::<TokenMintDetails>(token_account)?;
+
+    match token.balances.get(holder_account.key) {
+        Some(0) => {
+            token.balances.remove(holder_account.key);
+        }
+        Some(_) => return Err(FungibleTokenError::BalanceNotEmpty.into()),
+        None => return Err(ProgramError::UninitializedAccount),
+    }
 
+    storage::write(token_account, &token)?;
     Ok(())
 }
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct GetBalanceInput {
+    pub holder: Pubkey,
+}
+
+/// Log line emitted by `GetBalance`: `balance:<holder>:<amount>`.
+pub fn balance_log_line(holder: &Pubkey, balance: u64) -> String {
+    format!("balance:{}:{}", holder, balance)
+}
+
+/// Logs `holder`'s balance so clients don't have to decode the whole mint.
+/// Holders without an entry have a balance of zero.
+pub(crate) fn get_balance(
+    token_account: &AccountInfo<'_>,
+    holder: &Pubkey,
+) -> Result<u64, ProgramError> {
+    let token = storage::read::<TokenMintDetails>(token_account)?;
+
+    let balance = token.balances.get(holder).copied().unwrap_or(0);
+
+    msg!("{}", balance_log_line(holder, balance));
+
+    Ok(balance)
+}
+
+/// Log line emitted by `GetSupply`: `supply:<supply>:<circulating_supply>`.
+/// The format is part of the program's interface; append fields, never
+/// reorder them.
+pub fn supply_log_line(supply: u64, circulating_supply: u64) -> String {
+    format!("supply:{}:{}", supply, circulating_supply)
+}
+
+/// Logs the mint's total and circulating supply, returned in that order.
+pub(crate) fn get_supply(token_account: &AccountInfo<'_>) -> Result<(u64, u64), ProgramError> {
+    let token = storage::read::<TokenMintDetails>(token_account)?;
+
+    msg!(
+        "{}",
+        supply_log_line(token.supply, token.circulating_supply)
+    );
+
+    Ok((token.supply, token.circulating_supply))
+}
+
+#[cfg(test)]
+mod batch_mint_tests {
+    use super::*;
+    use crate::test_utils::{balance_of, mint_account, TestAccount};
+
+    #[test]
+    fn test_batch_mint_credits_every_recipient() {
+        let program_id = Pubkey::new_unique();
+        let mut authority = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = mint_account(&program_id, &authority.key(), 1_000);
+        let (alice, bob) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        batch_mint_tokens(
+            &token_account.info(),
+            &authority.info(),
+            &[(alice, 300), (bob, 200), (alice, 50)],
+        )
+        .unwrap();
+
+        assert_eq!(balance_of(&token_account, &alice), 350);
+        assert_eq!(balance_of(&token_account, &bob), 200);
+        let token = TokenMintDetails::try_from_slice(token_account.data()).unwrap();
+        assert_eq!(token.circulating_supply, 550);
+    }
+
+    #[test]
+    fn test_batch_mint_over_cap_rolls_back() {
+        let program_id = Pubkey::new_unique();
+        let mut authority = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = mint_account(&program_id, &authority.key(), 1_000);
+        let before = token_account.data().to_vec();
+
+        let result = batch_mint_tokens(
+            &token_account.info(),
+            &authority.info(),
+            &[
+                (Pubkey::new_unique(), 600),
+                (Pubkey::new_unique(), 300),
+                (Pubkey::new_unique(), 200),
+            ],
+        );
+
+        assert_eq!(
+            result,
+            Err(FungibleTokenError::NotEnoughRemainingMintableTokens.into())
+        );
+        assert_eq!(token_account.data(), &before[..]);
+    }
+
+    #[test]
+    fn test_batch_mint_requires_mint_authority() {
+        let program_id = Pubkey::new_unique();
+        let mut impostor = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = mint_account(&program_id, &Pubkey::new_unique(), 1_000);
+
+        let result = batch_mint_tokens(
+            &token_account.info(),
+            &impostor.info(),
+            &[(Pubkey::new_unique(), 1)],
+        );
+
+        assert_eq!(result, Err(ProgramError::IncorrectAuthority));
+    }
+
+    #[test]
+    fn test_batch_mint_rejects_oversized_batch() {
+        let program_id = Pubkey::new_unique();
+        let mut authority = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = mint_account(&program_id, &authority.key(), 1_000);
+        let recipients = vec![(Pubkey::new_unique(), 1); MAX_BATCH_MINT_RECIPIENTS + 1];
+
+        let result = batch_mint_tokens(&token_account.info(), &authority.info(), &recipients);
+
+        assert_eq!(result, Err(FungibleTokenError::BatchTooLarge.into()));
+    }
+}
+
+#[cfg(test)]
+mod mint_authority_tests {
+    use super::*;
+    use crate::process_instruction;
+    use crate::test_utils::{balance_of, config_account, mint_account, TestAccount};
+    use crate::types::MintTokenParams;
+
+    fn owner_of(token_account: &TestAccount) -> [u8; 32] {
+        TokenMintDetails::try_from_slice(token_account.data())
+            .unwrap()
+            .owner
+    }
+
+    fn mint_to_self(
+        program_id: &Pubkey,
+        token_account: &mut TestAccount,
+        authority: &mut TestAccount,
+    ) -> Result<(), ProgramError> {
+        let mut config = config_account(program_id, &Pubkey::new_unique(), false);
+        let mut data = vec![6];
+        data.extend(
+            borsh::to_vec(&MintTokenParams {
+                amount: 10,
+                nonce: None,
+            })
+            .unwrap(),
+        );
+        process_instruction(
+            program_id,
+            &[token_account.info(), authority.info(), config.info()],
+            &data,
+        )
+    }
+
+    #[test]
+    fn test_authority_rotates_to_the_new_key() {
+        let program_id = Pubkey::new_unique();
+        let mut old_authority = TestAccount::new(&program_id, &[]).signer();
+        let mut new_authority = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = mint_account(&program_id, &old_authority.key(), 1_000);
+
+        transfer_mint_authority(
+            &token_account.info(),
+            &old_authority.info(),
+            Some(new_authority.key().serialize()),
+        )
+        .unwrap();
+        assert_eq!(owner_of(&token_account), new_authority.key().serialize());
+
+        let recipients = [(Pubkey::new_unique(), 10)];
+        assert_eq!(
+            batch_mint_tokens(&token_account.info(), &old_authority.info(), &recipients),
+            Err(ProgramError::IncorrectAuthority)
+        );
+        batch_mint_tokens(&token_account.info(), &new_authority.info(), &recipients).unwrap();
+    }
+
+    #[test]
+    fn test_only_the_authority_can_transfer_it() {
+        let program_id = Pubkey::new_unique();
+        let authority = Pubkey::new_unique();
+        let mut impostor = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = mint_account(&program_id, &authority, 1_000);
+        let before = token_account.data().to_vec();
+        let impostor_key = impostor.key().serialize();
+
+        assert_eq!(
+            transfer_mint_authority(&token_account.info(), &impostor.info(), Some(impostor_key)),
+            Err(ProgramError::IncorrectAuthority)
+        );
+        assert_eq!(token_account.data(), &before[..]);
+
+        let mut unsigned = TestAccount::new(&program_id, &[]).with_key(authority);
+        assert_eq!(
+            transfer_mint_authority(&token_account.info(), &unsigned.info(), None),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+    }
+
+    #[test]
+    fn test_renounced_authority_disables_minting() {
+        let program_id = Pubkey::new_unique();
+        let mut authority = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = mint_account(&program_id, &authority.key(), 1_000);
+
+        let authority_key = authority.key().serialize();
+
+        transfer_mint_authority(&token_account.info(), &authority.info(), None).unwrap();
+        assert_eq!(owner_of(&token_account), RENOUNCED_MINT_AUTHORITY);
+
+        assert_eq!(
+            batch_mint_tokens(
+                &token_account.info(),
+                &authority.info(),
+                &[(Pubkey::new_unique(), 1)]
+            ),
+            Err(ProgramError::IncorrectAuthority)
+        );
+        assert_eq!(
+            transfer_mint_authority(
+                &token_account.info(),
+                &authority.info(),
+                Some(authority_key)
+            ),
+            Err(ProgramError::IncorrectAuthority)
+        );
+        assert_eq!(
+            mint_to_self(&program_id, &mut token_account, &mut authority),
+            Err(ProgramError::IncorrectAuthority)
+        );
+    }
+
+    #[test]
+    fn test_mint_needs_the_authority_to_sign() {
+        let program_id = Pubkey::new_unique();
+        let mut authority = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = mint_account(&program_id, &authority.key(), 1_000);
+        let mut unsigned = TestAccount::new(&program_id, &[]).with_key(authority.key());
+        let mut impostor = TestAccount::new(&program_id, &[]).signer();
+
+        assert_eq!(
+            mint_to_self(&program_id, &mut token_account, &mut unsigned),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert_eq!(
+            mint_to_self(&program_id, &mut token_account, &mut impostor),
+            Err(ProgramError::IncorrectAuthority)
+        );
+        mint_to_self(&program_id, &mut token_account, &mut authority).unwrap();
+        assert_eq!(balance_of(&token_account, &authority.key()), 10);
+    }
+
+    #[test]
+    fn test_finished_mint_takes_no_more_mints() {
+        let program_id = Pubkey::new_unique();
+        let mut authority = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = mint_account(&program_id, &authority.key(), 1_000);
+        let mut token = TokenMintDetails::try_from_slice(token_account.data()).unwrap();
+        token.status = MintStatus::Finished;
+        storage::write(&token_account.info(), &token).unwrap();
+
+        assert_eq!(
+            mint_to_self(&program_id, &mut token_account, &mut authority),
+            Err(FungibleTokenError::MintOver.into())
+        );
+    }
+}
+
+#[cfg(test)]
+mod multisig_tests {
+    use super::*;
+    use crate::process_instruction;
+    use crate::test_utils::{balance_of, config_account, mint_account, TestAccount};
+    use crate::types::MintTokenParams;
+
+    fn multisig_mint(program_id: &Pubkey, signers: &[&TestAccount], threshold: u8) -> TestAccount {
+        let multisig = MintMultisig {
+            signers: signers.iter().map(|s| s.key().serialize()).collect(),
+            threshold,
+        };
+        let input = InitializeMintInput::new([0; 32], 1_000, "BANGO".to_string(), 0)
+            .with_multisig(multisig);
+        let mut token_account =
+            TestAccount::new(program_id, &[]).with_key(derive_mint_address(program_id, "BANGO"));
+        initialize_mint(&token_account.info(), program_id, input).unwrap();
+        token_account
+    }
+
+    fn mint_with(
+        program_id: &Pubkey,
+        token_account: &mut TestAccount,
+        holder: &mut TestAccount,
+        signers: &mut [&mut TestAccount],
+    ) -> Result<(), ProgramError> {
+        let mut config = config_account(program_id, &Pubkey::new_unique(), false);
+        let mut accounts = vec![token_account.info(), holder.info(), config.info()];
+        accounts.extend(signers.iter_mut().map(|signer| signer.info()));
+
+        let mut data = vec![6];
+        data.extend(
+            borsh::to_vec(&MintTokenParams {
+                amount: 10,
+                nonce: None,
+            })
+            .unwrap(),
+        );
+        process_instruction(program_id, &accounts, &data)
+    }
+
+    #[test]
+    fn test_mint_with_the_threshold_of_signatures() {
+        let program_id = Pubkey::new_unique();
+        let mut a = TestAccount::new(&program_id, &[]).signer();
+        let mut b = TestAccount::new(&program_id, &[]).signer();
+        let c = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = multisig_mint(&program_id, &[&a, &b, &c], 2);
+        let mut holder = TestAccount::new(&program_id, &[]);
+
+        mint_with(
+            &program_id,
+            &mut token_account,
+            &mut holder,
+            &mut [&mut a, &mut b],
+        )
+        .unwrap();
+
+        assert_eq!(balance_of(&token_account, &holder.key()), 10);
+    }
+
+    #[test]
+    fn test_mint_short_of_the_threshold_is_rejected() {
+        let program_id = Pubkey::new_unique();
+        let mut a = TestAccount::new(&program_id, &[]).signer();
+        let mut b = TestAccount::new(&program_id, &[]);
+        let c = TestAccount::new(&program_id, &[]).signer();
+        let mut outsider = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = multisig_mint(&program_id, &[&a, &b, &c], 2);
+        let mut holder = TestAccount::new(&program_id, &[]);
+        let threshold_not_met = Err(FungibleTokenError::MultisigThresholdNotMet.into());
+
+        // b is listed but didn't sign, the outsider signed but isn't a member
+        assert_eq!(
+            mint_with(
+                &program_id,
+                &mut token_account,
+                &mut holder,
+                &mut [&mut a, &mut b, &mut outsider]
+            ),
+            threshold_not_met
+        );
+        assert_eq!(
+            mint_with(&program_id, &mut token_account, &mut holder, &mut []),
+            threshold_not_met
+        );
+        assert_eq!(balance_of(&token_account, &holder.key()), 0);
+    }
+
+    #[test]
+    fn test_the_same_signer_twice_is_rejected() {
+        let program_id = Pubkey::new_unique();
+        let a = TestAccount::new(&program_id, &[]).signer();
+        let b = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = multisig_mint(&program_id, &[&a, &b], 2);
+        let mut first = TestAccount::new(&program_id, &[])
+            .signer()
+            .with_key(a.key());
+        let mut again = TestAccount::new(&program_id, &[])
+            .signer()
+            .with_key(a.key());
+
+        let mut holder = TestAccount::new(&program_id, &[]);
+
+        assert_eq!(
+            ensure_multisig_signed(
+                &token_account.info(),
+                &holder.info(),
+                &[first.info(), again.info()]
+            ),
+            Err(FungibleTokenError::DuplicateSigner.into())
+        );
+    }
+
+    #[test]
+    fn test_single_owner_path_needs_the_owner_to_sign() {
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let mut token_account = mint_account(&program_id, &owner, 1_000);
+        let mut unsigned = TestAccount::new(&program_id, &[]).with_key(owner);
+        let mut signed = TestAccount::new(&program_id, &[]).signer().with_key(owner);
+        let mut bystander = TestAccount::new(&program_id, &[]).signer();
+
+        assert_eq!(
+            ensure_multisig_signed(&token_account.info(), &unsigned.info(), &[bystander.info()]),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        ensure_multisig_signed(&token_account.info(), &signed.info(), &[]).unwrap();
+    }
+
+    #[test]
+    fn test_unreachable_thresholds_are_refused_at_initialization() {
+        let program_id = Pubkey::new_unique();
+        let key = Pubkey::new_unique().serialize();
+
+        for multisig in [
+            MintMultisig {
+                signers: vec![key],
+                threshold: 2,
+            },
+            MintMultisig {
+                signers: vec![key],
+                threshold: 0,
+            },
+            MintMultisig {
+                signers: vec![key, key],
+                threshold: 1,
+            },
+        ] {
+            let input = InitializeMintInput::new(key, 1_000, "BANGO".to_string(), 0)
+                .with_multisig(multisig);
+            let mut token_account = TestAccount::new(&program_id, &[])
+                .with_key(derive_mint_address(&program_id, "BANGO"));
+
+            assert_eq!(
+                initialize_mint(&token_account.info(), &program_id, input),
+                Err(FungibleTokenError::InvalidMultisig.into())
+            );
+        }
+    }
+
+    #[test]
+    fn test_batch_mint_needs_only_the_multisig() {
+        let program_id = Pubkey::new_unique();
+        let mut a = TestAccount::new(&program_id, &[]).signer();
+        let mut b = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = multisig_mint(&program_id, &[&a, &b], 2);
+        // whoever submits the batch holds no authority and doesn't sign
+        let mut submitter = TestAccount::new(&program_id, &[]);
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+        let recipient = Pubkey::new_unique();
+        let mut data = vec![13];
+        data.extend(
+            borsh::to_vec(&BatchMintInput {
+                recipients: vec![(recipient, 25)],
+            })
+            .unwrap(),
+        );
+
+        assert_eq!(
+            process_instruction(
+                &program_id,
+                &[
+                    token_account.info(),
+                    submitter.info(),
+                    config.info(),
+                    a.info()
+                ],
+                &data,
+            ),
+            Err(FungibleTokenError::MultisigThresholdNotMet.into())
+        );
+        process_instruction(
+            &program_id,
+            &[
+                token_account.info(),
+                submitter.info(),
+                config.info(),
+                a.info(),
+                b.info(),
+            ],
+            &data,
+        )
+        .unwrap();
+
+        assert_eq!(balance_of(&token_account, &recipient), 25);
+    }
+}
+
+#[cfg(test)]
+mod close_balance_tests {
+    use super::*;
+    use crate::test_utils::{token_account, TestAccount};
+
+    #[test]
+    fn test_close_zero_balance_removes_entry_and_shrinks_account() {
+        let program_id = Pubkey::new_unique();
+        let mut holder = TestAccount::new(&program_id, &[]).signer();
+        let other = Pubkey::new_unique();
+        let mut token_account = token_account(&program_id, &[(holder.key(), 0), (other, 5)]);
+        let len_before = token_account.data().len();
+
+        close_balance(&token_account.info(), &holder.info()).unwrap();
+
+        let token = TokenMintDetails::try_from_slice(token_account.data()).unwrap();
+        assert!(!token.balances.contains_key(&holder.key()));
+        assert_eq!(token.balances[&other], 5);
+        assert_eq!(token_account.data().len(), len_before - 40);
+    }
+
+    #[test]
+    fn test_close_non_zero_balance_is_rejected() {
+        let program_id = Pubkey::new_unique();
+        let mut holder = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = token_account(&program_id, &[(holder.key(), 1)]);
+        let before = token_account.data().to_vec();
+
+        let result = close_balance(&token_account.info(), &holder.info());
+
+        assert_eq!(result, Err(FungibleTokenError::BalanceNotEmpty.into()));
+        assert_eq!(token_account.data(), &before[..]);
+    }
+}
+
+#[cfg(test)]
+mod get_balance_tests {
+    use super::*;
+    use crate::test_utils::token_account;
+
+    #[test]
+    fn test_balance_of_present_and_absent_holders() {
+        let program_id = Pubkey::new_unique();
+        let holder = Pubkey::new_unique();
+        let absent = Pubkey::new_unique();
+        let mut token_account = token_account(&program_id, &[(holder, 42)]);
+
+        assert_eq!(get_balance(&token_account.info(), &holder), Ok(42));
+        assert_eq!(get_balance(&token_account.info(), &absent), Ok(0));
+        assert_eq!(
+            balance_log_line(&holder, 42),
+            format!("balance:{}:42", holder)
+        );
+        assert_eq!(
+            balance_log_line(&absent, 0),
+            format!("balance:{}:0", absent)
+        );
+    }
+}
+
+#[cfg(test)]
+mod supply_tests {
+    use super::*;
+    use crate::test_utils::token_account;
+
+    #[test]
+    fn test_supply_tracks_mint_and_burn() {
+        let program_id = Pubkey::new_unique();
+        let holder = Pubkey::new_unique();
+        let mut token_account = token_account(&program_id, &[(holder, 50)]);
+
+        assert_eq!(get_supply(&token_account.info()), Ok((1_000_000, 50)));
+
+        mint_tokens(&token_account.info(), &holder, 100, None).unwrap();
+        assert_eq!(get_supply(&token_account.info()), Ok((1_000_000, 150)));
+
+        burn_balance(&token_account.info(), &holder, 30).unwrap();
+        assert_eq!(get_supply(&token_account.info()), Ok((1_000_000, 120)));
+        assert_eq!(supply_log_line(1_000_000, 120), "supply:1000000:120");
+    }
+
+    #[test]
+    fn test_mint_beyond_supply_is_rejected() {
+        let program_id = Pubkey::new_unique();
+        let holder = Pubkey::new_unique();
+        let mut token_account = token_account(&program_id, &[(holder, 999_990)]);
+
+        assert_eq!(
+            mint_tokens(&token_account.info(), &holder, 11, None),
+            Err(FungibleTokenError::NotEnoughRemainingMintableTokens.into())
+        );
+        mint_tokens(&token_account.info(), &holder, 10, None).unwrap();
+        assert_eq!(
+            get_supply(&token_account.info()),
+            Ok((1_000_000, 1_000_000))
+        );
+    }
+}
+
+#[cfg(test)]
+mod burn_tests {
+    use super::*;
+    use crate::process_instruction;
+    use crate::test_utils::{balance_of, config_account, token_account, TestAccount};
+    use crate::types::MintTokenParams;
+
+    fn burn(
+        program_id: &Pubkey,
+        token_account: &mut TestAccount,
+        holder: &mut TestAccount,
+        amount: u64,
+    ) -> Result<(), ProgramError> {
+        let mut config = config_account(program_id, &Pubkey::new_unique(), false);
+        let mut data = vec![7];
+        data.extend(
+            borsh::to_vec(&MintTokenParams {
+                amount,
+                nonce: None,
+            })
+            .unwrap(),
+        );
+        process_instruction(
+            program_id,
+            &[token_account.info(), holder.info(), config.info()],
+            &data,
+        )
+    }
+
+    #[test]
+    fn test_holder_burns_their_own_balance() {
+        let program_id = Pubkey::new_unique();
+        let mut holder = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = token_account(&program_id, &[(holder.key(), 50)]);
+
+        burn(&program_id, &mut token_account, &mut holder, 20).unwrap();
+
+        assert_eq!(balance_of(&token_account, &holder.key()), 30);
+        assert_eq!(get_supply(&token_account.info()), Ok((1_000_000, 30)));
+    }
+
+    #[test]
+    fn test_burning_another_holders_balance_is_rejected() {
+        let program_id = Pubkey::new_unique();
+        let victim = Pubkey::new_unique();
+        let mut token_account = token_account(&program_id, &[(victim, 50)]);
+        // naming the holder's key without their signature
+        let mut unsigned = TestAccount::new(&program_id, &[]).with_key(victim);
+
+        assert_eq!(
+            burn(&program_id, &mut token_account, &mut unsigned, 20),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert_eq!(balance_of(&token_account, &victim), 50);
+    }
+}
+
+#[cfg(test)]
+mod nonce_tests {
+    use super::*;
+    use crate::process_instruction;
+    use crate::test_utils::{balance_of, config_account, mint_account, TestAccount};
+    use crate::types::MintTokenParams;
+
+    fn mint_once(
+        program_id: &Pubkey,
+        token_account: &mut TestAccount,
+        holder: &mut TestAccount,
+        nonce: [u8; 32],
+    ) -> Result<(), ProgramError> {
+        let mut config = config_account(program_id, &Pubkey::new_unique(), false);
+        let mut data = vec![6];
+        data.extend(
+            borsh::to_vec(&MintTokenParams {
+                amount: 10,
+                nonce: Some(nonce),
+            })
+            .unwrap(),
+        );
+        process_instruction(
+            program_id,
+            &[token_account.info(), holder.info(), config.info()],
+            &data,
+        )
+    }
+
+    #[test]
+    fn test_resubmitted_mint_is_rejected() {
+        let program_id = Pubkey::new_unique();
+        // the mint's authority, minting to itself
+        let mut holder = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = mint_account(&program_id, &holder.key(), 1_000);
+
+        mint_once(&program_id, &mut token_account, &mut holder, [1; 32]).unwrap();
+        assert_eq!(
+            mint_once(&program_id, &mut token_account, &mut holder, [1; 32]),
+            Err(FungibleTokenError::NonceAlreadyProcessed.into())
+        );
+        assert_eq!(balance_of(&token_account, &holder.key()), 10);
+
+        mint_once(&program_id, &mut token_account, &mut holder, [2; 32]).unwrap();
+        assert_eq!(balance_of(&token_account, &holder.key()), 20);
+    }
+
+    #[test]
+    fn test_only_the_latest_nonces_are_remembered() {
+        let mut token = TokenMintDetails::new(
+            InitializeMintInput::new([0; 32], 1_000, "BANGO".to_string(), 0),
+            MintStatus::Ongoing,
+            HashMap::new(),
+        );
+        let nonce = |i: usize| {
+            let mut nonce = [0; 32];
+            nonce[..8].copy_from_slice(&(i as u64).to_le_bytes());
+            nonce
+        };
+
+        for i in 0..MAX_PROCESSED_NONCES {
+            token.record_nonce(nonce(i)).unwrap();
+        }
+        assert_eq!(
+            token.record_nonce(nonce(0)),
+            Err(FungibleTokenError::NonceAlreadyProcessed)
+        );
+
+        // one more pushes the oldest out of the window
+        token.record_nonce(nonce(MAX_PROCESSED_NONCES)).unwrap();
+        assert_eq!(token.processed_nonces.len(), MAX_PROCESSED_NONCES);
+        token.record_nonce(nonce(0)).unwrap();
+        assert_eq!(
+            token.record_nonce(nonce(2)),
+            Err(FungibleTokenError::NonceAlreadyProcessed)
+        );
+    }
+}
+
+#[cfg(test)]
+mod mint_address_tests {
+    use super::*;
+    use crate::test_utils::TestAccount;
+
+    #[test]
+    fn test_mint_address_derivation_is_stable() {
+        let program_id = Pubkey::new_unique();
+        let address = derive_mint_address(&program_id, "BANGO");
+
+        assert_eq!(address, derive_mint_address(&program_id, "BANGO"));
+        assert_ne!(address, derive_mint_address(&program_id, "BANG"));
+        assert_ne!(address, derive_mint_address(&Pubkey::new_unique(), "BANGO"));
+    }
+
+    #[test]
+    fn test_mint_off_its_derived_address_is_rejected() {
+        let program_id = Pubkey::new_unique();
+        let input = || InitializeMintInput::new([0; 32], 1_000, "BANGO".to_string(), 0);
+        let mut stray = TestAccount::new(&program_id, &[]);
+        let mut other_ticker =
+            TestAccount::new(&program_id, &[]).with_key(derive_mint_address(&program_id, "BANG"));
+
+        for account in [&mut stray, &mut other_ticker] {
+            assert_eq!(
+                initialize_mint(&account.info(), &program_id, input()),
+                Err(FungibleTokenError::MintAddressMismatch.into())
+            );
+            assert!(account.data().is_empty());
+        }
+
+        let mut derived =
+            TestAccount::new(&program_id, &[]).with_key(derive_mint_address(&program_id, "BANGO"));
+        initialize_mint(&derived.info(), &program_id, input()).unwrap();
+    }
+}
@@ -0,0 +1,424 @@
+//! Position offers. A holder sells part of their position on an outcome to
+//! whoever accepts first, at a fixed price in tokens. Offering takes the
+//! shares and their cost basis off the seller's position into the event,
+//! under an offer id of that event; accepting pays the seller the ask price
+//! and hands the shares to the buyer in one instruction. Nothing goes
+//! through the pool, so its totals stay as they are.
+//!
+//! Offers expire with the event: they can only be made and accepted while it
+//! is active and before its expiry height. Cancelling puts the shares back
+//! into the seller's position whatever the state of the event.
+use arch_program::{account::AccountInfo, msg, program_error::ProgramError, pubkey::Pubkey};
+
+use crate::{
+    config, ensure_distinct_accounts, ensure_program_owned, event_store, get_bitcoin_block_height,
+    transfer::move_balance,
+    types::{
+        net_stake, AcceptOfferParams, Bet, BetType, CancelOfferParams, EventStatus, MarketType,
+        OfferPositionParams, PositionOffer, PredictionEvent, PredictionMarketError,
+    },
+};
+
+/// Escrows `amount` of the signing seller's position on `outcome_id` in an
+/// offer at `ask_price`, returning the offer id. Only the free position can
+/// be offered: shares already on offer or resting in an ask are not part of
+/// it.
+pub fn process_offer_position<'a>(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'a>,
+    seller_account: &AccountInfo<'a>,
+    config_account: &AccountInfo<'a>,
+    params: OfferPositionParams,
+) -> Result<u64, ProgramError> {
+    ensure_distinct_accounts(&[event_account.key, seller_account.key, config_account.key])?;
+
+    ensure_program_owned(program_id, &[event_account, config_account])?;
+
+    if !seller_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    config::ensure_not_paused(config_account)?;
+
+    let mut event = event_store::load_event(event_account, &params.unique_id)?;
+    let height = get_bitcoin_block_height();
+    ensure_open_for_offers(&event, height)?;
+
+    let seller = *seller_account.key;
+    let outcome_index = outcome_index(&event, params.outcome_id)?;
+    event.ensure_token_backed(outcome_index, &seller, params.amount)?;
+    let outcome = &mut event.outcomes[outcome_index];
+    let held = net_stake(outcome, &seller);
+    outcome.withdraw_position(&seller, params.amount)?;
+    let cost_basis = event
+        .cost_basis
+        .entry((seller, params.outcome_id))
+        .or_default()
+        .release(params.amount, held);
+
+    let offer_id = event.next_offer_id;
+    event.next_offer_id = offer_id
+        .checked_add(1)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    event.offers.insert(
+        offer_id,
+        PositionOffer {
+            seller,
+            outcome_id: params.outcome_id,
+            amount: params.amount,
+            ask_price: params.ask_price,
+            cost_basis,
+        },
+    );
+
+    event_store::store_event(event_account, &event)?;
+
+    msg!(
+        "Offer {} sells {} of outcome {} for {}",
+        offer_id,
+        params.amount,
+        params.outcome_id,
+        params.ask_price
+    );
+
+    Ok(offer_id)
+}
+
+/// Buys an offer outright: the signing buyer pays the ask price to the
+/// seller and takes the offered shares, with the ask price as their cost.
+/// The offer is gone afterwards, so it can only be accepted once. The
+/// buyer's bet limit applies.
+pub fn process_accept_offer<'a>(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'a>,
+    token_account: &AccountInfo<'a>,
+    buyer_account: &AccountInfo<'a>,
+    config_account: &AccountInfo<'a>,
+    params: AcceptOfferParams,
+) -> Result<(), ProgramError> {
+    ensure_distinct_accounts(&[
+        event_account.key,
+        token_account.key,
+        buyer_account.key,
+        config_account.key,
+    ])?;
+
+    ensure_program_owned(program_id, &[event_account, token_account, config_account])?;
+
+    if !buyer_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    config::ensure_not_paused(config_account)?;
+
+    let mut event = event_store::load_event(event_account, &params.unique_id)?;
+    event.ensure_mint(token_account.key)?;
+    let height = get_bitcoin_block_height();
+    ensure_open_for_offers(&event, height)?;
+
+    let buyer = *buyer_account.key;
+    let offer = event
+        .offers
+        .remove(&params.offer_id)
+        .ok_or(PredictionMarketError::OfferNotFound)?;
+    if offer.seller == buyer {
+        return Err(PredictionMarketError::SelfTransfer.into());
+    }
+    event.ensure_within_bet_limit(&buyer, offer.amount)?;
+
+    move_balance(token_account, &buyer, &offer.seller, offer.ask_price)?;
+
+    let outcome_index = outcome_index(&event, offer.outcome_id)?;
+    event.outcomes[outcome_index].deposit_position(Bet {
+        user: buyer,
+        event_id: params.unique_id,
+        outcome_id: offer.outcome_id,
+        amount: offer.amount,
+        timestamp: height as i64,
+        bet_type: BetType::BUY,
+    });
+    event
+        .cost_basis
+        .entry((offer.seller, offer.outcome_id))
+        .or_default()
+        .realize(offer.ask_price, offer.cost_basis)?;
+    event
+        .cost_basis
+        .entry((buyer, offer.outcome_id))
+        .or_default()
+        .buy(offer.ask_price)?;
+    if let MarketType::Lmsr { .. } = event.market_type {
+        if let Some(paid) = event.net_paid.get_mut(&offer.seller) {
+            *paid = paid.saturating_sub(offer.ask_price);
+        }
+        *event.net_paid.entry(buyer).or_default() += offer.ask_price;
+    }
+
+    event_store::store_event(event_account, &event)?;
+
+    msg!(
+        "Offer {} accepted by {} for {}",
+        params.offer_id,
+        buyer,
+        offer.ask_price
+    );
+
+    Ok(())
+}
+
+/// Withdraws the signing seller's offer, putting its shares and cost basis
+/// back into their position. Shares that come back after the seller claimed
+/// cannot be claimed again.
+pub fn process_cancel_offer<'a>(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'a>,
+    seller_account: &AccountInfo<'a>,
+    config_account: &AccountInfo<'a>,
+    params: CancelOfferParams,
+) -> Result<(), ProgramError> {
+    ensure_distinct_accounts(&[event_account.key, seller_account.key, config_account.key])?;
+
+    ensure_program_owned(program_id, &[event_account, config_account])?;
+
+    if !seller_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    config::ensure_not_paused(config_account)?;
+
+    let mut event = event_store::load_event(event_account, &params.unique_id)?;
+
+    let offer = event
+        .offers
+        .remove(&params.offer_id)
+        .ok_or(PredictionMarketError::OfferNotFound)?;
+    if offer.seller != *seller_account.key {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let height = get_bitcoin_block_height();
+    let outcome_index = outcome_index(&event, offer.outcome_id)?;
+    event.outcomes[outcome_index].deposit_position(Bet {
+        user: offer.seller,
+        event_id: params.unique_id,
+        outcome_id: offer.outcome_id,
+        amount: offer.amount,
+        timestamp: height as i64,
+        bet_type: BetType::BUY,
+    });
+    event
+        .cost_basis
+        .entry((offer.seller, offer.outcome_id))
+        .or_default()
+        .buy(offer.cost_basis)?;
+
+    event_store::store_event(event_account, &event)?;
+
+    msg!("Offer {} cancelled", params.offer_id);
+
+    Ok(())
+}
+
+fn ensure_open_for_offers(event: &PredictionEvent, height: u64) -> Result<(), ProgramError> {
+    if event.status != EventStatus::Active {
+        return Err(PredictionMarketError::EventNotActive.into());
+    }
+
+    if height >= event.expiry_timestamp as u64 {
+        return Err(PredictionMarketError::EventExpired.into());
+    }
+
+    Ok(())
+}
+
+fn outcome_index(event: &PredictionEvent, outcome_id: u8) -> Result<usize, ProgramError> {
+    event
+        .outcome_index(outcome_id)
+        .ok_or(PredictionMarketError::InvalidOutcome.into())
+}
+
+#[cfg(test)]
+mod offer_tests {
+    use super::*;
+    use crate::process_resolve_event;
+    use crate::test_utils::{set_block_height, Market, TestAccount};
+
+    const EVENT_ID: [u8; 32] = [57; 32];
+
+    fn offer(
+        market: &mut Market,
+        seller: &mut TestAccount,
+        outcome_id: u8,
+        amount: u64,
+        ask_price: u64,
+    ) -> Result<u64, ProgramError> {
+        let program_id = market.event.owner;
+        process_offer_position(
+            &program_id,
+            &market.event.info(),
+            &seller.info(),
+            &market.config.info(),
+            OfferPositionParams {
+                unique_id: EVENT_ID,
+                outcome_id,
+                amount,
+                ask_price,
+            },
+        )
+    }
+
+    fn accept(
+        market: &mut Market,
+        buyer: &mut TestAccount,
+        offer_id: u64,
+    ) -> Result<(), ProgramError> {
+        let program_id = market.event.owner;
+        process_accept_offer(
+            &program_id,
+            &market.event.info(),
+            &market.token.info(),
+            &buyer.info(),
+            &market.config.info(),
+            AcceptOfferParams {
+                unique_id: EVENT_ID,
+                offer_id,
+            },
+        )
+    }
+
+    fn cancel(
+        market: &mut Market,
+        seller: &mut TestAccount,
+        offer_id: u64,
+    ) -> Result<(), ProgramError> {
+        let program_id = market.event.owner;
+        process_cancel_offer(
+            &program_id,
+            &market.event.info(),
+            &seller.info(),
+            &market.config.info(),
+            CancelOfferParams {
+                unique_id: EVENT_ID,
+                offer_id,
+            },
+        )
+    }
+
+    // alice holds 100 of outcome 0 and carol 100 of outcome 1; bob has 200
+    // tokens to buy with
+    fn market(alice: &mut TestAccount, bob: &TestAccount, carol: &mut TestAccount) -> Market {
+        let program_id = alice.owner;
+        let mut market = Market::new(
+            &program_id,
+            EVENT_ID,
+            &[(alice.key(), 100), (bob.key(), 200), (carol.key(), 100)],
+        );
+        market.buy(alice, 0, 100).unwrap();
+        market.buy(carol, 1, 100).unwrap();
+        market
+    }
+
+    #[test]
+    fn test_accepted_offer_swaps_position_for_tokens() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut carol = TestAccount::new(&program_id, &[]).signer();
+        let mut market = market(&mut alice, &bob, &mut carol);
+
+        let offer_id = offer(&mut market, &mut alice, 0, 60, 90).unwrap();
+        // the offered shares are no longer free to offer again
+        assert_eq!(
+            offer(&mut market, &mut alice, 0, 50, 10),
+            Err(PredictionMarketError::InsufficientPosition.into())
+        );
+        assert_eq!(
+            accept(&mut market, &mut alice, offer_id),
+            Err(PredictionMarketError::SelfTransfer.into())
+        );
+
+        accept(&mut market, &mut bob, offer_id).unwrap();
+        assert_eq!(
+            accept(&mut market, &mut carol, offer_id),
+            Err(PredictionMarketError::OfferNotFound.into())
+        );
+
+        let event = market.event_state();
+        assert_eq!(net_stake(&event.outcomes[0], &alice.key()), 40);
+        assert_eq!(net_stake(&event.outcomes[0], &bob.key()), 60);
+        assert_eq!(event.outcomes[0].total_amount, 100);
+        assert_eq!(event.total_pool_amount, 200);
+        assert_eq!(event.cost_basis[&(alice.key(), 0)].realized_pnl, 30);
+        assert_eq!(event.cost_basis[&(bob.key(), 0)].cost_basis_total, 90);
+        assert_eq!(market.balance(&alice), 90);
+        assert_eq!(market.balance(&bob), 110);
+
+        process_resolve_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+            0,
+        )
+        .unwrap();
+        market.claim(&mut alice).unwrap();
+        market.claim(&mut bob).unwrap();
+        assert_eq!(market.balance(&alice), 170);
+        assert_eq!(market.balance(&bob), 230);
+    }
+
+    #[test]
+    fn test_cancelled_offer_returns_the_position() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut carol = TestAccount::new(&program_id, &[]).signer();
+        let mut market = market(&mut alice, &bob, &mut carol);
+        let before = market.event_state();
+
+        let offer_id = offer(&mut market, &mut alice, 0, 60, 90).unwrap();
+        assert_eq!(
+            cancel(&mut market, &mut bob, offer_id),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        cancel(&mut market, &mut alice, offer_id).unwrap();
+        assert_eq!(
+            accept(&mut market, &mut bob, offer_id),
+            Err(PredictionMarketError::OfferNotFound.into())
+        );
+
+        let event = market.event_state();
+        assert_eq!(net_stake(&event.outcomes[0], &alice.key()), 100);
+        assert_eq!(event.cost_basis, before.cost_basis);
+        assert!(event.offers.is_empty());
+        assert_eq!(market.balance(&bob), 200);
+    }
+
+    #[test]
+    fn test_offers_expire_with_the_event() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut carol = TestAccount::new(&program_id, &[]).signer();
+        let mut market = market(&mut alice, &bob, &mut carol);
+        let offer_id = offer(&mut market, &mut alice, 0, 60, 90).unwrap();
+
+        // the market's event expires at height 100
+        set_block_height(100);
+        assert_eq!(
+            accept(&mut market, &mut bob, offer_id),
+            Err(PredictionMarketError::EventExpired.into())
+        );
+        assert_eq!(
+            offer(&mut market, &mut alice, 0, 10, 10),
+            Err(PredictionMarketError::EventExpired.into())
+        );
+
+        // the seller still gets their shares back
+        cancel(&mut market, &mut alice, offer_id).unwrap();
+        assert_eq!(
+            net_stake(&market.event_state().outcomes[0], &alice.key()),
+            100
+        );
+    }
+}
@@ -0,0 +1,1082 @@
+//! Limit orders. Next to its pool, an event can have an order book account
+//! where users rest orders on its outcomes at a price of their own, in basis
+//! points of a token per share. A bid escrows what it would pay in a vault
+//! balance of the event's mint; an ask takes the shares it offers off its
+//! owner's position until it fills or is cancelled. Market buys and sells
+//! cross the book before falling back to the pool, best price first and
+//! oldest first at the same price, for as long as the book beats the pool.
+//! A market order never fills its taker's own orders; the configured
+//! `SelfTradePolicy` decides what gives way instead.
+use std::collections::BTreeMap;
+
+use arch_program::{
+    account::AccountInfo,
+    bitcoin::hashes::{sha256, Hash, HashEngine},
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::{
+    config::{self, SelfTradePolicy},
+    ensure_distinct_accounts, ensure_program_owned, event_store, get_bitcoin_block_height,
+    math::{self, BPS_DENOMINATOR},
+    storage,
+    transfer::move_balance,
+    types::{
+        net_stake, Bet, BetType, EventStatus, MarketType, OrderSide, PlaceLimitOrderParams,
+        PredictionEvent, PredictionMarketError,
+    },
+};
+
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct Order {
+    pub order_id: u64,
+    pub owner: Pubkey,
+    pub side: OrderSide,
+    pub price_bps: u16,
+    // shares left to fill
+    pub amount: u64,
+    // bids only, what is left of the escrowed payment
+    pub escrowed: u64,
+    // asks only, the cost basis of the shares left
+    pub cost_basis: u64,
+}
+
+/// Resting orders on one outcome, each side sorted best price first and
+/// oldest first at the same price.
+#[derive(Debug, Clone, Default, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct OutcomeBook {
+    pub bids: Vec<Order>,
+    pub asks: Vec<Order>,
+}
+
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct OrderBook {
+    // the event account and the event in it the book trades
+    pub event: Pubkey,
+    pub unique_id: [u8; 32],
+    // id of the next order placed, so ids only ever increase
+    pub next_order_id: u64,
+    // keyed by outcome id
+    pub books: BTreeMap<u8, OutcomeBook>,
+}
+
+/// A resting order's part in a market order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fill {
+    pub maker: Pubkey,
+    pub amount: u64,
+    // what the taker pays for an ask, or is paid out of a bid's escrow
+    pub tokens: u64,
+    // asks only, the cost basis of the shares filled
+    pub cost_basis: u64,
+    // bids only, escrow left over once the bid filled completely
+    pub refund: u64,
+}
+
+/// What a market order did to the book.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Matched {
+    pub fills: Vec<Fill>,
+    // the taker's own orders, or parts of them, self-trade prevention took
+    // off the book without a trade
+    pub released: Vec<Order>,
+    // shares of the market order self-trade prevention cancelled
+    pub withheld: u64,
+}
+
+impl Matched {
+    pub fn filled(&self) -> u64 {
+        self.fills.iter().map(|fill| fill.amount).sum()
+    }
+
+    /// Whether the book changed, so it needs storing.
+    pub fn touched_book(&self) -> bool {
+        !self.fills.is_empty() || !self.released.is_empty()
+    }
+}
+
+/// Accounts of placing or cancelling an order, in instruction order.
+#[derive(Clone, Copy)]
+pub struct OrderAccounts<'b, 'a> {
+    pub event: &'b AccountInfo<'a>,
+    pub orders: &'b AccountInfo<'a>,
+    // the event's mint, holding the vault balance
+    pub token: &'b AccountInfo<'a>,
+    pub owner: &'b AccountInfo<'a>,
+    pub config: &'b AccountInfo<'a>,
+}
+
+/// Balance key holding what the bids of an order book escrow. Derived from
+/// the account, so it can't belong to any signer.
+pub fn vault_address(orders_account: &Pubkey) -> Pubkey {
+    let mut engine = sha256::Hash::engine();
+    engine.input(b"order_vault");
+    engine.input(&orders_account.0);
+    Pubkey(sha256::Hash::from_engine(engine).to_byte_array())
+}
+
+/// What the pool asks for a share of outcome `index`, in basis points of a
+/// token: par for a pool stake, the marginal LMSR price otherwise. Orders
+/// only cross when they beat it.
+pub fn pool_price_bps(event: &PredictionEvent, index: usize) -> u64 {
+    match event.market_type {
+        MarketType::ParimutuelPool | MarketType::Scalar { .. } => BPS_DENOMINATOR,
+        MarketType::Lmsr { .. } => math::compute_odds(event)[index].pool_share_bps as u64,
+    }
+}
+
+// what `amount` shares come to at `price_bps`
+fn price_of(amount: u64, price_bps: u16, round_up: bool) -> Result<u64, ProgramError> {
+    let value = amount as u128 * price_bps as u128;
+    let tokens = if round_up {
+        value.div_ceil(BPS_DENOMINATOR as u128)
+    } else {
+        value / BPS_DENOMINATOR as u128
+    };
+    u64::try_from(tokens).map_err(|_| ProgramError::ArithmeticOverflow)
+}
+
+impl Order {
+    // fills `amount`, at most what is left of the order; the taker pays the
+    // rounding either way
+    fn fill(&mut self, amount: u64) -> Result<Fill, ProgramError> {
+        let mut fill = Fill {
+            maker: self.owner,
+            amount,
+            tokens: 0,
+            cost_basis: 0,
+            refund: 0,
+        };
+
+        match self.side {
+            OrderSide::Sell => {
+                fill.tokens = price_of(amount, self.price_bps, true)?;
+                fill.cost_basis =
+                    (self.cost_basis as u128 * amount as u128 / self.amount as u128) as u64;
+                self.cost_basis -= fill.cost_basis;
+            }
+            OrderSide::Buy => {
+                fill.tokens = price_of(amount, self.price_bps, false)?.min(self.escrowed);
+                self.escrowed -= fill.tokens;
+            }
+        }
+
+        self.amount -= amount;
+        if self.amount == 0 {
+            fill.refund = std::mem::take(&mut self.escrowed);
+        }
+
+        Ok(fill)
+    }
+
+    // takes `amount` off the order without trading it, along with its share
+    // of the escrow or cost basis; all of it once nothing is left
+    fn split_off(&mut self, amount: u64) -> Result<Order, ProgramError> {
+        let mut part = Order {
+            amount,
+            escrowed: 0,
+            cost_basis: 0,
+            ..self.clone()
+        };
+
+        if amount == self.amount {
+            part.escrowed = std::mem::take(&mut self.escrowed);
+            part.cost_basis = std::mem::take(&mut self.cost_basis);
+        } else {
+            match self.side {
+                OrderSide::Sell => {
+                    part.cost_basis =
+                        (self.cost_basis as u128 * amount as u128 / self.amount as u128) as u64;
+                    self.cost_basis -= part.cost_basis;
+                }
+                OrderSide::Buy => {
+                    part.escrowed = price_of(amount, self.price_bps, false)?.min(self.escrowed);
+                    self.escrowed -= part.escrowed;
+                }
+            }
+        }
+
+        self.amount -= amount;
+
+        Ok(part)
+    }
+}
+
+impl OutcomeBook {
+    fn side_mut(&mut self, side: OrderSide) -> &mut Vec<Order> {
+        match side {
+            OrderSide::Buy => &mut self.bids,
+            OrderSide::Sell => &mut self.asks,
+        }
+    }
+
+    /// Rests `order` behind every order at the same or a better price.
+    pub fn insert(&mut self, order: Order) {
+        let price = order.price_bps;
+        let orders = self.side_mut(order.side);
+        let index = orders.partition_point(|resting| match resting.side {
+            OrderSide::Buy => resting.price_bps >= price,
+            OrderSide::Sell => resting.price_bps <= price,
+        });
+        orders.insert(index, order);
+    }
+}
+
+impl OrderBook {
+    /// Crosses `taker`'s market order on the `side` given, for `amount`
+    /// shares of `outcome_id`, against the resting orders of the other side
+    /// that beat `pool_price_bps`. Filled orders leave the book and partly
+    /// filled ones keep the rest; either way only in memory until the book is
+    /// stored. The taker's own orders are never filled but dealt with as
+    /// `policy` says.
+    pub fn match_market_order(
+        &mut self,
+        outcome_id: u8,
+        side: OrderSide,
+        taker: &Pubkey,
+        amount: u64,
+        pool_price_bps: u64,
+        policy: SelfTradePolicy,
+    ) -> Result<Matched, ProgramError> {
+        let mut matched = Matched::default();
+
+        let Some(book) = self.books.get_mut(&outcome_id) else {
+            return Ok(matched);
+        };
+        let resting = match side {
+            OrderSide::Buy => &mut book.asks,
+            OrderSide::Sell => &mut book.bids,
+        };
+
+        let mut remaining = amount;
+
+        while remaining > 0 {
+            let Some(order) = resting.first_mut() else {
+                break;
+            };
+            let price = order.price_bps as u64;
+            let crosses = match side {
+                OrderSide::Buy => price <= pool_price_bps,
+                OrderSide::Sell => price >= pool_price_bps,
+            };
+            if !crosses {
+                break;
+            }
+
+            if order.owner == *taker {
+                match policy {
+                    SelfTradePolicy::CancelResting => {
+                        matched.released.push(resting.remove(0));
+                    }
+                    SelfTradePolicy::CancelIncoming => {
+                        matched.withheld = remaining;
+                        break;
+                    }
+                    SelfTradePolicy::DecrementBoth => {
+                        let cut = order.amount.min(remaining);
+                        matched.released.push(order.split_off(cut)?);
+                        matched.withheld += cut;
+                        remaining -= cut;
+
+                        if order.amount == 0 {
+                            resting.remove(0);
+                        }
+                    }
+                }
+                continue;
+            }
+
+            let filled = order.amount.min(remaining);
+            matched.fills.push(order.fill(filled)?);
+            remaining -= filled;
+
+            if order.amount == 0 {
+                resting.remove(0);
+            }
+        }
+
+        Ok(matched)
+    }
+
+    fn remove(&mut self, order_id: u64) -> Option<(u8, Order)> {
+        self.books.iter_mut().find_map(|(outcome_id, book)| {
+            [&mut book.bids, &mut book.asks]
+                .into_iter()
+                .find_map(|orders| {
+                    let index = orders.iter().position(|o| o.order_id == order_id)?;
+                    Some((*outcome_id, orders.remove(index)))
+                })
+        })
+    }
+}
+
+/// Loads the book of the event `unique_id` in `event_account`; an empty
+/// account holds none yet.
+pub fn load_book(
+    orders_account: &AccountInfo<'_>,
+    event_account: &Pubkey,
+    unique_id: &[u8; 32],
+) -> Result<OrderBook, ProgramError> {
+    if orders_account.data_is_empty() {
+        return Ok(OrderBook {
+            event: *event_account,
+            unique_id: *unique_id,
+            next_order_id: 0,
+            books: BTreeMap::new(),
+        });
+    }
+
+    let book = decode_book(orders_account)?;
+
+    if book.event != *event_account || book.unique_id != *unique_id {
+        return Err(PredictionMarketError::OrderBookMismatch.into());
+    }
+
+    Ok(book)
+}
+
+fn decode_book(orders_account: &AccountInfo<'_>) -> Result<OrderBook, ProgramError> {
+    storage::read::<OrderBook>(orders_account)
+}
+
+fn bought_position(
+    event: &PredictionEvent,
+    user: Pubkey,
+    outcome_id: u8,
+    amount: u64,
+    height: u64,
+) -> Bet {
+    Bet {
+        user,
+        event_id: event.unique_id,
+        outcome_id,
+        amount,
+        timestamp: height as i64,
+        bet_type: BetType::BUY,
+    }
+}
+
+/// Pays the makers of the asks a market buy filled. The taker's position and
+/// cost basis are the buy's to record.
+pub fn settle_buy_fills(
+    token_account: &AccountInfo<'_>,
+    event: &mut PredictionEvent,
+    outcome_id: u8,
+    taker: &Pubkey,
+    fills: &[Fill],
+) -> Result<(), ProgramError> {
+    for fill in fills {
+        if fill.tokens > 0 {
+            move_balance(token_account, taker, &fill.maker, fill.tokens)?;
+        }
+
+        event
+            .cost_basis
+            .entry((fill.maker, outcome_id))
+            .or_default()
+            .realize(fill.tokens, fill.cost_basis)?;
+        if let Some(paid) = event.net_paid.get_mut(&fill.maker) {
+            *paid = paid.saturating_sub(fill.tokens);
+        }
+    }
+
+    Ok(())
+}
+
+/// Pays a market sell out of the escrow of the bids it filled and hands
+/// their makers the shares, bought at `height`. The taker's position must
+/// already be withdrawn.
+pub fn settle_sell_fills(
+    token_account: &AccountInfo<'_>,
+    orders_account: &AccountInfo<'_>,
+    event: &mut PredictionEvent,
+    outcome_index: usize,
+    taker: &Pubkey,
+    fills: &[Fill],
+    height: u64,
+) -> Result<(), ProgramError> {
+    let vault = vault_address(orders_account.key);
+    let outcome_id = event.outcomes[outcome_index].id;
+
+    for fill in fills {
+        if fill.tokens > 0 {
+            move_balance(token_account, &vault, taker, fill.tokens)?;
+        }
+        if fill.refund > 0 {
+            move_balance(token_account, &vault, &fill.maker, fill.refund)?;
+        }
+
+        let bet = bought_position(event, fill.maker, outcome_id, fill.amount, height);
+        event.outcomes[outcome_index].deposit_position(bet);
+        event
+            .cost_basis
+            .entry((fill.maker, outcome_id))
+            .or_default()
+            .buy(fill.tokens)?;
+        if let MarketType::Lmsr { .. } = event.market_type {
+            *event.net_paid.entry(fill.maker).or_default() += fill.tokens;
+        }
+    }
+
+    Ok(())
+}
+
+/// Hands the owners of the orders self-trade prevention took off the book
+/// back what they held, as a cancel at `height` would.
+pub fn settle_released(
+    token_account: &AccountInfo<'_>,
+    orders_account: &AccountInfo<'_>,
+    event: &mut PredictionEvent,
+    outcome_index: usize,
+    released: &[Order],
+    height: u64,
+) -> Result<(), ProgramError> {
+    for order in released {
+        match order.side {
+            OrderSide::Buy => refund_escrow(token_account, orders_account, order)?,
+            OrderSide::Sell => restore_shares(event, outcome_index, order, height)?,
+        }
+    }
+
+    Ok(())
+}
+
+// pays what is left of a bid's escrow back to its owner
+fn refund_escrow(
+    token_account: &AccountInfo<'_>,
+    orders_account: &AccountInfo<'_>,
+    order: &Order,
+) -> Result<(), ProgramError> {
+    if order.escrowed > 0 {
+        move_balance(
+            token_account,
+            &vault_address(orders_account.key),
+            &order.owner,
+            order.escrowed,
+        )?;
+    }
+
+    Ok(())
+}
+
+// puts what is left of an ask back into its owner's position
+fn restore_shares(
+    event: &mut PredictionEvent,
+    outcome_index: usize,
+    order: &Order,
+    height: u64,
+) -> Result<(), ProgramError> {
+    let outcome_id = event.outcomes[outcome_index].id;
+    let bet = bought_position(event, order.owner, outcome_id, order.amount, height);
+    event.outcomes[outcome_index].deposit_position(bet);
+    event
+        .cost_basis
+        .entry((order.owner, outcome_id))
+        .or_default()
+        .buy(order.cost_basis)
+}
+
+fn ensure_order_accounts(
+    program_id: &Pubkey,
+    accounts: OrderAccounts<'_, '_>,
+) -> Result<(), ProgramError> {
+    ensure_distinct_accounts(&[
+        accounts.event.key,
+        accounts.orders.key,
+        accounts.token.key,
+        accounts.owner.key,
+        accounts.config.key,
+    ])?;
+
+    ensure_program_owned(
+        program_id,
+        &[
+            accounts.event,
+            accounts.orders,
+            accounts.token,
+            accounts.config,
+        ],
+    )?;
+
+    if !accounts.owner.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    config::ensure_not_paused(accounts.config)
+}
+
+/// Rests a limit order on an active event and returns its id. A bid escrows
+/// its whole price up front, rounded up; an ask withdraws the shares it
+/// offers, along with their cost basis.
+pub fn process_place_limit_order(
+    program_id: &Pubkey,
+    accounts: OrderAccounts<'_, '_>,
+    params: PlaceLimitOrderParams,
+) -> Result<u64, ProgramError> {
+    ensure_order_accounts(program_id, accounts)?;
+
+    if params.amount == 0 || params.price_bps == 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let owner = accounts.owner.key;
+    let mut event = event_store::load_event(accounts.event, &params.unique_id)?;
+
+    if event.status != EventStatus::Active {
+        return Err(PredictionMarketError::EventNotActive.into());
+    }
+
+    event.ensure_mint(accounts.token.key)?;
+
+    let outcome_index = event
+        .outcome_index(params.outcome_id)
+        .ok_or(PredictionMarketError::InvalidOutcome)?;
+
+    let mut book = load_book(accounts.orders, accounts.event.key, &params.unique_id)?;
+
+    let mut order = Order {
+        order_id: book.next_order_id,
+        owner: *owner,
+        side: params.side,
+        price_bps: params.price_bps,
+        amount: params.amount,
+        escrowed: 0,
+        cost_basis: 0,
+    };
+
+    match params.side {
+        OrderSide::Buy => {
+            event.ensure_within_bet_limit(owner, params.amount)?;
+            order.escrowed = price_of(params.amount, params.price_bps, true)?;
+            move_balance(
+                accounts.token,
+                owner,
+                &vault_address(accounts.orders.key),
+                order.escrowed,
+            )?;
+        }
+        OrderSide::Sell => {
+            event.ensure_token_backed(outcome_index, owner, params.amount)?;
+            let outcome = &mut event.outcomes[outcome_index];
+            let held = net_stake(outcome, owner);
+            outcome.withdraw_position(owner, params.amount)?;
+            order.cost_basis = event
+                .cost_basis
+                .entry((*owner, params.outcome_id))
+                .or_default()
+                .release(params.amount, held);
+        }
+    }
+
+    book.next_order_id = book
+        .next_order_id
+        .checked_add(1)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    book.books
+        .entry(params.outcome_id)
+        .or_default()
+        .insert(order);
+
+    storage::write(accounts.orders, &book)?;
+    event_store::store_event(accounts.event, &event)?;
+
+    msg!(
+        "Order {} rests {} of outcome {} at {} bps",
+        book.next_order_id - 1,
+        params.amount,
+        params.outcome_id,
+        params.price_bps
+    );
+
+    Ok(book.next_order_id - 1)
+}
+
+/// Cancels what is left of the owner's order: a bid's escrow goes back to
+/// them, an ask's shares and cost basis back into their position. Orders can
+/// be cancelled whatever the state of the event, so nothing stays locked in
+/// the book once trading stops, but shares that come back after the owner
+/// claimed cannot be claimed again. A bid doesn't need the event at all, so
+/// its escrow comes back even once the event is pruned; only the mint the
+/// vault holds it in pays it out.
+pub fn process_cancel_limit_order(
+    program_id: &Pubkey,
+    accounts: OrderAccounts<'_, '_>,
+    order_id: u64,
+) -> Result<(), ProgramError> {
+    ensure_order_accounts(program_id, accounts)?;
+
+    if accounts.orders.data_is_empty() {
+        return Err(PredictionMarketError::OrderNotFound.into());
+    }
+    let mut book = decode_book(accounts.orders)?;
+
+    if book.event != *accounts.event.key {
+        return Err(PredictionMarketError::OrderBookMismatch.into());
+    }
+
+    let (outcome_id, order) = book
+        .remove(order_id)
+        .ok_or(PredictionMarketError::OrderNotFound)?;
+
+    if order.owner != *accounts.owner.key {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    match order.side {
+        OrderSide::Buy => refund_escrow(accounts.token, accounts.orders, &order)?,
+        OrderSide::Sell => {
+            let mut event = event_store::load_event(accounts.event, &book.unique_id)?;
+            event.ensure_mint(accounts.token.key)?;
+
+            let height = get_bitcoin_block_height();
+            let outcome_index = event
+                .outcome_index(outcome_id)
+                .ok_or(PredictionMarketError::InvalidOutcome)?;
+            restore_shares(&mut event, outcome_index, &order, height)?;
+            event_store::store_event(accounts.event, &event)?;
+        }
+    }
+
+    storage::write(accounts.orders, &book)?;
+
+    msg!("Order {} cancelled with {} left", order_id, order.amount);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod orders_tests {
+    use super::*;
+    use crate::test_utils::{balance_of, config_account, user_limits_account, Market, TestAccount};
+    use crate::types::{BetOnPredictionEventParams, CostBasis, SlippageLimits};
+    use crate::{process_buy_bet, process_instruction, BetAccounts};
+
+    const EVENT_ID: [u8; 32] = [49; 32];
+
+    // a pool market on outcome 0 of `EVENT_ID` and its order book
+    struct Exchange {
+        market: Market,
+        orders: TestAccount,
+    }
+
+    impl Exchange {
+        fn new(program_id: &Pubkey, balances: &[(Pubkey, u64)]) -> Self {
+            Exchange {
+                market: Market::new(program_id, EVENT_ID, balances),
+                orders: TestAccount::new(program_id, &[]),
+            }
+        }
+
+        fn place(
+            &mut self,
+            owner: &mut TestAccount,
+            side: OrderSide,
+            price_bps: u16,
+            amount: u64,
+        ) -> Result<u64, ProgramError> {
+            let program_id = self.market.event.owner;
+            process_place_limit_order(
+                &program_id,
+                OrderAccounts {
+                    event: &self.market.event.info(),
+                    orders: &self.orders.info(),
+                    token: &self.market.token.info(),
+                    owner: &owner.info(),
+                    config: &self.market.config.info(),
+                },
+                PlaceLimitOrderParams {
+                    unique_id: self.market.unique_id,
+                    outcome_id: 0,
+                    side,
+                    price_bps,
+                    amount,
+                },
+            )
+        }
+
+        fn cancel(&mut self, owner: &mut TestAccount, order_id: u64) -> Result<(), ProgramError> {
+            let program_id = self.market.event.owner;
+            process_cancel_limit_order(
+                &program_id,
+                OrderAccounts {
+                    event: &self.market.event.info(),
+                    orders: &self.orders.info(),
+                    token: &self.market.token.info(),
+                    owner: &owner.info(),
+                    config: &self.market.config.info(),
+                },
+                order_id,
+            )
+        }
+
+        fn buy(&mut self, better: &mut TestAccount, amount: u64) -> Result<(), ProgramError> {
+            let program_id = self.market.event.owner;
+            let mut limits = user_limits_account(&program_id, &better.key());
+            process_buy_bet(
+                &program_id,
+                BetAccounts {
+                    event: &self.market.event.info(),
+                    token: &self.market.token.info(),
+                    better: &better.info(),
+                    limits: Some(&limits.info()),
+                    config: &self.market.config.info(),
+                    referral: None,
+                    orders: Some(&self.orders.info()),
+                },
+                EVENT_ID,
+                0,
+                amount,
+                None,
+                SlippageLimits::default(),
+            )
+        }
+
+        // through the instruction, the order book trailing the config
+        fn sell(&mut self, better: &mut TestAccount, amount: u64) -> Result<(), ProgramError> {
+            let program_id = self.market.event.owner;
+            let mut data = vec![4];
+            data.extend(
+                borsh::to_vec(&BetOnPredictionEventParams {
+                    unique_id: EVENT_ID,
+                    outcome_id: 0,
+                    amount,
+                    min_expected_payout_bps: 0,
+                    max_cost: 0,
+                    referrer: None,
+                })
+                .unwrap(),
+            );
+            process_instruction(
+                &program_id,
+                &[
+                    self.market.event.info(),
+                    self.market.token.info(),
+                    better.info(),
+                    self.market.config.info(),
+                    self.orders.info(),
+                ],
+                &data,
+            )
+        }
+
+        // through the instruction, as the config's admin
+        fn set_policy(&mut self, policy: SelfTradePolicy) {
+            let program_id = self.market.event.owner;
+            let mut admin = TestAccount::new(&program_id, &[]).signer();
+            self.market.config = config_account(&program_id, &admin.key(), false);
+            let mut data = vec![47];
+            data.extend(borsh::to_vec(&config::SetSelfTradePolicyParams { policy }).unwrap());
+            process_instruction(
+                &program_id,
+                &[self.market.config.info(), admin.info()],
+                &data,
+            )
+            .unwrap();
+        }
+
+        fn book(&self) -> OutcomeBook {
+            OrderBook::try_from_slice(self.orders.data()).unwrap().books[&0].clone()
+        }
+
+        fn stake(&self, user: &TestAccount) -> u64 {
+            net_stake(&self.market.event_state().outcomes[0], &user.key())
+        }
+
+        fn vault_balance(&self) -> u64 {
+            balance_of(&self.market.token, &vault_address(&self.orders.key()))
+        }
+    }
+
+    fn ids(orders: &[Order]) -> Vec<u64> {
+        orders.iter().map(|order| order.order_id).collect()
+    }
+
+    #[test]
+    fn test_bids_escrow_their_price_in_price_time_order() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut exchange = Exchange::new(&program_id, &[(alice.key(), 1_000)]);
+
+        let first = exchange.place(&mut alice, OrderSide::Buy, 4_000, 100);
+        let second = exchange.place(&mut alice, OrderSide::Buy, 3_333, 50);
+        let third = exchange.place(&mut alice, OrderSide::Buy, 4_000, 10);
+
+        assert_eq!((first, second, third), (Ok(0), Ok(1), Ok(2)));
+        // 50 at 0.3333 is 16.665, rounded up
+        assert_eq!(exchange.vault_balance(), 40 + 17 + 4);
+        assert_eq!(exchange.market.balance(&alice), 1_000 - 61);
+        // the later bid at 0.40 queues behind the first, ahead of the lower one
+        assert_eq!(ids(&exchange.book().bids), vec![0, 2, 1]);
+    }
+
+    #[test]
+    fn test_market_sell_fills_the_best_bids_before_the_pool() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut exchange = Exchange::new(&program_id, &[(alice.key(), 1_000), (bob.key(), 100)]);
+        exchange.market.buy(&mut bob, 0, 100).unwrap();
+        exchange
+            .place(&mut alice, OrderSide::Buy, 11_000, 50)
+            .unwrap();
+        exchange
+            .place(&mut alice, OrderSide::Buy, 12_000, 30)
+            .unwrap();
+        // below par, a pool sell pays more
+        exchange
+            .place(&mut alice, OrderSide::Buy, 9_000, 40)
+            .unwrap();
+
+        // 30 at 1.2 and 30 of the 50 at 1.1
+        exchange.sell(&mut bob, 60).unwrap();
+
+        let event = exchange.market.event_state();
+        assert_eq!(exchange.market.balance(&bob), 36 + 33);
+        assert_eq!((exchange.stake(&bob), exchange.stake(&alice)), (40, 60));
+        assert_eq!(event.outcomes[0].total_amount, 100);
+        assert_eq!(event.total_pool_amount, 100);
+        let book = exchange.book();
+        assert_eq!(ids(&book.bids), vec![0, 2]);
+        assert_eq!((book.bids[0].amount, book.bids[0].escrowed), (20, 22));
+
+        // the rest of the 1.1 bid, then the pool at par
+        exchange.sell(&mut bob, 30).unwrap();
+
+        let event = exchange.market.event_state();
+        assert_eq!(exchange.market.balance(&bob), 69 + 22 + 10);
+        assert_eq!(event.outcomes[0].total_amount, 90);
+        assert_eq!(event.total_pool_amount, 90);
+        assert_eq!(ids(&exchange.book().bids), vec![2]);
+        assert_eq!(
+            event.cost_basis[&(alice.key(), 0)],
+            CostBasis {
+                cost_basis_total: 36 + 55,
+                realized_pnl: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_market_buy_fills_the_cheapest_asks_before_the_pool() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut exchange = Exchange::new(&program_id, &[(alice.key(), 1_000), (bob.key(), 100)]);
+        exchange.market.buy(&mut bob, 0, 100).unwrap();
+        exchange
+            .place(&mut bob, OrderSide::Sell, 9_500, 20)
+            .unwrap();
+        exchange
+            .place(&mut bob, OrderSide::Sell, 8_000, 40)
+            .unwrap();
+        // above par, the pool is cheaper
+        exchange
+            .place(&mut bob, OrderSide::Sell, 12_000, 10)
+            .unwrap();
+        assert_eq!(exchange.stake(&bob), 30);
+
+        // 40 at 0.8 and 10 of the 20 at 0.95, rounded up
+        exchange.buy(&mut alice, 50).unwrap();
+
+        let event = exchange.market.event_state();
+        assert_eq!(exchange.market.balance(&alice), 1_000 - 32 - 10);
+        assert_eq!(exchange.market.balance(&bob), 42);
+        assert_eq!(exchange.stake(&alice), 50);
+        assert_eq!(event.outcomes[0].total_amount, 100);
+        assert_eq!(event.total_pool_amount, 100);
+        // bob paid par, so the 40 he sold for 32 lost 8 and the 10 broke even
+        assert_eq!(event.cost_basis[&(bob.key(), 0)].realized_pnl, -8);
+        assert_eq!(ids(&exchange.book().asks), vec![0, 2]);
+
+        // the other 10 at 0.95, then the pool
+        exchange.buy(&mut alice, 20).unwrap();
+
+        let event = exchange.market.event_state();
+        assert_eq!(exchange.market.balance(&alice), 958 - 10 - 10);
+        assert_eq!(event.outcomes[0].total_amount, 110);
+        assert_eq!(event.total_pool_amount, 110);
+        assert_eq!(ids(&exchange.book().asks), vec![2]);
+    }
+
+    #[test]
+    fn test_cancel_releases_what_the_order_escrowed() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut exchange = Exchange::new(&program_id, &[(alice.key(), 1_000), (bob.key(), 100)]);
+        exchange.market.buy(&mut bob, 0, 100).unwrap();
+        let bid = exchange
+            .place(&mut alice, OrderSide::Buy, 5_000, 100)
+            .unwrap();
+        let ask = exchange
+            .place(&mut bob, OrderSide::Sell, 9_000, 30)
+            .unwrap();
+
+        assert_eq!(
+            exchange.cancel(&mut bob, bid),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+
+        exchange.cancel(&mut alice, bid).unwrap();
+        exchange.cancel(&mut bob, ask).unwrap();
+
+        assert_eq!(exchange.market.balance(&alice), 1_000);
+        assert_eq!(exchange.vault_balance(), 0);
+        assert_eq!(exchange.stake(&bob), 100);
+        assert_eq!(
+            exchange.market.event_state().cost_basis[&(bob.key(), 0)],
+            CostBasis {
+                cost_basis_total: 100,
+                realized_pnl: 0,
+            }
+        );
+        assert_eq!(
+            exchange.cancel(&mut alice, bid),
+            Err(PredictionMarketError::OrderNotFound.into())
+        );
+
+        // ids are not reused after a cancel
+        assert_eq!(exchange.place(&mut alice, OrderSide::Buy, 5_000, 10), Ok(2));
+    }
+
+    #[test]
+    fn test_asks_need_the_shares_and_the_book_its_event() {
+        let program_id = Pubkey::new_unique();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut exchange = Exchange::new(&program_id, &[(bob.key(), 100)]);
+        exchange.market.buy(&mut bob, 0, 10).unwrap();
+
+        assert_eq!(
+            exchange.place(&mut bob, OrderSide::Sell, 9_000, 11),
+            Err(PredictionMarketError::InsufficientPosition.into())
+        );
+        exchange
+            .place(&mut bob, OrderSide::Sell, 9_000, 10)
+            .unwrap();
+
+        // the same book against another event
+        exchange.market = Market::new(&program_id, [48; 32], &[(bob.key(), 100)]);
+        exchange.market.buy(&mut bob, 0, 10).unwrap();
+        assert_eq!(
+            exchange.place(&mut bob, OrderSide::Sell, 9_000, 10),
+            Err(PredictionMarketError::OrderBookMismatch.into())
+        );
+    }
+
+    #[test]
+    fn test_cancel_resting_skips_the_takers_own_asks() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut exchange = Exchange::new(&program_id, &[(alice.key(), 1_000), (bob.key(), 1_000)]);
+        exchange.market.buy(&mut bob, 0, 100).unwrap();
+        exchange.market.buy(&mut alice, 0, 50).unwrap();
+        exchange
+            .place(&mut bob, OrderSide::Sell, 8_000, 40)
+            .unwrap();
+        exchange
+            .place(&mut alice, OrderSide::Sell, 9_000, 20)
+            .unwrap();
+
+        // bob's own ask is cancelled by default, then alice's fills and the
+        // pool takes the rest
+        exchange.buy(&mut bob, 30).unwrap();
+
+        let event = exchange.market.event_state();
+        assert_eq!(exchange.market.balance(&bob), 1_000 - 100 - 18 - 10);
+        assert_eq!(exchange.market.balance(&alice), 1_000 - 50 + 18);
+        assert_eq!((exchange.stake(&bob), exchange.stake(&alice)), (130, 30));
+        assert_eq!(event.outcomes[0].total_amount, 160);
+        assert!(exchange.book().asks.is_empty());
+        assert_eq!(
+            event.cost_basis[&(bob.key(), 0)],
+            CostBasis {
+                cost_basis_total: 100 + 28,
+                realized_pnl: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_cancel_incoming_stops_at_the_takers_own_bid() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut exchange = Exchange::new(&program_id, &[(alice.key(), 1_000), (bob.key(), 1_000)]);
+        exchange.set_policy(SelfTradePolicy::CancelIncoming);
+        exchange.market.buy(&mut bob, 0, 100).unwrap();
+        exchange
+            .place(&mut alice, OrderSide::Buy, 12_000, 20)
+            .unwrap();
+        exchange
+            .place(&mut bob, OrderSide::Buy, 11_000, 50)
+            .unwrap();
+        exchange
+            .place(&mut alice, OrderSide::Buy, 10_500, 30)
+            .unwrap();
+
+        // alice's best bid fills, the rest of the sell stops at bob's own
+        exchange.sell(&mut bob, 60).unwrap();
+
+        assert_eq!(exchange.market.balance(&bob), 1_000 - 100 - 55 + 24);
+        assert_eq!((exchange.stake(&bob), exchange.stake(&alice)), (80, 20));
+        assert_eq!(ids(&exchange.book().bids), vec![1, 2]);
+
+        // with bob's bid first in line nothing of the sell executes
+        exchange.sell(&mut bob, 10).unwrap();
+
+        assert_eq!(exchange.market.balance(&bob), 869);
+        assert_eq!(exchange.stake(&bob), 80);
+        assert_eq!(exchange.market.event_state().outcomes[0].total_amount, 100);
+        assert_eq!(ids(&exchange.book().bids), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_decrement_both_takes_the_overlap_off_without_a_trade() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut exchange = Exchange::new(&program_id, &[(alice.key(), 1_000), (bob.key(), 1_000)]);
+        exchange.set_policy(SelfTradePolicy::DecrementBoth);
+        exchange.market.buy(&mut bob, 0, 100).unwrap();
+        exchange.market.buy(&mut alice, 0, 50).unwrap();
+        exchange
+            .place(&mut bob, OrderSide::Sell, 8_000, 30)
+            .unwrap();
+        exchange
+            .place(&mut alice, OrderSide::Sell, 9_000, 40)
+            .unwrap();
+
+        // the whole buy cancels against 10 of bob's ask
+        exchange.buy(&mut bob, 10).unwrap();
+
+        assert_eq!(exchange.market.balance(&bob), 900);
+        assert_eq!(exchange.stake(&bob), 80);
+        let book = exchange.book();
+        assert_eq!(ids(&book.asks), vec![0, 1]);
+        assert_eq!((book.asks[0].amount, book.asks[0].cost_basis), (20, 20));
+
+        // the other 20 of it cancel against 20 of the buy, alice's ask fills
+        // the remaining 30
+        exchange.buy(&mut bob, 50).unwrap();
+
+        let event = exchange.market.event_state();
+        assert_eq!(exchange.market.balance(&bob), 900 - 27);
+        assert_eq!(exchange.market.balance(&alice), 1_000 - 50 + 27);
+        assert_eq!((exchange.stake(&bob), exchange.stake(&alice)), (130, 10));
+        assert_eq!(event.outcomes[0].total_amount, 150);
+        let book = exchange.book();
+        assert_eq!(ids(&book.asks), vec![1]);
+        assert_eq!(book.asks[0].amount, 10);
+        assert_eq!(
+            event.cost_basis[&(bob.key(), 0)],
+            CostBasis {
+                cost_basis_total: 100 + 27,
+                realized_pnl: 0,
+            }
+        );
+    }
+}
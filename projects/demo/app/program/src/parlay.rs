@@ -0,0 +1,654 @@
+//! Parlays. A parlay combines legs on `MIN_PARLAY_LEGS` to `MAX_PARLAY_LEGS`
+//! events of one mint into a single bet: its stake is escrowed once, in a
+//! vault balance of the parlay's own account, and pays the stake times the
+//! multipliers of its legs, locked at the odds their events quoted when it was
+//! placed, only if every leg wins. The events' pools take the other side. A
+//! losing parlay forfeits its stake into them, split evenly across the legs,
+//! and a winning one is paid its profit out of them in the same split, as far
+//! as what their losing outcomes hold reaches. As settling moves the pools,
+//! claims on a resolved event wait until the parlays on it are settled, which
+//! anyone may do once every leg's event is final.
+//!
+//! A leg whose event is cancelled or resolved without a winner drops out: its
+//! share of the stake is refunded and the rest of the parlay stands.
+//!
+//! Both instructions read several events: `PlaceParlay` takes
+//! [parlay, token, owner, config] and `SettleParlay` [parlay, token, config],
+//! each followed by the account holding every leg's event, in leg order. Legs
+//! on events in the same account repeat it.
+use arch_program::{
+    account::AccountInfo,
+    bitcoin::hashes::{sha256, Hash, HashEngine},
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::{
+    config, ensure_distinct_accounts, ensure_program_owned, event_store, get_bitcoin_block_height,
+    math::{self, FIXED_POINT_ONE},
+    storage,
+    transfer::move_balance,
+    types::{
+        escrow_address, EventStatus, PlaceParlayParams, PredictionEvent, PredictionMarketError,
+    },
+};
+
+/// Fewest legs a parlay takes; a single leg is a plain bet.
+pub const MIN_PARLAY_LEGS: usize = 2;
+/// Most legs a parlay takes, which bounds the event accounts it reads.
+pub const MAX_PARLAY_LEGS: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct PlacedLeg {
+    // the account holding the leg's event
+    pub event: Pubkey,
+    pub unique_id: [u8; 32],
+    pub outcome_id: u8,
+    // what a unit on the outcome paid when the parlay was placed, scaled by
+    // `FIXED_POINT_ONE`
+    pub multiplier_fp: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct Parlay {
+    pub owner: Pubkey,
+    // the mint every leg's event escrows in
+    pub mint: Pubkey,
+    pub stake: u64,
+    pub legs: Vec<PlacedLeg>,
+    pub settled: bool,
+}
+
+// how a leg's event ended, as far as the parlay is concerned
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LegResult {
+    Won,
+    Lost,
+    Void,
+}
+
+/// Balance key holding a parlay's stake until it settles. Derived from the
+/// account, so it can't belong to any signer.
+pub fn vault_address(parlay_account: &Pubkey) -> Pubkey {
+    let mut engine = sha256::Hash::engine();
+    engine.input(b"parlay_vault");
+    engine.input(&parlay_account.0);
+    Pubkey(sha256::Hash::from_engine(engine).to_byte_array())
+}
+
+// each leg's share of `stake`, even but for the remainder, which goes to the
+// first leg
+fn leg_shares(stake: u64, legs: usize) -> Vec<u64> {
+    let mut shares = vec![stake / legs as u64; legs];
+    shares[0] += stake % legs as u64;
+    shares
+}
+
+// `stake` times every multiplier, rounded down at each step
+fn parlay_payout(stake: u64, multipliers: impl Iterator<Item = u64>) -> u64 {
+    multipliers.fold(stake, |value, multiplier_fp| {
+        let value = value as u128 * multiplier_fp as u128 / FIXED_POINT_ONE as u128;
+        value.min(u64::MAX as u128) as u64
+    })
+}
+
+fn leg_result(
+    event: &PredictionEvent,
+    outcome_id: u8,
+    height: u64,
+) -> Result<LegResult, ProgramError> {
+    match event.status {
+        EventStatus::Cancelled => return Ok(LegResult::Void),
+        EventStatus::Resolved => {}
+        _ => return Err(PredictionMarketError::EventNotResolved.into()),
+    }
+
+    if event.in_dispute_window(height) {
+        return Err(PredictionMarketError::DisputeWindowOpen.into());
+    }
+
+    Ok(match event.winning_outcome {
+        None => LegResult::Void,
+        Some(_) if event.is_winner(outcome_id) => LegResult::Won,
+        Some(_) => LegResult::Lost,
+    })
+}
+
+// the fixed accounts must be distinct from each other and from the event
+// accounts, which only repeat among themselves
+fn ensure_parlay_accounts(
+    program_id: &Pubkey,
+    fixed: &[&AccountInfo<'_>],
+    owned: &[&AccountInfo<'_>],
+    event_accounts: &[AccountInfo<'_>],
+) -> Result<(), ProgramError> {
+    let mut keys: Vec<&Pubkey> = fixed.iter().map(|account| account.key).collect();
+    for event_account in event_accounts {
+        if !keys[fixed.len()..].contains(&event_account.key) {
+            keys.push(event_account.key);
+        }
+    }
+    ensure_distinct_accounts(&keys)?;
+
+    ensure_program_owned(program_id, owned)?;
+    ensure_program_owned(program_id, &event_accounts.iter().collect::<Vec<_>>())
+}
+
+/// Places a parlay on active events, escrowing `params.amount` from the
+/// owner. Every leg's multiplier is locked at what a unit on its outcome
+/// pays as the event stands, so an outcome nobody backs yet can't be a leg.
+pub fn process_place_parlay<'a>(
+    program_id: &Pubkey,
+    parlay_account: &AccountInfo<'a>,
+    token_account: &AccountInfo<'a>,
+    owner_account: &AccountInfo<'a>,
+    config_account: &AccountInfo<'a>,
+    event_accounts: &[AccountInfo<'a>],
+    params: PlaceParlayParams,
+) -> Result<(), ProgramError> {
+    ensure_parlay_accounts(
+        program_id,
+        &[parlay_account, token_account, owner_account, config_account],
+        &[parlay_account, token_account, config_account],
+        event_accounts,
+    )?;
+
+    if !owner_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    config::ensure_not_paused(config_account)?;
+
+    if !(MIN_PARLAY_LEGS..=MAX_PARLAY_LEGS).contains(&params.legs.len()) {
+        return Err(PredictionMarketError::InvalidParlay.into());
+    }
+
+    if event_accounts.len() != params.legs.len() {
+        return Err(PredictionMarketError::InvalidAccountCount.into());
+    }
+
+    if params.amount == 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    if !parlay_account.data_is_empty() {
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    let mut legs: Vec<PlacedLeg> = Vec::with_capacity(params.legs.len());
+
+    for (leg, event_account) in params.legs.iter().zip(event_accounts) {
+        if legs
+            .iter()
+            .any(|placed| placed.event == *event_account.key && placed.unique_id == leg.unique_id)
+        {
+            return Err(PredictionMarketError::DuplicateParlayLeg.into());
+        }
+
+        let mut event = event_store::load_event(event_account, &leg.unique_id)?;
+
+        if event.status != EventStatus::Active {
+            return Err(PredictionMarketError::EventNotActive.into());
+        }
+
+        event.ensure_mint(token_account.key)?;
+
+        let outcome_index = event
+            .outcome_index(leg.outcome_id)
+            .ok_or(PredictionMarketError::InvalidOutcome)?;
+
+        let multiplier_fp = math::compute_odds(&event)[outcome_index].implied_payout_per_unit_fp;
+        if multiplier_fp == 0 {
+            return Err(PredictionMarketError::InvalidParlay.into());
+        }
+
+        event.open_parlays = event
+            .open_parlays
+            .checked_add(1)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        event_store::store_event(event_account, &event)?;
+
+        legs.push(PlacedLeg {
+            event: *event_account.key,
+            unique_id: leg.unique_id,
+            outcome_id: leg.outcome_id,
+            multiplier_fp,
+        });
+    }
+
+    move_balance(
+        token_account,
+        owner_account.key,
+        &vault_address(parlay_account.key),
+        params.amount,
+    )?;
+
+    let parlay = Parlay {
+        owner: *owner_account.key,
+        mint: *token_account.key,
+        stake: params.amount,
+        legs,
+        settled: false,
+    };
+    storage::write(parlay_account, &parlay)?;
+
+    msg!(
+        "Parlay of {} legs staking {}",
+        parlay.legs.len(),
+        parlay.stake
+    );
+
+    Ok(())
+}
+
+/// Settles a parlay once every leg's event is final: resolved past its
+/// dispute window, or cancelled. Anyone may settle; what the parlay pays goes
+/// to its owner, and is returned.
+pub fn process_settle_parlay<'a>(
+    program_id: &Pubkey,
+    parlay_account: &AccountInfo<'a>,
+    token_account: &AccountInfo<'a>,
+    config_account: &AccountInfo<'a>,
+    event_accounts: &[AccountInfo<'a>],
+) -> Result<u64, ProgramError> {
+    let fixed = [parlay_account, token_account, config_account];
+    ensure_parlay_accounts(program_id, &fixed, &fixed, event_accounts)?;
+
+    config::ensure_not_paused(config_account)?;
+
+    let mut parlay = storage::read::<Parlay>(parlay_account)?;
+
+    if parlay.settled {
+        return Err(PredictionMarketError::AlreadyClaimed.into());
+    }
+
+    if parlay.mint != *token_account.key {
+        return Err(PredictionMarketError::MintMismatch.into());
+    }
+
+    if event_accounts.len() != parlay.legs.len() {
+        return Err(PredictionMarketError::InvalidAccountCount.into());
+    }
+
+    let mut events = Vec::with_capacity(parlay.legs.len());
+    let mut results = Vec::with_capacity(parlay.legs.len());
+    let height = get_bitcoin_block_height();
+
+    for (leg, event_account) in parlay.legs.iter().zip(event_accounts) {
+        if leg.event != *event_account.key {
+            return Err(PredictionMarketError::InvalidParlay.into());
+        }
+
+        let event = event_store::load_event(event_account, &leg.unique_id)?;
+        results.push(leg_result(&event, leg.outcome_id, height)?);
+        events.push(event);
+    }
+
+    let shares = leg_shares(parlay.stake, parlay.legs.len());
+    let refund: u64 = shares
+        .iter()
+        .zip(&results)
+        .filter(|(_, result)| **result == LegResult::Void)
+        .map(|(share, _)| share)
+        .sum();
+    let standing = parlay.stake - refund;
+    let vault = vault_address(parlay_account.key);
+
+    // what leaves the vault for the owner, and what the pools add to it
+    let mut returned = refund;
+    let mut profit_paid = 0u64;
+
+    if results.contains(&LegResult::Lost) {
+        // every leg that stood forfeits its share into its event's pool
+        for (i, leg) in parlay.legs.iter().enumerate() {
+            if results[i] == LegResult::Void || shares[i] == 0 {
+                continue;
+            }
+
+            events[i].total_pool_amount = events[i]
+                .total_pool_amount
+                .checked_add(shares[i])
+                .ok_or(ProgramError::ArithmeticOverflow)?;
+            let escrow = escrow_address(&leg.event, &leg.unique_id);
+            move_balance(token_account, &vault, &escrow, shares[i])?;
+        }
+    } else {
+        returned += standing;
+
+        let payout = parlay_payout(
+            standing,
+            parlay
+                .legs
+                .iter()
+                .zip(&results)
+                .filter(|(_, result)| **result == LegResult::Won)
+                .map(|(leg, _)| leg.multiplier_fp),
+        );
+        let profit = payout.saturating_sub(standing);
+
+        for (i, leg) in parlay.legs.iter().enumerate() {
+            if results[i] != LegResult::Won {
+                continue;
+            }
+
+            let event = &mut events[i];
+            let winning_total = event
+                .outcomes
+                .iter()
+                .find(|outcome| outcome.id == leg.outcome_id)
+                .map_or(0, |outcome| outcome.total_amount);
+            // never more than the losing outcomes hold, so the event's own
+            // winners keep at least their stakes
+            let available = event.total_pool_amount.saturating_sub(winning_total);
+            let share = (profit as u128 * shares[i] as u128 / standing as u128) as u64;
+            let taken = share.min(available);
+            if taken == 0 {
+                continue;
+            }
+
+            event.total_pool_amount -= taken;
+            let escrow = escrow_address(&leg.event, &leg.unique_id);
+            move_balance(token_account, &escrow, &parlay.owner, taken)?;
+            profit_paid += taken;
+        }
+    }
+
+    if returned > 0 {
+        move_balance(token_account, &vault, &parlay.owner, returned)?;
+    }
+
+    for (event, event_account) in events.iter_mut().zip(event_accounts) {
+        event.open_parlays = event.open_parlays.saturating_sub(1);
+        event_store::store_event(event_account, event)?;
+    }
+
+    parlay.settled = true;
+    storage::write(parlay_account, &parlay)?;
+
+    let paid = returned + profit_paid;
+    msg!("Parlay settled, paying {}", paid);
+
+    Ok(paid)
+}
+
+#[cfg(test)]
+mod parlay_tests {
+    use super::*;
+    use crate::test_utils::{
+        create_event_with, event_params, user_limits_account, Market, TestAccount,
+    };
+    use crate::types::{ParlayLeg, SlippageLimits};
+    use crate::{
+        process_buy_bet, process_cancel_event, process_instruction, process_resolve_event,
+        BetAccounts,
+    };
+
+    const FIRST: [u8; 32] = [52; 32];
+    const SECOND: [u8; 32] = [53; 32];
+
+    // two pool events in their own accounts on the market's mint, 4.0 and
+    // 2.0 on outcome 0, and an empty parlay account
+    struct Parlays {
+        market: Market,
+        second: TestAccount,
+        second_creator: TestAccount,
+        parlay: TestAccount,
+    }
+
+    impl Parlays {
+        fn new(
+            program_id: &Pubkey,
+            alice: &TestAccount,
+            bob: &mut TestAccount,
+            carol: &mut TestAccount,
+        ) -> Self {
+            let (second, second_creator) =
+                create_event_with(program_id, event_params(SECOND, 100, 2));
+            let mut parlays = Parlays {
+                market: Market::new(
+                    program_id,
+                    FIRST,
+                    &[
+                        (alice.key(), 1_000),
+                        (bob.key(), 1_000),
+                        (carol.key(), 1_000),
+                    ],
+                ),
+                second,
+                second_creator,
+                parlay: TestAccount::new(program_id, &[]),
+            };
+            parlays.bet(bob, FIRST, 0, 100);
+            parlays.bet(carol, FIRST, 1, 300);
+            parlays.bet(bob, SECOND, 0, 200);
+            parlays.bet(carol, SECOND, 1, 200);
+            parlays
+        }
+
+        fn event_account(&mut self, unique_id: [u8; 32]) -> AccountInfo<'_> {
+            if unique_id == FIRST {
+                self.market.event.info()
+            } else {
+                self.second.info()
+            }
+        }
+
+        fn bet(
+            &mut self,
+            better: &mut TestAccount,
+            unique_id: [u8; 32],
+            outcome_id: u8,
+            amount: u64,
+        ) {
+            let program_id = self.market.event.owner;
+            let event = if unique_id == FIRST {
+                &mut self.market.event
+            } else {
+                &mut self.second
+            };
+            let mut limits = user_limits_account(&program_id, &better.key());
+            process_buy_bet(
+                &program_id,
+                BetAccounts {
+                    event: &event.info(),
+                    token: &self.market.token.info(),
+                    better: &better.info(),
+                    limits: Some(&limits.info()),
+                    config: &self.market.config.info(),
+                    referral: None,
+                    orders: None,
+                },
+                unique_id,
+                outcome_id,
+                amount,
+                None,
+                SlippageLimits::default(),
+            )
+            .unwrap();
+        }
+
+        fn resolve(&mut self, unique_id: [u8; 32], winning_outcome: u8) {
+            let program_id = self.market.event.owner;
+            let (event, creator) = if unique_id == FIRST {
+                (&mut self.market.event, &mut self.market.creator)
+            } else {
+                (&mut self.second, &mut self.second_creator)
+            };
+            process_resolve_event(
+                &program_id,
+                &event.info(),
+                &creator.info(),
+                unique_id,
+                winning_outcome,
+            )
+            .unwrap();
+        }
+
+        // through the instruction, each leg's event account after the config
+        fn place(
+            &mut self,
+            owner: &mut TestAccount,
+            legs: &[([u8; 32], u8)],
+            amount: u64,
+        ) -> Result<(), ProgramError> {
+            let program_id = self.market.event.owner;
+            let mut data = vec![48];
+            data.extend(
+                borsh::to_vec(&PlaceParlayParams {
+                    legs: legs
+                        .iter()
+                        .map(|&(unique_id, outcome_id)| ParlayLeg {
+                            unique_id,
+                            outcome_id,
+                        })
+                        .collect(),
+                    amount,
+                })
+                .unwrap(),
+            );
+            let first = self.market.event.info();
+            let second = self.second.info();
+            let mut accounts = vec![
+                self.parlay.info(),
+                self.market.token.info(),
+                owner.info(),
+                self.market.config.info(),
+            ];
+            accounts.extend(legs.iter().map(|(unique_id, _)| {
+                if *unique_id == FIRST {
+                    first.clone()
+                } else {
+                    second.clone()
+                }
+            }));
+            process_instruction(&program_id, &accounts, &data)
+        }
+
+        fn settle(&mut self) -> Result<(), ProgramError> {
+            let program_id = self.market.event.owner;
+            let first = self.market.event.info();
+            let second = self.second.info();
+            process_instruction(
+                &program_id,
+                &[
+                    self.parlay.info(),
+                    self.market.token.info(),
+                    self.market.config.info(),
+                    first,
+                    second,
+                ],
+                &[49],
+            )
+        }
+
+        fn pool(&mut self, unique_id: [u8; 32]) -> u64 {
+            let event = self.event_account(unique_id);
+            event_store::load_event(&event, &unique_id)
+                .unwrap()
+                .total_pool_amount
+        }
+    }
+
+    #[test]
+    fn test_winning_parlay_pays_the_product_out_of_the_pools() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut carol = TestAccount::new(&program_id, &[]).signer();
+        let mut parlays = Parlays::new(&program_id, &alice, &mut bob, &mut carol);
+
+        parlays
+            .place(&mut alice, &[(FIRST, 0), (SECOND, 0)], 10)
+            .unwrap();
+        assert_eq!(parlays.market.balance(&alice), 990);
+
+        parlays.resolve(FIRST, 0);
+        assert_eq!(
+            parlays.settle(),
+            Err(PredictionMarketError::EventNotResolved.into())
+        );
+        // the parlay may still be paid out of the first pool
+        assert_eq!(
+            parlays.market.claim(&mut bob),
+            Err(PredictionMarketError::ParlaysOutstanding.into())
+        );
+
+        parlays.resolve(SECOND, 0);
+        parlays.settle().unwrap();
+
+        // 10 at 4.0 and 2.0, each pool paying half the profit of 70
+        assert_eq!(parlays.market.balance(&alice), 990 + 80);
+        assert_eq!(parlays.pool(FIRST), 400 - 35);
+        assert_eq!(parlays.pool(SECOND), 400 - 35);
+        assert_eq!(
+            parlays.settle(),
+            Err(PredictionMarketError::AlreadyClaimed.into())
+        );
+
+        parlays.market.claim(&mut bob).unwrap();
+        assert_eq!(parlays.market.balance(&bob), 700 + 365);
+    }
+
+    #[test]
+    fn test_losing_parlay_forfeits_its_stake_to_the_pools() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut carol = TestAccount::new(&program_id, &[]).signer();
+        let mut parlays = Parlays::new(&program_id, &alice, &mut bob, &mut carol);
+        parlays
+            .place(&mut alice, &[(FIRST, 0), (SECOND, 0)], 10)
+            .unwrap();
+
+        parlays.resolve(FIRST, 1);
+        parlays.resolve(SECOND, 0);
+        parlays.settle().unwrap();
+
+        assert_eq!(parlays.market.balance(&alice), 990);
+        assert_eq!(parlays.pool(FIRST), 405);
+        assert_eq!(parlays.pool(SECOND), 405);
+
+        // carol's 300 on the first event now share its forfeit too
+        parlays.market.claim(&mut carol).unwrap();
+        assert_eq!(parlays.market.balance(&carol), 500 + 405);
+    }
+
+    #[test]
+    fn test_cancelled_leg_refunds_its_share_and_the_rest_stands() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut carol = TestAccount::new(&program_id, &[]).signer();
+        let mut parlays = Parlays::new(&program_id, &alice, &mut bob, &mut carol);
+
+        assert_eq!(
+            parlays.place(&mut alice, &[(FIRST, 0), (FIRST, 1)], 10),
+            Err(PredictionMarketError::DuplicateParlayLeg.into())
+        );
+        assert_eq!(
+            parlays.place(&mut alice, &[(FIRST, 0)], 10),
+            Err(PredictionMarketError::InvalidParlay.into())
+        );
+
+        parlays
+            .place(&mut alice, &[(FIRST, 0), (SECOND, 0)], 11)
+            .unwrap();
+
+        process_cancel_event(
+            &program_id,
+            &parlays.second.info(),
+            &parlays.second_creator.info(),
+            SECOND,
+        )
+        .unwrap();
+        parlays.resolve(FIRST, 0);
+        parlays.settle().unwrap();
+
+        // 5 of the second leg back, the first leg's 6 at 4.0
+        assert_eq!(parlays.market.balance(&alice), 989 + 5 + 24);
+        assert_eq!(parlays.pool(FIRST), 400 - 18);
+        assert_eq!(parlays.pool(SECOND), 400);
+    }
+}
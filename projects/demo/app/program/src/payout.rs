@@ -0,0 +1,1405 @@
+use std::collections::BTreeSet;
+
+use arch_program::{
+    account::AccountInfo,
+    bitcoin::{
+        absolute::LockTime, consensus, hashes::Hash, transaction::Version, Amount, ScriptBuf,
+        Sequence, Transaction, TxIn, TxOut, Witness,
+    },
+    entrypoint::ProgramResult,
+    input_to_sign::InputToSign,
+    msg,
+    program::get_account_script_pubkey,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    transaction_to_sign::TransactionToSign,
+    utxo::UtxoMeta,
+};
+#[cfg(not(test))]
+use arch_program::{helper::add_state_transition, program::set_transaction_to_sign};
+
+#[cfg(test)]
+use crate::test_utils::{add_state_transition, set_transaction_to_sign};
+
+use crate::{
+    ensure_distinct_accounts, ensure_program_owned, event_store,
+    events::{self, EventStatusLog},
+    get_bitcoin_block_height, math,
+    types::{
+        net_stake, BitcoinPayout, BitcoinPayoutKind, BumpSettlementFeeParams, EscrowedUtxo,
+        EventStatus, Outcome, PredictionEvent, PredictionMarketError, RefundOnBitcoinParams,
+        SettleOnBitcoinParams,
+    },
+    utxo_bets::MIN_UTXO_SATS,
+};
+
+/// Highest fee rate a settlement may pay.
+pub const MAX_FEE_RATE_SATS_PER_VBYTE: u64 = 1_000;
+
+/// Size a settlement allows for each input's witness, a taproot key-path
+/// signature rounded up.
+const WITNESS_VBYTES_PER_INPUT: u64 = 17;
+
+/// One payout of a bitcoin settlement: where it goes and what a token claim
+/// would have paid, in bet units.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SettlementShare {
+    pub script_pubkey: ScriptBuf,
+    pub units: u64,
+}
+
+/// Everything a utxo-funded event pays out on bitcoin.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settlement {
+    // spent after the event account's state transition, in this order
+    pub escrowed_utxos: Vec<UtxoMeta>,
+    // what they hold in all
+    pub escrowed_sats: u64,
+    // the pool in bet units, which the shares split
+    pub pool_units: u64,
+    pub shares: Vec<SettlementShare>,
+    // the program's own address, where rounding and dropped dust go
+    pub change_script_pubkey: ScriptBuf,
+    // the event's expiry, a block height the transaction can't confirm before
+    pub lock_height: u32,
+}
+
+/// Adds the winner's output to a transaction whose first input and output are
+/// the event account's state transition. The payout is carved out of the event
+/// account's own utxo, so the event account is the only input to sign.
+pub fn build_payout_transaction(
+    mut transaction: Transaction,
+    event_key: &Pubkey,
+    payout: u64,
+    winner_script_pubkey: ScriptBuf,
+) -> Result<(Transaction, Vec<InputToSign>), ProgramError> {
+    let vault_output = transaction
+        .output
+        .first_mut()
+        .ok_or(ProgramError::InvalidAccountData)?;
+
+    let remaining = vault_output
+        .value
+        .to_sat()
+        .checked_sub(payout)
+        .ok_or(ProgramError::InsufficientFunds)?;
+    vault_output.value = Amount::from_sat(remaining);
+
+    transaction.output.push(TxOut {
+        value: Amount::from_sat(payout),
+        script_pubkey: winner_script_pubkey,
+    });
+
+    let inputs_to_sign = vec![InputToSign {
+        index: 0,
+        signer: *event_key,
+    }];
+
+    Ok((transaction, inputs_to_sign))
+}
+
+/// Pays `payout` sats (one sat per token unit) from the event account's utxo to
+/// the winner's account address and hands the transaction to the runtime.
+pub fn settle_claim_on_bitcoin(
+    event_account: &AccountInfo,
+    winner_account: &AccountInfo,
+    payout: u64,
+) -> ProgramResult {
+    let mut transaction = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![],
+        output: vec![],
+    };
+    add_state_transition(&mut transaction, event_account);
+
+    let winner_script_pubkey =
+        ScriptBuf::from_bytes(get_account_script_pubkey(winner_account.key).to_vec());
+
+    let (transaction, inputs_to_sign) =
+        build_payout_transaction(transaction, event_account.key, payout, winner_script_pubkey)?;
+
+    msg!("Paying {} sats on Bitcoin", payout);
+
+    let tx_bytes = consensus::serialize(&transaction);
+    // the event account is the only signer, so it is the only utxo to update
+    set_transaction_to_sign(
+        std::slice::from_ref(event_account),
+        TransactionToSign {
+            tx_bytes: &tx_bytes,
+            inputs_to_sign: &inputs_to_sign,
+        },
+    )
+}
+
+/// Spends `settlement`'s utxos in a transaction whose first input and output
+/// are the event account's state transition. The fee is the fee rate times
+/// the transaction's size once signed, and comes off the escrowed sats
+/// before they are split pro rata to the shares' units. A share below the
+/// dust limit gets no output; what it and the rounding leave goes to a
+/// change output, or to the fee if that would be dust too. The event account
+/// signs every input.
+///
+/// The transaction is locked to `settlement.lock_height`, so it can't confirm
+/// before the event expires however early it was built. Fails with
+/// `InvalidLockTime` if that isn't a block height bitcoin accepts.
+pub fn build_settlement_transaction(
+    mut transaction: Transaction,
+    event_key: &Pubkey,
+    settlement: &Settlement,
+    fee_rate_sats_per_vbyte: u64,
+) -> Result<(Transaction, Vec<InputToSign>), ProgramError> {
+    transaction.lock_time = LockTime::from_height(settlement.lock_height)
+        .map_err(|_| PredictionMarketError::InvalidLockTime)?;
+    // a lock time only holds with an input that isn't final; these also
+    // signal the transaction may be replaced by a fee bump
+    transaction
+        .input
+        .extend(settlement.escrowed_utxos.iter().map(|utxo| TxIn {
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            ..escrow_input(utxo)
+        }));
+
+    let fee = fee_with_outputs(
+        &transaction,
+        settlement
+            .shares
+            .iter()
+            .map(|share| &share.script_pubkey)
+            .chain([&settlement.change_script_pubkey]),
+        fee_rate_sats_per_vbyte,
+    )?;
+    let distributable = settlement
+        .escrowed_sats
+        .checked_sub(fee)
+        .ok_or(ProgramError::InsufficientFunds)?;
+
+    let mut paid = 0u64;
+    for share in &settlement.shares {
+        let sats = math::mul_div(distributable, share.units, settlement.pool_units)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        if sats < MIN_UTXO_SATS {
+            msg!("Dropping a payout of {} sats as dust", sats);
+            continue;
+        }
+
+        paid = paid
+            .checked_add(sats)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        transaction.output.push(TxOut {
+            value: Amount::from_sat(sats),
+            script_pubkey: share.script_pubkey.clone(),
+        });
+    }
+
+    // shares past the pool would pay more than is escrowed
+    let change = distributable
+        .checked_sub(paid)
+        .ok_or(ProgramError::InsufficientFunds)?;
+    if change >= MIN_UTXO_SATS {
+        transaction.output.push(TxOut {
+            value: Amount::from_sat(change),
+            script_pubkey: settlement.change_script_pubkey.clone(),
+        });
+    }
+
+    let inputs_to_sign = inputs_signed_by(&transaction, event_key);
+
+    Ok((transaction, inputs_to_sign))
+}
+
+/// Spends `refunds`' utxos in a transaction whose first input and output are
+/// the event account's state transition, paying each back to its refund
+/// script. The fee is priced as for a settlement and each refund bears a
+/// share of it in proportion to its value, rounded up. A refund left below
+/// the dust limit gets no output and goes to the fee.
+pub fn build_refund_transaction(
+    mut transaction: Transaction,
+    event_key: &Pubkey,
+    refunds: &[EscrowedUtxo],
+    fee_rate_sats_per_vbyte: u64,
+) -> Result<(Transaction, Vec<InputToSign>), ProgramError> {
+    transaction
+        .input
+        .extend(refunds.iter().map(|refund| escrow_input(&refund.utxo)));
+
+    let scripts: Vec<ScriptBuf> = refunds
+        .iter()
+        .map(|refund| ScriptBuf::from_bytes(refund.refund_script_pubkey.clone()))
+        .collect();
+    let fee = fee_with_outputs(&transaction, &scripts, fee_rate_sats_per_vbyte)?;
+    let total = refunds
+        .iter()
+        .try_fold(0u64, |total, refund| total.checked_add(refund.value_sats))
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    if fee > total {
+        return Err(ProgramError::InsufficientFunds);
+    }
+
+    for (refund, script_pubkey) in refunds.iter().zip(scripts) {
+        let fee_share = math::mul_div_up(fee, refund.value_sats, total)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        let sats = refund.value_sats.saturating_sub(fee_share);
+        if sats < MIN_UTXO_SATS {
+            msg!("Folding a refund of {} sats into the fee", sats);
+            continue;
+        }
+
+        transaction.output.push(TxOut {
+            value: Amount::from_sat(sats),
+            script_pubkey,
+        });
+    }
+
+    let inputs_to_sign = inputs_signed_by(&transaction, event_key);
+
+    Ok((transaction, inputs_to_sign))
+}
+
+pub(crate) fn escrow_input(utxo: &UtxoMeta) -> TxIn {
+    TxIn {
+        previous_output: utxo.to_outpoint(),
+        script_sig: ScriptBuf::new(),
+        sequence: Sequence::MAX,
+        witness: Witness::new(),
+    }
+}
+
+// the fee for `transaction` once it also pays to each of `scripts` and every
+// input is signed; priced with every output it may get, since dropping one
+// only makes it smaller
+pub(crate) fn fee_with_outputs<'s>(
+    transaction: &Transaction,
+    scripts: impl IntoIterator<Item = &'s ScriptBuf>,
+    fee_rate_sats_per_vbyte: u64,
+) -> Result<u64, ProgramError> {
+    let mut priced = transaction.clone();
+    priced
+        .output
+        .extend(scripts.into_iter().map(|script_pubkey| TxOut {
+            value: Amount::ZERO,
+            script_pubkey: script_pubkey.clone(),
+        }));
+    let vbytes = priced.vsize() as u64 + WITNESS_VBYTES_PER_INPUT * priced.input.len() as u64;
+
+    vbytes
+        .checked_mul(fee_rate_sats_per_vbyte)
+        .ok_or(ProgramError::ArithmeticOverflow)
+}
+
+// the escrowed utxos are held by the account whose state transition comes
+// first, an event's or a wrapped-BTC mint's, so it signs every input
+pub(crate) fn inputs_signed_by(transaction: &Transaction, signer: &Pubkey) -> Vec<InputToSign> {
+    (0..transaction.input.len() as u32)
+        .map(|index| InputToSign {
+            index,
+            signer: *signer,
+        })
+        .collect()
+}
+
+/// Pays a resolved event whose whole pool was staked with utxos out on
+/// bitcoin, see `build_settlement_transaction`. Each winner, and the creator
+/// for their fee, is paid at their account's address in proportion to what a
+/// claim would pay them; anyone who already claimed in tokens is left out.
+/// The resolution authority settles, as it picks the fee rate; the event is
+/// `Settled` afterwards, so nothing can be claimed from it again.
+pub fn process_settle_on_bitcoin<'a>(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'a>,
+    authority_account: &AccountInfo<'a>,
+    config_account: &AccountInfo<'a>,
+    params: SettleOnBitcoinParams,
+) -> ProgramResult {
+    ensure_distinct_accounts(&[event_account.key, authority_account.key, config_account.key])?;
+
+    ensure_program_owned(program_id, &[event_account, config_account])?;
+
+    if !authority_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if !(1..=MAX_FEE_RATE_SATS_PER_VBYTE).contains(&params.fee_rate_sats_per_vbyte) {
+        return Err(PredictionMarketError::FeeRateOutOfRange.into());
+    }
+
+    let mut event = event_store::load_event(event_account, &params.unique_id)?;
+
+    if event.resolution_authority() != *authority_account.key {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    match (event.status, event.winning_outcome) {
+        (EventStatus::Resolved, Some(_)) => {}
+        (EventStatus::Settled, _) => return Err(PredictionMarketError::ClaimWindowClosed.into()),
+        _ => return Err(PredictionMarketError::EventNotResolved.into()),
+    }
+
+    let height = get_bitcoin_block_height();
+    if event.in_dispute_window(height) {
+        return Err(PredictionMarketError::DisputeWindowOpen.into());
+    }
+
+    event.ensure_no_open_parlays()?;
+
+    // token stakes can't be paid from utxos, nor utxo stakes in tokens
+    if event.total_pool_amount == 0 || event.utxo_stake != event.total_pool_amount {
+        return Err(PredictionMarketError::NotUtxoFunded.into());
+    }
+
+    let state_utxo = event_account.utxo.clone();
+    let (transaction, inputs_to_sign) = build_bitcoin_payout(
+        event_account,
+        &event,
+        &BitcoinPayoutKind::Settlement,
+        params.fee_rate_sats_per_vbyte,
+    )?;
+
+    event.transition_to(EventStatus::Settled)?;
+    event.bitcoin_payout = Some(BitcoinPayout {
+        txid: runtime_txid(&transaction),
+        state_utxo,
+        fee_rate_sats_per_vbyte: params.fee_rate_sats_per_vbyte,
+        kind: BitcoinPayoutKind::Settlement,
+    });
+    event_store::store_event(event_account, &event)?;
+
+    events::emit(&EventStatusLog {
+        unique_id: params.unique_id,
+        status: EventStatus::Settled,
+        block_height: height,
+    });
+
+    msg!(
+        "Settling {} sats on Bitcoin in {} outputs",
+        event.escrowed_sats(),
+        transaction.output.len()
+    );
+
+    sign_payout(event_account, &transaction, &inputs_to_sign)
+}
+
+/// Returns every escrowed utxo of a cancelled or void event to the refund
+/// script its better gave, see `build_refund_transaction`. The resolution
+/// authority refunds, as it picks the fee rate. When the whole pool was
+/// staked with utxos the event is `Settled` afterwards, which marks the
+/// refunds issued: neither they nor any claim can be made again. Otherwise
+/// token stakes are left to claims, and only the utxos of pending bets gone
+/// stale are returned while bets are open, see `take_refunds`.
+pub fn process_refund_on_bitcoin<'a>(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'a>,
+    authority_account: &AccountInfo<'a>,
+    config_account: &AccountInfo<'a>,
+    params: RefundOnBitcoinParams,
+) -> ProgramResult {
+    ensure_distinct_accounts(&[event_account.key, authority_account.key, config_account.key])?;
+
+    ensure_program_owned(program_id, &[event_account, config_account])?;
+
+    if !authority_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if !(1..=MAX_FEE_RATE_SATS_PER_VBYTE).contains(&params.fee_rate_sats_per_vbyte) {
+        return Err(PredictionMarketError::FeeRateOutOfRange.into());
+    }
+
+    let mut event = event_store::load_event(event_account, &params.unique_id)?;
+
+    if event.resolution_authority() != *authority_account.key {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let height = get_bitcoin_block_height();
+    let (refunds, settled) = take_refunds(&mut event, height)?;
+
+    let refunded = refunds.len();
+    let state_utxo = event_account.utxo.clone();
+    let kind = BitcoinPayoutKind::Refund(refunds);
+    let (transaction, inputs_to_sign) =
+        build_bitcoin_payout(event_account, &event, &kind, params.fee_rate_sats_per_vbyte)?;
+
+    event.bitcoin_payout = Some(BitcoinPayout {
+        txid: runtime_txid(&transaction),
+        state_utxo,
+        fee_rate_sats_per_vbyte: params.fee_rate_sats_per_vbyte,
+        kind,
+    });
+    event_store::store_event(event_account, &event)?;
+
+    if settled {
+        events::emit(&EventStatusLog {
+            unique_id: params.unique_id,
+            status: EventStatus::Settled,
+            block_height: height,
+        });
+    }
+
+    msg!("Refunding {} utxos on Bitcoin", refunded);
+
+    sign_payout(event_account, &transaction, &inputs_to_sign)
+}
+
+/// Replaces the event's last bitcoin payout with one paying
+/// `new_fee_rate_sats_per_vbyte`, for when it is stuck unconfirmed. The
+/// replacement spends the same utxos, so bitcoin confirms one of the two at
+/// most, and pays the same outputs, each smaller by its share of the higher
+/// fee. The program can't see whether the payout confirmed, so it only
+/// replaces one that is still the event account's latest transition; after
+/// that the account has moved on and `NoPayoutToBump` is returned. The new
+/// rate must be above the last one, within `MAX_FEE_RATE_SATS_PER_VBYTE`.
+pub fn process_bump_settlement_fee<'a>(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'a>,
+    authority_account: &AccountInfo<'a>,
+    config_account: &AccountInfo<'a>,
+    params: BumpSettlementFeeParams,
+) -> ProgramResult {
+    ensure_distinct_accounts(&[event_account.key, authority_account.key, config_account.key])?;
+
+    ensure_program_owned(program_id, &[event_account, config_account])?;
+
+    if !authority_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut event = event_store::load_event(event_account, &params.unique_id)?;
+
+    if event.resolution_authority() != *authority_account.key {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let payout = match &event.bitcoin_payout {
+        Some(payout) if event_account.utxo.txid() == payout.txid => payout.clone(),
+        _ => return Err(PredictionMarketError::NoPayoutToBump.into()),
+    };
+
+    if params.new_fee_rate_sats_per_vbyte <= payout.fee_rate_sats_per_vbyte
+        || params.new_fee_rate_sats_per_vbyte > MAX_FEE_RATE_SATS_PER_VBYTE
+    {
+        return Err(PredictionMarketError::FeeRateOutOfRange.into());
+    }
+
+    // back to the utxo the payout spent, so the replacement spends it too
+    event_account.set_utxo(&payout.state_utxo);
+    let (transaction, inputs_to_sign) = build_bitcoin_payout(
+        event_account,
+        &event,
+        &payout.kind,
+        params.new_fee_rate_sats_per_vbyte,
+    )?;
+
+    event.bitcoin_payout = Some(BitcoinPayout {
+        txid: runtime_txid(&transaction),
+        fee_rate_sats_per_vbyte: params.new_fee_rate_sats_per_vbyte,
+        ..payout
+    });
+    event_store::store_event(event_account, &event)?;
+
+    msg!(
+        "Bumping a payout on Bitcoin to {} sats/vbyte",
+        params.new_fee_rate_sats_per_vbyte
+    );
+
+    sign_payout(event_account, &transaction, &inputs_to_sign)
+}
+
+// builds `kind` for the event on top of the event account's state transition
+fn build_bitcoin_payout(
+    event_account: &AccountInfo<'_>,
+    event: &PredictionEvent,
+    kind: &BitcoinPayoutKind,
+    fee_rate_sats_per_vbyte: u64,
+) -> Result<(Transaction, Vec<InputToSign>), ProgramError> {
+    let mut transaction = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![],
+        output: vec![],
+    };
+    add_state_transition(&mut transaction, event_account);
+
+    match kind {
+        BitcoinPayoutKind::Settlement => build_settlement_transaction(
+            transaction,
+            event_account.key,
+            &settlement_of(event, event_account.key)?,
+            fee_rate_sats_per_vbyte,
+        ),
+        BitcoinPayoutKind::Refund(refunds) => build_refund_transaction(
+            transaction,
+            event_account.key,
+            refunds,
+            fee_rate_sats_per_vbyte,
+        ),
+    }
+}
+
+// what a resolved event pays out on bitcoin, see `process_settle_on_bitcoin`
+fn settlement_of(event: &PredictionEvent, event_key: &Pubkey) -> Result<Settlement, ProgramError> {
+    let winning_index = event
+        .winning_outcome
+        .and_then(|id| event.outcome_index(id))
+        .ok_or(PredictionMarketError::EventNotResolved)?;
+    let winning: Vec<&Outcome> = event
+        .outcomes
+        .iter()
+        .filter(|outcome| event.is_winner(outcome.id))
+        .collect();
+
+    // sorted so every validator builds the same transaction; whoever took
+    // their payout in tokens already is not paid again
+    let winners: BTreeSet<&Pubkey> = winning
+        .iter()
+        .flat_map(|outcome| outcome.bets.keys())
+        .filter(|winner| !event.claimed.contains(winner))
+        .collect();
+
+    let mut shares = Vec::with_capacity(winners.len() + 1);
+    for winner in winners {
+        let stake = winning.iter().fold(0u64, |stake, outcome| {
+            stake.saturating_add(net_stake(outcome, winner))
+        });
+        let units = math::winning_payout(event, winning_index, stake)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        shares.push(SettlementShare {
+            script_pubkey: account_script_pubkey(winner),
+            units,
+        });
+    }
+    if !event.claimed.contains(&event.creator) {
+        shares.push(SettlementShare {
+            script_pubkey: account_script_pubkey(&event.creator),
+            units: event.creator_fee(),
+        });
+    }
+    shares.retain(|share| share.units > 0);
+
+    Ok(Settlement {
+        escrowed_utxos: event
+            .escrowed_utxos
+            .iter()
+            .map(|escrowed| escrowed.utxo.clone())
+            .collect(),
+        escrowed_sats: event.escrowed_sats(),
+        pool_units: event.total_pool_amount,
+        shares,
+        change_script_pubkey: account_script_pubkey(event_key),
+        lock_height: event.expiry_timestamp,
+    })
+}
+
+// hands `transaction` to the runtime for `account` to sign
+pub(crate) fn sign_payout(
+    account: &AccountInfo<'_>,
+    transaction: &Transaction,
+    inputs_to_sign: &[InputToSign],
+) -> ProgramResult {
+    let tx_bytes = consensus::serialize(transaction);
+    set_transaction_to_sign(
+        std::slice::from_ref(account),
+        TransactionToSign {
+            tx_bytes: &tx_bytes,
+            inputs_to_sign,
+        },
+    )
+}
+
+// the txid in the byte order the runtime stores in a signer's utxo, which
+// is the order it is displayed in
+fn runtime_txid(transaction: &Transaction) -> [u8; 32] {
+    let mut txid = transaction.compute_txid().to_byte_array();
+    txid.reverse();
+    txid
+}
+
+/// Takes what `process_refund_on_bitcoin` refunds at `height` off the event,
+/// and whether that settles it. A cancelled or void event refunds every
+/// utxo, pending or credited, and takes the stakes they back out of the
+/// pool, so none is refunded twice. It moves to `Settled` when its whole
+/// pool was staked with utxos; token stakes are left to claims otherwise.
+/// Failing that, pending bets gone stale are refunded on their own and the
+/// event is left as it is.
+pub fn take_refunds(
+    event: &mut PredictionEvent,
+    height: u64,
+) -> Result<(Vec<EscrowedUtxo>, bool), ProgramError> {
+    let void = matches!(
+        (event.status, event.winning_outcome),
+        (EventStatus::Cancelled, _) | (EventStatus::Resolved, None)
+    );
+    let refunds_credited =
+        void && !event.escrowed_utxos.is_empty() && !event.in_dispute_window(height);
+    // token stakes are refunded by claims, which settling would shut out
+    let settles = refunds_credited && event.utxo_stake == event.total_pool_amount;
+
+    if !refunds_credited
+        && (event.pending_utxo_bets.is_empty() || !event.pending_bets_stale(height))
+    {
+        let error = match event.status {
+            EventStatus::Settled => PredictionMarketError::ClaimWindowClosed,
+            _ if !void => PredictionMarketError::EventNotResolved,
+            _ if event.in_dispute_window(height) => PredictionMarketError::DisputeWindowOpen,
+            _ => PredictionMarketError::NotUtxoFunded,
+        };
+        return Err(error.into());
+    }
+
+    let pending = std::mem::take(&mut event.pending_utxo_bets)
+        .into_iter()
+        .map(|bet| bet.escrowed);
+    if !refunds_credited {
+        return Ok((pending.collect(), false));
+    }
+
+    if settles {
+        event.transition_to(EventStatus::Settled)?;
+    }
+    event.release_utxo_stakes()?;
+    let refunds = std::mem::take(&mut event.escrowed_utxos)
+        .into_iter()
+        .chain(pending)
+        .collect();
+
+    Ok((refunds, settles))
+}
+
+pub(crate) fn account_script_pubkey(key: &Pubkey) -> ScriptBuf {
+    ScriptBuf::from_bytes(get_account_script_pubkey(key).to_vec())
+}
+
+#[cfg(test)]
+mod payout_transaction_tests {
+    use super::*;
+    use crate::process_instruction;
+    use crate::test_utils::{
+        config_account, event_params, register_utxo_owner, take_transactions_to_sign,
+        user_limits_account, Market, TestAccount,
+    };
+    use crate::types::{PredictionEventParams, UtxoBetParams};
+    use arch_program::bitcoin::{
+        absolute::{Height, Time},
+        OutPoint, Txid,
+    };
+    use std::str::FromStr;
+
+    fn state_transition_fixture(vault_sats: u64) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint {
+                    txid: Txid::from_str(
+                        "c5cc9251192330191366016c8dab0f67dc345bd024a206c313dbf26db0a66bb1",
+                    )
+                    .unwrap(),
+                    vout: 0,
+                },
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(vault_sats),
+                script_pubkey: ScriptBuf::from_bytes(vec![0x51, 0x20, 0xaa]),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_payout_outputs_and_inputs_to_sign() {
+        let event_key = Pubkey::new_unique();
+        let winner_script = ScriptBuf::from_bytes(vec![0x51, 0x20, 0xbb]);
+
+        let (transaction, inputs_to_sign) = build_payout_transaction(
+            state_transition_fixture(10_000),
+            &event_key,
+            2_500,
+            winner_script.clone(),
+        )
+        .unwrap();
+
+        assert_eq!(transaction.input.len(), 1);
+        assert_eq!(transaction.output.len(), 2);
+        assert_eq!(transaction.output[0].value, Amount::from_sat(7_500));
+        assert_eq!(
+            transaction.output[0].script_pubkey,
+            ScriptBuf::from_bytes(vec![0x51, 0x20, 0xaa])
+        );
+        assert_eq!(transaction.output[1].value, Amount::from_sat(2_500));
+        assert_eq!(transaction.output[1].script_pubkey, winner_script);
+        assert_eq!(
+            inputs_to_sign,
+            vec![InputToSign {
+                index: 0,
+                signer: event_key
+            }]
+        );
+
+        let tx_bytes = consensus::serialize(&transaction);
+        let serialized = TransactionToSign {
+            tx_bytes: &tx_bytes,
+            inputs_to_sign: &inputs_to_sign,
+        }
+        .serialise();
+        let transaction_to_sign = TransactionToSign::from_slice(&serialized);
+        let decoded: Transaction = consensus::deserialize(transaction_to_sign.tx_bytes).unwrap();
+        assert_eq!(decoded, transaction);
+        assert_eq!(transaction_to_sign.inputs_to_sign, &inputs_to_sign[..]);
+    }
+
+    #[test]
+    fn test_payout_larger_than_vault_is_rejected() {
+        let result = build_payout_transaction(
+            state_transition_fixture(1_000),
+            &Pubkey::new_unique(),
+            1_001,
+            ScriptBuf::new(),
+        );
+
+        assert_eq!(result.unwrap_err(), ProgramError::InsufficientFunds);
+    }
+
+    fn script(tag: u8) -> ScriptBuf {
+        ScriptBuf::from_bytes(vec![0x51, 0x20, tag])
+    }
+
+    // 100_000 sats in two utxos backing a pool of 1_000 units
+    fn settlement(units: &[u64]) -> Settlement {
+        Settlement {
+            escrowed_utxos: vec![UtxoMeta::from([0x11; 32], 0), UtxoMeta::from([0x22; 32], 1)],
+            escrowed_sats: 100_000,
+            pool_units: 1_000,
+            shares: units
+                .iter()
+                .enumerate()
+                .map(|(i, units)| SettlementShare {
+                    script_pubkey: script(0xb0 + i as u8),
+                    units: *units,
+                })
+                .collect(),
+            change_script_pubkey: script(0xcc),
+            lock_height: 840_000,
+        }
+    }
+
+    fn output(sats: u64, script_pubkey: ScriptBuf) -> TxOut {
+        TxOut {
+            value: Amount::from_sat(sats),
+            script_pubkey,
+        }
+    }
+
+    #[test]
+    fn test_settlement_matches_its_fixture() {
+        let event_key = Pubkey::new_unique();
+
+        let (transaction, inputs_to_sign) = build_settlement_transaction(
+            state_transition_fixture(10_000),
+            &event_key,
+            &settlement(&[600, 300, 1]),
+            2,
+        )
+        .unwrap();
+
+        // 244 vbytes signed at 2 sats leaves 99_512 to split; the third
+        // share's 99 sats are dust and go to the change with the rounding
+        let mut expected = state_transition_fixture(10_000);
+        expected.lock_time = LockTime::from_height(840_000).unwrap();
+        for utxo in &settlement(&[]).escrowed_utxos {
+            expected.input.push(TxIn {
+                previous_output: utxo.to_outpoint(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::new(),
+            });
+        }
+        expected.output.extend([
+            output(59_707, script(0xb0)),
+            output(29_853, script(0xb1)),
+            output(9_952, script(0xcc)),
+        ]);
+        assert_eq!(
+            consensus::serialize(&transaction),
+            consensus::serialize(&expected)
+        );
+        assert_eq!(
+            inputs_to_sign,
+            (0..3)
+                .map(|index| InputToSign {
+                    index,
+                    signer: event_key
+                })
+                .collect::<Vec<_>>()
+        );
+
+        let tx_bytes = consensus::serialize(&transaction);
+        let serialized = TransactionToSign {
+            tx_bytes: &tx_bytes,
+            inputs_to_sign: &inputs_to_sign,
+        }
+        .serialise();
+        let decoded: Transaction =
+            consensus::deserialize(TransactionToSign::from_slice(&serialized).tx_bytes).unwrap();
+        assert_eq!(decoded, expected);
+        assert!(decoded.is_lock_time_enabled());
+    }
+
+    #[test]
+    fn test_settlement_is_locked_until_expiry() {
+        let (transaction, _) = build_settlement_transaction(
+            state_transition_fixture(10_000),
+            &Pubkey::new_unique(),
+            &settlement(&[600, 400]),
+            2,
+        )
+        .unwrap();
+
+        let decoded: Transaction =
+            consensus::deserialize(&consensus::serialize(&transaction)).unwrap();
+        assert_eq!(decoded.lock_time, LockTime::from_height(840_000).unwrap());
+        assert!(decoded.is_lock_time_enabled());
+        assert!(!decoded
+            .is_absolute_timelock_satisfied(Height::from_consensus(839_999).unwrap(), Time::MIN));
+        assert!(decoded
+            .is_absolute_timelock_satisfied(Height::from_consensus(840_001).unwrap(), Time::MIN));
+
+        // heights from 500_000_000 on would be read as timestamps
+        let mut too_late = settlement(&[1_000]);
+        too_late.lock_height = 500_000_000;
+        assert_eq!(
+            build_settlement_transaction(
+                state_transition_fixture(10_000),
+                &Pubkey::new_unique(),
+                &too_late,
+                2,
+            )
+            .unwrap_err(),
+            PredictionMarketError::InvalidLockTime.into()
+        );
+    }
+
+    #[test]
+    fn test_dust_change_is_left_to_the_fee() {
+        let (transaction, _) = build_settlement_transaction(
+            state_transition_fixture(10_000),
+            &Pubkey::new_unique(),
+            &settlement(&[600, 400]),
+            2,
+        )
+        .unwrap();
+
+        // 232 vbytes at 2 sats leaves 99_536, of which a single sat is left
+        assert_eq!(
+            transaction.output[1..],
+            [output(59_721, script(0xb0)), output(39_814, script(0xb1))]
+        );
+    }
+
+    #[test]
+    fn test_settlement_fee_must_fit_the_escrow() {
+        let mut too_small = settlement(&[1_000]);
+        too_small.escrowed_sats = 400;
+
+        let result = build_settlement_transaction(
+            state_transition_fixture(10_000),
+            &Pubkey::new_unique(),
+            &too_small,
+            2,
+        );
+
+        assert_eq!(result.unwrap_err(), ProgramError::InsufficientFunds);
+    }
+
+    fn refund(value_sats: u64, tag: u8) -> EscrowedUtxo {
+        EscrowedUtxo {
+            utxo: UtxoMeta::from([tag; 32], 0),
+            value_sats,
+            refund_script_pubkey: script(tag).to_bytes(),
+        }
+    }
+
+    #[test]
+    fn test_refund_matches_its_fixture() {
+        let event_key = Pubkey::new_unique();
+        let refunds = [refund(50_000, 0xd0), refund(20_000, 0xd1)];
+
+        let (transaction, inputs_to_sign) =
+            build_refund_transaction(state_transition_fixture(10_000), &event_key, &refunds, 2)
+                .unwrap();
+
+        // 220 vbytes signed at 2 sats, shared 5:2 and rounded up
+        let mut expected = state_transition_fixture(10_000);
+        for refund in &refunds {
+            expected.input.push(TxIn {
+                previous_output: refund.utxo.to_outpoint(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            });
+        }
+        expected
+            .output
+            .extend([output(49_685, script(0xd0)), output(19_874, script(0xd1))]);
+        assert_eq!(
+            consensus::serialize(&transaction),
+            consensus::serialize(&expected)
+        );
+        assert_eq!(inputs_to_sign.len(), 3);
+        assert!(inputs_to_sign.iter().all(|input| input.signer == event_key));
+    }
+
+    #[test]
+    fn test_dust_refund_is_folded_into_the_fee() {
+        let refunds = [refund(50_000, 0xd0), refund(550, 0xd1)];
+
+        let (transaction, inputs_to_sign) = build_refund_transaction(
+            state_transition_fixture(10_000),
+            &Pubkey::new_unique(),
+            &refunds,
+            2,
+        )
+        .unwrap();
+
+        // the second refund's share of the 440 sat fee leaves it 545 sats
+        assert_eq!(transaction.output[1..], [output(49_564, script(0xd0))]);
+        assert_eq!(inputs_to_sign.len(), 3);
+
+        assert_eq!(
+            build_refund_transaction(
+                state_transition_fixture(10_000),
+                &Pubkey::new_unique(),
+                &[refund(250, 0xd0)],
+                2,
+            )
+            .unwrap_err(),
+            ProgramError::InsufficientFunds
+        );
+    }
+
+    fn refund_on_bitcoin(
+        market: &mut Market,
+        authority: &mut TestAccount,
+    ) -> Result<(), ProgramError> {
+        let program_id = market.event.owner;
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+        let mut data = vec![63];
+        data.extend(
+            borsh::to_vec(&RefundOnBitcoinParams {
+                unique_id: market.unique_id,
+                fee_rate_sats_per_vbyte: 2,
+            })
+            .unwrap(),
+        );
+        process_instruction(
+            &program_id,
+            &[market.event.info(), authority.info(), config.info()],
+            &data,
+        )
+    }
+
+    #[test]
+    fn test_only_cancelled_utxo_funded_events_refund_on_bitcoin() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, [66; 32], &[(alice.key(), 100)]);
+        market.buy(&mut alice, 0, 50).unwrap();
+        let mut creator = TestAccount::new(&program_id, &[])
+            .signer()
+            .with_key(market.creator.key());
+
+        assert_eq!(
+            refund_on_bitcoin(&mut market, &mut creator),
+            Err(PredictionMarketError::EventNotResolved.into())
+        );
+
+        crate::process_cancel_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            [66; 32],
+        )
+        .unwrap();
+        assert_eq!(
+            refund_on_bitcoin(&mut market, &mut alice),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        // the stake is in tokens, which a claim refunds
+        assert_eq!(
+            refund_on_bitcoin(&mut market, &mut creator),
+            Err(PredictionMarketError::NotUtxoFunded.into())
+        );
+        market.claim(&mut alice).unwrap();
+        assert_eq!(market.balance(&alice), 100);
+    }
+
+    fn settle(
+        market: &mut Market,
+        authority: &mut TestAccount,
+        fee_rate_sats_per_vbyte: u64,
+    ) -> Result<(), ProgramError> {
+        let program_id = market.event.owner;
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+        let mut data = vec![61];
+        data.extend(
+            borsh::to_vec(&SettleOnBitcoinParams {
+                unique_id: market.unique_id,
+                fee_rate_sats_per_vbyte,
+            })
+            .unwrap(),
+        );
+        process_instruction(
+            &program_id,
+            &[market.event.info(), authority.info(), config.info()],
+            &data,
+        )
+    }
+
+    #[test]
+    fn test_only_resolved_utxo_funded_events_settle_on_bitcoin() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, [65; 32], &[(alice.key(), 100)]);
+        market.buy(&mut alice, 0, 50).unwrap();
+        let mut creator = TestAccount::new(&program_id, &[])
+            .signer()
+            .with_key(market.creator.key());
+
+        assert_eq!(
+            settle(&mut market, &mut creator, 0),
+            Err(PredictionMarketError::FeeRateOutOfRange.into())
+        );
+        assert_eq!(
+            settle(&mut market, &mut creator, MAX_FEE_RATE_SATS_PER_VBYTE + 1),
+            Err(PredictionMarketError::FeeRateOutOfRange.into())
+        );
+        assert_eq!(
+            settle(&mut market, &mut creator, 2),
+            Err(PredictionMarketError::EventNotResolved.into())
+        );
+
+        crate::process_resolve_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            [65; 32],
+            0,
+        )
+        .unwrap();
+        assert_eq!(
+            settle(&mut market, &mut alice, 2),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        // the pool is all tokens
+        assert_eq!(
+            settle(&mut market, &mut creator, 2),
+            Err(PredictionMarketError::NotUtxoFunded.into())
+        );
+        assert_eq!(market.event_state().status, EventStatus::Resolved);
+    }
+
+    fn bet_with_utxo(
+        market: &mut Market,
+        better: &mut TestAccount,
+        outcome_id: u8,
+        utxo: UtxoMeta,
+        value_sats: u64,
+    ) {
+        let program_id = market.event.owner;
+        register_utxo_owner(utxo.clone(), better.key());
+        let mut limits = user_limits_account(&program_id, &better.key());
+        let mut data = vec![60];
+        data.extend(
+            borsh::to_vec(&UtxoBetParams {
+                unique_id: market.unique_id,
+                outcome_id,
+                utxo,
+                value_sats,
+                refund_script_pubkey: [vec![0x51, 0x20], vec![0xaa; 32]].concat(),
+            })
+            .unwrap(),
+        );
+        process_instruction(
+            &program_id,
+            &[
+                market.event.info(),
+                better.info(),
+                limits.info(),
+                market.config.info(),
+            ],
+            &data,
+        )
+        .unwrap();
+    }
+
+    fn bump(
+        market: &mut Market,
+        authority: &mut TestAccount,
+        new_fee_rate_sats_per_vbyte: u64,
+    ) -> Result<(), ProgramError> {
+        let program_id = market.event.owner;
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+        let mut data = vec![66];
+        data.extend(
+            borsh::to_vec(&BumpSettlementFeeParams {
+                unique_id: market.unique_id,
+                new_fee_rate_sats_per_vbyte,
+            })
+            .unwrap(),
+        );
+        process_instruction(
+            &program_id,
+            &[market.event.info(), authority.info(), config.info()],
+            &data,
+        )
+    }
+
+    // the payout just handed to the runtime, with the event account moved to
+    // it as the runtime would once it is signed
+    fn signed_payout(market: &mut Market) -> Transaction {
+        let (transaction, _) = take_transactions_to_sign().pop().unwrap();
+        market.event.utxo = UtxoMeta::from(runtime_txid(&transaction), 0);
+        transaction
+    }
+
+    fn previous_outputs(transaction: &Transaction) -> Vec<OutPoint> {
+        transaction
+            .input
+            .iter()
+            .map(|input| input.previous_output)
+            .collect()
+    }
+
+    #[test]
+    fn test_bumped_settlement_spends_the_same_utxos_for_more_fee() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, [77; 32], &[]);
+        bet_with_utxo(
+            &mut market,
+            &mut alice,
+            0,
+            UtxoMeta::from([15; 32], 0),
+            10_000,
+        );
+        bet_with_utxo(
+            &mut market,
+            &mut bob,
+            1,
+            UtxoMeta::from([15; 32], 1),
+            10_000,
+        );
+        let mut creator = TestAccount::new(&program_id, &[])
+            .signer()
+            .with_key(market.creator.key());
+        crate::process_resolve_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            [77; 32],
+            0,
+        )
+        .unwrap();
+        let state_utxo = market.event.utxo.clone();
+
+        assert_eq!(
+            bump(&mut market, &mut creator, 4),
+            Err(PredictionMarketError::NoPayoutToBump.into())
+        );
+
+        settle(&mut market, &mut creator, 2).unwrap();
+        let settled = signed_payout(&mut market);
+        let payout = market.event_state().bitcoin_payout.unwrap();
+        assert_eq!(payout.txid, market.event.utxo.txid());
+        assert_eq!(payout.state_utxo, state_utxo);
+        assert_eq!(payout.fee_rate_sats_per_vbyte, 2);
+        assert_eq!(payout.kind, BitcoinPayoutKind::Settlement);
+
+        assert_eq!(
+            bump(&mut market, &mut alice, 4),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert_eq!(
+            bump(&mut market, &mut creator, 2),
+            Err(PredictionMarketError::FeeRateOutOfRange.into())
+        );
+        assert_eq!(
+            bump(&mut market, &mut creator, MAX_FEE_RATE_SATS_PER_VBYTE + 1),
+            Err(PredictionMarketError::FeeRateOutOfRange.into())
+        );
+
+        bump(&mut market, &mut creator, 4).unwrap();
+        let bumped = signed_payout(&mut market);
+        assert_eq!(previous_outputs(&bumped), previous_outputs(&settled));
+        assert_eq!(bumped.output.len(), settled.output.len());
+        // the higher fee comes out of the payout
+        assert!(bumped.output[1].value < settled.output[1].value);
+        assert_eq!(
+            bumped.output[1].script_pubkey,
+            settled.output[1].script_pubkey
+        );
+        let payout = market.event_state().bitcoin_payout.unwrap();
+        assert_eq!(payout.txid, market.event.utxo.txid());
+        assert_eq!(payout.state_utxo, state_utxo);
+        assert_eq!(payout.fee_rate_sats_per_vbyte, 4);
+
+        // the event account has moved on, so the payout may have confirmed
+        market.event.utxo = UtxoMeta::from([0xee; 32], 0);
+        assert_eq!(
+            bump(&mut market, &mut creator, 8),
+            Err(PredictionMarketError::NoPayoutToBump.into())
+        );
+    }
+
+    #[test]
+    fn test_bumped_refund_pays_each_refund_less() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, [78; 32], &[]);
+        let utxo = UtxoMeta::from([16; 32], 0);
+        bet_with_utxo(&mut market, &mut alice, 0, utxo.clone(), 10_000);
+        let mut creator = TestAccount::new(&program_id, &[])
+            .signer()
+            .with_key(market.creator.key());
+        crate::process_cancel_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            [78; 32],
+        )
+        .unwrap();
+
+        refund_on_bitcoin(&mut market, &mut creator).unwrap();
+        let refunded = signed_payout(&mut market);
+        bump(&mut market, &mut creator, 3).unwrap();
+        let bumped = signed_payout(&mut market);
+
+        assert_eq!(previous_outputs(&bumped), previous_outputs(&refunded));
+        assert_eq!(bumped.input[1].previous_output, utxo.to_outpoint());
+        assert!(bumped.output[1].value < refunded.output[1].value);
+        assert_eq!(
+            bumped.output[1].script_pubkey,
+            refunded.output[1].script_pubkey
+        );
+        assert_eq!(
+            market.event_state().bitcoin_payout.unwrap().kind,
+            BitcoinPayoutKind::Refund(vec![EscrowedUtxo {
+                utxo,
+                value_sats: 10_000,
+                refund_script_pubkey: [vec![0x51, 0x20], vec![0xaa; 32]].concat(),
+            }])
+        );
+    }
+
+    #[test]
+    fn test_settlement_pays_no_one_twice() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let params = PredictionEventParams {
+            creator_fee_bps: 100,
+            ..event_params([79; 32], 100, 2)
+        };
+        let mut market = Market::with_params(&program_id, params, &[]);
+        bet_with_utxo(
+            &mut market,
+            &mut alice,
+            0,
+            UtxoMeta::from([17; 32], 0),
+            10_000,
+        );
+        bet_with_utxo(
+            &mut market,
+            &mut bob,
+            1,
+            UtxoMeta::from([17; 32], 1),
+            10_000,
+        );
+        crate::process_resolve_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            [79; 32],
+            0,
+        )
+        .unwrap();
+
+        // a winning utxo stake is only paid on bitcoin
+        assert_eq!(
+            market.claim(&mut alice),
+            Err(PredictionMarketError::UtxoStakeLocked.into())
+        );
+
+        let mut event = market.event_state();
+        let scripts = |settlement: Settlement| {
+            settlement
+                .shares
+                .into_iter()
+                .map(|share| share.script_pubkey)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            scripts(settlement_of(&event, &market.event.key()).unwrap()),
+            [
+                account_script_pubkey(&alice.key()),
+                account_script_pubkey(&market.creator.key()),
+            ]
+        );
+
+        // the creator took the fee in tokens
+        event.claimed.insert(market.creator.key());
+        assert_eq!(
+            scripts(settlement_of(&event, &market.event.key()).unwrap()),
+            [account_script_pubkey(&alice.key())]
+        );
+    }
+
+    #[test]
+    fn test_mixed_void_event_refunds_its_utxos_and_leaves_tokens_to_claims() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, [80; 32], &[(alice.key(), 100)]);
+        market.buy(&mut alice, 0, 50).unwrap();
+        let utxo = UtxoMeta::from([18; 32], 0);
+        bet_with_utxo(&mut market, &mut bob, 1, utxo.clone(), 5_000);
+        let mut creator = TestAccount::new(&program_id, &[])
+            .signer()
+            .with_key(market.creator.key());
+        crate::process_cancel_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            [80; 32],
+        )
+        .unwrap();
+
+        assert_eq!(
+            market.claim(&mut bob),
+            Err(PredictionMarketError::UtxoStakeLocked.into())
+        );
+        refund_on_bitcoin(&mut market, &mut creator).unwrap();
+        let refunded = signed_payout(&mut market);
+        assert_eq!(refunded.input[1].previous_output, utxo.to_outpoint());
+
+        let event = market.event_state();
+        assert_eq!(event.status, EventStatus::Cancelled);
+        assert!(event.escrowed_utxos.is_empty());
+        assert_eq!(event.utxo_stake, 0);
+        assert_eq!(event.total_pool_amount, 50);
+
+        // the refund is issued once, and bob has nothing left to claim
+        assert_eq!(
+            refund_on_bitcoin(&mut market, &mut creator),
+            Err(PredictionMarketError::NotUtxoFunded.into())
+        );
+        assert_eq!(
+            market.claim(&mut bob),
+            Err(PredictionMarketError::NoWinningPosition.into())
+        );
+        market.claim(&mut alice).unwrap();
+        assert_eq!(market.balance(&alice), 100);
+    }
+}
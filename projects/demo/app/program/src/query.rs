@@ -0,0 +1,977 @@
+//! Read-only instructions that hand a compact view of an event back through
+//! the program's return data, so clients don't have to decode the event
+//! account themselves. They only borrow the event account immutably, so they
+//! work against read-only accounts and never resize anything.
+#[cfg(not(test))]
+use arch_program::program::set_return_data;
+use arch_program::{
+    account::AccountInfo, msg, program::MAX_RETURN_DATA, program_error::ProgramError,
+    pubkey::Pubkey,
+};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+#[cfg(test)]
+use crate::test_utils::set_return_data;
+use crate::{
+    archive, ensure_program_owned, event_store,
+    math::{self, compute_odds, OddsEntry},
+    registry::{self, EventSummaryEntry},
+    stats::VolumeBucket,
+    types::{net_stake, Bet, CostBasis, EventStatus, PredictionEvent, PredictionMarketError},
+};
+
+/// Most event accounts one `UserBetHistory` or `ListEventsByStatus` reads.
+pub const MAX_HISTORY_EVENT_ACCOUNTS: usize = 8;
+
+/// Most bets one `UserBetHistory` page holds; a full page of borsh `Bet`s,
+/// 82 bytes each, still fits the return data.
+pub const MAX_HISTORY_PAGE: usize = 12;
+
+/// Most events one `ListEventsByStatus` page holds; a full page of borsh
+/// `EventListing`s, 47 bytes each, still fits the return data.
+pub const MAX_EVENT_LIST_PAGE: usize = 16;
+
+/// Most entries one `QueryRegistry` page holds; a full page of
+/// `EventSummaryEntry`s, 70 bytes each, still fits the return data.
+pub const MAX_REGISTRY_PAGE: usize = 14;
+
+/// Most events one `QueryEventsByCreator` page holds; a full page of
+/// `CreatorEvent`s, 33 bytes each, still fits the return data.
+pub const MAX_CREATOR_EVENTS_PAGE: usize = 30;
+
+/// Return data of `QueryEvent`. `outcome_totals` is indexed by outcome id.
+/// Amounts are in the smallest unit of the event's mint, which has
+/// `decimals`.
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct EventSummary {
+    pub status: EventStatus,
+    pub expiry_timestamp: u32,
+    pub decimals: u8,
+    pub total_pool_amount: u64,
+    pub outcome_totals: Vec<u64>,
+    // by outcome id, empty when the event has no labels
+    pub outcome_labels: Vec<String>,
+    pub winning_outcome: Option<u8>,
+}
+
+impl EventSummary {
+    fn of(event: PredictionEvent) -> Self {
+        EventSummary {
+            status: event.status,
+            expiry_timestamp: event.expiry_timestamp,
+            decimals: event.decimals,
+            total_pool_amount: event.total_pool_amount,
+            outcome_totals: event.outcomes.iter().map(|o| o.total_amount).collect(),
+            outcome_labels: event.labels,
+            winning_outcome: event.winning_outcome,
+        }
+    }
+}
+
+/// Return data of `QueryUserPosition`: the user's net stake and cost basis on
+/// each outcome, indexed by outcome id, and whether they already claimed.
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct UserPosition {
+    pub net_stakes: Vec<u64>,
+    pub cost_basis: Vec<CostBasis>,
+    pub claimed: bool,
+}
+
+/// Return data of `PreviewPayout`: what the user would be paid if
+/// `outcome_id` won as the event stands, and the stake that pays it.
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct PayoutPreview {
+    pub outcome_id: u8,
+    pub stake: u64,
+    pub payout: u64,
+}
+
+/// Return data of `UserBetHistory`: up to `MAX_HISTORY_PAGE` of the user's
+/// bets as the events store them, in the order the event accounts were
+/// passed, then by event, outcome id and time. `next_offset` is the `offset` to ask
+/// for the next page with, `None` once this page holds the last bet.
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct BetHistoryPage {
+    pub bets: Vec<Bet>,
+    pub next_offset: Option<u32>,
+}
+
+/// One event of a `ListEventsByStatus` page.
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct EventListing {
+    pub unique_id: [u8; 32],
+    pub status: EventStatus,
+    pub expiry_timestamp: u32,
+    pub total_pool_amount: u64,
+    pub winning_outcome: Option<u8>,
+}
+
+/// Return data of `ListEventsByStatus`: a page of the events in the asked
+/// status, in the order the event accounts were passed, then as each account
+/// stores them. `next_offset` works as in `BetHistoryPage`.
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct EventListPage {
+    pub events: Vec<EventListing>,
+    pub next_offset: Option<u32>,
+}
+
+/// Return data of `QueryRegistry`: a page of the registry's entries in the
+/// order their events were created, only those in the asked status if one
+/// was given. `next_offset` works as in `BetHistoryPage`.
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct RegistryPage {
+    pub entries: Vec<EventSummaryEntry>,
+    pub next_offset: Option<u32>,
+}
+
+/// One event of a `QueryEventsByCreator` page.
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct CreatorEvent {
+    pub unique_id: [u8; 32],
+    pub status: EventStatus,
+}
+
+/// Return data of `QueryEventsByCreator`: a page of the creator's registered
+/// events in the order they were created. `next_offset` works as in
+/// `BetHistoryPage`.
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct CreatorEventsPage {
+    pub events: Vec<CreatorEvent>,
+    pub next_offset: Option<u32>,
+}
+
+/// Return data of `QueryEventStats`: the event's `EventStats` without the
+/// bettor filter, at most 608 bytes. `unique_bettors` is approximate, see
+/// `stats`.
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct EventStatsReport {
+    pub total_bets: u64,
+    pub unique_bettors: u32,
+    pub buy_volume: u64,
+    pub sell_volume: u64,
+    pub recent_volume: Vec<VolumeBucket>,
+}
+
+/// Collects one page of a sequence: skips the first `offset` items, takes up
+/// to `limit` and notes where the next page starts if any are left.
+struct Paginator<T> {
+    items: Vec<T>,
+    next_offset: Option<u32>,
+    offset: u32,
+    limit: usize,
+    seen: u32,
+}
+
+impl<T> Paginator<T> {
+    fn new(offset: u32, limit: usize) -> Self {
+        Paginator {
+            items: Vec::new(),
+            next_offset: None,
+            offset,
+            limit,
+            seen: 0,
+        }
+    }
+
+    /// Offers the sequence's next item, built only if it lands on the page.
+    /// `false` once the page is full and the sequence can stop.
+    fn offer(&mut self, item: impl FnOnce() -> T) -> bool {
+        if self.seen >= self.offset {
+            if self.items.len() == self.limit {
+                self.next_offset = Some(self.seen);
+                return false;
+            }
+            self.items.push(item());
+        }
+        self.seen += 1;
+        true
+    }
+}
+
+fn return_borsh<T: BorshSerialize>(value: &T) -> Result<(), ProgramError> {
+    let data = borsh::to_vec(value).map_err(|e| ProgramError::BorshIoError(e.to_string()))?;
+
+    if data.len() > MAX_RETURN_DATA {
+        msg!(
+            "Query result of {} bytes exceeds the return data limit",
+            data.len()
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    set_return_data(&data);
+
+    Ok(())
+}
+
+pub fn process_query_event(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'_>,
+    unique_id: [u8; 32],
+) -> Result<EventSummary, ProgramError> {
+    ensure_program_owned(program_id, &[event_account])?;
+
+    let event = event_store::load_event(event_account, &unique_id)?;
+
+    let summary = EventSummary::of(event);
+    return_borsh(&summary)?;
+
+    Ok(summary)
+}
+
+/// `QueryEvent` for an event moved to the archive by `ArchiveEvent`.
+pub fn process_query_archived_event(
+    program_id: &Pubkey,
+    archive_account: &AccountInfo<'_>,
+    unique_id: [u8; 32],
+) -> Result<EventSummary, ProgramError> {
+    archive::ensure_archive(program_id, archive_account)?;
+
+    let event = archive::load_archived(&archive_account.try_borrow_data()?, &unique_id)?;
+
+    let summary = EventSummary::of(event);
+    return_borsh(&summary)?;
+
+    Ok(summary)
+}
+
+pub fn process_query_user_position(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'_>,
+    unique_id: [u8; 32],
+    user: &Pubkey,
+) -> Result<UserPosition, ProgramError> {
+    ensure_program_owned(program_id, &[event_account])?;
+
+    let event = event_store::load_event(event_account, &unique_id)?;
+
+    let position = UserPosition {
+        net_stakes: event
+            .outcomes
+            .iter()
+            .map(|outcome| net_stake(outcome, user))
+            .collect(),
+        cost_basis: event
+            .outcomes
+            .iter()
+            .map(|outcome| {
+                event
+                    .cost_basis
+                    .get(&(*user, outcome.id))
+                    .cloned()
+                    .unwrap_or_default()
+            })
+            .collect(),
+        claimed: event.claimed.contains(user),
+    };
+    return_borsh(&position)?;
+
+    Ok(position)
+}
+
+/// Previews what claiming would pay `user_account` were the event resolved to
+/// `outcome_id` now, by the same rule claims pay out: their share of the pool
+/// if they backed it, and for the creator the creator fee. Without a stake on
+/// the outcome, or before anything was bet on it, the preview is 0. Nothing is
+/// settled and the user need not sign.
+pub fn process_preview_payout(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'_>,
+    user_account: &AccountInfo<'_>,
+    unique_id: [u8; 32],
+    outcome_id: u8,
+) -> Result<PayoutPreview, ProgramError> {
+    ensure_program_owned(program_id, &[event_account])?;
+
+    let event = event_store::load_event(event_account, &unique_id)?;
+
+    let index = event
+        .outcome_index(outcome_id)
+        .ok_or(PredictionMarketError::InvalidOutcome)?;
+
+    let stake = net_stake(&event.outcomes[index], user_account.key);
+    let fee = if *user_account.key == event.creator {
+        math::creator_fee(
+            event.total_pool_amount,
+            event.outcomes[index].total_amount,
+            event.creator_fee_bps,
+        )
+    } else {
+        0
+    };
+
+    let preview = PayoutPreview {
+        outcome_id,
+        stake,
+        payout: math::winning_payout(&event, index, stake)
+            .and_then(|payout| payout.checked_add(fee))
+            .ok_or(ProgramError::ArithmeticOverflow)?,
+    };
+    msg!(
+        "Payout preview for {} if outcome {} wins: {}",
+        user_account.key,
+        outcome_id,
+        preview.payout
+    );
+    return_borsh(&preview)?;
+
+    Ok(preview)
+}
+
+/// Returns a page of every bet `user` placed on the events held by
+/// `event_accounts`, see `BetHistoryPage`.
+pub fn process_user_bet_history(
+    program_id: &Pubkey,
+    event_accounts: &[AccountInfo<'_>],
+    user: &Pubkey,
+    offset: u32,
+) -> Result<BetHistoryPage, ProgramError> {
+    let mut page = Paginator::new(offset, MAX_HISTORY_PAGE);
+
+    'accounts: for event_account in event_accounts {
+        ensure_program_owned(program_id, &[event_account])?;
+        let predictions = event_store::decode_predictions(&event_account.try_borrow_data()?)?;
+
+        let user_bets = predictions
+            .predictions
+            .iter()
+            .flat_map(|event| &event.outcomes)
+            .filter_map(|outcome| outcome.bets.get(user))
+            .flatten();
+        for bet in user_bets {
+            if !page.offer(|| bet.clone()) {
+                break 'accounts;
+            }
+        }
+    }
+
+    msg!("Returning {} bets of {}", page.items.len(), user);
+
+    let page = BetHistoryPage {
+        bets: page.items,
+        next_offset: page.next_offset,
+    };
+    return_borsh(&page)?;
+
+    Ok(page)
+}
+
+/// Returns a page of up to `limit` of the events held by `event_accounts`
+/// that are in `status`, see `EventListPage`. `limit` is capped at
+/// `MAX_EVENT_LIST_PAGE`; zero fails with `InvalidArgument`.
+pub fn process_list_events_by_status(
+    program_id: &Pubkey,
+    event_accounts: &[AccountInfo<'_>],
+    status: EventStatus,
+    offset: u32,
+    limit: u32,
+) -> Result<EventListPage, ProgramError> {
+    if limit == 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+    let mut page = Paginator::new(offset, (limit as usize).min(MAX_EVENT_LIST_PAGE));
+
+    'accounts: for event_account in event_accounts {
+        ensure_program_owned(program_id, &[event_account])?;
+        let predictions = event_store::decode_predictions(&event_account.try_borrow_data()?)?;
+
+        let matching = predictions
+            .predictions
+            .iter()
+            .filter(|event| event.status == status);
+        for event in matching {
+            let listing = || EventListing {
+                unique_id: event.unique_id,
+                status: event.status,
+                expiry_timestamp: event.expiry_timestamp,
+                total_pool_amount: event.total_pool_amount,
+                winning_outcome: event.winning_outcome,
+            };
+            if !page.offer(listing) {
+                break 'accounts;
+            }
+        }
+    }
+
+    msg!("Returning {} {:?} events", page.items.len(), status);
+
+    let page = EventListPage {
+        events: page.items,
+        next_offset: page.next_offset,
+    };
+    return_borsh(&page)?;
+
+    Ok(page)
+}
+
+/// Returns a page of up to `limit` of the registry's entries, see
+/// `RegistryPage`. `limit` is capped at `MAX_REGISTRY_PAGE`; zero fails with
+/// `InvalidArgument`.
+pub fn process_query_registry(
+    program_id: &Pubkey,
+    registry_account: &AccountInfo<'_>,
+    offset: u32,
+    limit: u8,
+    status_filter: Option<EventStatus>,
+) -> Result<RegistryPage, ProgramError> {
+    if limit == 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+    registry::ensure_registry(program_id, registry_account)?;
+
+    let data = registry_account.try_borrow_data()?;
+    let mut page = Paginator::new(offset, (limit as usize).min(MAX_REGISTRY_PAGE));
+    for index in 0..registry::entry_count(&data)? {
+        let entry = registry::entry_at(&data, index)?;
+        if status_filter.is_some_and(|status| entry.status != status) {
+            continue;
+        }
+        if !page.offer(|| entry) {
+            break;
+        }
+    }
+
+    msg!("Returning {} registry entries", page.items.len());
+
+    let page = RegistryPage {
+        entries: page.items,
+        next_offset: page.next_offset,
+    };
+    return_borsh(&page)?;
+
+    Ok(page)
+}
+
+/// Returns a page of up to `limit` of the events `creator` registered, see
+/// `CreatorEventsPage`. `limit` is capped at `MAX_CREATOR_EVENTS_PAGE`; zero
+/// fails with `InvalidArgument`.
+pub fn process_query_events_by_creator(
+    program_id: &Pubkey,
+    registry_account: &AccountInfo<'_>,
+    creator: &Pubkey,
+    offset: u32,
+    limit: u8,
+) -> Result<CreatorEventsPage, ProgramError> {
+    if limit == 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+    registry::ensure_registry(program_id, registry_account)?;
+
+    let data = registry_account.try_borrow_data()?;
+    let mut page = Paginator::new(offset, (limit as usize).min(MAX_CREATOR_EVENTS_PAGE));
+    for index in 0..registry::entry_count(&data)? {
+        let entry = registry::entry_at(&data, index)?;
+        if entry.creator != *creator {
+            continue;
+        }
+        let event = || CreatorEvent {
+            unique_id: entry.unique_id,
+            status: entry.status,
+        };
+        if !page.offer(event) {
+            break;
+        }
+    }
+
+    msg!("Returning {} events of {}", page.items.len(), creator);
+
+    let page = CreatorEventsPage {
+        events: page.items,
+        next_offset: page.next_offset,
+    };
+    return_borsh(&page)?;
+
+    Ok(page)
+}
+
+/// Returns `compute_odds` of the event, one entry per outcome.
+pub fn process_query_odds(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'_>,
+    unique_id: [u8; 32],
+) -> Result<Vec<OddsEntry>, ProgramError> {
+    ensure_program_owned(program_id, &[event_account])?;
+
+    let event = event_store::load_event(event_account, &unique_id)?;
+
+    let odds = compute_odds(&event);
+    return_borsh(&odds)?;
+
+    Ok(odds)
+}
+
+/// Returns the event's `EventStatsReport`.
+pub fn process_query_event_stats(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'_>,
+    unique_id: [u8; 32],
+) -> Result<EventStatsReport, ProgramError> {
+    ensure_program_owned(program_id, &[event_account])?;
+
+    let event = event_store::load_event(event_account, &unique_id)?;
+
+    let stats = event.stats;
+    let report = EventStatsReport {
+        total_bets: stats.total_bets,
+        unique_bettors: stats.unique_bettors,
+        buy_volume: stats.buy_volume,
+        sell_volume: stats.sell_volume,
+        recent_volume: stats.recent_volume,
+    };
+    return_borsh(&report)?;
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod query_tests {
+    use super::*;
+    use crate::test_utils::{
+        balance_of, config_account, create_event_on, event_account, event_params, set_block_height,
+        take_return_data, token_account_with_decimals, Market, TestAccount,
+    };
+    use crate::types::{
+        BetType, ListEventsByStatusParams, PredictionMarketError, PreviewPayoutParams,
+        QueryEventParams, QueryEventStatsParams, QueryOddsParams, QueryUserPositionParams,
+        UserBetHistoryParams,
+    };
+    use crate::{process_close_event, process_instruction, process_resolve_event};
+
+    const EVENT_ID: [u8; 32] = [34; 32];
+
+    fn instruction<T: BorshSerialize>(function_number: u8, params: &T) -> Vec<u8> {
+        let mut data = vec![function_number];
+        data.extend(borsh::to_vec(params).unwrap());
+        data
+    }
+
+    #[test]
+    fn test_query_event_returns_its_summary() {
+        let program_id = Pubkey::new_unique();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(better.key(), 100)]);
+        market.buy(&mut better, 1, 60).unwrap();
+        market.buy(&mut better, 0, 15).unwrap();
+        let before = market.event.data().to_vec();
+
+        let mut event = market.event.info();
+        event.is_writable = false;
+        let params = QueryEventParams {
+            unique_id: EVENT_ID,
+        };
+        process_instruction(&program_id, &[event], &instruction(28, &params)).unwrap();
+
+        let summary = EventSummary::try_from_slice(&take_return_data().unwrap()).unwrap();
+        assert_eq!(
+            summary,
+            EventSummary {
+                status: EventStatus::Active,
+                expiry_timestamp: 100,
+                decimals: 0,
+                total_pool_amount: 75,
+                outcome_totals: vec![15, 60],
+                outcome_labels: vec![],
+                winning_outcome: None,
+            }
+        );
+        assert_eq!(market.event.data(), &before[..]);
+    }
+
+    #[test]
+    fn test_amounts_are_in_the_mints_smallest_unit() {
+        let program_id = Pubkey::new_unique();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut token = token_account_with_decimals(&program_id, &[(better.key(), 250)], 2);
+        let (event, creator) =
+            create_event_on(&program_id, event_params([80; 32], 100, 2), &mut token);
+        let mut market = Market {
+            unique_id: [80; 32],
+            event,
+            creator,
+            token,
+            config: config_account(&program_id, &Pubkey::new_unique(), false),
+        };
+
+        // 1.50 of a token with 2 decimals
+        market.buy(&mut better, 0, 150).unwrap();
+        assert_eq!(balance_of(&market.token, &better.key()), 100);
+        assert_eq!(
+            market.buy(&mut better, 1, 0),
+            Err(PredictionMarketError::InvalidBetAmount.into())
+        );
+
+        let summary = process_query_event(&program_id, &market.event.info(), [80; 32]).unwrap();
+        assert_eq!(summary.decimals, 2);
+        assert_eq!(summary.total_pool_amount, 150);
+        assert_eq!(summary.outcome_totals, vec![150, 0]);
+    }
+
+    #[test]
+    fn test_query_user_position_returns_net_stakes() {
+        let program_id = Pubkey::new_unique();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(better.key(), 100)]);
+        market.buy(&mut better, 1, 60).unwrap();
+        market.sell(&mut better, 1, 25).unwrap();
+        let before = market.event.data().to_vec();
+
+        let mut event = market.event.info();
+        event.is_writable = false;
+        let params = QueryUserPositionParams {
+            unique_id: EVENT_ID,
+            user: better.key(),
+        };
+        process_instruction(&program_id, &[event], &instruction(29, &params)).unwrap();
+
+        let position = UserPosition::try_from_slice(&take_return_data().unwrap()).unwrap();
+        assert_eq!(
+            position,
+            UserPosition {
+                net_stakes: vec![0, 35],
+                cost_basis: vec![
+                    CostBasis::default(),
+                    CostBasis {
+                        cost_basis_total: 35,
+                        realized_pnl: 0,
+                    },
+                ],
+                claimed: false,
+            }
+        );
+        assert_eq!(market.event.data(), &before[..]);
+
+        // someone who never bet holds nothing
+        let stranger = process_query_user_position(
+            &program_id,
+            &market.event.info(),
+            EVENT_ID,
+            &Pubkey::new_unique(),
+        )
+        .unwrap();
+        assert_eq!(stranger.net_stakes, vec![0, 0]);
+    }
+
+    #[test]
+    fn test_query_odds_returns_every_outcome() {
+        let program_id = Pubkey::new_unique();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(better.key(), 100)]);
+        market.buy(&mut better, 0, 80).unwrap();
+
+        let mut event = market.event.info();
+        event.is_writable = false;
+        let params = QueryOddsParams {
+            unique_id: EVENT_ID,
+        };
+        process_instruction(&program_id, &[event], &instruction(30, &params)).unwrap();
+
+        let odds = Vec::<OddsEntry>::try_from_slice(&take_return_data().unwrap()).unwrap();
+        assert_eq!(odds, compute_odds(&market.event_state()));
+        assert_eq!(odds[0].pool_share_bps, 10_000);
+        assert_eq!(odds[1].implied_payout_per_unit_fp, 0);
+    }
+
+    #[test]
+    fn test_query_event_stats_counts_bets_and_volume() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(
+            &program_id,
+            EVENT_ID,
+            &[(alice.key(), 100), (bob.key(), 100)],
+        );
+        set_block_height(12);
+        market.buy(&mut alice, 0, 30).unwrap();
+        market.buy(&mut bob, 1, 45).unwrap();
+        set_block_height(20);
+        market.buy(&mut alice, 0, 10).unwrap();
+        market.sell(&mut alice, 0, 5).unwrap();
+
+        let mut event = market.event.info();
+        event.is_writable = false;
+        let params = QueryEventStatsParams {
+            unique_id: EVENT_ID,
+        };
+        process_instruction(&program_id, &[event], &instruction(73, &params)).unwrap();
+
+        let report = EventStatsReport::try_from_slice(&take_return_data().unwrap()).unwrap();
+        assert_eq!(report.total_bets, 4);
+        assert_eq!(report.unique_bettors, 2);
+        assert_eq!(report.buy_volume, 85);
+        assert_eq!(report.sell_volume, 5);
+        assert_eq!(
+            report.recent_volume,
+            vec![
+                VolumeBucket {
+                    start_height: 12,
+                    buy_volume: 75,
+                    sell_volume: 0,
+                },
+                VolumeBucket {
+                    start_height: 18,
+                    buy_volume: 10,
+                    sell_volume: 5,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_preview_matches_the_payout_after_resolving() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(
+            &program_id,
+            EVENT_ID,
+            &[(alice.key(), 100), (bob.key(), 100)],
+        );
+        market.buy(&mut alice, 0, 30).unwrap();
+        market.buy(&mut bob, 0, 45).unwrap();
+        market.buy(&mut bob, 1, 55).unwrap();
+        let before = market.event.data().to_vec();
+
+        let params = PreviewPayoutParams {
+            unique_id: EVENT_ID,
+            outcome_id: 0,
+        };
+        process_instruction(
+            &program_id,
+            &[market.event.info(), alice.info()],
+            &instruction(43, &params),
+        )
+        .unwrap();
+        let preview = PayoutPreview::try_from_slice(&take_return_data().unwrap()).unwrap();
+        assert_eq!(market.event.data(), &before[..]);
+
+        process_resolve_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            EVENT_ID,
+            0,
+        )
+        .unwrap();
+        market.claim(&mut alice).unwrap();
+
+        // 30 of the 75 on outcome 0 take 30/75 of the pool of 130
+        assert_eq!(
+            preview,
+            PayoutPreview {
+                outcome_id: 0,
+                stake: 30,
+                payout: 52,
+            }
+        );
+        assert_eq!(market.balance(&alice), 70 + preview.payout);
+    }
+
+    #[test]
+    fn test_preview_without_a_stake_or_a_pool_is_zero() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut stranger = TestAccount::new(&program_id, &[]);
+        let mut market = Market::new(&program_id, EVENT_ID, &[(alice.key(), 100)]);
+
+        let empty = process_preview_payout(
+            &program_id,
+            &market.event.info(),
+            &alice.info(),
+            EVENT_ID,
+            1,
+        )
+        .unwrap();
+        assert_eq!(empty.payout, 0);
+
+        market.buy(&mut alice, 0, 40).unwrap();
+        let unbacked = process_preview_payout(
+            &program_id,
+            &market.event.info(),
+            &stranger.info(),
+            EVENT_ID,
+            0,
+        )
+        .unwrap();
+        assert_eq!((unbacked.stake, unbacked.payout), (0, 0));
+
+        assert_eq!(
+            process_preview_payout(
+                &program_id,
+                &market.event.info(),
+                &alice.info(),
+                EVENT_ID,
+                9
+            ),
+            Err(PredictionMarketError::InvalidOutcome.into())
+        );
+    }
+
+    #[test]
+    fn test_queries_of_unknown_events_fail() {
+        let program_id = Pubkey::new_unique();
+        // the account an unknown event would live at holds nothing
+        let mut unknown = event_account(&program_id, &[0; 32]);
+        take_return_data();
+
+        assert_eq!(
+            process_query_event(&program_id, &unknown.info(), [0; 32]),
+            Err(PredictionMarketError::EventNotFound.into())
+        );
+        assert_eq!(take_return_data(), None);
+    }
+
+    #[test]
+    fn test_bet_history_reports_bets_across_events() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let balances = [(alice.key(), 100), (bob.key(), 100)];
+        let mut first = Market::new(&program_id, EVENT_ID, &balances);
+        let mut second = Market::new(&program_id, [35; 32], &balances);
+        first.buy(&mut alice, 1, 30).unwrap();
+        first.buy(&mut bob, 1, 50).unwrap();
+        second.buy(&mut alice, 0, 20).unwrap();
+        second.buy(&mut alice, 1, 10).unwrap();
+
+        let params = UserBetHistoryParams {
+            user: alice.key(),
+            offset: 0,
+        };
+        process_instruction(
+            &program_id,
+            &[first.event.info(), second.event.info()],
+            &instruction(56, &params),
+        )
+        .unwrap();
+
+        let page = BetHistoryPage::try_from_slice(&take_return_data().unwrap()).unwrap();
+        let bets: Vec<([u8; 32], u8, u64)> = page
+            .bets
+            .iter()
+            .map(|bet| {
+                assert_eq!(bet.user, alice.key());
+                assert!(matches!(bet.bet_type, BetType::BUY));
+                (bet.event_id, bet.outcome_id, bet.amount)
+            })
+            .collect();
+        assert_eq!(
+            bets,
+            vec![(EVENT_ID, 1, 30), ([35; 32], 0, 20), ([35; 32], 1, 10)]
+        );
+        assert_eq!(page.next_offset, None);
+    }
+
+    #[test]
+    fn test_bet_history_pages_through_many_bets() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(alice.key(), 200)]);
+        for amount in 1..=14 {
+            market.buy(&mut alice, 0, amount).unwrap();
+        }
+
+        let first =
+            process_user_bet_history(&program_id, &[market.event.info()], &alice.key(), 0).unwrap();
+        assert_eq!(first.bets.len(), MAX_HISTORY_PAGE);
+        assert_eq!(first.next_offset, Some(12));
+        assert!(take_return_data().unwrap().len() <= MAX_RETURN_DATA);
+
+        let second =
+            process_user_bet_history(&program_id, &[market.event.info()], &alice.key(), 12)
+                .unwrap();
+        let amounts: Vec<u64> = second.bets.iter().map(|bet| bet.amount).collect();
+        assert_eq!(amounts, vec![13, 14]);
+        assert_eq!(second.next_offset, None);
+    }
+
+    #[test]
+    fn test_events_listed_by_status() {
+        let program_id = Pubkey::new_unique();
+        let ids = [[66; 32], [67; 32], [68; 32], [69; 32]];
+        let mut markets: Vec<Market> = ids
+            .iter()
+            .map(|id| Market::new(&program_id, *id, &[]))
+            .collect();
+        let market = &mut markets[1];
+        process_resolve_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            ids[1],
+            0,
+        )
+        .unwrap();
+        let market = &mut markets[2];
+        process_close_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            ids[2],
+        )
+        .unwrap();
+
+        let mut list = |status: EventStatus, offset: u32, limit: u32| {
+            let params = ListEventsByStatusParams {
+                status,
+                offset,
+                limit,
+            };
+            let infos: Vec<AccountInfo> = markets.iter_mut().map(|m| m.event.info()).collect();
+            process_instruction(&program_id, &infos, &instruction(62, &params))?;
+            let page = EventListPage::try_from_slice(&take_return_data().unwrap()).unwrap();
+            let listed: Vec<[u8; 32]> = page.events.iter().map(|e| e.unique_id).collect();
+            Ok::<_, ProgramError>((listed, page.next_offset))
+        };
+
+        assert_eq!(
+            list(EventStatus::Active, 0, 10).unwrap(),
+            (vec![ids[0], ids[3]], None)
+        );
+        assert_eq!(
+            list(EventStatus::Resolved, 0, 10).unwrap(),
+            (vec![ids[1]], None)
+        );
+        assert_eq!(
+            list(EventStatus::Closed, 0, 10).unwrap(),
+            (vec![ids[2]], None)
+        );
+        assert_eq!(list(EventStatus::Cancelled, 0, 10).unwrap(), (vec![], None));
+
+        // a page at a time
+        assert_eq!(
+            list(EventStatus::Active, 0, 1).unwrap(),
+            (vec![ids[0]], Some(1))
+        );
+        assert_eq!(
+            list(EventStatus::Active, 1, 1).unwrap(),
+            (vec![ids[3]], None)
+        );
+        assert_eq!(
+            list(EventStatus::Active, 0, 0),
+            Err(ProgramError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn test_full_event_list_page_fits_the_return_data() {
+        let program_id = Pubkey::new_unique();
+        let mut market = Market::new(&program_id, [70; 32], &[]);
+        let listing = process_list_events_by_status(
+            &program_id,
+            &[market.event.info()],
+            EventStatus::Active,
+            0,
+            1,
+        )
+        .unwrap()
+        .events
+        .remove(0);
+
+        let full = EventListPage {
+            events: vec![listing; MAX_EVENT_LIST_PAGE],
+            next_offset: Some(u32::MAX),
+        };
+        assert!(borsh::to_vec(&full).unwrap().len() <= MAX_RETURN_DATA);
+    }
+}
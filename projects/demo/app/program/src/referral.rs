@@ -0,0 +1,387 @@
+//! Referral attribution. A buy may name a referrer: the referrer is credited
+//! with the buy's cost as referred volume and with `referral_share_bps` of the
+//! protocol fee the buy paid. Both are recorded in a program owned stats
+//! account, bound to one mint, whose accrued rewards sit in a vault balance
+//! of that mint until the referrer claims them.
+use std::collections::BTreeMap;
+
+use arch_program::{
+    account::AccountInfo,
+    bitcoin::hashes::{sha256, Hash, HashEngine},
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::{
+    ensure_distinct_accounts, ensure_program_owned, math::BPS_DENOMINATOR, storage,
+    transfer::move_balance, types::PredictionMarketError,
+};
+
+#[derive(Debug, Clone, Default, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct ReferrerStats {
+    // every buy cost the referrer brought in
+    pub referred_volume: u64,
+    // fee share not yet claimed
+    pub accrued_rewards: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct ReferralStats {
+    // the token the referred buys paid in, set by the first one
+    pub mint: Pubkey,
+    pub referrers: BTreeMap<Pubkey, ReferrerStats>,
+}
+
+/// A buy's referrer and the stats account it is recorded in.
+#[derive(Clone, Copy)]
+pub struct Referral<'b, 'a> {
+    pub referrer: Pubkey,
+    pub stats: &'b AccountInfo<'a>,
+}
+
+/// Balance key holding the unclaimed rewards of a stats account. Derived from
+/// the account, so it can't belong to any signer.
+pub fn vault_address(stats_account: &Pubkey) -> Pubkey {
+    let mut engine = sha256::Hash::engine();
+    engine.input(b"referral_vault");
+    engine.input(&stats_account.0);
+    Pubkey(sha256::Hash::from_engine(engine).to_byte_array())
+}
+
+/// Protocol fee on a buy costing `cost`, and the referrer's share of it.
+/// Both round down.
+pub fn split_protocol_fee(cost: u64, protocol_fee_bps: u16, referral_share_bps: u16) -> (u64, u64) {
+    let fee = cost as u128 * protocol_fee_bps as u128 / BPS_DENOMINATOR as u128;
+    let reward = fee * referral_share_bps as u128 / BPS_DENOMINATOR as u128;
+    (fee as u64, reward as u64)
+}
+
+/// Loads the stats of `mint`; an empty account holds none yet.
+pub fn load_stats(
+    stats_account: &AccountInfo<'_>,
+    mint: &Pubkey,
+) -> Result<ReferralStats, ProgramError> {
+    if stats_account.data_is_empty() {
+        return Ok(ReferralStats {
+            mint: *mint,
+            referrers: BTreeMap::new(),
+        });
+    }
+
+    let stats = storage::read::<ReferralStats>(stats_account)?;
+
+    if stats.mint != *mint {
+        return Err(PredictionMarketError::MintMismatch.into());
+    }
+
+    Ok(stats)
+}
+
+/// Adds `volume` and `reward` to the referrer's totals. The reward must
+/// already be in the vault.
+pub fn credit_referrer(
+    referral: Referral<'_, '_>,
+    mint: &Pubkey,
+    volume: u64,
+    reward: u64,
+) -> Result<(), ProgramError> {
+    let mut stats = load_stats(referral.stats, mint)?;
+
+    let entry = stats.referrers.entry(referral.referrer).or_default();
+    entry.referred_volume = entry.referred_volume.saturating_add(volume);
+    entry.accrued_rewards = entry
+        .accrued_rewards
+        .checked_add(reward)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    storage::write(referral.stats, &stats)?;
+
+    Ok(())
+}
+
+/// Pays the referrer everything accrued to them so far.
+pub fn process_claim_referral_rewards<'a>(
+    program_id: &Pubkey,
+    stats_account: &AccountInfo<'a>,
+    token_account: &AccountInfo<'a>,
+    referrer_account: &AccountInfo<'a>,
+) -> Result<u64, ProgramError> {
+    ensure_distinct_accounts(&[stats_account.key, token_account.key, referrer_account.key])?;
+
+    ensure_program_owned(program_id, &[stats_account, token_account])?;
+
+    if !referrer_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut stats = load_stats(stats_account, token_account.key)?;
+
+    let rewards = stats
+        .referrers
+        .get_mut(referrer_account.key)
+        .map(|entry| std::mem::take(&mut entry.accrued_rewards))
+        .unwrap_or(0);
+
+    if rewards == 0 {
+        return Err(PredictionMarketError::NoReferralRewards.into());
+    }
+
+    storage::write(stats_account, &stats)?;
+
+    msg!(
+        "Paying {} in referral rewards to {}",
+        rewards,
+        referrer_account.key
+    );
+
+    move_balance(
+        token_account,
+        &vault_address(stats_account.key),
+        referrer_account.key,
+        rewards,
+    )?;
+
+    Ok(rewards)
+}
+
+#[cfg(test)]
+mod referral_tests {
+    use super::*;
+    use crate::config::{set_protocol_fees, ProgramConfig, MAX_PROTOCOL_FEE_BPS};
+    use crate::test_utils::{balance_of, user_limits_account, Market, TestAccount};
+    use crate::types::SlippageLimits;
+    use crate::{escrow_address, process_buy_bet, process_instruction, BetAccounts};
+
+    const EVENT_ID: [u8; 32] = [45; 32];
+
+    // a 2% protocol fee, a quarter of which goes to referrers
+    fn fee_market(program_id: &Pubkey, balances: &[(Pubkey, u64)]) -> Market {
+        let mut market = Market::new(program_id, EVENT_ID, balances);
+        let mut config = ProgramConfig::new(Pubkey::new_unique());
+        config.protocol_fee_bps = 200;
+        config.referral_share_bps = 2_500;
+        market.config = TestAccount::new(program_id, &borsh::to_vec(&config).unwrap());
+        market
+    }
+
+    fn referred_buy(
+        market: &mut Market,
+        better: &mut TestAccount,
+        referrer: Pubkey,
+        stats: &mut TestAccount,
+        amount: u64,
+    ) -> Result<(), ProgramError> {
+        let program_id = market.event.owner;
+        let mut limits = user_limits_account(&program_id, &better.key());
+        process_buy_bet(
+            &program_id,
+            BetAccounts {
+                event: &market.event.info(),
+                token: &market.token.info(),
+                better: &better.info(),
+                limits: Some(&limits.info()),
+                config: &market.config.info(),
+                referral: Some(Referral {
+                    referrer,
+                    stats: &stats.info(),
+                }),
+                orders: None,
+            },
+            EVENT_ID,
+            0,
+            amount,
+            None,
+            SlippageLimits::default(),
+        )
+    }
+
+    fn stats_of(stats: &TestAccount, referrer: &Pubkey) -> ReferrerStats {
+        ReferralStats::try_from_slice(stats.data())
+            .unwrap()
+            .referrers[referrer]
+            .clone()
+    }
+
+    fn treasury(market: &mut Market) -> Pubkey {
+        crate::config::load_config(&market.config.info())
+            .unwrap()
+            .treasury
+    }
+
+    #[test]
+    fn test_fee_split_rounds_down() {
+        assert_eq!(split_protocol_fee(1_000, 200, 2_500), (20, 5));
+        assert_eq!(split_protocol_fee(49, 200, 2_500), (0, 0));
+        assert_eq!(split_protocol_fee(999, 1_000, 3_333), (99, 32));
+        assert_eq!(split_protocol_fee(1_000, 0, 10_000), (0, 0));
+        assert_eq!(
+            split_protocol_fee(u64::MAX, 1_000, 10_000),
+            (u64::MAX / 10, u64::MAX / 10)
+        );
+    }
+
+    #[test]
+    fn test_protocol_fee_is_capped() {
+        let program_id = Pubkey::new_unique();
+        let mut admin = TestAccount::new(&program_id, &[]).signer();
+        let mut config = TestAccount::new(
+            &program_id,
+            &borsh::to_vec(&ProgramConfig::new(admin.key())).unwrap(),
+        );
+
+        assert_eq!(
+            set_protocol_fees(&config.info(), &admin.info(), MAX_PROTOCOL_FEE_BPS + 1, 0),
+            Err(PredictionMarketError::ProtocolFeeTooHigh.into())
+        );
+        assert_eq!(
+            set_protocol_fees(&config.info(), &admin.info(), 200, 10_001),
+            Err(ProgramError::InvalidArgument)
+        );
+
+        set_protocol_fees(&config.info(), &admin.info(), MAX_PROTOCOL_FEE_BPS, 10_000).unwrap();
+        let stored = ProgramConfig::try_from_slice(config.data()).unwrap();
+        assert_eq!(
+            (stored.protocol_fee_bps, stored.referral_share_bps),
+            (MAX_PROTOCOL_FEE_BPS, 10_000)
+        );
+    }
+
+    #[test]
+    fn test_referred_buy_credits_volume_and_fee_share() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut stats = TestAccount::new(&program_id, &[]);
+        let referrer = Pubkey::new_unique();
+        let mut market = fee_market(&program_id, &[(alice.key(), 10_000)]);
+
+        referred_buy(&mut market, &mut alice, referrer, &mut stats, 1_000).unwrap();
+
+        let treasury = treasury(&mut market);
+        let escrow = escrow_address(&market.event.key(), &EVENT_ID);
+        assert_eq!(market.balance(&alice), 10_000 - 1_000 - 20);
+        assert_eq!(balance_of(&market.token, &escrow), 1_000);
+        assert_eq!(balance_of(&market.token, &treasury), 15);
+        assert_eq!(balance_of(&market.token, &vault_address(&stats.key())), 5);
+        assert_eq!(
+            stats_of(&stats, &referrer),
+            ReferrerStats {
+                referred_volume: 1_000,
+                accrued_rewards: 5,
+            }
+        );
+        assert_eq!(market.event_state().total_pool_amount, 1_000);
+    }
+
+    #[test]
+    fn test_self_referral_is_ignored() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut stats = TestAccount::new(&program_id, &[]);
+        let mut market = fee_market(&program_id, &[(alice.key(), 10_000)]);
+
+        let alice_key = alice.key();
+        referred_buy(&mut market, &mut alice, alice_key, &mut stats, 1_000).unwrap();
+
+        let treasury = treasury(&mut market);
+        assert_eq!(balance_of(&market.token, &treasury), 20);
+        assert!(stats.data().is_empty());
+    }
+
+    #[test]
+    fn test_referrer_can_change_between_bets() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut stats = TestAccount::new(&program_id, &[]);
+        let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut market = fee_market(&program_id, &[(alice.key(), 10_000)]);
+
+        referred_buy(&mut market, &mut alice, first, &mut stats, 1_000).unwrap();
+        referred_buy(&mut market, &mut alice, second, &mut stats, 400).unwrap();
+        referred_buy(&mut market, &mut alice, first, &mut stats, 600).unwrap();
+
+        // 2% of 400 is 8, a quarter of that 2; of 600 it is 12 and 3
+        assert_eq!(stats_of(&stats, &first).referred_volume, 1_600);
+        assert_eq!(stats_of(&stats, &first).accrued_rewards, 8);
+        assert_eq!(stats_of(&stats, &second).referred_volume, 400);
+        assert_eq!(stats_of(&stats, &second).accrued_rewards, 2);
+    }
+
+    #[test]
+    fn test_referrer_claims_accrued_rewards_once() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut stats = TestAccount::new(&program_id, &[]);
+        let mut referrer = TestAccount::new(&program_id, &[]).signer();
+        let mut market = fee_market(&program_id, &[(alice.key(), 10_000)]);
+        referred_buy(&mut market, &mut alice, referrer.key(), &mut stats, 1_000).unwrap();
+        referred_buy(&mut market, &mut alice, referrer.key(), &mut stats, 1_000).unwrap();
+
+        let claimed = process_claim_referral_rewards(
+            &program_id,
+            &stats.info(),
+            &market.token.info(),
+            &referrer.info(),
+        );
+
+        assert_eq!(claimed, Ok(10));
+        assert_eq!(market.balance(&referrer), 10);
+        assert_eq!(balance_of(&market.token, &vault_address(&stats.key())), 0);
+        assert_eq!(stats_of(&stats, &referrer.key()).referred_volume, 2_000);
+        assert_eq!(
+            process_claim_referral_rewards(
+                &program_id,
+                &stats.info(),
+                &market.token.info(),
+                &referrer.info(),
+            ),
+            Err(PredictionMarketError::NoReferralRewards.into())
+        );
+    }
+
+    #[test]
+    fn test_referred_buy_instruction_takes_the_stats_account_last() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut limits = user_limits_account(&program_id, &alice.key());
+        let mut stats = TestAccount::new(&program_id, &[]);
+        let referrer = Pubkey::new_unique();
+        let mut market = fee_market(&program_id, &[(alice.key(), 10_000)]);
+        let mut data = vec![3];
+        data.extend(
+            borsh::to_vec(&crate::types::BetOnPredictionEventParams {
+                unique_id: EVENT_ID,
+                outcome_id: 0,
+                amount: 1_000,
+                min_expected_payout_bps: 0,
+                max_cost: 0,
+                referrer: Some(referrer),
+            })
+            .unwrap(),
+        );
+
+        let accounts = [
+            market.event.info(),
+            market.token.info(),
+            alice.info(),
+            limits.info(),
+            market.config.info(),
+        ];
+        assert_eq!(
+            process_instruction(&program_id, &accounts, &data),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+
+        let accounts = [
+            market.event.info(),
+            market.token.info(),
+            alice.info(),
+            limits.info(),
+            market.config.info(),
+            stats.info(),
+        ];
+        process_instruction(&program_id, &accounts, &data).unwrap();
+        assert_eq!(stats_of(&stats, &referrer).referred_volume, 1_000);
+    }
+}
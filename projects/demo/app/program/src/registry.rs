@@ -0,0 +1,672 @@
+//! The event registry: one account listing a compact `EventSummaryEntry` for
+//! every event created with it, in creation order, so a frontend can page
+//! through markets with `QueryRegistry` instead of scanning the chain for
+//! event accounts. It holds a borsh `Vec<EventSummaryEntry>` whose entries
+//! all take `ENTRY_LEN` bytes, so one is appended or rewritten in place
+//! without decoding the others.
+//!
+//! Creating an event and every instruction that can change an event's status
+//! take the registry as an optional trailing account, see `tracks`. Creation
+//! appends the new event's entry and the others refresh the entries of the
+//! events they touched. An event created without the registry is never
+//! listed, unless the admin caps each creator's open events: the cap is
+//! counted in the registry, so creation then requires it.
+//!
+//! The entries double as each creator's index of their own events, which
+//! `QueryEventsByCreator` pages through.
+use arch_program::{
+    account::AccountInfo,
+    bitcoin::hashes::{sha256, Hash, HashEngine},
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::{
+    config, ensure_program_owned, event_store, storage,
+    types::{EventStatus, PredictionEvent, PredictionMarketError},
+};
+
+// the entry count
+const HEADER_LEN: usize = 4;
+pub const ENTRY_LEN: usize = 70;
+
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct EventSummaryEntry {
+    pub unique_id: [u8; 32],
+    pub creator: Pubkey,
+    pub expiry: u32,
+    pub status: EventStatus,
+    pub category: u8,
+}
+
+impl EventSummaryEntry {
+    pub fn of(event: &PredictionEvent) -> Self {
+        EventSummaryEntry {
+            unique_id: event.unique_id,
+            creator: event.creator,
+            expiry: event.expiry_timestamp,
+            status: event.status,
+            category: event.category,
+        }
+    }
+}
+
+/// Address of the registry. Derived like `escrow_address`, so no signer can
+/// hold it.
+pub fn derive_registry_address(program_id: &Pubkey) -> Pubkey {
+    let mut engine = sha256::Hash::engine();
+    engine.input(b"registry");
+    engine.input(&program_id.0);
+    Pubkey(sha256::Hash::from_engine(engine).to_byte_array())
+}
+
+/// Instructions that take the registry after their fixed accounts: creating
+/// an event and everything that can move one to another status.
+pub fn tracks(function_number: u8) -> bool {
+    matches!(
+        function_number,
+        1 | 2 | 9 | 18 | 20..=24 | 27 | 34 | 35 | 40 | 51 | 61 | 63 | 69
+    )
+}
+
+/// Fails with `RegistryAddressMismatch` unless `registry_account` is the
+/// program's registry.
+pub fn ensure_registry(
+    program_id: &Pubkey,
+    registry_account: &AccountInfo<'_>,
+) -> Result<(), ProgramError> {
+    ensure_program_owned(program_id, &[registry_account])?;
+
+    if *registry_account.key != derive_registry_address(program_id) {
+        return Err(PredictionMarketError::RegistryAddressMismatch.into());
+    }
+
+    Ok(())
+}
+
+/// Number of entries; an empty account holds none yet.
+pub fn entry_count(data: &[u8]) -> Result<usize, ProgramError> {
+    if data.is_empty() {
+        return Ok(0);
+    }
+
+    let count = data
+        .get(..HEADER_LEN)
+        .and_then(|header| u32::try_from_slice(header).ok())
+        .ok_or(ProgramError::InvalidAccountData)? as usize;
+    if data.len() != HEADER_LEN + count * ENTRY_LEN {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    Ok(count)
+}
+
+/// Decodes the `index`th entry, counting from the first event registered.
+pub fn entry_at(data: &[u8], index: usize) -> Result<EventSummaryEntry, ProgramError> {
+    let start = HEADER_LEN + index * ENTRY_LEN;
+    data.get(start..start + ENTRY_LEN)
+        .and_then(|bytes| EventSummaryEntry::try_from_slice(bytes).ok())
+        .ok_or(ProgramError::InvalidAccountData)
+}
+
+fn write_entry(
+    data: &mut [u8],
+    index: usize,
+    entry: &EventSummaryEntry,
+) -> Result<(), ProgramError> {
+    let start = HEADER_LEN + index * ENTRY_LEN;
+    entry
+        .serialize(&mut &mut data[start..start + ENTRY_LEN])
+        .map_err(|e| ProgramError::BorshIoError(e.to_string()))
+}
+
+fn position(data: &[u8], unique_id: &[u8; 32]) -> Result<Option<usize>, ProgramError> {
+    for index in 0..entry_count(data)? {
+        if entry_at(data, index)?.unique_id == *unique_id {
+            return Ok(Some(index));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Grows the registry by one entry and writes `entry` there.
+fn append(
+    registry_account: &AccountInfo<'_>,
+    entry: &EventSummaryEntry,
+) -> Result<(), ProgramError> {
+    let count = entry_count(&registry_account.try_borrow_data()?)?;
+    let len = HEADER_LEN + (count + 1) * ENTRY_LEN;
+
+    storage::realloc(registry_account, len, true)?;
+    storage::ensure_len(registry_account, len)?;
+
+    let mut data = registry_account.try_borrow_mut_data()?;
+    data[..HEADER_LEN].copy_from_slice(&(count as u32 + 1).to_le_bytes());
+    write_entry(&mut data, count, entry)
+}
+
+/// `creator`'s events still `Created` or `Active`.
+pub fn open_events(data: &[u8], creator: &Pubkey) -> Result<u32, ProgramError> {
+    let mut open = 0;
+    for index in 0..entry_count(data)? {
+        let entry = entry_at(data, index)?;
+        if entry.creator == *creator
+            && matches!(entry.status, EventStatus::Created | EventStatus::Active)
+        {
+            open += 1;
+        }
+    }
+
+    Ok(open)
+}
+
+/// Appends the entries of the events just created in `event_account`.
+/// Fails with `TooManyActiveEvents` if that takes a creator past the
+/// config's `max_active_events_per_creator`, and with `RegistryRequired`
+/// when that cap is set but no registry was passed to count against.
+pub fn register(
+    program_id: &Pubkey,
+    registry_account: Option<&AccountInfo<'_>>,
+    event_account: &AccountInfo<'_>,
+    config_account: &AccountInfo<'_>,
+) -> Result<(), ProgramError> {
+    let cap = config::load_config(config_account)?.max_active_events_per_creator;
+    let Some(registry_account) = registry_account else {
+        if cap > 0 {
+            return Err(PredictionMarketError::RegistryRequired.into());
+        }
+        return Ok(());
+    };
+    ensure_registry(program_id, registry_account)?;
+
+    let predictions = event_store::decode_predictions(&event_account.try_borrow_data()?)?;
+    for event in &predictions.predictions {
+        let data = registry_account.try_borrow_data()?;
+        if position(&data, &event.unique_id)?.is_some() {
+            continue;
+        }
+        if cap > 0 && open_events(&data, &event.creator)? >= cap {
+            msg!("{} already has {} open events", event.creator, cap);
+            return Err(PredictionMarketError::TooManyActiveEvents.into());
+        }
+        drop(data);
+
+        append(registry_account, &EventSummaryEntry::of(event))?;
+        msg!("Registered event {:?}", event.unique_id);
+    }
+
+    Ok(())
+}
+
+/// Rewrites the entry of each registered event in `event_account`; events
+/// the registry doesn't list stay unlisted.
+pub fn sync(
+    program_id: &Pubkey,
+    registry_account: &AccountInfo<'_>,
+    event_account: &AccountInfo<'_>,
+) -> Result<(), ProgramError> {
+    ensure_registry(program_id, registry_account)?;
+
+    let predictions = event_store::decode_predictions(&event_account.try_borrow_data()?)?;
+    for event in &predictions.predictions {
+        let position = position(&registry_account.try_borrow_data()?, &event.unique_id)?;
+        if let Some(index) = position {
+            let entry = EventSummaryEntry::of(event);
+            write_entry(&mut registry_account.try_borrow_mut_data()?, index, &entry)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod registry_tests {
+    use super::*;
+    use crate::config::{ProgramConfig, SetMaxActiveEventsParams};
+    use crate::query::{
+        process_query_registry, CreatorEvent, CreatorEventsPage, RegistryPage, MAX_REGISTRY_PAGE,
+    };
+    use crate::test_utils::{
+        config_account, event_params, take_return_data, token_account, TestAccount,
+    };
+    use crate::types::{QueryEventsByCreatorParams, QueryRegistryParams};
+    use crate::{expected_account_count, process_instruction};
+    use arch_program::{entrypoint::ProgramResult, program::MAX_RETURN_DATA};
+
+    fn registry_account(program_id: &Pubkey) -> TestAccount {
+        TestAccount::new(program_id, &[]).with_key(derive_registry_address(program_id))
+    }
+
+    fn entries(registry: &TestAccount) -> Vec<EventSummaryEntry> {
+        let data = registry.data();
+        (0..entry_count(data).unwrap())
+            .map(|index| entry_at(data, index).unwrap())
+            .collect()
+    }
+
+    // runs the event instruction `function_number` with `data`, then the
+    // registry as its trailing account
+    fn run(
+        program_id: &Pubkey,
+        function_number: u8,
+        data: &[u8],
+        fixed: &mut [&mut TestAccount],
+        registry: &mut TestAccount,
+    ) -> Result<(), ProgramError> {
+        let mut instruction = vec![function_number];
+        instruction.extend_from_slice(data);
+        let mut infos: Vec<AccountInfo> = fixed.iter_mut().map(|a| a.info()).collect();
+        infos.push(registry.info());
+        assert_eq!(
+            infos.len(),
+            expected_account_count(function_number).unwrap() + 1
+        );
+        process_instruction(program_id, &infos, &instruction)
+    }
+
+    struct Created {
+        unique_id: [u8; 32],
+        event: TestAccount,
+        creator: TestAccount,
+    }
+
+    fn create(
+        program_id: &Pubkey,
+        registry: &mut TestAccount,
+        unique_id: [u8; 32],
+        category: u8,
+    ) -> Created {
+        let mut event = crate::test_utils::event_account(program_id, &unique_id);
+        let mut creator = TestAccount::new(program_id, &[]).signer();
+        let mut token = token_account(program_id, &[]);
+        let mut config = config_account(program_id, &Pubkey::new_unique(), false);
+        let params = crate::types::PredictionEventParams {
+            category,
+            ..event_params(unique_id, 100, 2)
+        };
+
+        run(
+            program_id,
+            1,
+            &borsh::to_vec(&params).unwrap(),
+            &mut [&mut event, &mut creator, &mut token, &mut config],
+            registry,
+        )
+        .unwrap();
+
+        Created {
+            unique_id,
+            event,
+            creator,
+        }
+    }
+
+    fn activate(program_id: &Pubkey, created: &mut Created, registry: &mut TestAccount) {
+        let mut config = config_account(program_id, &Pubkey::new_unique(), false);
+        run(
+            program_id,
+            21,
+            &created.unique_id,
+            &mut [&mut created.event, &mut created.creator, &mut config],
+            registry,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_creation_appends_in_order() {
+        let program_id = Pubkey::new_unique();
+        let mut registry = registry_account(&program_id);
+
+        let first = create(&program_id, &mut registry, [1; 32], 3);
+        let second = create(&program_id, &mut registry, [2; 32], 7);
+
+        assert_eq!(registry.data().len(), HEADER_LEN + 2 * ENTRY_LEN);
+        assert_eq!(
+            entries(&registry),
+            vec![
+                EventSummaryEntry {
+                    unique_id: [1; 32],
+                    creator: first.creator.key(),
+                    expiry: 100,
+                    status: EventStatus::Created,
+                    category: 3,
+                },
+                EventSummaryEntry {
+                    unique_id: [2; 32],
+                    creator: second.creator.key(),
+                    expiry: 100,
+                    status: EventStatus::Created,
+                    category: 7,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_status_changes_rewrite_the_entry_in_place() {
+        let program_id = Pubkey::new_unique();
+        let mut registry = registry_account(&program_id);
+        create(&program_id, &mut registry, [1; 32], 0);
+        let mut second = create(&program_id, &mut registry, [2; 32], 0);
+
+        activate(&program_id, &mut second, &mut registry);
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+        run(
+            &program_id,
+            2,
+            &[2; 32],
+            &mut [&mut second.event, &mut second.creator, &mut config],
+            &mut registry,
+        )
+        .unwrap();
+
+        let statuses: Vec<_> = entries(&registry).iter().map(|e| e.status).collect();
+        assert_eq!(statuses, vec![EventStatus::Created, EventStatus::Closed]);
+        assert_eq!(registry.data().len(), HEADER_LEN + 2 * ENTRY_LEN);
+    }
+
+    #[test]
+    fn test_status_change_never_registers_an_unlisted_event() {
+        let program_id = Pubkey::new_unique();
+        let mut registry = registry_account(&program_id);
+        let mut other = registry_account(&program_id);
+        let mut created = create(&program_id, &mut other, [1; 32], 0);
+
+        activate(&program_id, &mut created, &mut other);
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+        run(
+            &program_id,
+            2,
+            &[1; 32],
+            &mut [&mut created.event, &mut created.creator, &mut config],
+            &mut registry,
+        )
+        .unwrap();
+
+        assert!(registry.data().is_empty());
+    }
+
+    #[test]
+    fn test_rejects_an_account_that_is_not_the_registry() {
+        let program_id = Pubkey::new_unique();
+        let mut impostor = TestAccount::new(&program_id, &[]);
+        let mut event = crate::test_utils::event_account(&program_id, &[1; 32]);
+        let mut creator = TestAccount::new(&program_id, &[]).signer();
+        let mut token = token_account(&program_id, &[]);
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+
+        let result = run(
+            &program_id,
+            1,
+            &borsh::to_vec(&event_params([1; 32], 100, 2)).unwrap(),
+            &mut [&mut event, &mut creator, &mut token, &mut config],
+            &mut impostor,
+        );
+
+        assert_eq!(
+            result,
+            Err(PredictionMarketError::RegistryAddressMismatch.into())
+        );
+    }
+
+    fn query(
+        program_id: &Pubkey,
+        registry: &mut TestAccount,
+        offset: u32,
+        limit: u8,
+        status_filter: Option<EventStatus>,
+    ) -> RegistryPage {
+        let mut data = vec![70];
+        data.extend(
+            borsh::to_vec(&QueryRegistryParams {
+                offset,
+                limit,
+                status_filter,
+            })
+            .unwrap(),
+        );
+        process_instruction(program_id, &[registry.info()], &data).unwrap();
+        RegistryPage::try_from_slice(&take_return_data().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_query_pages_through_entries_by_status() {
+        let program_id = Pubkey::new_unique();
+        let mut registry = registry_account(&program_id);
+        for id in 1..=4 {
+            let mut created = create(&program_id, &mut registry, [id; 32], id);
+            if id % 2 == 0 {
+                activate(&program_id, &mut created, &mut registry);
+            }
+        }
+        let ids = |page: &RegistryPage| -> Vec<u8> {
+            page.entries
+                .iter()
+                .map(|entry| entry.unique_id[0])
+                .collect()
+        };
+
+        let first = query(&program_id, &mut registry, 0, 3, None);
+        assert_eq!(ids(&first), vec![1, 2, 3]);
+        assert_eq!(first.next_offset, Some(3));
+        let rest = query(&program_id, &mut registry, 3, 3, None);
+        assert_eq!(ids(&rest), vec![4]);
+        assert_eq!(rest.next_offset, None);
+
+        let active = query(&program_id, &mut registry, 0, 1, Some(EventStatus::Active));
+        assert_eq!(ids(&active), vec![2]);
+        assert_eq!(active.next_offset, Some(1));
+        let active = query(&program_id, &mut registry, 1, 1, Some(EventStatus::Active));
+        assert_eq!(ids(&active), vec![4]);
+        assert_eq!(active.next_offset, None);
+    }
+
+    #[test]
+    fn test_query_caps_the_page_to_fit_the_return_data() {
+        let program_id = Pubkey::new_unique();
+        let mut registry = registry_account(&program_id);
+        for id in 0..MAX_REGISTRY_PAGE as u8 + 2 {
+            create(&program_id, &mut registry, [id; 32], 0);
+        }
+
+        let page = query(&program_id, &mut registry, 0, u8::MAX, None);
+
+        assert_eq!(page.entries.len(), MAX_REGISTRY_PAGE);
+        assert_eq!(page.next_offset, Some(MAX_REGISTRY_PAGE as u32));
+        assert!(borsh::to_vec(&page).unwrap().len() <= MAX_RETURN_DATA);
+    }
+
+    #[test]
+    fn test_query_rejects_a_zero_limit() {
+        let program_id = Pubkey::new_unique();
+        let mut registry = registry_account(&program_id);
+
+        assert_eq!(
+            process_query_registry(&program_id, &registry.info(), 0, 0, None).unwrap_err(),
+            ProgramError::InvalidArgument
+        );
+    }
+
+    fn capped_config(program_id: &Pubkey, cap: u32) -> TestAccount {
+        let mut config = ProgramConfig::new(Pubkey::new_unique());
+        config.max_active_events_per_creator = cap;
+        TestAccount::new(program_id, &borsh::to_vec(&config).unwrap())
+    }
+
+    // creates event `[id; 32]` for `creator`, passing the registry if given
+    fn create_for(
+        program_id: &Pubkey,
+        creator: &mut TestAccount,
+        config: &mut TestAccount,
+        registry: Option<&mut TestAccount>,
+        id: u8,
+    ) -> (TestAccount, ProgramResult) {
+        let mut event = crate::test_utils::event_account(program_id, &[id; 32]);
+        let mut token = token_account(program_id, &[]);
+        let mut data = vec![1];
+        data.extend(borsh::to_vec(&event_params([id; 32], 100, 2)).unwrap());
+
+        let mut infos = vec![event.info(), creator.info(), token.info(), config.info()];
+        infos.extend(registry.map(|registry| registry.info()));
+        let result = process_instruction(program_id, &infos, &data);
+        drop(infos);
+
+        (event, result)
+    }
+
+    fn cancel(
+        program_id: &Pubkey,
+        event: &mut TestAccount,
+        creator: &mut TestAccount,
+        registry: &mut TestAccount,
+        id: u8,
+    ) {
+        let mut config = config_account(program_id, &Pubkey::new_unique(), false);
+        run(
+            program_id,
+            18,
+            &[id; 32],
+            &mut [event, creator, &mut config],
+            registry,
+        )
+        .unwrap();
+    }
+
+    fn by_creator(
+        program_id: &Pubkey,
+        registry: &mut TestAccount,
+        creator: &Pubkey,
+        offset: u32,
+        limit: u8,
+    ) -> CreatorEventsPage {
+        let mut data = vec![72];
+        data.extend(
+            borsh::to_vec(&QueryEventsByCreatorParams {
+                creator: *creator,
+                offset,
+                limit,
+            })
+            .unwrap(),
+        );
+        process_instruction(program_id, &[registry.info()], &data).unwrap();
+        CreatorEventsPage::try_from_slice(&take_return_data().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_creator_index_lists_their_events_and_statuses() {
+        let program_id = Pubkey::new_unique();
+        let mut registry = registry_account(&program_id);
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+
+        let mut events = Vec::new();
+        for id in [1, 3, 4] {
+            let (event, result) = create_for(
+                &program_id,
+                &mut alice,
+                &mut config,
+                Some(&mut registry),
+                id,
+            );
+            result.unwrap();
+            events.push(event);
+        }
+        create(&program_id, &mut registry, [2; 32], 0);
+        cancel(&program_id, &mut events[1], &mut alice, &mut registry, 3);
+
+        let first = by_creator(&program_id, &mut registry, &alice.key(), 0, 2);
+        assert_eq!(
+            first.events,
+            vec![
+                CreatorEvent {
+                    unique_id: [1; 32],
+                    status: EventStatus::Created,
+                },
+                CreatorEvent {
+                    unique_id: [3; 32],
+                    status: EventStatus::Cancelled,
+                },
+            ]
+        );
+        assert_eq!(first.next_offset, Some(2));
+        let rest = by_creator(&program_id, &mut registry, &alice.key(), 2, 2);
+        assert_eq!(rest.events.len(), 1);
+        assert_eq!(rest.events[0].unique_id, [4; 32]);
+        assert_eq!(rest.next_offset, None);
+
+        let stranger = by_creator(&program_id, &mut registry, &Pubkey::new_unique(), 0, 10);
+        assert!(stranger.events.is_empty());
+    }
+
+    #[test]
+    fn test_creating_past_the_cap_of_open_events_fails() {
+        let program_id = Pubkey::new_unique();
+        let mut registry = registry_account(&program_id);
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut config = capped_config(&program_id, 2);
+
+        let (mut first, result) =
+            create_for(&program_id, &mut alice, &mut config, Some(&mut registry), 1);
+        result.unwrap();
+        let (_, result) = create_for(&program_id, &mut alice, &mut config, Some(&mut registry), 2);
+        result.unwrap();
+
+        let (_, result) = create_for(&program_id, &mut alice, &mut config, Some(&mut registry), 3);
+        assert_eq!(
+            result,
+            Err(PredictionMarketError::TooManyActiveEvents.into())
+        );
+        // the cap is per creator
+        let (_, result) = create_for(&program_id, &mut bob, &mut config, Some(&mut registry), 4);
+        result.unwrap();
+
+        // a cancelled event no longer counts
+        cancel(&program_id, &mut first, &mut alice, &mut registry, 1);
+        let (_, result) = create_for(&program_id, &mut alice, &mut config, Some(&mut registry), 5);
+        result.unwrap();
+        assert_eq!(open_events(registry.data(), &alice.key()), Ok(2));
+    }
+
+    #[test]
+    fn test_capped_creation_requires_the_registry() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+
+        let (_, result) = create_for(
+            &program_id,
+            &mut alice,
+            &mut capped_config(&program_id, 1),
+            None,
+            1,
+        );
+        assert_eq!(result, Err(PredictionMarketError::RegistryRequired.into()));
+
+        let mut uncapped = config_account(&program_id, &Pubkey::new_unique(), false);
+        let (_, result) = create_for(&program_id, &mut alice, &mut uncapped, None, 1);
+        result.unwrap();
+    }
+
+    #[test]
+    fn test_admin_sets_the_cap() {
+        let program_id = Pubkey::new_unique();
+        let mut admin = TestAccount::new(&program_id, &[]).signer();
+        let mut config = config_account(&program_id, &admin.key(), false);
+        let mut data = vec![71];
+        data.extend(
+            borsh::to_vec(&SetMaxActiveEventsParams {
+                max_active_events: 3,
+            })
+            .unwrap(),
+        );
+
+        process_instruction(&program_id, &[config.info(), admin.info()], &data).unwrap();
+
+        let config = ProgramConfig::try_from_slice(config.data()).unwrap();
+        assert_eq!(config.max_active_events_per_creator, 3);
+    }
+}
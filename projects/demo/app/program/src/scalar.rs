@@ -0,0 +1,351 @@
+//! Scalar markets. A `MarketType::Scalar` event asks for a number between
+//! its bounds instead of picking an outcome: users stake LONG or SHORT, the
+//! resolver submits the value, clamped to the bounds, and the pool is split
+//! between the two sides by linear interpolation, see `math::scalar_payout`.
+//! At or above the upper bound the longs take the whole pool, at or below the
+//! lower bound the shorts do.
+//!
+//! A user holds one side at a time; their stake on it is their exposure,
+//! positive for long and negative for short. A void or cancelled scalar event
+//! refunds every stake.
+use arch_program::{account::AccountInfo, msg, program_error::ProgramError, pubkey::Pubkey};
+
+use crate::{
+    config, ensure_distinct_accounts, ensure_escrow_backs_pool, ensure_program_owned, event_store,
+    get_bitcoin_block_height, math, referral,
+    transfer::move_balance,
+    types::{
+        escrow_address, EventStatus, MarketType, PredictionEvent, PredictionMarketError,
+        ScalarPositionParams, ScalarSide,
+    },
+};
+
+/// Stakes `amount` on one side of an active scalar event, charging the
+/// protocol fee on top. Adds to the better's position if they already hold
+/// that side; holding the other side fails with `OppositeScalarPosition`.
+pub fn process_take_scalar_position<'a>(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'a>,
+    token_account: &AccountInfo<'a>,
+    better_account: &AccountInfo<'a>,
+    config_account: &AccountInfo<'a>,
+    params: ScalarPositionParams,
+) -> Result<(), ProgramError> {
+    ensure_distinct_accounts(&[
+        event_account.key,
+        token_account.key,
+        better_account.key,
+        config_account.key,
+    ])?;
+
+    ensure_program_owned(program_id, &[event_account, token_account, config_account])?;
+
+    if !better_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    config::ensure_not_paused(config_account)?;
+    let config = config::load_config(config_account)?;
+
+    let mut event = event_store::load_event(event_account, &params.unique_id)?;
+
+    if event.status != EventStatus::Active {
+        return Err(PredictionMarketError::EventNotActive.into());
+    }
+
+    if !matches!(event.market_type, MarketType::Scalar { .. }) {
+        return Err(PredictionMarketError::InvalidMarketType.into());
+    }
+
+    let height = get_bitcoin_block_height();
+    event.ensure_not_frozen(height)?;
+    event.ensure_mint(token_account.key)?;
+    event.ensure_within_bet_limit(better_account.key, params.amount)?;
+    event.ensure_within_pool_cap(params.amount)?;
+
+    let held = event
+        .scalar_exposure
+        .get(better_account.key)
+        .copied()
+        .unwrap_or(0);
+    let amount = i64::try_from(params.amount).map_err(|_| ProgramError::ArithmeticOverflow)?;
+    let exposure = match params.side {
+        ScalarSide::Long if held >= 0 => held.checked_add(amount),
+        ScalarSide::Short if held <= 0 => held.checked_sub(amount),
+        _ => return Err(PredictionMarketError::OppositeScalarPosition.into()),
+    }
+    .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    // the protocol fee is charged on top of the stake and never enters the pool
+    let (protocol_fee, _) = referral::split_protocol_fee(params.amount, config.protocol_fee_bps, 0);
+
+    let escrow = escrow_address(event_account.key, &params.unique_id);
+    move_balance(token_account, better_account.key, &escrow, params.amount)?;
+    if protocol_fee > 0 {
+        move_balance(
+            token_account,
+            better_account.key,
+            &config.treasury,
+            protocol_fee,
+        )?;
+    }
+
+    event.scalar_exposure.insert(*better_account.key, exposure);
+    event
+        .stats
+        .record_buy(better_account.key, params.amount, height);
+    event.total_pool_amount = event
+        .total_pool_amount
+        .checked_add(params.amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    ensure_escrow_backs_pool(token_account, &escrow, &event)?;
+
+    msg!(
+        "{} took {:?} {} on the scalar event",
+        better_account.key,
+        params.side,
+        params.amount
+    );
+
+    event_store::store_event(event_account, &event)
+}
+
+/// What `user` is owed from a settled scalar event: their side's share of
+/// the pool once resolved to a value, their stake back if the event is void.
+/// Fails with `NoWinningPosition` when they are owed nothing.
+pub(crate) fn claimable_amount(
+    event: &PredictionEvent,
+    user: &Pubkey,
+) -> Result<u64, ProgramError> {
+    let exposure = event.scalar_exposure.get(user).copied().unwrap_or(0);
+
+    let payout = match (event.status, event.scalar_value) {
+        (EventStatus::Resolved, Some(value)) => {
+            math::scalar_payout(event, exposure, value).ok_or(ProgramError::ArithmeticOverflow)?
+        }
+        (EventStatus::Resolved, None) | (EventStatus::Cancelled, _) => exposure.unsigned_abs(),
+        (EventStatus::Settled, _) => return Err(PredictionMarketError::ClaimWindowClosed.into()),
+        _ => return Err(PredictionMarketError::EventNotResolved.into()),
+    };
+
+    if payout == 0 {
+        return Err(PredictionMarketError::NoWinningPosition.into());
+    }
+
+    Ok(payout)
+}
+
+#[cfg(test)]
+mod scalar_tests {
+    use super::*;
+    use crate::test_utils::{create_event_with, event_params, Market, TestAccount};
+    use crate::types::{PredictionEventParams, ResolveScalarParams};
+    use crate::{process_instruction, process_resolve_event, process_resolve_void};
+
+    const SCALAR: [u8; 32] = [54; 32];
+
+    fn scalar_params(lower_bound: i64, upper_bound: i64) -> PredictionEventParams {
+        PredictionEventParams {
+            market_type: MarketType::Scalar {
+                lower_bound,
+                upper_bound,
+            },
+            ..event_params(SCALAR, 100, 0)
+        }
+    }
+
+    // a scalar event on [0, 100] with alice long 60 and bob short 40
+    fn scalar_market(
+        program_id: &Pubkey,
+        alice: &mut TestAccount,
+        bob: &mut TestAccount,
+    ) -> Market {
+        let mut market = Market::with_params(
+            program_id,
+            scalar_params(0, 100),
+            &[(alice.key(), 1_000), (bob.key(), 1_000)],
+        );
+        take(&mut market, alice, ScalarSide::Long, 60).unwrap();
+        take(&mut market, bob, ScalarSide::Short, 40).unwrap();
+        market
+    }
+
+    fn take(
+        market: &mut Market,
+        better: &mut TestAccount,
+        side: ScalarSide,
+        amount: u64,
+    ) -> Result<(), ProgramError> {
+        let program_id = market.event.owner;
+        let mut data = vec![50];
+        data.extend(
+            borsh::to_vec(&ScalarPositionParams {
+                unique_id: market.unique_id,
+                side,
+                amount,
+            })
+            .unwrap(),
+        );
+        process_instruction(
+            &program_id,
+            &[
+                market.event.info(),
+                market.token.info(),
+                better.info(),
+                market.config.info(),
+            ],
+            &data,
+        )
+    }
+
+    fn resolve(market: &mut Market, value: i64) -> Result<(), ProgramError> {
+        let program_id = market.event.owner;
+        let mut data = vec![51];
+        data.extend(
+            borsh::to_vec(&ResolveScalarParams {
+                unique_id: market.unique_id,
+                value,
+            })
+            .unwrap(),
+        );
+        process_instruction(
+            &program_id,
+            &[
+                market.event.info(),
+                market.creator.info(),
+                market.config.info(),
+            ],
+            &data,
+        )
+    }
+
+    #[test]
+    fn test_scalar_payouts_at_below_and_above_the_bounds() {
+        let program_id = Pubkey::new_unique();
+        let no_position: ProgramError = PredictionMarketError::NoWinningPosition.into();
+
+        // (value, alice's payout, bob's payout); values past the bounds pay as
+        // the bounds do
+        let cases = [
+            (75, Ok(75), Ok(25)),
+            (0, Err(no_position.clone()), Ok(100)),
+            (-20, Err(no_position.clone()), Ok(100)),
+            (100, Ok(100), Err(no_position.clone())),
+            (150, Ok(100), Err(no_position)),
+        ];
+
+        for (value, alice_payout, bob_payout) in cases {
+            let mut alice = TestAccount::new(&program_id, &[]).signer();
+            let mut bob = TestAccount::new(&program_id, &[]).signer();
+            let mut market = scalar_market(&program_id, &mut alice, &mut bob);
+            resolve(&mut market, value).unwrap();
+            assert_eq!(market.event_state().scalar_value, Some(value.clamp(0, 100)));
+
+            for (better, payout) in [(&mut alice, alice_payout), (&mut bob, bob_payout)] {
+                let before = market.balance(better);
+                let claimed = market
+                    .claim(better)
+                    .map(|_| market.balance(better) - before);
+                assert_eq!(claimed, payout, "value {}", value);
+            }
+        }
+    }
+
+    #[test]
+    fn test_scalar_positions_hold_one_side() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = scalar_market(&program_id, &mut alice, &mut bob);
+
+        take(&mut market, &mut alice, ScalarSide::Long, 15).unwrap();
+        assert_eq!(
+            take(&mut market, &mut alice, ScalarSide::Short, 10),
+            Err(PredictionMarketError::OppositeScalarPosition.into())
+        );
+
+        let event = market.event_state();
+        assert_eq!(event.scalar_exposure.get(&alice.key()), Some(&75));
+        assert_eq!(event.scalar_exposure.get(&bob.key()), Some(&-40));
+        assert_eq!(event.total_pool_amount, 115);
+        assert_eq!(market.balance(&alice), 925);
+
+        // a scalar event has no outcomes to bet on
+        assert_eq!(
+            market.buy(&mut alice, 0, 10),
+            Err(PredictionMarketError::InvalidOutcome.into())
+        );
+    }
+
+    #[test]
+    fn test_void_scalar_event_refunds_stakes() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = scalar_market(&program_id, &mut alice, &mut bob);
+
+        process_resolve_void(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            SCALAR,
+        )
+        .unwrap();
+
+        market.claim(&mut alice).unwrap();
+        market.claim(&mut bob).unwrap();
+        assert_eq!(market.balance(&alice), 1_000);
+        assert_eq!(market.balance(&bob), 1_000);
+    }
+
+    #[test]
+    fn test_scalar_and_outcome_instructions_do_not_mix() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+
+        let mut pool = Market::new(&program_id, [55; 32], &[(alice.key(), 1_000)]);
+        assert_eq!(
+            take(&mut pool, &mut alice, ScalarSide::Long, 10),
+            Err(PredictionMarketError::InvalidMarketType.into())
+        );
+        assert_eq!(
+            resolve(&mut pool, 10),
+            Err(PredictionMarketError::InvalidMarketType.into())
+        );
+
+        let mut scalar = scalar_market(&program_id, &mut alice, &mut bob);
+        assert_eq!(
+            process_resolve_event(
+                &program_id,
+                &scalar.event.info(),
+                &scalar.creator.info(),
+                SCALAR,
+                0,
+            ),
+            Err(PredictionMarketError::InvalidOutcome.into())
+        );
+        resolve(&mut scalar, 50).unwrap();
+        assert_eq!(
+            take(&mut scalar, &mut alice, ScalarSide::Long, 10),
+            Err(PredictionMarketError::EventNotActive.into())
+        );
+    }
+
+    #[test]
+    fn test_scalar_bounds_must_be_ordered() {
+        let program_id = Pubkey::new_unique();
+        for (lower_bound, upper_bound) in [(100, 100), (100, 0)] {
+            let params = scalar_params(lower_bound, upper_bound);
+            assert_eq!(
+                params.market_type.validate(),
+                Err(PredictionMarketError::InvalidMarketType)
+            );
+        }
+
+        let (event, _) = create_event_with(&program_id, scalar_params(-100, 100));
+        let predictions = crate::test_utils::load_predictions(&event);
+        assert!(predictions.predictions[0].outcomes.is_empty());
+    }
+}
@@ -0,0 +1,221 @@
+//! Reading and writing borsh values that fill a whole account: mints,
+//! balances, the config, order books, parlays and referral stats. The event
+//! account has its own indexed layout, see `event_store`.
+use arch_program::{account::AccountInfo, msg, program_error::ProgramError};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::types::PredictionMarketError;
+
+/// Decodes the account's data as a `T`. Fails with `UninitializedAccount` if
+/// the account holds nothing yet and `InvalidAccountData` if it holds
+/// anything but a `T`.
+pub(crate) fn read<T: BorshDeserialize>(account: &AccountInfo<'_>) -> Result<T, ProgramError> {
+    let data = account.try_borrow_data()?;
+    if data.is_empty() {
+        return Err(ProgramError::UninitializedAccount);
+    }
+
+    T::try_from_slice(&data).map_err(|_| ProgramError::InvalidAccountData)
+}
+
+/// Borsh-serializes `value` straight into the account's data. The size is
+/// counted up front so the account is resized at most once, up or down to
+/// exactly the value, and no intermediate buffer is allocated: a shrink
+/// leaves no stale tail behind and growth is zeroed before it is written.
+/// Returns the number of bytes written.
+pub(crate) fn write<T: BorshSerialize>(
+    account: &AccountInfo<'_>,
+    value: &T,
+) -> Result<usize, ProgramError> {
+    let len = borsh::object_length(value).map_err(|e| ProgramError::BorshIoError(e.to_string()))?;
+
+    if account.data_len() != len {
+        realloc(account, len, true)?;
+    }
+    ensure_len(account, len)?;
+
+    let mut data = account
+        .data
+        .try_borrow_mut()
+        .map_err(|_e| ProgramError::AccountBorrowFailed)?;
+    // `&mut [u8]` is an `io::Write` that advances past what it has written
+    let mut writer: &mut [u8] = &mut data[..len];
+    value
+        .serialize(&mut writer)
+        .map_err(|e| ProgramError::BorshIoError(e.to_string()))?;
+
+    Ok(len)
+}
+
+/// `AccountInfo::realloc`, but growth the runtime refuses, such as more than
+/// one instruction may add, fails with `AccountTooSmall` rather than a bare
+/// `InvalidRealloc`. A refused shrink keeps the runtime's error.
+pub(crate) fn realloc(
+    account: &AccountInfo<'_>,
+    len: usize,
+    zero_init: bool,
+) -> Result<(), ProgramError> {
+    let old_len = account.data_len();
+    account.realloc(len, zero_init).map_err(|e| {
+        msg!(
+            "Account {} can't be resized from {} to {} bytes: {}",
+            account.key,
+            old_len,
+            len,
+            e
+        );
+        if len > old_len {
+            PredictionMarketError::AccountTooSmall.into()
+        } else {
+            e
+        }
+    })
+}
+
+/// Fails with `AccountDataTooSmall` unless the account holds at least `len`
+/// bytes, so a write past its end is a clean error instead of a panic.
+pub(crate) fn ensure_len(account: &AccountInfo<'_>, len: usize) -> Result<(), ProgramError> {
+    if account.data_len() < len {
+        msg!(
+            "Account {} holds {} bytes but {} are written",
+            account.key,
+            account.data_len(),
+            len
+        );
+        return Err(PredictionMarketError::AccountDataTooSmall.into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod storage_tests {
+    use std::collections::HashMap;
+
+    use arch_program::pubkey::Pubkey;
+
+    use super::*;
+    use crate::mint::{InitializeMintInput, MintStatus, TokenMintDetails};
+    use crate::test_utils::{Market, TestAccount};
+    use crate::token_account::TokenBalance;
+    use crate::types::Predictions;
+
+    // the path every write used to take: a full `to_vec`, then a copy
+    fn old_path_bytes<T: BorshSerialize>(value: &T) -> Vec<u8> {
+        borsh::to_vec(value).unwrap()
+    }
+
+    fn mint_with_balances(count: u64) -> TokenMintDetails {
+        let mut mint = TokenMintDetails::new(
+            InitializeMintInput::new([0; 32], 1_000_000, "BANGO".to_string(), 0),
+            MintStatus::Ongoing,
+            HashMap::new(),
+        );
+        for amount in 1..=count {
+            mint.balances.insert(Pubkey::new_unique(), amount);
+        }
+        mint
+    }
+
+    #[test]
+    fn test_predictions_written_in_place_match_the_old_path() {
+        let program_id = Pubkey::new_unique();
+        let mut better = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, [30; 32], &[(better.key(), 1_000)]);
+        for amount in 1..=10 {
+            market.buy(&mut better, amount as u8 % 2, amount).unwrap();
+        }
+        let event = market.event_state();
+
+        // more events would grow the fresh account past one realloc
+        for count in [1, 2, 4] {
+            let predictions = Predictions {
+                total_predictions: count,
+                predictions: vec![event.clone(); count as usize],
+            };
+            let expected = old_path_bytes(&predictions);
+            let mut account = TestAccount::new(&program_id, &[]);
+
+            let written = write(&account.info(), &predictions).unwrap();
+
+            assert_eq!(written, expected.len());
+            assert_eq!(account.data(), &expected[..]);
+        }
+    }
+
+    #[test]
+    fn test_write_grows_the_account() {
+        let program_id = Pubkey::new_unique();
+        let mut account = TestAccount::new(&program_id, &[]);
+
+        for count in [0, 1, 10, 50] {
+            let mint = mint_with_balances(count);
+            let expected = old_path_bytes(&mint);
+
+            let written = write(&account.info(), &mint).unwrap();
+
+            assert_eq!(written, expected.len());
+            assert_eq!(account.data(), &expected[..]);
+            let read_back: TokenMintDetails = read(&account.info()).unwrap();
+            assert_eq!(old_path_bytes(&read_back), expected);
+        }
+    }
+
+    #[test]
+    fn test_mint_written_in_place_shrinks_to_exact_size() {
+        let program_id = Pubkey::new_unique();
+        let mint = mint_with_balances(50);
+        let expected = old_path_bytes(&mint);
+        // stale bytes from an earlier, larger state
+        let mut account = TestAccount::new(&program_id, &vec![0xff; expected.len() + 64]);
+
+        let written = write(&account.info(), &mint).unwrap();
+
+        assert_eq!(written, expected.len());
+        assert_eq!(account.data(), &expected[..]);
+        let read_back: TokenMintDetails = read(&account.info()).unwrap();
+        assert_eq!(old_path_bytes(&read_back), expected);
+    }
+
+    #[test]
+    fn test_read_of_an_empty_or_foreign_account_is_a_clean_error() {
+        let program_id = Pubkey::new_unique();
+        let mut empty = TestAccount::new(&program_id, &[]);
+        assert_eq!(
+            read::<TokenBalance>(&empty.info()).unwrap_err(),
+            ProgramError::UninitializedAccount
+        );
+
+        let mut short = TestAccount::new(&program_id, &[1; 8]);
+        assert_eq!(
+            read::<TokenBalance>(&short.info()).unwrap_err(),
+            ProgramError::InvalidAccountData
+        );
+    }
+
+    #[test]
+    fn test_undersized_account_is_a_clean_error() {
+        let program_id = Pubkey::new_unique();
+        let balance = TokenBalance::new([1; 32], [2; 32]);
+        let predictions = Predictions {
+            total_predictions: 0,
+            predictions: vec![],
+        };
+
+        for bytes in [
+            old_path_bytes(&mint_with_balances(3)),
+            old_path_bytes(&balance),
+            old_path_bytes(&predictions),
+        ] {
+            let stale = vec![0xff; bytes.len() - 1];
+            let mut account = TestAccount::new(&program_id, &stale);
+
+            assert_eq!(
+                ensure_len(&account.info(), bytes.len()),
+                Err(PredictionMarketError::AccountDataTooSmall.into())
+            );
+            assert_eq!(account.data(), &stale[..]);
+            assert_eq!(ensure_len(&account.info(), bytes.len() - 1), Ok(()));
+        }
+    }
+}
@@ -0,0 +1,550 @@
+//! Off-chain stand-ins for the runtime, used by the unit tests.
+//!
+//! `AccountInfo::realloc` writes the new length 8 bytes in front of the data
+//! slice and reads the original length right after the key, exactly as the
+//! runtime lays accounts out in the input buffer. `TestAccount` reproduces
+//! that layout so handlers can be driven end to end without a validator.
+#![allow(dead_code)]
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+};
+
+use arch_program::{
+    account::AccountInfo,
+    bitcoin::{consensus, Amount, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness},
+    entrypoint::{ProgramResult, MAX_PERMITTED_DATA_INCREASE},
+    input_to_sign::InputToSign,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    transaction_to_sign::TransactionToSign,
+    utxo::UtxoMeta,
+};
+use borsh::BorshDeserialize;
+
+use crate::{
+    config::ProgramConfig,
+    event_store,
+    mint::{InitializeMintInput, MintStatus, TokenMintDetails},
+    process_activate_event, process_buy_bet, process_claim_winnings, process_create_event,
+    process_sell_bet,
+    types::{
+        derive_event_address, MarketType, PredictionEvent, PredictionEventParams, Predictions,
+        SlippageLimits,
+    },
+    user_limits::derive_limits_address,
+    BetAccounts,
+};
+
+thread_local! {
+    static BLOCK_HEIGHT: Cell<u64> = const { Cell::new(0) };
+    static BLOCK_HEIGHT_READS: Cell<u32> = const { Cell::new(0) };
+    static OWNED_UTXOS: RefCell<Vec<(UtxoMeta, Pubkey)>> = const { RefCell::new(Vec::new()) };
+    static LOGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    static RETURN_DATA: RefCell<Option<Vec<u8>>> = const { RefCell::new(None) };
+    static TRANSACTIONS_TO_SIGN: RefCell<Vec<(Transaction, Vec<InputToSign>)>> =
+        const { RefCell::new(Vec::new()) };
+}
+
+/// Mock of `arch_program::program::get_bitcoin_block_height`, counting each
+/// read for `take_block_height_reads`.
+pub fn get_bitcoin_block_height() -> u64 {
+    BLOCK_HEIGHT_READS.with(|reads| reads.set(reads.get() + 1));
+    BLOCK_HEIGHT.with(|height| height.get())
+}
+
+/// How many times the block height was read since the last call.
+pub fn take_block_height_reads() -> u32 {
+    BLOCK_HEIGHT_READS.with(|reads| reads.replace(0))
+}
+
+/// Moves the mock clock. Handlers read the height through
+/// `get_bitcoin_block_height`, which the crate swaps for the mock under
+/// `cfg(test)`, so tests drive expiry and every window keyed off it by
+/// setting the height before each call.
+pub fn set_block_height(height: u64) {
+    BLOCK_HEIGHT.with(|h| h.set(height));
+}
+
+/// Mock of `arch_program::program::validate_utxo_ownership`: a utxo is owned
+/// only if it was registered with `register_utxo_owner`.
+pub fn validate_utxo_ownership(utxo: &UtxoMeta, owner: &Pubkey) -> bool {
+    OWNED_UTXOS.with(|owned| {
+        owned
+            .borrow()
+            .iter()
+            .any(|(known, known_owner)| known == utxo && known_owner == owner)
+    })
+}
+
+pub fn register_utxo_owner(utxo: UtxoMeta, owner: Pubkey) {
+    OWNED_UTXOS.with(|owned| owned.borrow_mut().push((utxo, owner)));
+}
+
+/// Mock of `arch_program::log::sol_log` that keeps the line for `take_logs`.
+pub fn sol_log(message: &str) {
+    println!("{message}");
+    LOGS.with(|logs| logs.borrow_mut().push(message.to_string()));
+}
+
+/// Drains everything logged through `sol_log` on this thread so far.
+pub fn take_logs() -> Vec<String> {
+    LOGS.with(|logs| logs.take())
+}
+
+/// Mock of `arch_program::program::set_return_data`.
+pub fn set_return_data(data: &[u8]) {
+    RETURN_DATA.with(|return_data| *return_data.borrow_mut() = Some(data.to_vec()));
+}
+
+/// Takes whatever was last passed to `set_return_data` on this thread.
+pub fn take_return_data() -> Option<Vec<u8>> {
+    RETURN_DATA.with(|return_data| return_data.take())
+}
+
+/// Mock of `arch_program::helper::add_state_transition`. The runtime reads
+/// the value of the account's utxo off bitcoin; here the output carries none.
+pub fn add_state_transition(transaction: &mut Transaction, account: &AccountInfo) {
+    transaction.input.push(TxIn {
+        previous_output: account.utxo.to_outpoint(),
+        script_sig: ScriptBuf::new(),
+        sequence: Sequence::MAX,
+        witness: Witness::new(),
+    });
+    transaction.output.push(TxOut {
+        value: Amount::ZERO,
+        script_pubkey: ScriptBuf::new(),
+    });
+}
+
+/// Mock of `arch_program::program::set_transaction_to_sign` that keeps the
+/// transaction for `take_transactions_to_sign`.
+pub fn set_transaction_to_sign(
+    _accounts: &[AccountInfo],
+    transaction_to_sign: TransactionToSign,
+) -> ProgramResult {
+    let transaction = consensus::deserialize(transaction_to_sign.tx_bytes)
+        .map_err(|_| ProgramError::InvalidArgument)?;
+    TRANSACTIONS_TO_SIGN.with(|pending| {
+        pending
+            .borrow_mut()
+            .push((transaction, transaction_to_sign.inputs_to_sign.to_vec()))
+    });
+    Ok(())
+}
+
+/// Drains every transaction handed to `set_transaction_to_sign` on this
+/// thread so far.
+pub fn take_transactions_to_sign() -> Vec<(Transaction, Vec<InputToSign>)> {
+    TRANSACTIONS_TO_SIGN.with(|pending| pending.take())
+}
+
+#[repr(C)]
+struct KeyRecord {
+    key: Pubkey,
+    original_data_len: u64,
+}
+
+/// Key of the token account built by `token_account`, and the mint every
+/// event from `event_params` is bound to.
+pub const TEST_MINT: Pubkey = Pubkey([0x4d; 32]);
+
+pub struct TestAccount {
+    record: Box<KeyRecord>,
+    pub owner: Pubkey,
+    pub utxo: UtxoMeta,
+    // word 0 holds the current data length, the data follows it
+    buffer: Vec<u64>,
+    pub is_signer: bool,
+    pub is_writable: bool,
+}
+
+impl TestAccount {
+    pub fn new(owner: &Pubkey, data: &[u8]) -> Self {
+        let mut account = TestAccount {
+            record: Box::new(KeyRecord {
+                key: Pubkey::new_unique(),
+                original_data_len: 0,
+            }),
+            owner: *owner,
+            utxo: UtxoMeta::from([0; 32], 0),
+            buffer: vec![0],
+            is_signer: false,
+            is_writable: true,
+        };
+        account.set_data(data);
+        account
+    }
+
+    pub fn signer(mut self) -> Self {
+        self.is_signer = true;
+        self
+    }
+
+    pub fn with_key(mut self, key: Pubkey) -> Self {
+        self.record.key = key;
+        self
+    }
+
+    pub fn key(&self) -> Pubkey {
+        self.record.key
+    }
+
+    pub fn set_data(&mut self, data: &[u8]) {
+        self.buffer[0] = data.len() as u64;
+        self.reserve();
+        self.bytes_mut()[8..8 + data.len()].copy_from_slice(data);
+    }
+
+    pub fn data(&self) -> &[u8] {
+        let len = self.buffer[0] as usize;
+        let bytes = unsafe {
+            std::slice::from_raw_parts(self.buffer.as_ptr() as *const u8, self.buffer.len() * 8)
+        };
+        &bytes[8..8 + len]
+    }
+
+    pub fn info(&mut self) -> AccountInfo<'_> {
+        self.reserve();
+        let len = self.buffer[0] as usize;
+        self.record.original_data_len = len as u64;
+        let data = unsafe {
+            std::slice::from_raw_parts_mut((self.buffer.as_mut_ptr() as *mut u8).add(8), len)
+        };
+        AccountInfo::new(
+            &self.record.key,
+            data,
+            &self.owner,
+            &self.utxo,
+            self.is_signer,
+            self.is_writable,
+            false,
+        )
+    }
+
+    // keeps room for the largest realloc the runtime would allow
+    fn reserve(&mut self) {
+        let len = self.buffer[0] as usize;
+        let words = (8 + len + MAX_PERMITTED_DATA_INCREASE).div_ceil(8);
+        if self.buffer.len() < words {
+            self.buffer.resize(words, 0);
+        }
+    }
+
+    fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            std::slice::from_raw_parts_mut(
+                self.buffer.as_mut_ptr() as *mut u8,
+                self.buffer.len() * 8,
+            )
+        }
+    }
+}
+
+pub fn event_params(
+    unique_id: [u8; 32],
+    expiry_timestamp: u32,
+    num_outcomes: u8,
+) -> PredictionEventParams {
+    PredictionEventParams {
+        unique_id,
+        expiry_timestamp,
+        resolution_deadline: 0,
+        num_outcomes,
+        labels: vec![],
+        resolver: None,
+        committee: None,
+        mint: TEST_MINT,
+        max_bet_per_user: None,
+        max_pool: None,
+        max_outcome_exposure: 0,
+        min_participants: 0,
+        market_type: MarketType::ParimutuelPool,
+        claim_deadline_blocks: 1_000,
+        dispute_window: 0,
+        freeze_blocks_before_expiry: 0,
+        creator_fee_bps: 0,
+        auto_resolve: false,
+        category: 0,
+    }
+}
+
+/// Creates and activates an event through the instruction handlers, returning
+/// the event account and its creator.
+pub fn create_event(
+    program_id: &Pubkey,
+    unique_id: [u8; 32],
+    expiry_timestamp: u32,
+    num_outcomes: u8,
+) -> (TestAccount, TestAccount) {
+    create_event_with(
+        program_id,
+        event_params(unique_id, expiry_timestamp, num_outcomes),
+    )
+}
+
+/// `create_event` with full control over the creation parameters.
+pub fn create_event_with(
+    program_id: &Pubkey,
+    params: PredictionEventParams,
+) -> (TestAccount, TestAccount) {
+    create_event_on(program_id, params, &mut token_account(program_id, &[]))
+}
+
+/// Like `create_event_with`, on `token` for a mint.
+pub fn create_event_on(
+    program_id: &Pubkey,
+    params: PredictionEventParams,
+    token: &mut TestAccount,
+) -> (TestAccount, TestAccount) {
+    let unique_id = params.unique_id;
+    let mut event_account = event_account(program_id, &unique_id);
+    let mut creator = TestAccount::new(program_id, &[]).signer();
+    let mut config = config_account(program_id, &Pubkey::new_unique(), false);
+    process_create_event(
+        program_id,
+        &event_account.info(),
+        &creator.info(),
+        &token.info(),
+        &config.info(),
+        params,
+    )
+    .unwrap();
+    process_activate_event(
+        program_id,
+        &event_account.info(),
+        &creator.info(),
+        unique_id,
+    )
+    .unwrap();
+    (event_account, creator)
+}
+
+/// An empty account at the address derived for `user`'s limits.
+pub fn user_limits_account(program_id: &Pubkey, user: &Pubkey) -> TestAccount {
+    TestAccount::new(program_id, &[]).with_key(derive_limits_address(program_id, user))
+}
+
+/// An empty account at the address derived for event `unique_id`.
+pub fn event_account(program_id: &Pubkey, unique_id: &[u8; 32]) -> TestAccount {
+    TestAccount::new(program_id, &[]).with_key(derive_event_address(program_id, unique_id))
+}
+
+pub fn token_account(program_id: &Pubkey, balances: &[(Pubkey, u64)]) -> TestAccount {
+    token_account_with_decimals(program_id, balances, 0)
+}
+
+pub fn token_account_with_decimals(
+    program_id: &Pubkey,
+    balances: &[(Pubkey, u64)],
+    decimals: u8,
+) -> TestAccount {
+    let mut mint = TokenMintDetails::new(
+        InitializeMintInput::new([0; 32], 1_000_000, "BANGO".to_string(), decimals),
+        MintStatus::Ongoing,
+        HashMap::new(),
+    );
+    mint.balances.extend(balances.iter().copied());
+    mint.circulating_supply = balances.iter().map(|(_, amount)| amount).sum();
+    TestAccount::new(program_id, &borsh::to_vec(&mint).unwrap()).with_key(TEST_MINT)
+}
+
+/// A mint with no balances whose authority is `authority`.
+pub fn mint_account(program_id: &Pubkey, authority: &Pubkey, supply: u64) -> TestAccount {
+    let mint = TokenMintDetails::new(
+        InitializeMintInput::new(authority.serialize(), supply, "BANGO".to_string(), 0),
+        MintStatus::Ongoing,
+        HashMap::new(),
+    );
+    TestAccount::new(program_id, &borsh::to_vec(&mint).unwrap())
+}
+
+/// A wrapped-BTC mint with an empty reserve.
+pub fn btc_mint_account(program_id: &Pubkey) -> TestAccount {
+    let mint = TokenMintDetails::new(
+        InitializeMintInput::new([0; 32], 21_000_000 * 100_000_000, "WBTC".to_string(), 8)
+            .btc_backed(),
+        MintStatus::Ongoing,
+        HashMap::new(),
+    );
+    TestAccount::new(program_id, &borsh::to_vec(&mint).unwrap())
+}
+
+pub fn config_account(program_id: &Pubkey, admin: &Pubkey, paused: bool) -> TestAccount {
+    let mut config = ProgramConfig::new(*admin);
+    config.paused = paused;
+    TestAccount::new(program_id, &borsh::to_vec(&config).unwrap())
+}
+
+/// An activated event together with the token and config accounts its
+/// instructions need.
+pub struct Market {
+    pub unique_id: [u8; 32],
+    pub event: TestAccount,
+    pub creator: TestAccount,
+    pub token: TestAccount,
+    pub config: TestAccount,
+}
+
+impl Market {
+    pub fn new(program_id: &Pubkey, unique_id: [u8; 32], balances: &[(Pubkey, u64)]) -> Self {
+        Self::with_params(program_id, event_params(unique_id, 100, 2), balances)
+    }
+
+    pub fn with_params(
+        program_id: &Pubkey,
+        params: PredictionEventParams,
+        balances: &[(Pubkey, u64)],
+    ) -> Self {
+        let unique_id = params.unique_id;
+        let (event, creator) = create_event_with(program_id, params);
+        Market {
+            unique_id,
+            event,
+            creator,
+            token: token_account(program_id, balances),
+            config: config_account(program_id, &Pubkey::new_unique(), false),
+        }
+    }
+
+    pub fn buy(
+        &mut self,
+        better: &mut TestAccount,
+        outcome_id: u8,
+        amount: u64,
+    ) -> Result<(), ProgramError> {
+        self.buy_with_slippage(better, outcome_id, amount, SlippageLimits::default())
+    }
+
+    pub fn buy_with_slippage(
+        &mut self,
+        better: &mut TestAccount,
+        outcome_id: u8,
+        amount: u64,
+        slippage: SlippageLimits,
+    ) -> Result<(), ProgramError> {
+        let mut limits = user_limits_account(&self.event.owner, &better.key());
+        self.buy_with(better, &mut limits, outcome_id, amount, slippage)
+    }
+
+    /// A buy held to the limits in `limits`, see `user_limits`.
+    pub fn buy_limited(
+        &mut self,
+        better: &mut TestAccount,
+        limits: &mut TestAccount,
+        outcome_id: u8,
+        amount: u64,
+    ) -> Result<(), ProgramError> {
+        self.buy_with(
+            better,
+            limits,
+            outcome_id,
+            amount,
+            SlippageLimits::default(),
+        )
+    }
+
+    fn buy_with(
+        &mut self,
+        better: &mut TestAccount,
+        limits: &mut TestAccount,
+        outcome_id: u8,
+        amount: u64,
+        slippage: SlippageLimits,
+    ) -> Result<(), ProgramError> {
+        let program_id = self.event.owner;
+        process_buy_bet(
+            &program_id,
+            BetAccounts {
+                event: &self.event.info(),
+                token: &self.token.info(),
+                better: &better.info(),
+                limits: Some(&limits.info()),
+                config: &self.config.info(),
+                referral: None,
+                orders: None,
+            },
+            self.unique_id,
+            outcome_id,
+            amount,
+            None,
+            slippage,
+        )
+    }
+
+    pub fn sell(
+        &mut self,
+        better: &mut TestAccount,
+        outcome_id: u8,
+        amount: u64,
+    ) -> Result<(), ProgramError> {
+        let program_id = self.event.owner;
+        process_sell_bet(
+            &program_id,
+            BetAccounts {
+                event: &self.event.info(),
+                token: &self.token.info(),
+                better: &better.info(),
+                limits: None,
+                config: &self.config.info(),
+                referral: None,
+                orders: None,
+            },
+            self.unique_id,
+            outcome_id,
+            amount,
+        )
+    }
+
+    pub fn claim(&mut self, better: &mut TestAccount) -> Result<(), ProgramError> {
+        let program_id = self.event.owner;
+        process_claim_winnings(
+            &program_id,
+            &self.event.info(),
+            &self.token.info(),
+            &better.info(),
+            self.unique_id,
+            false,
+        )
+    }
+
+    pub fn balance(&self, holder: &TestAccount) -> u64 {
+        balance_of(&self.token, &holder.key())
+    }
+
+    pub fn event_state(&self) -> PredictionEvent {
+        load_predictions(&self.event).predictions[0].clone()
+    }
+}
+
+pub fn load_predictions(event_account: &TestAccount) -> Predictions {
+    event_store::decode_predictions(event_account.data()).unwrap()
+}
+
+/// Stores `event` beside the events `event_account` already holds. Handlers
+/// only create an event at its own address, so an account holding several
+/// is laid out directly.
+pub fn stack_event(event_account: &mut TestAccount, event: PredictionEvent) {
+    let info = event_account.info();
+    let total = event_store::total_predictions(&info).unwrap();
+    event_store::store_event(&info, &event).unwrap();
+    event_store::set_total_predictions(&info, total + 1).unwrap();
+}
+
+pub fn balance_of(token_account: &TestAccount, holder: &Pubkey) -> u64 {
+    let mint = TokenMintDetails::try_from_slice(token_account.data()).unwrap();
+    mint.balances.get(holder).copied().unwrap_or(0)
+}
+
+/// Deterministic xorshift, so property cases are reproducible.
+pub struct Rng(pub u64);
+
+impl Rng {
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}
@@ -1,7 +1,7 @@
 use arch_program::{account::AccountInfo, msg, program_error::ProgramError, pubkey::Pubkey};
 use borsh::{BorshDeserialize, BorshSerialize};
 
-use crate::mint::TokenMintDetails;
+use crate::{mint::TokenMintDetails, storage};
 
 #[derive(Clone, Copy, BorshSerialize, BorshDeserialize, Debug)]
 pub struct TokenBalance {
@@ -22,7 +22,7 @@ impl TokenBalance {
     pub fn increase_balance(
         &mut self,
         amount: u64, // in smallest denomination of token
-        token_mint_details: &TokenMintDetails,
+        _token_mint_details: &TokenMintDetails,
     ) {
         // let fraction_multiplier = 10_u64.pow(token_mint_details.decimals as u32);
 
@@ -48,7 +48,7 @@ impl TokenBalance {
     pub fn decrease_balance(
         &mut self,
         amount: u64,
-        token_mint_details: &TokenMintDetails,
+        _token_mint_details: &TokenMintDetails,
     ) -> Result<(), ProgramError> {
         // Check if sufficient whole tokens are available
         if self.current_balance < amount {
@@ -95,18 +95,9 @@ pub fn initialize_balance_account(
     let token_balance =
         TokenBalance::new(owner_account.key.serialize(), mint_account.key.serialize());
 
-    let serialized_token_balance =
-        borsh::to_vec(&token_balance).map_err(|e| ProgramError::BorshIoError(e.to_string()))?;
-
-    balance_account.realloc(serialized_token_balance.len(), true)?;
-
     msg!("Changing account data to {:?}!", token_balance);
 
-    balance_account
-        .data
-        .try_borrow_mut()
-        .unwrap()
-        .copy_from_slice(&serialized_token_balance);
+    storage::write(balance_account, &token_balance)?;
     Ok(())
 }
 
@@ -118,7 +109,7 @@ mod balance_change_tests {
     use super::*;
     use std::collections::HashMap;
 
-    fn create_token_mint_details(mint_price: u64, decimals: u8) -> TokenMintDetails {
+    fn create_token_mint_details(_mint_price: u64, decimals: u8) -> TokenMintDetails {
         let owner = [0u8; 32];
         let initialize_input = InitializeMintInput::new(owner, 1000, "TEST".to_string(), decimals);
         let token_metadata = HashMap::new();
@@ -1,7 +1,20 @@
+use std::collections::HashSet;
+
 use arch_program::{account::AccountInfo, program_error::ProgramError, pubkey::Pubkey};
 use borsh::{BorshDeserialize, BorshSerialize};
 
-use crate::{mint::TokenMintDetails, token_account::TokenBalance};
+use crate::{
+    ensure_distinct_accounts,
+    errors::FungibleTokenError,
+    events::{self, TransferredLog},
+    get_bitcoin_block_height,
+    mint::TokenMintDetails,
+    storage,
+    token_account::TokenBalance,
+};
+
+/// Upper bound on recipients per `BatchTransfer`.
+pub const MAX_BATCH_TRANSFER_RECIPIENTS: usize = 32;
 
 #[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
 pub struct TransferInput {
@@ -13,6 +26,11 @@ impl TransferInput {
     }
 }
 
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct BatchTransferInput {
+    pub recipients: Vec<(Pubkey, u64)>,
+}
+
 pub fn transfer_tokens(
     owner_account: &AccountInfo<'_>,
     mint_account: &AccountInfo<'_>,
@@ -21,14 +39,12 @@ pub fn transfer_tokens(
     program_id: &Pubkey,
     transfer_input: TransferInput,
 ) -> Result<(), ProgramError> {
-    /* ------------------------- Sender account checks ------------------------- */
-    let mut sender_token_balance_data = sender_account
-        .data
-        .try_borrow_mut()
-        .map_err(|_| ProgramError::AccountBorrowFailed)?;
+    // sender and receiver may be the same account, which is handled below
+    ensure_distinct_accounts(&[owner_account.key, mint_account.key, sender_account.key])?;
+    ensure_distinct_accounts(&[owner_account.key, mint_account.key, receiver_account.key])?;
 
-    let mut sender_token_balance = TokenBalance::deserialize(&mut &sender_token_balance_data[..])
-        .map_err(|_| ProgramError::InvalidAccountData)?;
+    /* ------------------------- Sender account checks ------------------------- */
+    let mut sender_token_balance = storage::read::<TokenBalance>(sender_account)?;
 
     if sender_account.owner != program_id {
         return Err(ProgramError::Custom(501));
@@ -42,16 +58,28 @@ pub fn transfer_tokens(
         return Err(ProgramError::Custom(502));
     }
 
-    /* ------------------------- Receiver account checks ------------------------- */
+    /* ------------------------- Transfer to self ------------------------- */
+
+    // nothing moves
+    if sender_account.key == receiver_account.key {
+        if mint_account.owner != program_id {
+            return Err(ProgramError::Custom(504));
+        }
+
+        if !owner_account.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+
+        if sender_token_balance.current_balance < transfer_input.amount {
+            return Err(ProgramError::InsufficientFunds);
+        }
+
+        return Ok(());
+    }
 
-    let mut receiver_token_balance_data = receiver_account
-        .data
-        .try_borrow_mut()
-        .map_err(|_| ProgramError::AccountBorrowFailed)?;
+    /* ------------------------- Receiver account checks ------------------------- */
 
-    let mut receiver_token_balance =
-        TokenBalance::deserialize(&mut &receiver_token_balance_data[..])
-            .map_err(|_| ProgramError::InvalidAccountData)?;
+    let mut receiver_token_balance = storage::read::<TokenBalance>(receiver_account)?;
 
     if receiver_account.owner != program_id {
         return Err(ProgramError::Custom(505));
@@ -63,13 +91,7 @@ pub fn transfer_tokens(
 
     /* --------------------------- MINT ACCOUNT CHECKS -------------------------- */
 
-    let mint_data = mint_account
-        .data
-        .try_borrow_mut()
-        .map_err(|_| ProgramError::AccountBorrowFailed)?;
-
-    let mint_details = TokenMintDetails::deserialize(&mut &mint_data[..])
-        .map_err(|_| ProgramError::InvalidAccountData)?;
+    let mint_details = storage::read::<TokenMintDetails>(mint_account)?;
 
     if mint_account.owner != program_id {
         return Err(ProgramError::Custom(504));
@@ -84,25 +106,382 @@ pub fn transfer_tokens(
 
     receiver_token_balance.increase_balance(transfer_input.amount, &mint_details);
 
-    /* -------------------------- UPDATE SENDER BALANCE ------------------------- */
+    /* ----------------------------- UPDATE BALANCES ---------------------------- */
+
+    storage::write(receiver_account, &receiver_token_balance)?;
+
+    storage::write(sender_account, &sender_token_balance)?;
+
+    events::emit(&TransferredLog {
+        from: *sender_account.key,
+        to: *receiver_account.key,
+        amount: transfer_input.amount,
+        block_height: get_bitcoin_block_height(),
+    });
+
+    Ok(())
+}
+
+/// Moves tokens from the signing sender to every recipient in the mint's
+/// balance map. The whole batch is validated against the sender's balance
+/// before anything is written, so it is all-or-nothing. Each recipient gets
+/// its amount less the mint's transfer fee, which goes to the fee collector;
+/// see `TokenMintDetails::transfer_fee` for how it rounds.
+pub fn batch_transfer_tokens(
+    token_account: &AccountInfo<'_>,
+    sender_account: &AccountInfo<'_>,
+    recipients: &[(Pubkey, u64)],
+) -> Result<(), ProgramError> {
+    if !sender_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    ensure_distinct_accounts(&[token_account.key, sender_account.key])?;
+
+    if recipients.is_empty() || recipients.len() > MAX_BATCH_TRANSFER_RECIPIENTS {
+        return Err(FungibleTokenError::BatchTooLarge.into());
+    }
+
+    let mut seen = HashSet::with_capacity(recipients.len());
+    let mut total: u64 = 0;
+    for (recipient, amount) in recipients {
+        if *amount == 0 {
+            return Err(FungibleTokenError::ZeroAmount.into());
+        }
+        if !seen.insert(recipient) {
+            return Err(FungibleTokenError::DuplicateRecipient.into());
+        }
+        total = total
+            .checked_add(*amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+    }
+
+    let mut token = storage::read::<TokenMintDetails>(token_account)?;
+
+    let sender_balance = token.balances.get(sender_account.key).copied().unwrap_or(0);
+    if sender_balance < total {
+        return Err(FungibleTokenError::InsufficientBalance.into());
+    }
+
+    token
+        .balances
+        .insert(*sender_account.key, sender_balance - total);
+    let mut fees: u64 = 0;
+    for (recipient, amount) in recipients {
+        let fee = token.transfer_fee(*amount);
+        fees += fee;
+        let balance = token.balances.entry(*recipient).or_insert(0);
+        *balance = balance
+            .checked_add(amount - fee)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+    }
+    if fees > 0 {
+        let collected = token.balances.entry(token.fee_collector).or_insert(0);
+        *collected = collected
+            .checked_add(fees)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+    }
 
-    let new_serialized_sender_balance = borsh::to_vec(&sender_token_balance).unwrap();
+    storage::write(token_account, &token)?;
 
-    if new_serialized_sender_balance.len() > sender_token_balance_data.len() {
-        sender_account.realloc(new_serialized_sender_balance.len(), true)?;
+    let height = get_bitcoin_block_height();
+    for (recipient, amount) in recipients {
+        events::emit(&TransferredLog {
+            from: *sender_account.key,
+            to: *recipient,
+            amount: *amount,
+            block_height: height,
+        });
     }
 
-    /* ------------------------- UPDATE RECEIVER BALANCE ------------------------ */
+    Ok(())
+}
 
-    let new_serialized_receiver_balance = borsh::to_vec(&receiver_token_balance).unwrap();
+/// Moves `amount` between two entries of the mint's balance map. Callers are
+/// responsible for authorising the debit. These are the program's own moves,
+/// stakes into escrow and payouts out of it, so no transfer fee is taken.
+pub(crate) fn move_balance(
+    token_account: &AccountInfo<'_>,
+    from: &Pubkey,
+    to: &Pubkey,
+    amount: u64,
+) -> Result<(), ProgramError> {
+    let mut token = storage::read::<TokenMintDetails>(token_account)?;
 
-    if new_serialized_receiver_balance.len() > receiver_token_balance_data.len() {
-        receiver_account.realloc(new_serialized_receiver_balance.len(), true)?;
+    let from_balance = token.balances.get(from).copied().unwrap_or(0);
+    if from_balance < amount {
+        return Err(FungibleTokenError::InsufficientBalance.into());
     }
 
-    receiver_token_balance_data.copy_from_slice(&new_serialized_receiver_balance);
+    token.balances.insert(*from, from_balance - amount);
+    let balance = token.balances.entry(*to).or_insert(0);
+    *balance = balance
+        .checked_add(amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
 
-    sender_token_balance_data.copy_from_slice(&new_serialized_sender_balance);
+    storage::write(token_account, &token)?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod batch_transfer_tests {
+    use super::*;
+    use crate::test_utils::{balance_of, token_account, TestAccount};
+
+    #[test]
+    fn test_batch_transfer_moves_tokens_to_every_recipient() {
+        let program_id = Pubkey::new_unique();
+        let mut sender = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = token_account(&program_id, &[(sender.key(), 1_000)]);
+        let (alice, bob) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        batch_transfer_tokens(
+            &token_account.info(),
+            &sender.info(),
+            &[(alice, 300), (bob, 450)],
+        )
+        .unwrap();
+
+        assert_eq!(balance_of(&token_account, &sender.key()), 250);
+        assert_eq!(balance_of(&token_account, &alice), 300);
+        assert_eq!(balance_of(&token_account, &bob), 450);
+    }
+
+    #[test]
+    fn test_batch_transfer_insufficient_total_changes_nothing() {
+        let program_id = Pubkey::new_unique();
+        let mut sender = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = token_account(&program_id, &[(sender.key(), 500)]);
+        let before = token_account.data().to_vec();
+
+        let result = batch_transfer_tokens(
+            &token_account.info(),
+            &sender.info(),
+            &[(Pubkey::new_unique(), 300), (Pubkey::new_unique(), 201)],
+        );
+
+        assert_eq!(result, Err(FungibleTokenError::InsufficientBalance.into()));
+        assert_eq!(token_account.data(), &before[..]);
+    }
+
+    #[test]
+    fn test_batch_transfer_rejects_zero_amount() {
+        let program_id = Pubkey::new_unique();
+        let mut sender = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = token_account(&program_id, &[(sender.key(), 500)]);
+
+        let result = batch_transfer_tokens(
+            &token_account.info(),
+            &sender.info(),
+            &[(Pubkey::new_unique(), 100), (Pubkey::new_unique(), 0)],
+        );
+
+        assert_eq!(result, Err(FungibleTokenError::ZeroAmount.into()));
+    }
+
+    #[test]
+    fn test_batch_transfer_rejects_duplicate_recipient() {
+        let program_id = Pubkey::new_unique();
+        let mut sender = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = token_account(&program_id, &[(sender.key(), 500)]);
+        let alice = Pubkey::new_unique();
+
+        let result = batch_transfer_tokens(
+            &token_account.info(),
+            &sender.info(),
+            &[(alice, 100), (alice, 100)],
+        );
+
+        assert_eq!(result, Err(FungibleTokenError::DuplicateRecipient.into()));
+    }
+}
+
+#[cfg(test)]
+mod transfer_aliasing_tests {
+    use super::*;
+    use crate::{
+        test_utils::{token_account, TestAccount},
+        types::PredictionMarketError,
+    };
+
+    fn balance_account(
+        program_id: &Pubkey,
+        owner: &Pubkey,
+        mint: &Pubkey,
+        amount: u64,
+    ) -> TestAccount {
+        let mut balance = TokenBalance::new(owner.serialize(), mint.serialize());
+        balance.current_balance = amount;
+        TestAccount::new(program_id, &borsh::to_vec(&balance).unwrap())
+    }
+
+    #[test]
+    fn test_transfer_to_self_is_a_checked_noop() {
+        let program_id = Pubkey::new_unique();
+        let mut owner = TestAccount::new(&program_id, &[]).signer();
+        let mut mint = token_account(&program_id, &[]);
+        let mut sender = balance_account(&program_id, &owner.key(), &mint.key(), 40);
+        let before = sender.data().to_vec();
+        let sender_info = sender.info();
+
+        transfer_tokens(
+            &owner.info(),
+            &mint.info(),
+            &sender_info,
+            &sender_info,
+            &program_id,
+            TransferInput::new(40),
+        )
+        .unwrap();
+        let overdrawn = transfer_tokens(
+            &owner.info(),
+            &mint.info(),
+            &sender_info,
+            &sender_info,
+            &program_id,
+            TransferInput::new(41),
+        );
+
+        assert_eq!(overdrawn, Err(ProgramError::InsufficientFunds));
+        assert_eq!(sender.data(), &before[..]);
+    }
+
+    #[test]
+    fn test_transfer_with_mint_as_receiver_is_rejected() {
+        let program_id = Pubkey::new_unique();
+        let mut owner = TestAccount::new(&program_id, &[]).signer();
+        let mut mint = token_account(&program_id, &[]);
+        let mut sender = balance_account(&program_id, &owner.key(), &mint.key(), 40);
+        let mint_info = mint.info();
+
+        let result = transfer_tokens(
+            &owner.info(),
+            &mint_info,
+            &sender.info(),
+            &mint_info,
+            &program_id,
+            TransferInput::new(10),
+        );
+
+        assert_eq!(result, Err(PredictionMarketError::AccountAliasing.into()));
+    }
+
+    #[test]
+    fn test_batch_transfer_with_sender_as_token_account_is_rejected() {
+        let program_id = Pubkey::new_unique();
+        let mut token_account = token_account(&program_id, &[]);
+        let mut token_info = token_account.info();
+        token_info.is_signer = true;
+
+        let result = batch_transfer_tokens(&token_info, &token_info, &[(Pubkey::new_unique(), 1)]);
+
+        assert_eq!(result, Err(PredictionMarketError::AccountAliasing.into()));
+    }
+}
+
+#[cfg(test)]
+mod transfer_fee_tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::mint::{
+        derive_mint_address, initialize_mint, InitializeMintInput, MintStatus, MAX_TRANSFER_FEE_BPS,
+    };
+    use crate::process_instruction;
+    use crate::test_utils::{balance_of, config_account, TestAccount};
+
+    // `sender` holding 1_000 of a mint charging `transfer_fee_bps` to
+    // `collector`
+    fn fee_mint(
+        program_id: &Pubkey,
+        transfer_fee_bps: u16,
+        collector: Pubkey,
+        sender: &Pubkey,
+    ) -> TestAccount {
+        let mut details = TokenMintDetails::new(
+            InitializeMintInput::new([0; 32], 1_000_000, "BANGO".to_string(), 0)
+                .with_transfer_fee(transfer_fee_bps, collector),
+            MintStatus::Ongoing,
+            HashMap::new(),
+        );
+        details.balances.insert(*sender, 1_000);
+        details.circulating_supply = 1_000;
+        TestAccount::new(program_id, &borsh::to_vec(&details).unwrap())
+    }
+
+    fn send(
+        token_account: &mut TestAccount,
+        sender: &mut TestAccount,
+        recipients: &[(Pubkey, u64)],
+    ) -> Result<(), ProgramError> {
+        let program_id = token_account.owner;
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+        let mut data = vec![16];
+        data.extend(
+            borsh::to_vec(&BatchTransferInput {
+                recipients: recipients.to_vec(),
+            })
+            .unwrap(),
+        );
+        process_instruction(
+            &program_id,
+            &[token_account.info(), sender.info(), config.info()],
+            &data,
+        )
+    }
+
+    #[test]
+    fn test_fee_is_split_off_to_the_collector() {
+        let program_id = Pubkey::new_unique();
+        let collector = Pubkey::new_unique();
+        let mut sender = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = fee_mint(&program_id, 250, collector, &sender.key());
+        let (alice, bob) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        send(&mut token_account, &mut sender, &[(alice, 400)]).unwrap();
+        assert_eq!(balance_of(&token_account, &sender.key()), 600);
+        assert_eq!(balance_of(&token_account, &alice), 390);
+        assert_eq!(balance_of(&token_account, &collector), 10);
+
+        // each recipient pays its own fee; 2.5% of 39 rounds down to nothing
+        send(&mut token_account, &mut sender, &[(alice, 39), (bob, 200)]).unwrap();
+        assert_eq!(balance_of(&token_account, &sender.key()), 361);
+        assert_eq!(balance_of(&token_account, &alice), 429);
+        assert_eq!(balance_of(&token_account, &bob), 195);
+        assert_eq!(balance_of(&token_account, &collector), 15);
+    }
+
+    #[test]
+    fn test_zero_fee_mint_transfers_the_full_amount() {
+        let program_id = Pubkey::new_unique();
+        let collector = Pubkey::new_unique();
+        let mut sender = TestAccount::new(&program_id, &[]).signer();
+        let mut token_account = fee_mint(&program_id, 0, collector, &sender.key());
+        let alice = Pubkey::new_unique();
+
+        send(&mut token_account, &mut sender, &[(alice, 400)]).unwrap();
+
+        assert_eq!(balance_of(&token_account, &sender.key()), 600);
+        assert_eq!(balance_of(&token_account, &alice), 400);
+        let details = TokenMintDetails::try_from_slice(token_account.data()).unwrap();
+        assert_eq!(details.balances.get(&collector), None);
+    }
+
+    #[test]
+    fn test_fee_above_the_cap_is_refused_at_initialization() {
+        let program_id = Pubkey::new_unique();
+        let mut mint =
+            TestAccount::new(&program_id, &[]).with_key(derive_mint_address(&program_id, "BANGO"));
+        let input = |bps| {
+            InitializeMintInput::new([0; 32], 1_000, "BANGO".to_string(), 0)
+                .with_transfer_fee(bps, Pubkey::new_unique())
+        };
+
+        assert_eq!(
+            initialize_mint(&mint.info(), &program_id, input(MAX_TRANSFER_FEE_BPS + 1)),
+            Err(FungibleTokenError::TransferFeeTooHigh.into())
+        );
+        initialize_mint(&mint.info(), &program_id, input(MAX_TRANSFER_FEE_BPS)).unwrap();
+    }
+}
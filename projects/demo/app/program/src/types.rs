@@ -1,11 +1,17 @@
 use borsh::{BorshDeserialize, BorshSerialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 use arch_program::{
+    bitcoin::hashes::{sha256, Hash, HashEngine},
+    msg,
+    program_error::ProgramError,
     pubkey::Pubkey,
     utxo::UtxoMeta,
 };
 
+use crate::math::{self, BPS_DENOMINATOR, MAX_LMSR_B};
+use crate::stats::EventStats;
+
 
 #[derive(Clone, BorshSerialize, BorshDeserialize, Debug)]
 pub struct Outcome {
@@ -14,15 +20,773 @@ pub struct Outcome {
     pub bets: HashMap<Pubkey, Vec<Bet>>,
 }
 
+impl Outcome {
+    /// Sells `amount` of `user`'s position, reducing their BUY bets oldest
+    /// first. Bets sold down to zero are dropped, and so is the user once
+    /// nothing is left. Fails with `InsufficientPosition` past the net stake.
+    pub fn sell_position(
+        &mut self,
+        user: &Pubkey,
+        amount: u64,
+    ) -> Result<(), PredictionMarketError> {
+        if amount > net_stake(self, user) {
+            return Err(PredictionMarketError::InsufficientPosition);
+        }
+
+        let Some(bets) = self.bets.get_mut(user) else {
+            return Ok(());
+        };
+
+        // sells recorded before positions were reduced in place settle first
+        let mut remaining = bets
+            .iter()
+            .filter(|bet| bet.bet_type == BetType::SELL)
+            .fold(amount, |total, bet| total.saturating_add(bet.amount));
+        bets.retain(|bet| bet.bet_type == BetType::BUY);
+
+        for bet in bets.iter_mut() {
+            let sold = bet.amount.min(remaining);
+            bet.amount -= sold;
+            remaining -= sold;
+        }
+        bets.retain(|bet| bet.amount > 0);
+
+        if bets.is_empty() {
+            self.bets.remove(user);
+        }
+        self.total_amount = self.total_amount.saturating_sub(amount);
+
+        Ok(())
+    }
+
+    /// Takes `amount` of `user`'s position off them without selling it back,
+    /// so the outcome total keeps counting it for whoever holds it next.
+    pub fn withdraw_position(
+        &mut self,
+        user: &Pubkey,
+        amount: u64,
+    ) -> Result<(), PredictionMarketError> {
+        self.sell_position(user, amount)?;
+        self.total_amount += amount;
+
+        Ok(())
+    }
+
+    /// Hands `bet.amount` of a position withdrawn earlier to `bet.user`.
+    pub fn deposit_position(&mut self, bet: Bet) {
+        self.bets.entry(bet.user).or_default().push(bet);
+    }
+}
+
+/// Balance key that holds an event's pool inside the mint. Derived from the
+/// event account and the event id, so it can't belong to any signer.
+pub fn escrow_address(event_account: &Pubkey, unique_id: &[u8; 32]) -> Pubkey {
+    let mut engine = sha256::Hash::engine();
+    engine.input(b"escrow");
+    engine.input(&event_account.0);
+    engine.input(unique_id);
+    Pubkey(sha256::Hash::from_engine(engine).to_byte_array())
+}
+
+/// Address of the account that holds event `unique_id`, so a client can find
+/// a market from its id alone. Derived like `escrow_address`, so no signer
+/// can hold it.
+pub fn derive_event_address(program_id: &Pubkey, unique_id: &[u8; 32]) -> Pubkey {
+    let mut engine = sha256::Hash::engine();
+    engine.input(b"event");
+    engine.input(&program_id.0);
+    engine.input(unique_id);
+    Pubkey(sha256::Hash::from_engine(engine).to_byte_array())
+}
+
+/// A user's open position on `outcome`: everything they bought minus
+/// everything they sold.
+pub fn net_stake(outcome: &Outcome, user: &Pubkey) -> u64 {
+    outcome
+        .bets
+        .get(user)
+        .map(|bets| {
+            bets.iter().fold(0u64, |stake, bet| match bet.bet_type {
+                BetType::BUY => stake.saturating_add(bet.amount),
+                BetType::SELL => stake.saturating_sub(bet.amount),
+            })
+        })
+        .unwrap_or(0)
+}
+
+/// Average-cost accounting of one user's position on one outcome, the way a
+/// brokerage reports it.
+#[derive(Clone, Debug, Default, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct CostBasis {
+    // what the shares still held cost; selling part of them releases the
+    // same part of it
+    pub cost_basis_total: u64,
+    // proceeds of sells, cashouts and the claim minus the cost they released
+    pub realized_pnl: i64,
+}
+
+impl CostBasis {
+    pub fn buy(&mut self, cost: u64) -> Result<(), ProgramError> {
+        self.cost_basis_total = self
+            .cost_basis_total
+            .checked_add(cost)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        Ok(())
+    }
+
+    /// Disposes of `amount` of the `held` shares for `proceeds`, realizing
+    /// them against the average cost of what is held.
+    pub fn dispose(&mut self, amount: u64, held: u64, proceeds: u64) -> Result<(), ProgramError> {
+        let released = self.cost_of(amount, held);
+        self.realize(proceeds, released)?;
+        self.cost_basis_total -= released;
+
+        Ok(())
+    }
+
+    /// Takes the cost of `amount` of the `held` shares out of the basis
+    /// without realizing anything, for shares that leave the position
+    /// unsold.
+    pub fn release(&mut self, amount: u64, held: u64) -> u64 {
+        let released = self.cost_of(amount, held);
+        self.cost_basis_total -= released;
+        released
+    }
+
+    /// Realizes `proceeds` of shares that cost `released`, already taken out
+    /// of the basis.
+    pub fn realize(&mut self, proceeds: u64, released: u64) -> Result<(), ProgramError> {
+        let as_signed =
+            |value: u64| i64::try_from(value).map_err(|_| ProgramError::ArithmeticOverflow);
+        self.realized_pnl = as_signed(proceeds)?
+            .checked_sub(as_signed(released)?)
+            .and_then(|pnl| self.realized_pnl.checked_add(pnl))
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+
+        Ok(())
+    }
+
+    // the average cost of `amount` of the `held` shares, rounded down; all of
+    // the basis once nothing would be left
+    fn cost_of(&self, amount: u64, held: u64) -> u64 {
+        if amount >= held {
+            self.cost_basis_total
+        } else {
+            (self.cost_basis_total as u128 * amount as u128 / held as u128) as u64
+        }
+    }
+}
+
+/// Shares a seller has put up for sale at a fixed price, see `offers`.
+#[derive(Clone, Debug, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct PositionOffer {
+    pub seller: Pubkey,
+    pub outcome_id: u8,
+    pub amount: u64,
+    // tokens the buyer pays for all of `amount`
+    pub ask_price: u64,
+    // what the offered shares cost the seller, taken out of their basis
+    pub cost_basis: u64,
+}
+
+/// A utxo staked through `utxo_bets` and where it is refunded to.
+#[derive(Clone, Debug, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct EscrowedUtxo {
+    pub utxo: UtxoMeta,
+    // as declared by the better
+    pub value_sats: u64,
+    pub refund_script_pubkey: Vec<u8>,
+}
+
+/// A utxo-funded bet pending confirmation of its deposit. It counts toward
+/// no total until `utxo_bets::process_confirm_pending_bets` credits it.
+#[derive(Clone, Debug, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct PendingUtxoBet {
+    pub user: Pubkey,
+    pub outcome_id: u8,
+    // in bet units
+    pub amount: u64,
+    pub escrowed: EscrowedUtxo,
+    // bitcoin block height the deposit was made at
+    pub deposit_height: u64,
+}
+
+/// What a bitcoin payout of an event pays, enough to build it again.
+#[derive(Clone, Debug, PartialEq, BorshSerialize, BorshDeserialize)]
+pub enum BitcoinPayoutKind {
+    // the winners and the creator of a resolved event
+    Settlement,
+    // these utxos, each to its refund script
+    Refund(Vec<EscrowedUtxo>),
+}
+
+/// The last payout transaction of an event handed to the runtime, which
+/// `payout::process_bump_settlement_fee` replaces with one paying more fee.
+#[derive(Clone, Debug, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct BitcoinPayout {
+    // as the runtime records it in the event account's utxo once signed
+    pub txid: [u8; 32],
+    // the event account's utxo it spends
+    pub state_utxo: UtxoMeta,
+    pub fee_rate_sats_per_vbyte: u64,
+    pub kind: BitcoinPayoutKind,
+}
+
 #[derive(Clone, BorshSerialize, BorshDeserialize, Debug)]
 pub struct PredictionEvent {
     pub unique_id: [u8; 32],
     pub creator: Pubkey,
+    pub resolver: Option<Pubkey>,
+    // the token account stakes are escrowed and paid out in
+    pub mint: Pubkey,
+    // of that mint, taken at creation; every amount on the event is in its
+    // smallest unit
+    pub decimals: u8,
     pub expiry_timestamp: u32,
+    // blocks after expiry, or after a dispute, the event may stay unresolved
+    // before anyone can cancel it
+    pub resolution_deadline: u32,
+    // blocks after the resolution deadline winners have to claim before what
+    // is left in escrow can be swept to the treasury
+    pub claim_deadline_blocks: u32,
+    // blocks a resolution stays open to dispute, 0 makes it final at once
+    pub dispute_window: u32,
+    // last block height the current resolution can be disputed at, claims
+    // open after it; `None` while unresolved or without a dispute window
+    pub dispute_until: Option<u64>,
+    // last block height a disputed event can be resolved again at, set by
+    // the dispute; `None` until disputed and again once re-resolved
+    pub dispute_deadline: Option<u64>,
+    // blocks before expiry in which buying and selling are frozen, 0 never
+    // freezes
+    pub freeze_blocks_before_expiry: u32,
+    // bond held in escrow for the creator to take back; zeroed once returned
+    // or forfeited, a forfeited bond staying in escrow until swept
+    pub creator_bond: u64,
+    // cut of the losing outcomes' stakes the creator claims once resolved
+    pub creator_fee_bps: u16,
+    // anyone may resolve the event after expiry in favour of the outcome with
+    // the most stake, see `process_auto_resolve`
+    pub auto_resolve: bool,
+    // cap on a single user's net stake across all outcomes, `None` is unlimited
+    pub max_bet_per_user: Option<u64>,
+    // cap on `total_pool_amount`, `None` is unlimited
+    pub max_pool: Option<u64>,
+    // cap on any one outcome's `total_amount`, 0 is unlimited
+    pub max_outcome_exposure: u64,
+    // distinct bettors the event needs to resolve to an outcome, with fewer
+    // it resolves void; 0 is no minimum
+    pub min_participants: u32,
+    pub market_type: MarketType,
+    // free-form tag frontends file the event under, e.g. sports or politics;
+    // the program gives it no meaning
+    pub category: u8,
     pub outcomes: Vec<Outcome>,
+    // name of each outcome by id, empty when the creator gave none
+    pub labels: Vec<String>,
     pub total_pool_amount: u64,
     pub status: EventStatus,
     pub winning_outcome: Option<u8>,
+    // outcomes sharing the win with `winning_outcome`, empty unless the event
+    // was resolved to several
+    pub co_winners: Vec<u8>,
+    pub committee: Option<ResolutionCommittee>,
+    // users who already took their payout or refund
+    pub claimed: BTreeSet<Pubkey>,
+    // LMSR only: what each user paid for their shares minus what selling
+    // returned, the basis of refunds when the event is void
+    pub net_paid: BTreeMap<Pubkey, u64>,
+    // keyed by user and outcome id
+    pub cost_basis: BTreeMap<(Pubkey, u8), CostBasis>,
+    // unsettled parlays with a leg on the event, see `parlay`
+    pub open_parlays: u32,
+    // scalar markets only: each user's stake, positive long and negative short
+    pub scalar_exposure: BTreeMap<Pubkey, i64>,
+    // scalar markets only: the resolved value, clamped to the bounds; `None`
+    // until resolved, and when resolved void
+    pub scalar_value: Option<i64>,
+    // open position offers by offer id, see `offers`
+    pub offers: BTreeMap<u64, PositionOffer>,
+    pub next_offer_id: u64,
+    // utxos staked through `utxo_bets`, each backing one bet, until they are
+    // refunded
+    pub escrowed_utxos: Vec<EscrowedUtxo>,
+    // the part of `total_pool_amount` those utxos back, which holds no
+    // tokens in escrow
+    pub utxo_stake: u64,
+    // keyed by user and outcome id: how much of each position those utxos
+    // back, which can only leave the pool on bitcoin
+    pub utxo_stakes: BTreeMap<(Pubkey, u8), u64>,
+    // utxo-funded bets waiting for their deposits to confirm, in the order
+    // they were made
+    pub pending_utxo_bets: Vec<PendingUtxoBet>,
+    // the last settlement or refund made on bitcoin, `None` before any
+    pub bitcoin_payout: Option<BitcoinPayout>,
+    // bet counts and volume, see `stats`
+    pub stats: EventStats,
+}
+
+impl PredictionEvent {
+    /// The key allowed to resolve the event: its designated resolver, or the
+    /// creator when none was set.
+    pub fn resolution_authority(&self) -> Pubkey {
+        self.resolver.unwrap_or(self.creator)
+    }
+
+    /// Last block height at which the event can still be resolved. Once
+    /// disputed, that is the deadline the dispute set for a new resolution.
+    pub fn resolution_deadline_height(&self) -> u64 {
+        self.dispute_deadline
+            .unwrap_or(self.expiry_timestamp as u64 + self.resolution_deadline as u64)
+    }
+
+    /// Last block height before unclaimed payouts can be swept. A dispute
+    /// window reaching past the resolution deadline pushes it back.
+    pub fn claim_deadline_height(&self) -> u64 {
+        self.resolution_deadline_height()
+            .max(self.dispute_until.unwrap_or(0))
+            + self.claim_deadline_blocks as u64
+    }
+
+    /// Fails with `BettingFrozen` once `height` is within
+    /// `freeze_blocks_before_expiry` of expiry. Closing, resolving and
+    /// claiming are not affected.
+    pub fn ensure_not_frozen(&self, height: u64) -> Result<(), PredictionMarketError> {
+        let frozen_from =
+            (self.expiry_timestamp as u64).saturating_sub(self.freeze_blocks_before_expiry as u64);
+        if self.freeze_blocks_before_expiry > 0 && height >= frozen_from {
+            return Err(PredictionMarketError::BettingFrozen);
+        }
+
+        Ok(())
+    }
+
+    /// How many distinct users have bet on the event.
+    pub fn bettor_count(&self) -> usize {
+        self.outcomes
+            .iter()
+            .flat_map(|outcome| outcome.bets.keys())
+            .chain(self.scalar_exposure.keys())
+            .collect::<BTreeSet<_>>()
+            .len()
+    }
+
+    /// Whether the resolution can still be disputed at `height`.
+    pub fn in_dispute_window(&self, height: u64) -> bool {
+        self.dispute_until.is_some_and(|until| height <= until)
+    }
+
+    /// Moves the event to `Resolved` with `winning_outcome` and opens the
+    /// dispute window. It runs from expiry, or from `height` when the event
+    /// is resolved later than that. A first resolution past the resolution
+    /// deadline forfeits the creator's bond. With fewer than
+    /// `min_participants` bettors the event resolves void instead, whatever
+    /// outcome or value it was resolved to.
+    pub fn resolve(
+        &mut self,
+        winning_outcome: Option<u8>,
+        height: u64,
+    ) -> Result<(), PredictionMarketError> {
+        if self.status != EventStatus::Disputed && height > self.resolution_deadline_height() {
+            self.forfeit_creator_bond();
+        }
+
+        self.transition_to(EventStatus::Resolved)?;
+        self.dispute_deadline = None;
+        self.co_winners.clear();
+        let bettors = self.bettor_count();
+        if bettors < self.min_participants as usize {
+            msg!(
+                "{} bettors of the {} required, resolving void",
+                bettors,
+                self.min_participants
+            );
+            self.winning_outcome = None;
+            self.scalar_value = None;
+        } else {
+            self.winning_outcome = winning_outcome;
+        }
+        self.dispute_until = (self.dispute_window > 0)
+            .then(|| height.max(self.expiry_timestamp as u64) + self.dispute_window as u64);
+        Ok(())
+    }
+
+    /// Everyone holding a position or, in LMSR markets, a payment record,
+    /// and the creator while a creator fee is owed.
+    pub fn participants(&self) -> BTreeSet<Pubkey> {
+        self.outcomes
+            .iter()
+            .flat_map(|outcome| outcome.bets.keys())
+            .chain(self.net_paid.keys())
+            .chain(self.scalar_exposure.keys())
+            .chain((self.creator_fee() > 0).then_some(&self.creator))
+            .copied()
+            .collect()
+    }
+
+    /// Index of the outcome with `outcome_id`. Outcomes are created with ids
+    /// matching their positions, so this is a direct lookup; the search only
+    /// runs should that ever not hold.
+    pub fn outcome_index(&self, outcome_id: u8) -> Option<usize> {
+        let index = outcome_id as usize;
+        match self.outcomes.get(index) {
+            Some(outcome) if outcome.id == outcome_id => Some(index),
+            _ => self
+                .outcomes
+                .iter()
+                .position(|outcome| outcome.id == outcome_id),
+        }
+    }
+
+    /// Ids of every winning outcome, `winning_outcome` first.
+    pub fn winning_ids(&self) -> Vec<u8> {
+        self.winning_outcome
+            .into_iter()
+            .chain(self.co_winners.iter().copied())
+            .collect()
+    }
+
+    /// Whether `outcome_id` wins, alone or with others.
+    pub fn is_winner(&self, outcome_id: u8) -> bool {
+        self.winning_outcome == Some(outcome_id) || self.co_winners.contains(&outcome_id)
+    }
+
+    /// Stake on the winning outcomes together, which share the pool as one.
+    pub fn winning_total(&self) -> u64 {
+        self.outcomes
+            .iter()
+            .filter(|outcome| self.is_winner(outcome.id))
+            .fold(0u64, |total, outcome| {
+                total.saturating_add(outcome.total_amount)
+            })
+    }
+
+    /// The creator's fee out of the pool, 0 until the event has a winner.
+    pub fn creator_fee(&self) -> u64 {
+        if self.winning_outcome.is_none() {
+            return 0;
+        }
+
+        math::creator_fee(
+            self.total_pool_amount,
+            self.winning_total(),
+            self.creator_fee_bps,
+        )
+    }
+
+    /// Whether pending utxo bets can no longer be credited at `height`: the
+    /// event has stopped taking bets or expired, and they are only refunded.
+    pub fn pending_bets_stale(&self, height: u64) -> bool {
+        self.status != EventStatus::Active || height >= self.expiry_timestamp as u64
+    }
+
+    /// What the utxos staked on the event hold in all, in sats.
+    pub fn escrowed_sats(&self) -> u64 {
+        self.escrowed_utxos
+            .iter()
+            .map(|escrowed| escrowed.value_sats)
+            .sum()
+    }
+
+    /// How much of `user`'s position on `outcome_id` is backed by a utxo.
+    pub fn utxo_position(&self, user: &Pubkey, outcome_id: u8) -> u64 {
+        self.utxo_stakes
+            .get(&(*user, outcome_id))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// The part of `user`'s position on `outcome` the escrow holds tokens
+    /// for, all of it that can be sold, cashed out, handed on or claimed in
+    /// tokens.
+    pub fn token_stake(&self, outcome: &Outcome, user: &Pubkey) -> u64 {
+        net_stake(outcome, user).saturating_sub(self.utxo_position(user, outcome.id))
+    }
+
+    /// Fails with `UtxoStakeLocked` if taking `amount` of `user`'s position
+    /// on the outcome at `index` would reach into the part a utxo backs.
+    /// Past the whole position it is left to the position itself to fail.
+    pub fn ensure_token_backed(
+        &self,
+        index: usize,
+        user: &Pubkey,
+        amount: u64,
+    ) -> Result<(), PredictionMarketError> {
+        let outcome = &self.outcomes[index];
+        if amount <= net_stake(outcome, user) && amount > self.token_stake(outcome, user) {
+            return Err(PredictionMarketError::UtxoStakeLocked);
+        }
+
+        Ok(())
+    }
+
+    /// Takes every position a utxo backs out of the pool, once its utxo has
+    /// been refunded on bitcoin.
+    pub fn release_utxo_stakes(&mut self) -> Result<(), PredictionMarketError> {
+        for ((user, outcome_id), amount) in std::mem::take(&mut self.utxo_stakes) {
+            let index = self
+                .outcome_index(outcome_id)
+                .ok_or(PredictionMarketError::InvalidOutcome)?;
+            self.outcomes[index].sell_position(&user, amount)?;
+            self.total_pool_amount = self.total_pool_amount.saturating_sub(amount);
+        }
+        self.utxo_stake = 0;
+
+        Ok(())
+    }
+
+    pub fn ensure_can_transition(&self, to: EventStatus) -> Result<(), PredictionMarketError> {
+        if can_transition(self.status, to) {
+            Ok(())
+        } else if self.status == EventStatus::Resolved {
+            Err(PredictionMarketError::EventAlreadyResolved)
+        } else {
+            Err(PredictionMarketError::InvalidStatusTransition)
+        }
+    }
+
+    /// Fails with `MintMismatch` unless `token_account` is the mint the event
+    /// was created with.
+    pub fn ensure_mint(&self, token_account: &Pubkey) -> Result<(), PredictionMarketError> {
+        if self.mint != *token_account {
+            return Err(PredictionMarketError::MintMismatch);
+        }
+
+        Ok(())
+    }
+
+    /// Fails with `BetLimitExceeded` if buying `amount` more would take
+    /// `user`'s net stake in the event past `max_bet_per_user`.
+    pub fn ensure_within_bet_limit(
+        &self,
+        user: &Pubkey,
+        amount: u64,
+    ) -> Result<(), PredictionMarketError> {
+        let Some(limit) = self.max_bet_per_user else {
+            return Ok(());
+        };
+
+        if self.user_stake(user).saturating_add(amount) > limit {
+            return Err(PredictionMarketError::BetLimitExceeded);
+        }
+
+        Ok(())
+    }
+
+    /// `user`'s net stake in the event, across every outcome or either side
+    /// of a scalar market.
+    pub fn user_stake(&self, user: &Pubkey) -> u64 {
+        let stake = self.outcomes.iter().fold(0u64, |stake, outcome| {
+            stake.saturating_add(net_stake(outcome, user))
+        });
+        stake.saturating_add(self.scalar_stake(user))
+    }
+
+    /// What `user` staked on either side of a scalar market.
+    pub fn scalar_stake(&self, user: &Pubkey) -> u64 {
+        self.scalar_exposure
+            .get(user)
+            .map_or(0, |exposure| exposure.unsigned_abs())
+    }
+
+    /// Total stake on the long and the short side of a scalar market.
+    pub fn scalar_totals(&self) -> (u64, u64) {
+        self.scalar_exposure
+            .values()
+            .fold((0u64, 0u64), |(long, short), exposure| {
+                if *exposure > 0 {
+                    (long.saturating_add(exposure.unsigned_abs()), short)
+                } else {
+                    (long, short.saturating_add(exposure.unsigned_abs()))
+                }
+            })
+    }
+
+    /// Each outcome's `total_amount`, in outcome order: the LMSR's quantities.
+    pub fn outcome_quantities(&self) -> Vec<u64> {
+        self.outcomes
+            .iter()
+            .map(|outcome| outcome.total_amount)
+            .collect()
+    }
+
+    /// Fails with `PoolCapExceeded` if `amount` more would take the pool past
+    /// `max_pool`. Bets are never partially filled: one that doesn't fit is
+    /// rejected whole, and the better can retry with what is left.
+    pub fn ensure_within_pool_cap(&self, amount: u64) -> Result<(), PredictionMarketError> {
+        match self.max_pool {
+            Some(cap) if self.total_pool_amount.saturating_add(amount) > cap => {
+                Err(PredictionMarketError::PoolCapExceeded)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails with `ExposureCapReached` if `amount` more would take the outcome
+    /// at `index` past `max_outcome_exposure`, logging the headroom it has
+    /// left.
+    pub fn ensure_within_outcome_exposure(
+        &self,
+        index: usize,
+        amount: u64,
+    ) -> Result<(), ProgramError> {
+        if self.max_outcome_exposure == 0 {
+            return Ok(());
+        }
+
+        let outcome = &self.outcomes[index];
+        let total = outcome
+            .total_amount
+            .checked_add(amount)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        if total > self.max_outcome_exposure {
+            msg!(
+                "Outcome {} can take {} more before its exposure cap",
+                outcome.id,
+                self.max_outcome_exposure
+                    .saturating_sub(outcome.total_amount)
+            );
+            return Err(PredictionMarketError::ExposureCapReached.into());
+        }
+
+        Ok(())
+    }
+
+    /// Fails with `ParlaysOutstanding` while parlays with a leg on the event
+    /// are unsettled, since settling them still moves its pool.
+    pub fn ensure_no_open_parlays(&self) -> Result<(), PredictionMarketError> {
+        if self.open_parlays > 0 {
+            return Err(PredictionMarketError::ParlaysOutstanding);
+        }
+
+        Ok(())
+    }
+
+    /// Realizes every position `user` holds against `proceeds`, what their
+    /// claim paid for them. A winner's proceeds are all the winning
+    /// outcomes', split by what they hold on each when there are several; a
+    /// void event's refund is split by cost basis, since that is what it
+    /// returns.
+    pub fn realize_claim(&mut self, user: &Pubkey, proceeds: u64) -> Result<(), ProgramError> {
+        let positions: Vec<(usize, u64)> = self
+            .outcomes
+            .iter()
+            .enumerate()
+            .map(|(index, outcome)| (index, net_stake(outcome, user)))
+            .collect();
+        let total_basis = self
+            .cost_basis
+            .range((*user, 0)..=(*user, u8::MAX))
+            .fold(0u128, |total, (_, basis)| {
+                total + basis.cost_basis_total as u128
+            });
+        let winners = self.winning_ids();
+        let mut winning_held = positions
+            .iter()
+            .filter(|(index, _)| winners.contains(&self.outcomes[*index].id))
+            .fold(0u64, |total, (_, held)| total.saturating_add(*held));
+        let mut unassigned = proceeds;
+
+        for (index, held) in positions {
+            let outcome_id = self.outcomes[index].id;
+            let Some(basis) = self.cost_basis.get_mut(&(*user, outcome_id)) else {
+                continue;
+            };
+
+            let share = match self.winning_outcome {
+                // the last winning position takes what rounding left
+                Some(_) if winners.contains(&outcome_id) => {
+                    let share = math::mul_div(unassigned, held, winning_held).unwrap_or(unassigned);
+                    winning_held -= held;
+                    share
+                }
+                Some(_) => 0,
+                None => (proceeds as u128 * basis.cost_basis_total as u128)
+                    .checked_div(total_basis)
+                    .unwrap_or(0) as u64,
+            }
+            .min(unassigned);
+            unassigned -= share;
+
+            basis.dispose(held, held, share)?;
+        }
+
+        Ok(())
+    }
+
+    /// Drops the creator's claim on their bond. The tokens stay in escrow,
+    /// so sweeping the event hands them to the treasury.
+    pub fn forfeit_creator_bond(&mut self) {
+        self.creator_bond = 0;
+    }
+
+    /// Moves the event to `to`, refusing anything `can_transition` forbids.
+    pub fn transition_to(&mut self, to: EventStatus) -> Result<(), PredictionMarketError> {
+        self.ensure_can_transition(to)?;
+        self.status = to;
+        Ok(())
+    }
+}
+
+pub const MAX_COMMITTEE_MEMBERS: usize = 8;
+
+#[derive(Clone, BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub struct CommitteeVote {
+    pub member: Pubkey,
+    pub outcome_id: u8,
+}
+
+/// N-of-M resolution: the event resolves once `threshold` members have voted
+/// for the same outcome. Each member holds at most one vote.
+#[derive(Clone, BorshSerialize, BorshDeserialize, Debug)]
+pub struct ResolutionCommittee {
+    pub members: Vec<Pubkey>,
+    pub threshold: u8,
+    pub votes: Vec<CommitteeVote>,
+}
+
+impl ResolutionCommittee {
+    pub fn new(params: CommitteeParams) -> Result<Self, PredictionMarketError> {
+        let mut unique_members = params.members.clone();
+        unique_members.sort();
+        unique_members.dedup();
+
+        if params.members.is_empty()
+            || params.members.len() > MAX_COMMITTEE_MEMBERS
+            || unique_members.len() != params.members.len()
+            || params.threshold == 0
+            || params.threshold as usize > params.members.len()
+        {
+            return Err(PredictionMarketError::InvalidCommittee);
+        }
+
+        Ok(ResolutionCommittee {
+            members: params.members,
+            threshold: params.threshold,
+            votes: Vec::new(),
+        })
+    }
+
+    pub fn is_member(&self, key: &Pubkey) -> bool {
+        self.members.contains(key)
+    }
+
+    /// Records `member`'s vote, replacing any earlier one, and returns the
+    /// outcome once it has reached the threshold.
+    pub fn record_vote(&mut self, member: Pubkey, outcome_id: u8) -> Option<u8> {
+        match self.votes.iter_mut().find(|v| v.member == member) {
+            Some(vote) => vote.outcome_id = outcome_id,
+            None => self.votes.push(CommitteeVote { member, outcome_id }),
+        }
+
+        let matching = self
+            .votes
+            .iter()
+            .filter(|v| v.outcome_id == outcome_id)
+            .count();
+
+        (matching >= self.threshold as usize).then_some(outcome_id)
+    }
 }
 
 #[derive(Clone, BorshSerialize, BorshDeserialize, Debug)]
@@ -37,6 +801,8 @@ pub struct Bet {
 
 #[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
 pub struct Predictions {
+    // events ever created in the account; closing, cancelling or pruning one
+    // doesn't lower it
     pub total_predictions: u32,
     pub predictions: Vec<PredictionEvent>,
 }
@@ -45,7 +811,64 @@ pub struct Predictions {
 pub struct PredictionEventParams {
     pub unique_id: [u8; 32],
     pub expiry_timestamp: u32,
+    pub resolution_deadline: u32,
     pub num_outcomes: u8,
+    // one per outcome or none at all, each at most `MAX_LABEL_LEN` bytes
+    pub labels: Vec<String>,
+    pub resolver: Option<Pubkey>,
+    pub committee: Option<CommitteeParams>,
+    pub mint: Pubkey,
+    pub max_bet_per_user: Option<u64>,
+    pub max_pool: Option<u64>,
+    pub max_outcome_exposure: u64,
+    pub min_participants: u32,
+    pub market_type: MarketType,
+    pub claim_deadline_blocks: u32,
+    pub dispute_window: u32,
+    pub freeze_blocks_before_expiry: u32,
+    // at most `MAX_CREATOR_FEE_BPS`
+    pub creator_fee_bps: u16,
+    pub auto_resolve: bool,
+    pub category: u8,
+}
+
+pub const MAX_LABEL_LEN: usize = 32;
+
+/// Outcome labels are optional, but when given there is exactly one per
+/// outcome, none empty and none longer than `MAX_LABEL_LEN` bytes. A scalar
+/// event has no outcomes and so takes no labels.
+pub fn validate_labels(labels: &[String], num_outcomes: u8) -> Result<(), PredictionMarketError> {
+    if labels.is_empty() {
+        return Ok(());
+    }
+
+    if labels.len() != num_outcomes as usize
+        || labels
+            .iter()
+            .any(|label| label.is_empty() || label.len() > MAX_LABEL_LEN)
+    {
+        return Err(PredictionMarketError::InvalidOutcomeLabels);
+    }
+
+    Ok(())
+}
+
+/// Fails with `InvalidOutcome` if two outcomes share an id, which would leave
+/// the second unreachable to every lookup by id. Creation numbers outcomes in
+/// order, but any path that takes outcomes from the caller must check this.
+pub fn validate_outcome_ids(outcomes: &[Outcome]) -> Result<(), PredictionMarketError> {
+    let mut seen = BTreeSet::new();
+    if !outcomes.iter().all(|outcome| seen.insert(outcome.id)) {
+        return Err(PredictionMarketError::InvalidOutcome);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct CommitteeParams {
+    pub members: Vec<Pubkey>,
+    pub threshold: u8,
 }
 
 #[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
@@ -53,27 +876,361 @@ pub struct ClosePredictionEventParams {
     pub unique_id: [u8; 32],
 }
 
+pub const MAX_CRANK_EVENTS: usize = 32;
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct CrankCloseExpiredParams {
+    pub unique_ids: Vec<[u8; 32]>,
+}
+
 #[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct SweepExpiredParams {
+    // index into `Predictions::predictions` to start scanning from
+    pub offset: u32,
+    pub limit: u8,
+}
+
+/// Instruction data of buys and sells, after the function number.
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
 pub struct BetOnPredictionEventParams {
-    pub unused_uid: [u8; 32],
     pub unique_id: [u8; 32],
     pub outcome_id: u8,
-    pub amount: u64
+    pub amount: u64,
+    // buys only, see `SlippageLimits`
+    pub min_expected_payout_bps: u64,
+    pub max_cost: u64,
+    // buys only, credited with the buy's volume and a share of its protocol
+    // fee; its stats account follows the config account
+    pub referrer: Option<Pubkey>,
+}
+
+/// Bounds a buy to the price the better signed for. `min_expected_payout_bps`
+/// is what the bet pays if it wins, after the bet, in basis points of its
+/// cost; `max_cost` is the most it may debit, protocol fee included. Zero
+/// disables either bound.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SlippageLimits {
+    pub min_expected_payout_bps: u64,
+    pub max_cost: u64,
+}
+
+impl SlippageLimits {
+    pub fn check(&self, cost: u64, expected_payout: u64) -> Result<(), PredictionMarketError> {
+        if self.max_cost != 0 && cost > self.max_cost {
+            return Err(PredictionMarketError::SlippageExceeded);
+        }
+
+        if self.min_expected_payout_bps != 0 && cost != 0 {
+            let payout_bps = expected_payout as u128 * BPS_DENOMINATOR as u128 / cost as u128;
+            if payout_bps < self.min_expected_payout_bps as u128 {
+                return Err(PredictionMarketError::SlippageExceeded);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct ResolveEventParams {
+    pub unique_id: [u8; 32],
+    pub winning_outcome: u8,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct ResolveMultipleParams {
+    pub unique_id: [u8; 32],
+    pub winning_outcomes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct VoteResolutionParams {
+    pub unique_id: [u8; 32],
+    pub winning_outcome: u8,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct ChangeResolverParams {
+    pub unique_id: [u8; 32],
+    pub resolver: Option<Pubkey>,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct TransferEventOwnershipParams {
+    pub unique_id: [u8; 32],
+    pub new_creator: Pubkey,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct TransferPositionParams {
+    pub unique_id: [u8; 32],
+    pub outcome_id: u8,
+    pub amount: u64,
+    pub recipient: Pubkey,
 }
 
 #[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct ClaimWinningsParams {
+    pub unique_id: [u8; 32],
+    pub settle_on_bitcoin: bool,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct UtxoBackedBetParams {
+    pub unique_id: [u8; 32],
+    pub outcome_id: u8,
+    pub amount: u64,
+    pub utxo: UtxoMeta,
+    pub min_expected_payout_bps: u64,
+    pub max_cost: u64,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct UtxoBetParams {
+    pub unique_id: [u8; 32],
+    pub outcome_id: u8,
+    pub utxo: UtxoMeta,
+    // what the utxo holds, as declared by the better
+    pub value_sats: u64,
+    // where the utxo's value goes back to if the event is cancelled
+    pub refund_script_pubkey: Vec<u8>,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct SettleOnBitcoinParams {
+    pub unique_id: [u8; 32],
+    pub fee_rate_sats_per_vbyte: u64,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct RefundOnBitcoinParams {
+    pub unique_id: [u8; 32],
+    pub fee_rate_sats_per_vbyte: u64,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct BumpSettlementFeeParams {
+    pub unique_id: [u8; 32],
+    pub new_fee_rate_sats_per_vbyte: u64,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct ConfirmPendingBetsParams {
+    pub unique_id: [u8; 32],
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct QueryEventParams {
+    pub unique_id: [u8; 32],
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct QueryUserPositionParams {
+    pub unique_id: [u8; 32],
+    pub user: Pubkey,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct QueryOddsParams {
+    pub unique_id: [u8; 32],
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct QueryEventStatsParams {
+    pub unique_id: [u8; 32],
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct UserBetHistoryParams {
+    pub user: Pubkey,
+    // how many of the user's bets earlier pages already returned
+    pub offset: u32,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct ListEventsByStatusParams {
+    pub status: EventStatus,
+    // how many matching events earlier pages already returned
+    pub offset: u32,
+    pub limit: u32,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct QueryRegistryParams {
+    // how many matching entries earlier pages already returned
+    pub offset: u32,
+    pub limit: u8,
+    // only entries in this status, all of them if `None`
+    pub status_filter: Option<EventStatus>,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct QueryEventsByCreatorParams {
+    pub creator: Pubkey,
+    // how many of the creator's events earlier pages already returned
+    pub offset: u32,
+    pub limit: u8,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct PreviewPayoutParams {
+    pub unique_id: [u8; 32],
+    pub outcome_id: u8,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct ScalarPositionParams {
+    pub unique_id: [u8; 32],
+    pub side: ScalarSide,
+    pub amount: u64,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct ResolveScalarParams {
+    pub unique_id: [u8; 32],
+    // clamped to the market's bounds
+    pub value: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct ParlayLeg {
+    pub unique_id: [u8; 32],
+    pub outcome_id: u8,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct PlaceParlayParams {
+    pub legs: Vec<ParlayLeg>,
+    pub amount: u64,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct PlaceLimitOrderParams {
+    pub unique_id: [u8; 32],
+    pub outcome_id: u8,
+    pub side: OrderSide,
+    pub price_bps: u16,
+    pub amount: u64,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct CancelLimitOrderParams {
+    pub order_id: u64,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct OfferPositionParams {
+    pub unique_id: [u8; 32],
+    pub outcome_id: u8,
+    pub amount: u64,
+    pub ask_price: u64,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct AcceptOfferParams {
+    pub unique_id: [u8; 32],
+    pub offer_id: u64,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct CancelOfferParams {
+    pub unique_id: [u8; 32],
+    pub offer_id: u64,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct CashoutPositionParams {
+    pub unique_id: [u8; 32],
+    pub outcome_id: u8,
+    pub amount: u64,
+}
+
+/// Instruction data of mints and burns, after the function number.
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
 pub struct MintTokenParams {
-    pub uid: [u8; 32],
-    pub amount: u64
+    pub amount: u64,
+    // idempotency key: a mint or burn whose nonce was already processed is
+    // rejected, so a resubmitted transaction can't apply twice
+    pub nonce: Option<[u8; 32]>,
 }
 
 
-#[derive(Clone, BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+/// How bets are priced. In a `ParimutuelPool` a bet's amount is its stake
+/// and winners split the whole pool, less any creator fee. In an `Lmsr`
+/// market a bet's amount is a number of shares, priced by the logarithmic
+/// market scoring rule with liquidity `b`; each winning share pays one token,
+/// scaled down pro rata if the pool can't cover them all. A void LMSR event refunds what each user
+/// paid net of sells, pro rata if sells took out more than they paid in.
+/// A `Scalar` market has no outcomes: users stake LONG or SHORT on a value
+/// between the bounds, and the pool is split between the two sides by where
+/// the resolved value falls, see `math::scalar_payout`. A void scalar event
+/// refunds the stakes.
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, Debug, PartialEq)]
+pub enum MarketType {
+    ParimutuelPool,
+    Lmsr { b: u64 },
+    Scalar { lower_bound: i64, upper_bound: i64 },
+}
+
+impl MarketType {
+    pub fn validate(&self) -> Result<(), PredictionMarketError> {
+        match *self {
+            MarketType::Lmsr { b } if b == 0 || b > MAX_LMSR_B => {
+                Err(PredictionMarketError::InvalidMarketType)
+            }
+            MarketType::Scalar {
+                lower_bound,
+                upper_bound,
+            } if lower_bound >= upper_bound => Err(PredictionMarketError::InvalidMarketType),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Which way a scalar position bets: `Long` gains as the value rises toward
+/// the upper bound, `Short` as it falls toward the lower one.
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, Debug, PartialEq, Eq)]
+pub enum ScalarSide {
+    Long,
+    Short,
+}
+
+/// Event lifecycle. Events start out `Created` and take bets once the creator
+/// activates them; `Closed` stops betting but still allows resolution or
+/// cancellation; `Resolved` and `Cancelled` are final.
+///
+/// `Created` is declared last so the encoding of existing statuses is kept.
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, Debug, PartialEq)]
 pub enum EventStatus {
     Active,
     Closed,
     Resolved,
     Cancelled,
+    Created,
+    // resolved or cancelled, and the unclaimed rest swept to the treasury
+    Settled,
+    // resolution challenged within its dispute window, awaiting a new one
+    Disputed,
+}
+
+pub fn can_transition(from: EventStatus, to: EventStatus) -> bool {
+    use EventStatus::*;
+
+    matches!(
+        (from, to),
+        (Created, Active)
+            | (Created, Cancelled)
+            | (Active, Closed)
+            | (Active, Resolved)
+            | (Active, Cancelled)
+            | (Closed, Resolved)
+            | (Closed, Cancelled)
+            | (Resolved, Settled)
+            | (Cancelled, Settled)
+            | (Resolved, Disputed)
+            | (Disputed, Resolved)
+            | (Disputed, Cancelled)
+    )
 }
 
 #[derive(Clone, BorshSerialize, BorshDeserialize, Debug, PartialEq)]
@@ -82,6 +1239,15 @@ pub enum BetType {
     BUY
 }
 
+/// Which side of the book a limit order rests on: a `Buy` bids for shares of
+/// an outcome, a `Sell` offers shares the owner holds.
+#[derive(Clone, Copy, BorshSerialize, BorshDeserialize, Debug, PartialEq, Eq)]
+pub enum OrderSide {
+    Buy,
+    Sell,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum PredictionMarketError {
     InvalidInstruction,
     InsufficientFunds,
@@ -90,4 +1256,319 @@ pub enum PredictionMarketError {
     InvalidOutcome,
     EventNotResolved,
     EventAlreadyResolved,
+    UtxoOwnershipNotProven,
+    NoWinningPosition,
+    ProgramPaused,
+    CreatorNotAllowed,
+    EventExpired,
+    InvalidCommittee,
+    NotCommitteeMember,
+    CommitteeVoteRequired,
+    InvalidStatusTransition,
+    EventNotActive,
+    ResolutionDeadlineNotReached,
+    InsufficientPosition,
+    AlreadyClaimed,
+    EscrowMismatch,
+    InvalidAccountCount,
+    AccountAliasing,
+    MintMismatch,
+    BetLimitExceeded,
+    PoolCapExceeded,
+    InvalidMarketType,
+    EventFinalized,
+    SlippageExceeded,
+    ClaimsOutstanding,
+    ClaimWindowClosed,
+    ClaimDeadlineNotReached,
+    DisputeWindowOpen,
+    DisputeWindowClosed,
+    InsufficientBond,
+    BondNotReturnable,
+    CreatorFeeTooHigh,
+    AccountTooLarge,
+    ProtocolFeeTooHigh,
+    NoReferralRewards,
+    AutoResolveNotEnabled,
+    EventNotExpired,
+    OrderNotFound,
+    OrderBookMismatch,
+    EscrowNotEmpty,
+    InvalidParlay,
+    DuplicateParlayLeg,
+    ParlaysOutstanding,
+    OppositeScalarPosition,
+    /// The account holds fewer bytes than a read or write at an offset in
+    /// it needs; no resize was attempted, unlike `AccountTooSmall`.
+    AccountDataTooSmall,
+    SelfTransfer,
+    OfferNotFound,
+    EventAddressMismatch,
+    BettingFrozen,
+    ExposureCapReached,
+    InvalidOutcomeLabels,
+    UserLimitsAddressMismatch,
+    SelfExcluded,
+    UserStakeLimitReached,
+    MintNotAllowed,
+    UtxoAlreadyEscrowed,
+    UtxoBelowDust,
+    NotUtxoFunded,
+    FeeRateOutOfRange,
+    InvalidRefundScript,
+    NoPayoutToBump,
+    InvalidBetAmount,
+    InvalidLockTime,
+    RegistryAddressMismatch,
+    TooManyActiveEvents,
+    RegistryRequired,
+    ArchiveAddressMismatch,
+    EventCapacityReached,
+    /// The runtime refused to grow the account to fit what is being stored,
+    /// see `storage::realloc`; unlike `AccountDataTooSmall`, nothing was
+    /// read or written short.
+    AccountTooSmall,
+    UtxoStakeLocked,
+}
+
+impl From<PredictionMarketError> for ProgramError {
+    fn from(e: PredictionMarketError) -> Self {
+        ProgramError::Custom(e as u32)
+    }
+}
+
+#[cfg(test)]
+mod status_transition_tests {
+    use super::*;
+    use EventStatus::*;
+
+    const ALL: [EventStatus; 7] = [
+        Created, Active, Closed, Resolved, Cancelled, Settled, Disputed,
+    ];
+
+    #[test]
+    fn test_transition_table() {
+        let legal = [
+            (Created, Active),
+            (Created, Cancelled),
+            (Active, Closed),
+            (Active, Resolved),
+            (Active, Cancelled),
+            (Closed, Resolved),
+            (Closed, Cancelled),
+            (Resolved, Settled),
+            (Cancelled, Settled),
+            (Resolved, Disputed),
+            (Disputed, Resolved),
+            (Disputed, Cancelled),
+        ];
+
+        for from in ALL {
+            for to in ALL {
+                assert_eq!(
+                    can_transition(from, to),
+                    legal.contains(&(from, to)),
+                    "{:?} -> {:?}",
+                    from,
+                    to
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod cost_basis_tests {
+    use super::*;
+
+    fn basis(cost_basis_total: u64, realized_pnl: i64) -> CostBasis {
+        CostBasis {
+            cost_basis_total,
+            realized_pnl,
+        }
+    }
+
+    #[test]
+    fn test_sells_realize_against_the_average_cost() {
+        let mut position = CostBasis::default();
+
+        // 10 at 10 and 10 at 30 average out at 20 a share
+        position.buy(100).unwrap();
+        position.buy(300).unwrap();
+        assert_eq!(position, basis(400, 0));
+
+        // 5 sold at 30 gain 10 each
+        position.dispose(5, 20, 150).unwrap();
+        assert_eq!(position, basis(300, 50));
+
+        // 5 more at 10 bring the average of the 20 held down to 17.5
+        position.buy(50).unwrap();
+        assert_eq!(position, basis(350, 50));
+
+        // all 20 sold at 15 lose 2.5 each
+        position.dispose(20, 20, 300).unwrap();
+        assert_eq!(position, basis(0, 0));
+    }
+
+    #[test]
+    fn test_partial_sells_round_the_released_cost_down() {
+        let mut position = CostBasis::default();
+        position.buy(10).unwrap();
+
+        position.dispose(1, 3, 0).unwrap();
+        assert_eq!(position, basis(7, -3));
+
+        // the last sell releases whatever is left
+        position.dispose(2, 2, 7).unwrap();
+        assert_eq!(position, basis(0, -3));
+    }
+
+    #[test]
+    fn test_overflow_is_an_error() {
+        let mut position = basis(u64::MAX, 0);
+        assert_eq!(position.buy(1), Err(ProgramError::ArithmeticOverflow));
+
+        // a u64 basis does not fit the signed pnl
+        assert_eq!(
+            position.dispose(1, 1, 0),
+            Err(ProgramError::ArithmeticOverflow)
+        );
+
+        let mut position = basis(0, i64::MAX);
+        assert_eq!(
+            position.dispose(1, 1, 1),
+            Err(ProgramError::ArithmeticOverflow)
+        );
+        assert_eq!(position, basis(0, i64::MAX));
+    }
+}
+
+#[cfg(test)]
+mod outcome_index_tests {
+    use super::*;
+    use crate::test_utils::{event_params, Market};
+
+    fn searched(event: &PredictionEvent, outcome_id: u8) -> Option<usize> {
+        event
+            .outcomes
+            .iter()
+            .position(|outcome| outcome.id == outcome_id)
+    }
+
+    #[test]
+    fn test_indexed_lookup_matches_the_search() {
+        let program_id = Pubkey::new_unique();
+        let market = Market::with_params(&program_id, event_params([79; 32], 100, 8), &[]);
+        let mut event = market.event_state();
+        for outcome_id in 0..=9 {
+            assert_eq!(
+                event.outcome_index(outcome_id),
+                searched(&event, outcome_id)
+            );
+        }
+        assert_eq!(event.outcome_index(7), Some(7));
+        assert_eq!(event.outcome_index(8), None);
+
+        // ids out of step with positions still resolve, by searching
+        event.outcomes.swap(2, 5);
+        for outcome_id in 0..=9 {
+            assert_eq!(
+                event.outcome_index(outcome_id),
+                searched(&event, outcome_id)
+            );
+        }
+        assert_eq!(event.outcome_index(2), Some(5));
+    }
+}
+
+#[cfg(test)]
+mod outcome_id_tests {
+    use super::*;
+
+    fn outcomes(ids: &[u8]) -> Vec<Outcome> {
+        ids.iter()
+            .map(|id| Outcome {
+                id: *id,
+                total_amount: 0,
+                bets: HashMap::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_duplicate_outcome_ids_are_rejected() {
+        assert_eq!(
+            validate_outcome_ids(&outcomes(&[0, 1, 0])),
+            Err(PredictionMarketError::InvalidOutcome)
+        );
+        assert_eq!(
+            validate_outcome_ids(&outcomes(&[3, 3])),
+            Err(PredictionMarketError::InvalidOutcome)
+        );
+    }
+
+    #[test]
+    fn test_distinct_outcome_ids_pass_in_any_order() {
+        assert_eq!(validate_outcome_ids(&outcomes(&[0, 1, 2])), Ok(()));
+        assert_eq!(validate_outcome_ids(&outcomes(&[2, 0, 5])), Ok(()));
+        assert_eq!(validate_outcome_ids(&[]), Ok(()));
+    }
+}
+
+#[cfg(test)]
+mod instruction_layout_tests {
+    use super::*;
+
+    #[test]
+    fn test_bet_params_start_with_the_event_id() {
+        let params = BetOnPredictionEventParams {
+            unique_id: [7; 32],
+            outcome_id: 1,
+            amount: 500,
+            min_expected_payout_bps: 12_000,
+            max_cost: 600,
+            referrer: Some(Pubkey([9; 32])),
+        };
+
+        let mut expected = vec![7; 32];
+        expected.push(1);
+        expected.extend(500u64.to_le_bytes());
+        expected.extend(12_000u64.to_le_bytes());
+        expected.extend(600u64.to_le_bytes());
+        expected.push(1);
+        expected.extend([9; 32]);
+        let bytes = borsh::to_vec(&params).unwrap();
+        assert_eq!(bytes, expected);
+        assert_eq!(
+            BetOnPredictionEventParams::try_from_slice(&bytes).unwrap(),
+            params
+        );
+
+        // the old layout led with 32 unused bytes
+        let old = [&[0; 32][..], &bytes].concat();
+        assert!(BetOnPredictionEventParams::try_from_slice(&old).is_err());
+    }
+
+    #[test]
+    fn test_mint_params_start_with_the_amount() {
+        let params = MintTokenParams {
+            amount: 10,
+            nonce: Some([3; 32]),
+        };
+
+        let mut expected = 10u64.to_le_bytes().to_vec();
+        expected.push(1);
+        expected.extend([3; 32]);
+        let bytes = borsh::to_vec(&params).unwrap();
+        assert_eq!(bytes, expected);
+        assert_eq!(MintTokenParams::try_from_slice(&bytes).unwrap(), params);
+
+        // the old layout led with a 32 byte uid
+        let without_nonce = MintTokenParams {
+            nonce: None,
+            ..params
+        };
+        let old = [&[0; 32][..], &borsh::to_vec(&without_nonce).unwrap()].concat();
+        assert!(MintTokenParams::try_from_slice(&old).is_err());
+    }
 }
@@ -0,0 +1,318 @@
+//! Limits a user sets on their own betting: a cap on their stake in any one
+//! event and a block height before which they can't bet at all. They live in
+//! a program owned account derived from the user, which every buy reads.
+//!
+//! Tightening a limit takes effect at once. Loosening one only takes effect
+//! `LOOSEN_COOLDOWN_BLOCKS` after it was asked for, until then the stricter
+//! limit stays in force, so an exclusion can't be lifted on a whim.
+use arch_program::{
+    account::AccountInfo,
+    bitcoin::hashes::{sha256, Hash, HashEngine},
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+use crate::{
+    ensure_distinct_accounts, ensure_program_owned, get_bitcoin_block_height, storage,
+    types::PredictionMarketError,
+};
+
+/// Blocks a loosened limit waits before it applies, about a week.
+pub const LOOSEN_COOLDOWN_BLOCKS: u64 = 1_008;
+
+/// Instruction data of `SetUserLimit`, and the limits a user is held to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, BorshSerialize, BorshDeserialize)]
+pub struct UserLimit {
+    // cap on the user's net stake in one event, 0 is unlimited
+    pub max_total_stake_per_event: u64,
+    // the user can't bet below this block height
+    pub excluded_until_height: u64,
+}
+
+impl UserLimit {
+    /// The stricter of `self` and `other` in each limit.
+    pub fn tightest(&self, other: &UserLimit) -> UserLimit {
+        let max_total_stake_per_event = match (
+            self.max_total_stake_per_event,
+            other.max_total_stake_per_event,
+        ) {
+            (0, cap) | (cap, 0) => cap,
+            (a, b) => a.min(b),
+        };
+
+        UserLimit {
+            max_total_stake_per_event,
+            excluded_until_height: self.excluded_until_height.max(other.excluded_until_height),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct PendingLimit {
+    pub limit: UserLimit,
+    // first block height the limit applies at
+    pub effective_height: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct UserLimits {
+    pub user: Pubkey,
+    pub current: UserLimit,
+    // a loosening still waiting out its cooldown
+    pub pending: Option<PendingLimit>,
+}
+
+impl UserLimits {
+    /// The limits the user is held to at `height`.
+    pub fn in_force(&self, height: u64) -> UserLimit {
+        match self.pending {
+            Some(pending) if height >= pending.effective_height => pending.limit,
+            _ => self.current,
+        }
+    }
+
+    /// Asks for `limit` at `height`. Whatever part of it is stricter than the
+    /// limits in force applies at once; if that isn't all of it, `limit` as a
+    /// whole applies after the cooldown. A new request replaces a pending one.
+    pub fn request(&mut self, limit: UserLimit, height: u64) {
+        self.current = self.in_force(height).tightest(&limit);
+        self.pending = (self.current != limit).then_some(PendingLimit {
+            limit,
+            effective_height: height.saturating_add(LOOSEN_COOLDOWN_BLOCKS),
+        });
+    }
+}
+
+/// Address of the account holding `user`'s limits. Derived like
+/// `escrow_address`, so no signer can hold it.
+pub fn derive_limits_address(program_id: &Pubkey, user: &Pubkey) -> Pubkey {
+    let mut engine = sha256::Hash::engine();
+    engine.input(b"user_limits");
+    engine.input(&program_id.0);
+    engine.input(&user.0);
+    Pubkey(sha256::Hash::from_engine(engine).to_byte_array())
+}
+
+/// Loads `user`'s limits, checking the account is the one derived for them;
+/// an empty account holds none yet.
+pub fn load_limits(
+    limits_account: &AccountInfo<'_>,
+    user: &Pubkey,
+) -> Result<UserLimits, ProgramError> {
+    if *limits_account.key != derive_limits_address(limits_account.owner, user) {
+        return Err(PredictionMarketError::UserLimitsAddressMismatch.into());
+    }
+
+    if limits_account.data_is_empty() {
+        return Ok(UserLimits {
+            user: *user,
+            current: UserLimit::default(),
+            pending: None,
+        });
+    }
+
+    storage::read(limits_account)
+}
+
+/// Fails with `SelfExcluded` while `user` has excluded themselves at
+/// `height`, and with `UserStakeLimitReached` if `stake` would take them past
+/// their own cap on one event. `stake` is their net stake in the event with
+/// the bet included.
+pub fn ensure_bet_allowed(
+    limits_account: &AccountInfo<'_>,
+    user: &Pubkey,
+    stake: u64,
+    height: u64,
+) -> Result<(), ProgramError> {
+    let limit = load_limits(limits_account, user)?.in_force(height);
+
+    if height < limit.excluded_until_height {
+        msg!(
+            "{} excluded themselves until block {}",
+            user,
+            limit.excluded_until_height
+        );
+        return Err(PredictionMarketError::SelfExcluded.into());
+    }
+
+    if limit.max_total_stake_per_event > 0 && stake > limit.max_total_stake_per_event {
+        return Err(PredictionMarketError::UserStakeLimitReached.into());
+    }
+
+    Ok(())
+}
+
+/// Sets the signing user's own limits, see `UserLimits::request`.
+pub fn process_set_user_limit(
+    program_id: &Pubkey,
+    limits_account: &AccountInfo<'_>,
+    user_account: &AccountInfo<'_>,
+    limit: UserLimit,
+) -> Result<(), ProgramError> {
+    ensure_distinct_accounts(&[limits_account.key, user_account.key])?;
+
+    ensure_program_owned(program_id, &[limits_account])?;
+
+    if !user_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let height = get_bitcoin_block_height();
+    let mut limits = load_limits(limits_account, user_account.key)?;
+    limits.request(limit, height);
+
+    match limits.pending {
+        Some(pending) => msg!(
+            "{} loosened their limits from block {}",
+            user_account.key,
+            pending.effective_height
+        ),
+        None => msg!("{} tightened their limits", user_account.key),
+    }
+
+    storage::write(limits_account, &limits)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod user_limits_tests {
+    use super::*;
+    use crate::process_instruction;
+    use crate::test_utils::{
+        config_account, set_block_height, user_limits_account, Market, TestAccount,
+    };
+
+    const EVENT_ID: [u8; 32] = [61; 32];
+
+    fn set_limit(
+        program_id: &Pubkey,
+        limits: &mut TestAccount,
+        user: &mut TestAccount,
+        max_total_stake_per_event: u64,
+        excluded_until_height: u64,
+    ) -> Result<(), ProgramError> {
+        let mut config = config_account(program_id, &Pubkey::new_unique(), false);
+        let mut data = vec![57];
+        data.extend(
+            borsh::to_vec(&UserLimit {
+                max_total_stake_per_event,
+                excluded_until_height,
+            })
+            .unwrap(),
+        );
+        process_instruction(
+            program_id,
+            &[limits.info(), user.info(), config.info()],
+            &data,
+        )
+    }
+
+    // the limit alice is held to at the current height
+    fn in_force(limits: &TestAccount, alice: &TestAccount) -> UserLimit {
+        let limits = UserLimits::try_from_slice(limits.data()).unwrap();
+        assert_eq!(limits.user, alice.key());
+        limits.in_force(get_bitcoin_block_height())
+    }
+
+    #[test]
+    fn test_buys_respect_the_users_stake_cap_and_exclusion() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut limits = user_limits_account(&program_id, &alice.key());
+        let mut market = Market::new(&program_id, EVENT_ID, &[(alice.key(), 1_000)]);
+        set_block_height(10);
+
+        set_limit(&program_id, &mut limits, &mut alice, 100, 0).unwrap();
+        market.buy_limited(&mut alice, &mut limits, 0, 60).unwrap();
+        // the cap counts every outcome of the event
+        assert_eq!(
+            market.buy_limited(&mut alice, &mut limits, 1, 41),
+            Err(PredictionMarketError::UserStakeLimitReached.into())
+        );
+        market.buy_limited(&mut alice, &mut limits, 1, 40).unwrap();
+
+        set_limit(&program_id, &mut limits, &mut alice, 100, 20).unwrap();
+        market.sell(&mut alice, 0, 10).unwrap();
+        assert_eq!(
+            market.buy_limited(&mut alice, &mut limits, 0, 5),
+            Err(PredictionMarketError::SelfExcluded.into())
+        );
+
+        set_block_height(20);
+        market.buy_limited(&mut alice, &mut limits, 0, 10).unwrap();
+        assert_eq!(market.balance(&alice), 900);
+    }
+
+    #[test]
+    fn test_tightening_applies_at_once_and_loosening_after_the_cooldown() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut limits = user_limits_account(&program_id, &alice.key());
+        set_block_height(100);
+
+        set_limit(&program_id, &mut limits, &mut alice, 500, 0).unwrap();
+        assert_eq!(in_force(&limits, &alice).max_total_stake_per_event, 500);
+        set_limit(&program_id, &mut limits, &mut alice, 200, 5_000).unwrap();
+        let tight = in_force(&limits, &alice);
+        assert_eq!(tight.max_total_stake_per_event, 200);
+        assert_eq!(tight.excluded_until_height, 5_000);
+
+        // lifting both only applies a cooldown later
+        set_limit(&program_id, &mut limits, &mut alice, 0, 0).unwrap();
+        set_block_height(100 + LOOSEN_COOLDOWN_BLOCKS - 1);
+        assert_eq!(in_force(&limits, &alice), tight);
+        set_block_height(100 + LOOSEN_COOLDOWN_BLOCKS);
+        assert_eq!(in_force(&limits, &alice), UserLimit::default());
+    }
+
+    #[test]
+    fn test_mixed_request_tightens_now_and_loosens_later() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut limits = user_limits_account(&program_id, &alice.key());
+        set_block_height(100);
+        set_limit(&program_id, &mut limits, &mut alice, 200, 0).unwrap();
+
+        // a higher cap and an exclusion: the exclusion is stricter so it
+        // holds at once, the cap only rises after the cooldown
+        set_limit(&program_id, &mut limits, &mut alice, 300, 400).unwrap();
+        assert_eq!(
+            in_force(&limits, &alice),
+            UserLimit {
+                max_total_stake_per_event: 200,
+                excluded_until_height: 400,
+            }
+        );
+
+        // asking again restarts the cooldown instead of skipping it
+        set_block_height(100 + LOOSEN_COOLDOWN_BLOCKS - 1);
+        set_limit(&program_id, &mut limits, &mut alice, 300, 400).unwrap();
+        set_block_height(100 + LOOSEN_COOLDOWN_BLOCKS);
+        assert_eq!(in_force(&limits, &alice).max_total_stake_per_event, 200);
+        set_block_height(99 + 2 * LOOSEN_COOLDOWN_BLOCKS);
+        assert_eq!(in_force(&limits, &alice).max_total_stake_per_event, 300);
+    }
+
+    #[test]
+    fn test_limits_belong_to_their_user() {
+        let program_id = Pubkey::new_unique();
+        let alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut alices_limits = user_limits_account(&program_id, &alice.key());
+
+        assert_eq!(
+            set_limit(&program_id, &mut alices_limits, &mut bob, 0, 1_000),
+            Err(PredictionMarketError::UserLimitsAddressMismatch.into())
+        );
+
+        let mut market = Market::new(&program_id, EVENT_ID, &[(bob.key(), 100)]);
+        assert_eq!(
+            market.buy_limited(&mut bob, &mut alices_limits, 0, 10),
+            Err(PredictionMarketError::UserLimitsAddressMismatch.into())
+        );
+        assert!(alices_limits.data().is_empty());
+    }
+}
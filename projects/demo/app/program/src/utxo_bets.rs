@@ -0,0 +1,726 @@
+//! Bets funded straight from a bitcoin utxo instead of the token mint. The
+//! better proves they control the utxo, its value is converted to bet units
+//! at `SATS_PER_BET_UNIT` and staked on a pool outcome, and the utxo is kept
+//! on the event with the better's refund script, so it backs only one bet
+//! and can be refunded by `payout::process_refund_on_bitcoin`.
+//!
+//! A deposit isn't credited until it is `ProgramConfig::min_confirmations`
+//! blocks deep, so a reorg can't take back a stake that already moved the
+//! odds. Until then the bet is pending on the event, counting toward no
+//! total but holding its place under the caps, and anyone may crank it in
+//! with `process_confirm_pending_bets`. A bet still pending when the event
+//! expires or stops taking bets is never credited, only refunded.
+//!
+//! The runtime proves who owns a utxo but not what it holds, so the value is
+//! taken as the better declares it. A utxo-funded stake puts no tokens in
+//! escrow; `PredictionEvent::utxo_stake` counts it so the escrow check still
+//! balances, and `utxo_stakes` keeps that part of a position from being
+//! sold, handed on or claimed in tokens. An event staked wholly with utxos pays out on bitcoin, see
+//! `payout::process_settle_on_bitcoin`.
+use arch_program::{account::AccountInfo, msg, program_error::ProgramError, pubkey::Pubkey};
+
+use crate::{
+    config, ensure_distinct_accounts, ensure_program_owned, event_store,
+    events::{self, BetPlacedLog},
+    get_bitcoin_block_height,
+    types::{
+        Bet, BetType, EscrowedUtxo, EventStatus, MarketType, PendingUtxoBet, PredictionEvent,
+        PredictionMarketError, UtxoBetParams,
+    },
+    user_limits, validate_utxo_ownership,
+};
+
+/// Satoshis one bet unit is worth; what a utxo holds beyond whole units is
+/// not staked.
+pub const SATS_PER_BET_UNIT: u64 = 100;
+
+/// Smallest utxo accepted, bitcoin's dust limit for a standard output.
+pub const MIN_UTXO_SATS: u64 = 546;
+
+/// Longest refund script accepted, that of a segwit v1 output.
+pub const MAX_REFUND_SCRIPT_LEN: usize = 34;
+
+/// Stakes the value of `params.utxo` on an outcome of an active pool event.
+/// Fails with `UtxoOwnershipNotProven` unless the better controls the utxo,
+/// `UtxoBelowDust` if it holds less than `MIN_UTXO_SATS`,
+/// `UtxoAlreadyEscrowed` if it already backs a bet on the event and
+/// `InvalidRefundScript` if the refund script is empty or longer than
+/// `MAX_REFUND_SCRIPT_LEN`. With `min_confirmations` set the bet is left
+/// pending instead of credited, its stake checked against the caps along
+/// with the bets pending before it.
+pub fn process_buy_bet_with_utxo<'a>(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'a>,
+    better_account: &AccountInfo<'a>,
+    limits_account: &AccountInfo<'a>,
+    config_account: &AccountInfo<'a>,
+    params: UtxoBetParams,
+) -> Result<(), ProgramError> {
+    ensure_distinct_accounts(&[
+        event_account.key,
+        better_account.key,
+        limits_account.key,
+        config_account.key,
+    ])?;
+
+    ensure_program_owned(program_id, &[event_account, limits_account, config_account])?;
+
+    if !better_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let min_confirmations = config::load_config(config_account)?.min_confirmations;
+    let mut event = event_store::load_event(event_account, &params.unique_id)?;
+
+    if event.status != EventStatus::Active {
+        return Err(PredictionMarketError::EventNotActive.into());
+    }
+
+    // an LMSR or scalar stake isn't a plain amount on an outcome
+    if event.market_type != MarketType::ParimutuelPool {
+        return Err(PredictionMarketError::InvalidMarketType.into());
+    }
+
+    let height = get_bitcoin_block_height();
+    event.ensure_not_frozen(height)?;
+
+    if !validate_utxo_ownership(&params.utxo, better_account.key) {
+        msg!("Utxo {:?} is not owned by the better", params.utxo);
+        return Err(PredictionMarketError::UtxoOwnershipNotProven.into());
+    }
+
+    if params.value_sats < MIN_UTXO_SATS {
+        msg!(
+            "Utxo of {} sats is below the dust limit of {}",
+            params.value_sats,
+            MIN_UTXO_SATS
+        );
+        return Err(PredictionMarketError::UtxoBelowDust.into());
+    }
+
+    if params.refund_script_pubkey.is_empty()
+        || params.refund_script_pubkey.len() > MAX_REFUND_SCRIPT_LEN
+    {
+        return Err(PredictionMarketError::InvalidRefundScript.into());
+    }
+
+    let already_escrowed = event
+        .escrowed_utxos
+        .iter()
+        .chain(
+            event
+                .pending_utxo_bets
+                .iter()
+                .map(|pending| &pending.escrowed),
+        )
+        .any(|escrowed| escrowed.utxo == params.utxo);
+    if already_escrowed {
+        return Err(PredictionMarketError::UtxoAlreadyEscrowed.into());
+    }
+
+    // the sum `escrowed_sats` reports can't overflow, nor can it once every
+    // pending bet is credited
+    event
+        .pending_utxo_bets
+        .iter()
+        .try_fold(event.escrowed_sats(), |sats, pending| {
+            sats.checked_add(pending.escrowed.value_sats)
+        })
+        .and_then(|sats| sats.checked_add(params.value_sats))
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    let amount = params.value_sats / SATS_PER_BET_UNIT;
+
+    // the caps hold the stake of bets still pending as if it were credited
+    let pending_user = pending_amount(&event, |pending| pending.user == *better_account.key);
+    let pending_outcome = pending_amount(&event, |pending| pending.outcome_id == params.outcome_id);
+    let pending_pool = pending_amount(&event, |_| true);
+
+    event.ensure_within_bet_limit(better_account.key, pending_user.saturating_add(amount))?;
+    user_limits::ensure_bet_allowed(
+        limits_account,
+        better_account.key,
+        event
+            .user_stake(better_account.key)
+            .saturating_add(pending_user)
+            .saturating_add(amount),
+        height,
+    )?;
+
+    let outcome_index = event
+        .outcome_index(params.outcome_id)
+        .ok_or(PredictionMarketError::InvalidOutcome)?;
+
+    event.ensure_within_pool_cap(pending_pool.saturating_add(amount))?;
+    event.ensure_within_outcome_exposure(outcome_index, pending_outcome.saturating_add(amount))?;
+
+    let bet = PendingUtxoBet {
+        user: *better_account.key,
+        outcome_id: params.outcome_id,
+        amount,
+        escrowed: EscrowedUtxo {
+            utxo: params.utxo,
+            value_sats: params.value_sats,
+            refund_script_pubkey: params.refund_script_pubkey,
+        },
+        deposit_height: height,
+    };
+
+    if min_confirmations > 0 {
+        msg!(
+            "Bet of {} pending until block {}",
+            amount,
+            height.saturating_add(min_confirmations)
+        );
+        event.pending_utxo_bets.push(bet);
+        return event_store::store_event(event_account, &event);
+    }
+
+    let placed = credit_bet(&mut event, bet, height)?;
+
+    event_store::store_event(event_account, &event)?;
+
+    events::emit(&placed);
+
+    Ok(())
+}
+
+/// Credits every pending utxo bet on the event whose deposit is at least
+/// `min_confirmations` blocks deep, in the order they were made; the rest
+/// stay pending. Anyone may crank it. Fails with `EventNotActive` or
+/// `EventExpired` once pending bets are stale and can only be refunded.
+pub fn process_confirm_pending_bets<'a>(
+    program_id: &Pubkey,
+    event_account: &AccountInfo<'a>,
+    config_account: &AccountInfo<'a>,
+    unique_id: [u8; 32],
+) -> Result<(), ProgramError> {
+    ensure_distinct_accounts(&[event_account.key, config_account.key])?;
+
+    ensure_program_owned(program_id, &[event_account, config_account])?;
+
+    let min_confirmations = config::load_config(config_account)?.min_confirmations;
+    let mut event = event_store::load_event(event_account, &unique_id)?;
+    let height = get_bitcoin_block_height();
+
+    if event.status != EventStatus::Active {
+        return Err(PredictionMarketError::EventNotActive.into());
+    }
+
+    if event.pending_bets_stale(height) {
+        return Err(PredictionMarketError::EventExpired.into());
+    }
+
+    let (confirmed, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut event.pending_utxo_bets)
+        .into_iter()
+        .partition(|bet| height.saturating_sub(bet.deposit_height) >= min_confirmations);
+    event.pending_utxo_bets = pending;
+
+    msg!(
+        "Confirmed {} pending bets, {} still pending",
+        confirmed.len(),
+        event.pending_utxo_bets.len()
+    );
+
+    let placed = confirmed
+        .into_iter()
+        .map(|bet| credit_bet(&mut event, bet, height))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    event_store::store_event(event_account, &event)?;
+
+    for log in &placed {
+        events::emit(log);
+    }
+
+    Ok(())
+}
+
+// the stake of the event's pending bets that `filter` picks
+fn pending_amount(event: &PredictionEvent, filter: impl Fn(&PendingUtxoBet) -> bool) -> u64 {
+    event
+        .pending_utxo_bets
+        .iter()
+        .filter(|pending| filter(pending))
+        .fold(0u64, |total, pending| total.saturating_add(pending.amount))
+}
+
+// stakes `bet` on its outcome and escrows its utxo, returning the log to emit
+// once the event is stored
+fn credit_bet(
+    event: &mut PredictionEvent,
+    bet: PendingUtxoBet,
+    height: u64,
+) -> Result<BetPlacedLog, ProgramError> {
+    let outcome = event
+        .outcomes
+        .iter_mut()
+        .find(|outcome| outcome.id == bet.outcome_id)
+        .ok_or(PredictionMarketError::InvalidOutcome)?;
+    outcome.total_amount = outcome
+        .total_amount
+        .checked_add(bet.amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    outcome.bets.entry(bet.user).or_default().push(Bet {
+        user: bet.user,
+        event_id: event.unique_id,
+        outcome_id: bet.outcome_id,
+        amount: bet.amount,
+        timestamp: height as i64,
+        bet_type: BetType::BUY,
+    });
+    event.total_pool_amount = event
+        .total_pool_amount
+        .checked_add(bet.amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    event.utxo_stake = event
+        .utxo_stake
+        .checked_add(bet.amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    let position = event
+        .utxo_stakes
+        .entry((bet.user, bet.outcome_id))
+        .or_default();
+    *position = position
+        .checked_add(bet.amount)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    event
+        .cost_basis
+        .entry((bet.user, bet.outcome_id))
+        .or_default()
+        .buy(bet.amount)?;
+    event.escrowed_utxos.push(bet.escrowed);
+    event.stats.record_buy(&bet.user, bet.amount, height);
+
+    Ok(BetPlacedLog {
+        unique_id: event.unique_id,
+        user: bet.user,
+        outcome_id: bet.outcome_id,
+        amount: bet.amount,
+        bet_type: BetType::BUY,
+        block_height: height,
+        cost: bet.amount,
+    })
+}
+
+#[cfg(test)]
+mod utxo_bet_tests {
+    use arch_program::utxo::UtxoMeta;
+
+    use super::*;
+    use crate::config::SetMinConfirmationsParams;
+    use crate::payout::take_refunds;
+    use crate::process_instruction;
+    use crate::test_utils::{
+        config_account, event_params, register_utxo_owner, set_block_height, user_limits_account,
+        Market, TestAccount,
+    };
+    use crate::types::{
+        net_stake, ConfirmPendingBetsParams, PredictionEventParams, TransferPositionParams,
+    };
+
+    const EVENT_ID: [u8; 32] = [64; 32];
+
+    // a segwit v1 output script
+    fn refund_script() -> Vec<u8> {
+        [vec![0x51, 0x20], vec![0xaa; 32]].concat()
+    }
+
+    fn bet_with_utxo(
+        market: &mut Market,
+        better: &mut TestAccount,
+        utxo: &UtxoMeta,
+        value_sats: u64,
+    ) -> Result<(), ProgramError> {
+        bet_with_utxo_refunding_to(market, better, utxo, value_sats, refund_script())
+    }
+
+    fn bet_with_utxo_refunding_to(
+        market: &mut Market,
+        better: &mut TestAccount,
+        utxo: &UtxoMeta,
+        value_sats: u64,
+        refund_script_pubkey: Vec<u8>,
+    ) -> Result<(), ProgramError> {
+        let program_id = market.event.owner;
+        let mut limits = user_limits_account(&program_id, &better.key());
+        let mut data = vec![60];
+        data.extend(
+            borsh::to_vec(&UtxoBetParams {
+                unique_id: market.unique_id,
+                outcome_id: 0,
+                utxo: utxo.clone(),
+                value_sats,
+                refund_script_pubkey,
+            })
+            .unwrap(),
+        );
+        process_instruction(
+            &program_id,
+            &[
+                market.event.info(),
+                better.info(),
+                limits.info(),
+                market.config.info(),
+            ],
+            &data,
+        )
+    }
+
+    // has the admin of a fresh config require `min_confirmations` of the market
+    fn require_confirmations(market: &mut Market, min_confirmations: u64) {
+        let program_id = market.event.owner;
+        let mut admin = TestAccount::new(&program_id, &[]).signer();
+        market.config = config_account(&program_id, &admin.key(), false);
+        let mut data = vec![64];
+        data.extend(borsh::to_vec(&SetMinConfirmationsParams { min_confirmations }).unwrap());
+        process_instruction(&program_id, &[market.config.info(), admin.info()], &data).unwrap();
+    }
+
+    fn confirm(market: &mut Market) -> Result<(), ProgramError> {
+        let program_id = market.event.owner;
+        let mut data = vec![65];
+        data.extend(
+            borsh::to_vec(&ConfirmPendingBetsParams {
+                unique_id: market.unique_id,
+            })
+            .unwrap(),
+        );
+        process_instruction(
+            &program_id,
+            &[market.event.info(), market.config.info()],
+            &data,
+        )
+    }
+
+    #[test]
+    fn test_utxo_stakes_its_value_without_touching_the_mint() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[(alice.key(), 100)]);
+        let utxo = UtxoMeta::from([7; 32], 1);
+        register_utxo_owner(utxo.clone(), alice.key());
+        let token_before = market.token.data().to_vec();
+
+        // 12_345 sats is 123 whole units
+        bet_with_utxo(&mut market, &mut alice, &utxo, 12_345).unwrap();
+
+        let event = market.event_state();
+        assert_eq!(event.outcomes[0].total_amount, 123);
+        assert_eq!(event.total_pool_amount, 123);
+        assert_eq!(event.utxo_stake, 123);
+        assert_eq!(
+            event.escrowed_utxos,
+            vec![EscrowedUtxo {
+                utxo,
+                value_sats: 12_345,
+                refund_script_pubkey: refund_script(),
+            }]
+        );
+        assert_eq!(event.escrowed_sats(), 12_345);
+        assert_eq!(market.token.data(), &token_before[..]);
+
+        // token bets still balance against the escrow next to it
+        market.buy(&mut alice, 1, 40).unwrap();
+        assert_eq!(market.event_state().total_pool_amount, 163);
+    }
+
+    #[test]
+    fn test_utxo_backs_only_one_bet() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[]);
+        let utxo = UtxoMeta::from([8; 32], 0);
+        register_utxo_owner(utxo.clone(), alice.key());
+        bet_with_utxo(&mut market, &mut alice, &utxo, 1_000).unwrap();
+        let before = market.event.data().to_vec();
+
+        assert_eq!(
+            bet_with_utxo(&mut market, &mut alice, &utxo, 1_000),
+            Err(PredictionMarketError::UtxoAlreadyEscrowed.into())
+        );
+        assert_eq!(market.event.data(), &before[..]);
+
+        // another output of the same transaction is a different utxo
+        let sibling = UtxoMeta::from([8; 32], 1);
+        register_utxo_owner(sibling.clone(), alice.key());
+        bet_with_utxo(&mut market, &mut alice, &sibling, 1_000).unwrap();
+        assert_eq!(market.event_state().outcomes[0].total_amount, 20);
+    }
+
+    #[test]
+    fn test_dust_and_unowned_utxos_are_rejected() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[]);
+        let utxo = UtxoMeta::from([9; 32], 0);
+        register_utxo_owner(utxo.clone(), alice.key());
+
+        assert_eq!(
+            bet_with_utxo(&mut market, &mut alice, &utxo, MIN_UTXO_SATS - 1),
+            Err(PredictionMarketError::UtxoBelowDust.into())
+        );
+        assert_eq!(
+            bet_with_utxo(&mut market, &mut bob, &utxo, 10_000),
+            Err(PredictionMarketError::UtxoOwnershipNotProven.into())
+        );
+        assert!(market.event_state().escrowed_utxos.is_empty());
+
+        bet_with_utxo(&mut market, &mut alice, &utxo, MIN_UTXO_SATS).unwrap();
+        assert_eq!(market.event_state().outcomes[0].total_amount, 5);
+    }
+
+    #[test]
+    fn test_refund_script_must_be_a_standard_length() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, EVENT_ID, &[]);
+        let utxo = UtxoMeta::from([10; 32], 0);
+        register_utxo_owner(utxo.clone(), alice.key());
+
+        for script in [vec![], vec![0x51; MAX_REFUND_SCRIPT_LEN + 1]] {
+            assert_eq!(
+                bet_with_utxo_refunding_to(&mut market, &mut alice, &utxo, 1_000, script),
+                Err(PredictionMarketError::InvalidRefundScript.into())
+            );
+        }
+        assert!(market.event_state().escrowed_utxos.is_empty());
+    }
+
+    #[test]
+    fn test_bets_are_credited_once_their_deposit_confirms() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, [71; 32], &[]);
+        require_confirmations(&mut market, 6);
+        let utxo = UtxoMeta::from([11; 32], 0);
+        register_utxo_owner(utxo.clone(), alice.key());
+        set_block_height(40);
+
+        bet_with_utxo(&mut market, &mut alice, &utxo, 5_000).unwrap();
+        let event = market.event_state();
+        assert_eq!(event.total_pool_amount, 0);
+        assert_eq!(event.outcomes[0].total_amount, 0);
+        assert_eq!(event.bettor_count(), 0);
+        assert!(event.escrowed_utxos.is_empty());
+        assert_eq!(event.pending_utxo_bets.len(), 1);
+        assert_eq!(event.pending_utxo_bets[0].deposit_height, 40);
+        // a pending deposit can't back a second bet either
+        assert_eq!(
+            bet_with_utxo(&mut market, &mut alice, &utxo, 5_000),
+            Err(PredictionMarketError::UtxoAlreadyEscrowed.into())
+        );
+
+        // one block short of the threshold the crank leaves it pending
+        set_block_height(45);
+        confirm(&mut market).unwrap();
+        assert_eq!(market.event_state().pending_utxo_bets.len(), 1);
+
+        set_block_height(46);
+        confirm(&mut market).unwrap();
+        let event = market.event_state();
+        assert!(event.pending_utxo_bets.is_empty());
+        assert_eq!(event.outcomes[0].total_amount, 50);
+        assert_eq!(event.total_pool_amount, 50);
+        assert_eq!(event.utxo_stake, 50);
+        assert_eq!(event.escrowed_sats(), 5_000);
+        assert_eq!(event.user_stake(&alice.key()), 50);
+    }
+
+    #[test]
+    fn test_pending_bets_hold_their_place_under_the_caps() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let params = PredictionEventParams {
+            max_bet_per_user: Some(16),
+            ..event_params([72; 32], 100, 2)
+        };
+        let mut market = Market::with_params(&program_id, params, &[]);
+        require_confirmations(&mut market, 3);
+        set_block_height(10);
+        let first = UtxoMeta::from([12; 32], 0);
+        let second = UtxoMeta::from([12; 32], 1);
+        register_utxo_owner(first.clone(), alice.key());
+        register_utxo_owner(second.clone(), alice.key());
+
+        bet_with_utxo(&mut market, &mut alice, &first, 1_000).unwrap();
+        assert_eq!(
+            bet_with_utxo(&mut market, &mut alice, &second, 1_000),
+            Err(PredictionMarketError::BetLimitExceeded.into())
+        );
+        bet_with_utxo(&mut market, &mut alice, &second, 600).unwrap();
+
+        set_block_height(13);
+        confirm(&mut market).unwrap();
+        assert_eq!(market.event_state().user_stake(&alice.key()), 16);
+    }
+
+    #[test]
+    fn test_bets_pending_past_expiry_are_refunded_not_credited() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, [73; 32], &[]);
+        require_confirmations(&mut market, 6);
+        let early = UtxoMeta::from([13; 32], 0);
+        let late = UtxoMeta::from([13; 32], 1);
+        register_utxo_owner(early.clone(), alice.key());
+        register_utxo_owner(late.clone(), alice.key());
+        set_block_height(90);
+        bet_with_utxo(&mut market, &mut alice, &early, 1_000).unwrap();
+        set_block_height(96);
+        bet_with_utxo(&mut market, &mut alice, &late, 2_000).unwrap();
+        confirm(&mut market).unwrap();
+
+        // nothing stale is refunded while the event is still taking bets
+        let mut event = market.event_state();
+        assert_eq!(
+            take_refunds(&mut event, 99),
+            Err(PredictionMarketError::EventNotResolved.into())
+        );
+
+        // the late deposit would confirm at 102, after the event expires at 100
+        set_block_height(102);
+        assert_eq!(
+            confirm(&mut market),
+            Err(PredictionMarketError::EventExpired.into())
+        );
+        let mut event = market.event_state();
+        assert_eq!(event.pending_utxo_bets.len(), 1);
+        let (refunds, settled) = take_refunds(&mut event, 102).unwrap();
+        assert!(!settled);
+        assert_eq!(refunds.len(), 1);
+        assert_eq!(refunds[0].utxo, late);
+        assert_eq!(refunds[0].value_sats, 2_000);
+        assert!(event.pending_utxo_bets.is_empty());
+        assert_eq!(event.status, EventStatus::Active);
+        assert_eq!(event.escrowed_utxos[0].utxo, early);
+    }
+
+    #[test]
+    fn test_cancelled_event_refunds_pending_and_credited_utxos() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, [74; 32], &[]);
+        let credited = UtxoMeta::from([14; 32], 0);
+        let pending = UtxoMeta::from([14; 32], 1);
+        register_utxo_owner(credited.clone(), alice.key());
+        register_utxo_owner(pending.clone(), alice.key());
+        set_block_height(20);
+        bet_with_utxo(&mut market, &mut alice, &credited, 1_000).unwrap();
+        require_confirmations(&mut market, 6);
+        bet_with_utxo(&mut market, &mut alice, &pending, 2_000).unwrap();
+
+        crate::process_cancel_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            [74; 32],
+        )
+        .unwrap();
+        assert_eq!(
+            confirm(&mut market),
+            Err(PredictionMarketError::EventNotActive.into())
+        );
+
+        let mut event = market.event_state();
+        let (refunds, settled) = take_refunds(&mut event, 30).unwrap();
+        assert!(settled);
+        assert_eq!(event.status, EventStatus::Settled);
+        // the refunded stakes are out of the pool
+        assert_eq!(event.utxo_stake, 0);
+        assert_eq!(event.total_pool_amount, 0);
+        assert!(event.outcomes[0].bets.is_empty());
+        assert_eq!(
+            refunds
+                .iter()
+                .map(|refund| &refund.utxo)
+                .collect::<Vec<_>>(),
+            [&credited, &pending]
+        );
+    }
+
+    #[test]
+    fn test_utxo_part_of_a_position_cannot_leave_in_tokens() {
+        let program_id = Pubkey::new_unique();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(&program_id, [75; 32], &[(bob.key(), 100)]);
+        let utxo = UtxoMeta::from([15; 32], 0);
+        register_utxo_owner(utxo.clone(), bob.key());
+        market.buy(&mut bob, 0, 30).unwrap();
+        bet_with_utxo(&mut market, &mut bob, &utxo, 5_000).unwrap();
+
+        // the 30 bought with tokens can go, the 50 the utxo backs can't
+        market.sell(&mut bob, 0, 20).unwrap();
+        assert_eq!(
+            market.sell(&mut bob, 0, 11),
+            Err(PredictionMarketError::UtxoStakeLocked.into())
+        );
+        assert_eq!(
+            crate::process_transfer_position(
+                &program_id,
+                &market.event.info(),
+                &bob.info(),
+                TransferPositionParams {
+                    unique_id: [75; 32],
+                    outcome_id: 0,
+                    amount: 11,
+                    recipient: Pubkey::new_unique(),
+                },
+            ),
+            Err(PredictionMarketError::UtxoStakeLocked.into())
+        );
+        // past the whole position it is still short
+        assert_eq!(
+            market.sell(&mut bob, 0, 61),
+            Err(PredictionMarketError::InsufficientPosition.into())
+        );
+        market.sell(&mut bob, 0, 10).unwrap();
+
+        let event = market.event_state();
+        assert_eq!(net_stake(&event.outcomes[0], &bob.key()), 50);
+        assert_eq!(event.utxo_position(&bob.key(), 0), 50);
+        assert_eq!(event.utxo_stake, 50);
+        assert_eq!(market.balance(&bob), 100);
+    }
+
+    #[test]
+    fn test_mixed_event_pays_only_token_stakes_from_the_escrow() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let mut bob = TestAccount::new(&program_id, &[]).signer();
+        let mut carol = TestAccount::new(&program_id, &[]).signer();
+        let mut market = Market::new(
+            &program_id,
+            [76; 32],
+            &[(alice.key(), 50), (carol.key(), 100)],
+        );
+        let utxo = UtxoMeta::from([16; 32], 0);
+        register_utxo_owner(utxo.clone(), bob.key());
+        market.buy(&mut alice, 0, 50).unwrap();
+        market.buy(&mut carol, 1, 100).unwrap();
+        bet_with_utxo(&mut market, &mut bob, &utxo, 5_000).unwrap();
+
+        crate::process_resolve_event(
+            &program_id,
+            &market.event.info(),
+            &market.creator.info(),
+            [76; 32],
+            0,
+        )
+        .unwrap();
+
+        assert_eq!(
+            market.claim(&mut bob),
+            Err(PredictionMarketError::UtxoStakeLocked.into())
+        );
+        assert_eq!(market.balance(&bob), 0);
+        assert_eq!(
+            market.claim(&mut carol),
+            Err(PredictionMarketError::NoWinningPosition.into())
+        );
+        // half of the winning stake takes the whole pool's half
+        market.claim(&mut alice).unwrap();
+        assert_eq!(market.balance(&alice), 100);
+    }
+}
@@ -0,0 +1,574 @@
+//! Wrapped bitcoin: a mint made with `InitializeMintInput::btc_backed` whose
+//! tokens are satoshis held in escrow. Depositing a utxo mints its value to
+//! the depositor, one token per satoshi, and keeps the utxo in the mint's
+//! reserve; withdrawing burns tokens and pays their value out of the reserve
+//! on bitcoin. Nothing else mints or burns on such a mint, so its circulating
+//! supply always equals what its reserve holds, and both instructions check
+//! that before they store.
+//!
+//! As with `utxo_bets`, the runtime proves who owns a deposited utxo but not
+//! what it holds, so the value is taken as the depositor declares it.
+#[cfg(not(test))]
+use arch_program::helper::add_state_transition;
+use arch_program::{
+    account::AccountInfo,
+    bitcoin::{absolute::LockTime, transaction::Version, Amount, ScriptBuf, Transaction, TxOut},
+    entrypoint::ProgramResult,
+    input_to_sign::InputToSign,
+    msg,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    utxo::UtxoMeta,
+};
+use borsh::{BorshDeserialize, BorshSerialize};
+
+#[cfg(test)]
+use crate::test_utils::add_state_transition;
+
+use crate::{
+    ensure_distinct_accounts, ensure_program_owned,
+    errors::FungibleTokenError,
+    events::{self, BurnedLog, MintedLog},
+    get_bitcoin_block_height,
+    mint::TokenMintDetails,
+    payout::{
+        account_script_pubkey, escrow_input, fee_with_outputs, inputs_signed_by, sign_payout,
+        MAX_FEE_RATE_SATS_PER_VBYTE,
+    },
+    storage,
+    types::PredictionMarketError,
+    utxo_bets::{MAX_REFUND_SCRIPT_LEN, MIN_UTXO_SATS},
+    validate_utxo_ownership,
+};
+
+/// A utxo in a wrapped-BTC mint's reserve and what it holds.
+#[derive(Debug, Clone, PartialEq, BorshSerialize, BorshDeserialize)]
+pub struct ReserveUtxo {
+    pub utxo: UtxoMeta,
+    pub value_sats: u64,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct DepositBtcParams {
+    pub utxo: UtxoMeta,
+    pub value_sats: u64,
+}
+
+#[derive(Debug, Clone, BorshSerialize, BorshDeserialize)]
+pub struct WithdrawBtcParams {
+    // in satoshis, as tokens are
+    pub amount: u64,
+    pub dest_script_pubkey: Vec<u8>,
+    pub fee_rate_sats_per_vbyte: u64,
+}
+
+/// A withdrawal built by `build_withdrawal_transaction`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Withdrawal {
+    pub transaction: Transaction,
+    pub inputs_to_sign: Vec<InputToSign>,
+    // tokens it burns: the amount asked for, or all of the utxos spent when
+    // what is left of them would be dust
+    pub burned: u64,
+    // what is left of the utxos spent, back in the reserve
+    pub change: Option<ReserveUtxo>,
+}
+
+/// Mints the value of `params.utxo` to the depositor and keeps the utxo in
+/// the mint's reserve. Fails with `NotBtcBacked` for a mint that isn't
+/// wrapped BTC, `UtxoOwnershipNotProven` unless the depositor controls the
+/// utxo, `UtxoBelowDust` if it holds less than `MIN_UTXO_SATS` and
+/// `UtxoAlreadyEscrowed` if it is already in the reserve. The mint's supply
+/// caps what it can hold.
+pub fn process_deposit_btc<'a>(
+    program_id: &Pubkey,
+    mint_account: &AccountInfo<'a>,
+    depositor_account: &AccountInfo<'a>,
+    config_account: &AccountInfo<'a>,
+    params: DepositBtcParams,
+) -> ProgramResult {
+    ensure_distinct_accounts(&[mint_account.key, depositor_account.key, config_account.key])?;
+
+    ensure_program_owned(program_id, &[mint_account, config_account])?;
+
+    if !depositor_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    let mut token = storage::read::<TokenMintDetails>(mint_account)?;
+    let reserve = token
+        .btc_reserve
+        .as_mut()
+        .ok_or(FungibleTokenError::NotBtcBacked)?;
+
+    if !validate_utxo_ownership(&params.utxo, depositor_account.key) {
+        msg!("Utxo {:?} is not owned by the depositor", params.utxo);
+        return Err(PredictionMarketError::UtxoOwnershipNotProven.into());
+    }
+
+    if params.value_sats < MIN_UTXO_SATS {
+        return Err(PredictionMarketError::UtxoBelowDust.into());
+    }
+
+    if reserve.iter().any(|reserved| reserved.utxo == params.utxo) {
+        return Err(PredictionMarketError::UtxoAlreadyEscrowed.into());
+    }
+
+    reserve.push(ReserveUtxo {
+        utxo: params.utxo,
+        value_sats: params.value_sats,
+    });
+
+    let new_circulating_supply = token
+        .circulating_supply
+        .checked_add(params.value_sats)
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    if new_circulating_supply > token.supply {
+        return Err(FungibleTokenError::NotEnoughRemainingMintableTokens.into());
+    }
+    token.circulating_supply = new_circulating_supply;
+    *token.balances.entry(*depositor_account.key).or_insert(0) += params.value_sats;
+
+    ensure_fully_backed(&token)?;
+    storage::write(mint_account, &token)?;
+
+    events::emit(&MintedLog {
+        user: *depositor_account.key,
+        amount: params.value_sats,
+        block_height: get_bitcoin_block_height(),
+    });
+
+    Ok(())
+}
+
+/// Burns `params.amount` of the holder's tokens and pays it, less the fee,
+/// to `params.dest_script_pubkey` on bitcoin, see
+/// `build_withdrawal_transaction`. The holder signs, and picks the fee rate.
+/// Fails with `UtxoBelowDust` for an amount below `MIN_UTXO_SATS`,
+/// `InvalidRefundScript` for an empty or over-long destination script and
+/// `InsufficientBalance` if the holder can't cover what is burned.
+pub fn process_withdraw_btc<'a>(
+    program_id: &Pubkey,
+    mint_account: &AccountInfo<'a>,
+    holder_account: &AccountInfo<'a>,
+    config_account: &AccountInfo<'a>,
+    params: WithdrawBtcParams,
+) -> ProgramResult {
+    ensure_distinct_accounts(&[mint_account.key, holder_account.key, config_account.key])?;
+
+    ensure_program_owned(program_id, &[mint_account, config_account])?;
+
+    if !holder_account.is_signer {
+        return Err(ProgramError::MissingRequiredSignature);
+    }
+
+    if !(1..=MAX_FEE_RATE_SATS_PER_VBYTE).contains(&params.fee_rate_sats_per_vbyte) {
+        return Err(PredictionMarketError::FeeRateOutOfRange.into());
+    }
+
+    if params.dest_script_pubkey.is_empty()
+        || params.dest_script_pubkey.len() > MAX_REFUND_SCRIPT_LEN
+    {
+        return Err(PredictionMarketError::InvalidRefundScript.into());
+    }
+
+    if params.amount < MIN_UTXO_SATS {
+        return Err(PredictionMarketError::UtxoBelowDust.into());
+    }
+
+    let mut token = storage::read::<TokenMintDetails>(mint_account)?;
+
+    let held = token.balances.get(holder_account.key).copied().unwrap_or(0);
+    if held < params.amount {
+        return Err(FungibleTokenError::InsufficientBalance.into());
+    }
+
+    let reserve = token
+        .btc_reserve
+        .take()
+        .ok_or(FungibleTokenError::NotBtcBacked)?;
+
+    let (selected, mut kept) = select_largest_first(reserve, params.amount)?;
+
+    let mut transaction = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![],
+        output: vec![],
+    };
+    add_state_transition(&mut transaction, mint_account);
+
+    let withdrawal = build_withdrawal_transaction(
+        transaction,
+        mint_account.key,
+        &selected,
+        params.amount,
+        ScriptBuf::from_bytes(params.dest_script_pubkey),
+        params.fee_rate_sats_per_vbyte,
+    )?;
+
+    let balance = token
+        .balances
+        .get_mut(holder_account.key)
+        .filter(|balance| **balance >= withdrawal.burned)
+        .ok_or(FungibleTokenError::InsufficientBalance)?;
+    *balance -= withdrawal.burned;
+    token.circulating_supply = token
+        .circulating_supply
+        .checked_sub(withdrawal.burned)
+        .ok_or(FungibleTokenError::ReserveMismatch)?;
+
+    kept.extend(withdrawal.change.clone());
+    token.btc_reserve = Some(kept);
+
+    ensure_fully_backed(&token)?;
+    storage::write(mint_account, &token)?;
+
+    events::emit(&BurnedLog {
+        user: *holder_account.key,
+        amount: withdrawal.burned,
+        block_height: get_bitcoin_block_height(),
+    });
+
+    msg!(
+        "Withdrawing {} sats on Bitcoin from {} utxos",
+        withdrawal.burned,
+        selected.len()
+    );
+
+    sign_payout(
+        mint_account,
+        &withdrawal.transaction,
+        &withdrawal.inputs_to_sign,
+    )
+}
+
+/// Spends `selected` in a transaction whose first input and output are the
+/// mint account's state transition, paying `amount` less the fee to
+/// `dest_script_pubkey` and what is left back to the mint's own address as
+/// change for the reserve. Change below the dust limit gets no output; it is
+/// withdrawn along with the amount instead, so the reserve still matches the
+/// supply. The mint account signs every input.
+pub fn build_withdrawal_transaction(
+    mut transaction: Transaction,
+    mint_key: &Pubkey,
+    selected: &[ReserveUtxo],
+    amount: u64,
+    dest_script_pubkey: ScriptBuf,
+    fee_rate_sats_per_vbyte: u64,
+) -> Result<Withdrawal, ProgramError> {
+    transaction
+        .input
+        .extend(selected.iter().map(|reserved| escrow_input(&reserved.utxo)));
+
+    let spent = selected
+        .iter()
+        .try_fold(0u64, |total, reserved| {
+            total.checked_add(reserved.value_sats)
+        })
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+    let change = spent
+        .checked_sub(amount)
+        .ok_or(FungibleTokenError::ReserveMismatch)?;
+    let (burned, change) = if change < MIN_UTXO_SATS {
+        (spent, 0)
+    } else {
+        (amount, change)
+    };
+
+    let change_script_pubkey = account_script_pubkey(mint_key);
+    let mut scripts = vec![&dest_script_pubkey];
+    if change > 0 {
+        scripts.push(&change_script_pubkey);
+    }
+    let fee = fee_with_outputs(&transaction, scripts, fee_rate_sats_per_vbyte)?;
+    let paid = burned
+        .checked_sub(fee)
+        .filter(|paid| *paid >= MIN_UTXO_SATS)
+        .ok_or(ProgramError::InsufficientFunds)?;
+
+    transaction.output.push(TxOut {
+        value: Amount::from_sat(paid),
+        script_pubkey: dest_script_pubkey,
+    });
+    if change > 0 {
+        transaction.output.push(TxOut {
+            value: Amount::from_sat(change),
+            script_pubkey: change_script_pubkey,
+        });
+    }
+
+    let change = (change > 0).then(|| ReserveUtxo {
+        utxo: UtxoMeta::from_outpoint(
+            transaction.compute_txid(),
+            transaction.output.len() as u32 - 1,
+        ),
+        value_sats: change,
+    });
+    let inputs_to_sign = inputs_signed_by(&transaction, mint_key);
+
+    Ok(Withdrawal {
+        transaction,
+        inputs_to_sign,
+        burned,
+        change,
+    })
+}
+
+/// Splits `reserve` into the utxos a withdrawal of `amount` spends, largest
+/// first, and those it keeps, in the order they were held.
+pub fn select_largest_first(
+    reserve: Vec<ReserveUtxo>,
+    amount: u64,
+) -> Result<(Vec<ReserveUtxo>, Vec<ReserveUtxo>), ProgramError> {
+    // ties broken by outpoint so every validator picks the same utxos
+    let mut order: Vec<usize> = (0..reserve.len()).collect();
+    order.sort_by(|&a, &b| {
+        reserve[b]
+            .value_sats
+            .cmp(&reserve[a].value_sats)
+            .then_with(|| {
+                reserve[a]
+                    .utxo
+                    .serialize()
+                    .cmp(&reserve[b].utxo.serialize())
+            })
+    });
+
+    let mut picked = vec![false; reserve.len()];
+    let mut total = 0u64;
+    for index in order {
+        if total >= amount {
+            break;
+        }
+        picked[index] = true;
+        total = total.saturating_add(reserve[index].value_sats);
+    }
+    // the holder's tokens are backed, so the reserve covers any balance
+    if total < amount {
+        return Err(FungibleTokenError::ReserveMismatch.into());
+    }
+
+    let (selected, kept): (Vec<_>, Vec<_>) = reserve
+        .into_iter()
+        .zip(picked)
+        .partition(|(_, picked)| *picked);
+    Ok((
+        selected.into_iter().map(|(reserved, _)| reserved).collect(),
+        kept.into_iter().map(|(reserved, _)| reserved).collect(),
+    ))
+}
+
+// fails with `ReserveMismatch` unless the reserve holds exactly the
+// circulating supply
+fn ensure_fully_backed(token: &TokenMintDetails) -> Result<(), ProgramError> {
+    let held = token
+        .btc_reserve
+        .as_ref()
+        .ok_or(FungibleTokenError::NotBtcBacked)?
+        .iter()
+        .try_fold(0u64, |total, reserved| {
+            total.checked_add(reserved.value_sats)
+        })
+        .ok_or(ProgramError::ArithmeticOverflow)?;
+
+    if held != token.circulating_supply {
+        msg!(
+            "Reserve of {} sats backs a supply of {}",
+            held,
+            token.circulating_supply
+        );
+        return Err(FungibleTokenError::ReserveMismatch.into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod wrapped_btc_tests {
+    use super::*;
+    use crate::mint::{mint_tokens, TokenMintDetails};
+    use crate::process_instruction;
+    use crate::test_utils::{
+        balance_of, btc_mint_account, config_account, register_utxo_owner,
+        take_transactions_to_sign, token_account, TestAccount,
+    };
+
+    fn deposit(
+        mint: &mut TestAccount,
+        depositor: &mut TestAccount,
+        utxo: &UtxoMeta,
+        value_sats: u64,
+    ) -> Result<(), ProgramError> {
+        let program_id = mint.owner;
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+        let mut data = vec![67];
+        data.extend(
+            borsh::to_vec(&DepositBtcParams {
+                utxo: utxo.clone(),
+                value_sats,
+            })
+            .unwrap(),
+        );
+        process_instruction(
+            &program_id,
+            &[mint.info(), depositor.info(), config.info()],
+            &data,
+        )
+    }
+
+    fn withdraw(
+        mint: &mut TestAccount,
+        holder: &mut TestAccount,
+        amount: u64,
+    ) -> Result<(), ProgramError> {
+        let program_id = mint.owner;
+        let mut config = config_account(&program_id, &Pubkey::new_unique(), false);
+        let mut data = vec![68];
+        data.extend(
+            borsh::to_vec(&WithdrawBtcParams {
+                amount,
+                dest_script_pubkey: [vec![0x51, 0x20], vec![0xbb; 32]].concat(),
+                fee_rate_sats_per_vbyte: 2,
+            })
+            .unwrap(),
+        );
+        process_instruction(
+            &program_id,
+            &[mint.info(), holder.info(), config.info()],
+            &data,
+        )
+    }
+
+    fn mint_state(mint: &TestAccount) -> TokenMintDetails {
+        TokenMintDetails::try_from_slice(mint.data()).unwrap()
+    }
+
+    fn reserved(tag: u8, value_sats: u64) -> ReserveUtxo {
+        ReserveUtxo {
+            utxo: UtxoMeta::from([tag; 32], 0),
+            value_sats,
+        }
+    }
+
+    #[test]
+    fn test_deposits_mint_their_value() {
+        let program_id = Pubkey::new_unique();
+        let mut mint = btc_mint_account(&program_id);
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let utxo = UtxoMeta::from([21; 32], 0);
+
+        assert_eq!(
+            deposit(&mut mint, &mut alice, &utxo, 10_000),
+            Err(PredictionMarketError::UtxoOwnershipNotProven.into())
+        );
+        register_utxo_owner(utxo.clone(), alice.key());
+        assert_eq!(
+            deposit(&mut mint, &mut alice, &utxo, MIN_UTXO_SATS - 1),
+            Err(PredictionMarketError::UtxoBelowDust.into())
+        );
+
+        deposit(&mut mint, &mut alice, &utxo, 10_000).unwrap();
+        assert_eq!(
+            deposit(&mut mint, &mut alice, &utxo, 10_000),
+            Err(PredictionMarketError::UtxoAlreadyEscrowed.into())
+        );
+
+        let state = mint_state(&mint);
+        assert_eq!(balance_of(&mint, &alice.key()), 10_000);
+        assert_eq!(state.circulating_supply, 10_000);
+        assert_eq!(state.btc_reserve, Some(vec![reserved(21, 10_000)]));
+    }
+
+    #[test]
+    fn test_withdrawal_spends_the_largest_utxo_and_keeps_the_change() {
+        let program_id = Pubkey::new_unique();
+        let mut mint = btc_mint_account(&program_id);
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        for (tag, value_sats) in [(22, 3_000), (23, 10_000)] {
+            let utxo = UtxoMeta::from([tag; 32], 0);
+            register_utxo_owner(utxo.clone(), alice.key());
+            deposit(&mut mint, &mut alice, &utxo, value_sats).unwrap();
+        }
+        take_transactions_to_sign();
+
+        assert_eq!(
+            withdraw(&mut mint, &mut alice, MIN_UTXO_SATS - 1),
+            Err(PredictionMarketError::UtxoBelowDust.into())
+        );
+        withdraw(&mut mint, &mut alice, 4_000).unwrap();
+
+        let (transaction, inputs_to_sign) = take_transactions_to_sign().pop().unwrap();
+        assert_eq!(
+            transaction.input[1].previous_output,
+            UtxoMeta::from([23; 32], 0).to_outpoint()
+        );
+        assert_eq!(inputs_to_sign.len(), 2);
+        assert_eq!(transaction.output.len(), 3);
+        assert!(transaction.output[1].value.to_sat() < 4_000);
+        assert_eq!(transaction.output[2].value.to_sat(), 6_000);
+
+        let state = mint_state(&mint);
+        assert_eq!(balance_of(&mint, &alice.key()), 9_000);
+        assert_eq!(state.circulating_supply, 9_000);
+        assert_eq!(
+            state.btc_reserve,
+            Some(vec![
+                reserved(22, 3_000),
+                ReserveUtxo {
+                    utxo: UtxoMeta::from_outpoint(transaction.compute_txid(), 2),
+                    value_sats: 6_000,
+                },
+            ])
+        );
+
+        assert_eq!(
+            withdraw(&mut mint, &mut alice, 9_001),
+            Err(FungibleTokenError::InsufficientBalance.into())
+        );
+        // spending both utxos leaves 300 sats of change, dust that goes too
+        withdraw(&mut mint, &mut alice, 8_700).unwrap();
+        assert_eq!(balance_of(&mint, &alice.key()), 0);
+        assert_eq!(mint_state(&mint).btc_reserve, Some(vec![]));
+    }
+
+    #[test]
+    fn test_only_wrapped_btc_mints_take_deposits() {
+        let program_id = Pubkey::new_unique();
+        let mut alice = TestAccount::new(&program_id, &[]).signer();
+        let utxo = UtxoMeta::from([24; 32], 0);
+        register_utxo_owner(utxo.clone(), alice.key());
+
+        let mut token = token_account(&program_id, &[]);
+        assert_eq!(
+            deposit(&mut token, &mut alice, &utxo, 10_000),
+            Err(FungibleTokenError::NotBtcBacked.into())
+        );
+
+        // nor can a wrapped-BTC mint be minted to without a deposit
+        let mut mint = btc_mint_account(&program_id);
+        assert_eq!(
+            mint_tokens(&mint.info(), &alice.key(), 10_000, None),
+            Err(FungibleTokenError::BtcBackedMint.into())
+        );
+    }
+
+    #[test]
+    fn test_selection_takes_the_largest_utxos_first() {
+        let reserve = vec![
+            reserved(1, 5_000),
+            reserved(2, 9_000),
+            reserved(3, 2_000),
+            reserved(4, 9_000),
+        ];
+
+        let (selected, kept) = select_largest_first(reserve.clone(), 10_000).unwrap();
+        assert_eq!(selected, vec![reserved(2, 9_000), reserved(4, 9_000)]);
+        assert_eq!(kept, vec![reserved(1, 5_000), reserved(3, 2_000)]);
+
+        assert_eq!(
+            select_largest_first(reserve, 25_001),
+            Err(FungibleTokenError::ReserveMismatch.into())
+        );
+    }
+}